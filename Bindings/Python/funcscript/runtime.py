@@ -21,6 +21,9 @@ class FsError(Exception):
     message: str
     line: int = -1
     column: int = -1
+    kind: str = ""
+    data: Any = None
+    cause: Optional["FsError"] = None
 
     def __str__(self) -> str:
         loc = ""
@@ -30,12 +33,18 @@ class FsError(Exception):
 
 
 class _FsErrorC(ctypes.Structure):
-    _fields_ = [
-        ("code", ctypes.c_uint32),
-        ("line", ctypes.c_int32),
-        ("column", ctypes.c_int32),
-        ("message", ctypes.c_void_p),
-    ]
+    pass
+
+
+_FsErrorC._fields_ = [
+    ("code", ctypes.c_uint32),
+    ("line", ctypes.c_int32),
+    ("column", ctypes.c_int32),
+    ("message", ctypes.c_void_p),
+    ("kind", ctypes.c_char_p),
+    ("data_json", ctypes.c_void_p),
+    ("cause", ctypes.POINTER(_FsErrorC)),
+]
 
 
 class _FsValueC(ctypes.Structure):
@@ -48,6 +57,45 @@ _FsHostFileExistsFn = ctypes.CFUNCTYPE(ctypes.c_int32, ctypes.c_void_p, ctypes.c
 _FsHostIsFileFn = ctypes.CFUNCTYPE(ctypes.c_int32, ctypes.c_void_p, ctypes.c_char_p, ctypes.POINTER(ctypes.c_int32), ctypes.POINTER(_FsErrorC))
 _FsHostDirListFn = ctypes.CFUNCTYPE(ctypes.c_int32, ctypes.c_void_p, ctypes.c_char_p, ctypes.c_void_p, _FsHostWriteFn, ctypes.POINTER(_FsErrorC))
 _FsHostLogLineFn = ctypes.CFUNCTYPE(None, ctypes.c_void_p, ctypes.c_char_p)
+_FsHostNowFn = ctypes.CFUNCTYPE(None, ctypes.c_void_p, ctypes.POINTER(ctypes.c_int64), ctypes.POINTER(ctypes.c_int32))
+_FsHostRandomFn = ctypes.CFUNCTYPE(ctypes.c_uint64, ctypes.c_void_p)
+
+
+class _FsFileMetadataC(ctypes.Structure):
+    _fields_ = [
+        ("exists", ctypes.c_int32),
+        ("is_dir", ctypes.c_int32),
+        ("size", ctypes.c_uint64),
+        ("modified_ticks", ctypes.c_int64),
+    ]
+
+
+_FsHostDirWalkFn = ctypes.CFUNCTYPE(ctypes.c_int32, ctypes.c_void_p, ctypes.c_char_p, ctypes.c_void_p, _FsHostWriteFn, ctypes.POINTER(_FsErrorC))
+_FsHostFileMetadataFn = ctypes.CFUNCTYPE(ctypes.c_int32, ctypes.c_void_p, ctypes.c_char_p, ctypes.POINTER(_FsFileMetadataC), ctypes.POINTER(_FsErrorC))
+_FsHostEnvVarFn = ctypes.CFUNCTYPE(None, ctypes.c_void_p, ctypes.c_char_p, ctypes.POINTER(ctypes.c_int32), ctypes.c_void_p, _FsHostWriteFn)
+_FsHostFileWriteFn = ctypes.CFUNCTYPE(ctypes.c_int32, ctypes.c_void_p, ctypes.c_char_p, ctypes.POINTER(ctypes.c_uint8), ctypes.c_uint64, ctypes.POINTER(_FsErrorC))
+
+
+class _FsHttpRequestC(ctypes.Structure):
+    _fields_ = [
+        ("method", ctypes.c_char_p),
+        ("url", ctypes.c_char_p),
+        ("headers", ctypes.c_char_p),
+        ("body", ctypes.POINTER(ctypes.c_uint8)),
+        ("body_len", ctypes.c_uint64),
+    ]
+
+
+_FsHostFetchFn = ctypes.CFUNCTYPE(
+    ctypes.c_int32,
+    ctypes.c_void_p,
+    ctypes.POINTER(_FsHttpRequestC),
+    ctypes.POINTER(ctypes.c_int32),
+    ctypes.c_void_p,
+    ctypes.c_void_p,
+    _FsHostWriteFn,
+    ctypes.POINTER(_FsErrorC),
+)
 
 
 class _FsHostCallbacksC(ctypes.Structure):
@@ -58,6 +106,14 @@ class _FsHostCallbacksC(ctypes.Structure):
         ("is_file", _FsHostIsFileFn),
         ("dir_list", _FsHostDirListFn),
         ("log_line", _FsHostLogLineFn),
+        ("now", _FsHostNowFn),
+        ("random", _FsHostRandomFn),
+        ("file_read_bytes", _FsHostFileReadFn),
+        ("dir_walk", _FsHostDirWalkFn),
+        ("file_metadata", _FsHostFileMetadataFn),
+        ("file_write", _FsHostFileWriteFn),
+        ("env_var", _FsHostEnvVarFn),
+        ("fetch", _FsHostFetchFn),
     ]
 
 
@@ -116,6 +172,10 @@ _LIB.fs_vm_free.argtypes = [ctypes.c_void_p]
 
 _LIB.fs_vm_set_host_callbacks.restype = ctypes.c_int32
 _LIB.fs_vm_set_host_callbacks.argtypes = [ctypes.c_void_p, ctypes.POINTER(_FsHostCallbacksC)]
+_LIB.fs_vm_set_random_seed.restype = ctypes.c_int32
+_LIB.fs_vm_set_random_seed.argtypes = [ctypes.c_void_p, ctypes.c_uint64]
+_LIB.fs_vm_clear_random_seed.restype = ctypes.c_int32
+_LIB.fs_vm_clear_random_seed.argtypes = [ctypes.c_void_p]
 
 _LIB.fs_vm_eval.restype = ctypes.c_int32
 _LIB.fs_vm_eval.argtypes = [
@@ -214,6 +274,7 @@ FS_VALUE_BIGINT = ctypes.c_uint32.in_dll(_LIB, "FS_VALUE_BIGINT").value
 FS_VALUE_BYTES = ctypes.c_uint32.in_dll(_LIB, "FS_VALUE_BYTES").value
 FS_VALUE_GUID = ctypes.c_uint32.in_dll(_LIB, "FS_VALUE_GUID").value
 FS_VALUE_DATETIME = ctypes.c_uint32.in_dll(_LIB, "FS_VALUE_DATETIME").value
+FS_VALUE_DURATION = ctypes.c_uint32.in_dll(_LIB, "FS_VALUE_DURATION").value
 FS_VALUE_STRING = ctypes.c_uint32.in_dll(_LIB, "FS_VALUE_STRING").value
 FS_VALUE_LIST = ctypes.c_uint32.in_dll(_LIB, "FS_VALUE_LIST").value
 FS_VALUE_KVC = ctypes.c_uint32.in_dll(_LIB, "FS_VALUE_KVC").value
@@ -404,16 +465,23 @@ def _convert_value(v: Any) -> Any:
             if isinstance(ticks, int):
                 unix_epoch_ticks = 621_355_968_000_000_000
                 ticks_per_sec = 10_000_000
-                delta = ticks - unix_epoch_ticks
+                # Ticks are clock time at the date's offset (minutes east of UTC), if it has one.
+                offset = v.get("offset") if isinstance(v.get("offset"), int) else 0
+                delta = ticks - offset * 60 * ticks_per_sec - unix_epoch_ticks
                 sec = delta // ticks_per_sec
                 sub = delta % ticks_per_sec
-                return _dt.datetime.fromtimestamp(sec, tz=_dt.timezone.utc).replace(microsecond=(sub // 10))
+                utc = _dt.datetime.fromtimestamp(sec, tz=_dt.timezone.utc).replace(microsecond=(sub // 10))
+                if offset:
+                    return utc.astimezone(_dt.timezone(_dt.timedelta(minutes=offset)))
+                return utc
+        if t == "duration" and isinstance(v.get("ticks"), int):
+            return _dt.timedelta(microseconds=v["ticks"] // 10)
         return {k: _convert_value(val) for k, val in v.items()}
     return v
 
 
 class FsVm:
-    def __init__(self) -> None:
+    def __init__(self, env: Optional[dict[str, str]] = None) -> None:
         self._vm = _LIB.fs_vm_new()
         if not self._vm:
             raise RuntimeError("fs_vm_new returned NULL")
@@ -423,6 +491,11 @@ class FsVm:
         self._host_is_file_fn = _FsHostIsFileFn(self._host_is_file)
         self._host_dir_list_fn = _FsHostDirListFn(self._host_dir_list)
         self._host_log_line_fn = _FsHostLogLineFn(self._host_log_line)
+        self._host_file_read_bytes_fn = _FsHostFileReadFn(self._host_file_read_text)
+        self._host_dir_walk_fn = _FsHostDirWalkFn(self._host_dir_walk)
+        self._host_file_metadata_fn = _FsHostFileMetadataFn(self._host_file_metadata)
+        self._env = dict(env or {})
+        self._host_env_var_fn = _FsHostEnvVarFn(self._host_env_var)
 
         cb = _FsHostCallbacksC(
             ctypes.c_void_p(0),
@@ -431,6 +504,10 @@ class FsVm:
             self._host_is_file_fn,
             self._host_dir_list_fn,
             self._host_log_line_fn,
+            file_read_bytes=self._host_file_read_bytes_fn,
+            dir_walk=self._host_dir_walk_fn,
+            file_metadata=self._host_file_metadata_fn,
+            env_var=self._host_env_var_fn,
         )
         rc = _LIB.fs_vm_set_host_callbacks(self._vm, ctypes.byref(cb))
         if rc != 0:
@@ -508,15 +585,70 @@ class FsVm:
             out_err.contents.code = 1
             return 1
 
+    @staticmethod
+    def _host_dir_walk(user_data: int, path: Any, out_ctx: int, out_write: Any, out_err: Any) -> int:
+        try:
+            p = (path or b"").decode("utf-8")
+            if not os.path.isdir(p):
+                out_err.contents.code = 1
+                return 1
+            entries: list[str] = []
+            for root, dirs, files in os.walk(p):
+                rel_root = os.path.relpath(root, p)
+                for name in dirs + files:
+                    rel = name if rel_root == "." else os.path.join(rel_root, name)
+                    entries.append(rel.replace(os.sep, "/"))
+            entries.sort()
+            payload = ("\n".join(entries)).encode("utf-8")
+            tmp = ctypes.create_string_buffer(payload)
+            out_write(out_ctx, ctypes.cast(tmp, ctypes.POINTER(ctypes.c_uint8)), ctypes.c_uint64(len(payload)))
+            return 0
+        except Exception:
+            out_err.contents.code = 1
+            return 1
+
+    @staticmethod
+    def _host_file_metadata(user_data: int, path: Any, out_meta: Any, out_err: Any) -> int:
+        try:
+            p = (path or b"").decode("utf-8")
+            if not os.path.exists(p):
+                out_meta.contents.exists = 0
+                return 0
+            st = os.stat(p)
+            out_meta.contents.exists = 1
+            out_meta.contents.is_dir = 1 if os.path.isdir(p) else 0
+            out_meta.contents.size = st.st_size
+            out_meta.contents.modified_ticks = 621_355_968_000_000_000 + st.st_mtime_ns // 100
+            return 0
+        except Exception:
+            out_err.contents.code = 1
+            return 1
+
+    def _host_env_var(self, user_data: int, name: Any, out_found: Any, out_ctx: int, out_write: Any) -> None:
+        value = self._env.get((name or b"").decode("utf-8"))
+        if value is None:
+            return
+        payload = value.encode("utf-8")
+        tmp = ctypes.create_string_buffer(payload)
+        out_write(out_ctx, ctypes.cast(tmp, ctypes.POINTER(ctypes.c_uint8)), ctypes.c_uint64(len(payload)))
+        out_found.contents.value = 1
+
     @staticmethod
     def _host_log_line(user_data: int, text: Any) -> None:
         _ = user_data
         _ = text
 
+    @staticmethod
+    def _error_from_c(err: _FsErrorC) -> FsError:
+        msg = _peek_c_string(err.message) if err.message else ""
+        data = json.loads(_peek_c_string(err.data_json)) if err.data_json else None
+        cause = FsVm._error_from_c(err.cause.contents) if err.cause else None
+        kind = err.kind.decode("utf-8") if err.kind else ""
+        return FsError(int(err.code), msg or "error", int(err.line), int(err.column), kind, data, cause)
+
     def _raise(self, out_err: _FsErrorC) -> None:
         try:
-            msg = _peek_c_string(out_err.message) if out_err.message else ""
-            raise FsError(int(out_err.code), msg or "error", int(out_err.line), int(out_err.column))
+            raise self._error_from_c(out_err)
         finally:
             _LIB.fs_error_free(ctypes.byref(out_err))
 
@@ -635,6 +767,7 @@ class FsVm:
             FS_VALUE_BYTES,
             FS_VALUE_GUID,
             FS_VALUE_DATETIME,
+            FS_VALUE_DURATION,
         ):
             s = self._value_to_json(h)
             _LIB.fs_vm_value_free(self._vm, h)
@@ -654,6 +787,12 @@ class FsVm:
         _LIB.fs_vm_value_free(self._vm, h)
         return _convert_value(json.loads(s))
 
+    def set_random_seed(self, seed: Optional[int]) -> None:
+        if seed is None:
+            _LIB.fs_vm_clear_random_seed(self._vm)
+        else:
+            _LIB.fs_vm_set_random_seed(self._vm, seed & 0xFFFFFFFFFFFFFFFF)
+
     def eval_json(self, source: str) -> str:
         h = self._eval_handle(source)
         try:
@@ -32,7 +32,8 @@ class FuncScriptCorePythonBindingTests(unittest.TestCase):
             with self.assertRaises(FsError) as ctx:
                 vm.eval("Range(1, -1)")
             err = ctx.exception
-            self.assertEqual(err.code, 1)
+            self.assertEqual(err.code, 2006)
+            self.assertEqual(err.kind, "invalid_value")
         finally:
             vm.close()
 
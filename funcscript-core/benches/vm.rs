@@ -0,0 +1,55 @@
+//! VM micro-benchmarks: arithmetic loops, list building and KVC access.
+//!
+//! Run with `cargo bench --bench vm`. Uses a tiny std-only harness so the crate has no
+//! benchmark dependencies; each case reports the best of several timed runs.
+
+use funcscript::obj::FsFunction;
+use funcscript::value::Value;
+use funcscript::vm::VM;
+use std::hint::black_box;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 7;
+
+fn bench(name: &str, source: &str, iters: u32) {
+    let program: Arc<FsFunction> = VM::compile(source).expect("benchmark source should compile");
+    let mut vm = VM::new();
+    let expected: Value = vm.execute(&program).expect("benchmark source should run");
+
+    let mut best = Duration::MAX;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        for _ in 0..iters {
+            black_box(vm.execute(black_box(&program)).unwrap());
+        }
+        best = best.min(start.elapsed());
+    }
+    let per_iter = best / iters;
+    println!("{name:<28} {per_iter:>12.2?}/iter   (result {expected})");
+}
+
+fn main() {
+    println!(
+        "value size: {} bytes, obj size: {} bytes",
+        std::mem::size_of::<Value>(),
+        std::mem::size_of::<funcscript::obj::Obj>()
+    );
+
+    bench("arith_reduce_10k", "Range(1, 10000) reduce (acc, x) => acc + x * 2 - 1 ~ 0", 20);
+    bench("arith_float_10k", "Range(1, 10000) reduce (acc, x) => acc + x / 3 ~ 0.0", 20);
+    bench("bigint_mul_chain", "Range(1, 200) reduce (acc, x) => acc * 3 ~ 1", 20);
+    bench("list_build_map_10k", "Len(Range(0, 10000) map (x) => x * x)", 20);
+    bench("list_build_strings_5k", "Len(Range(0, 5000) map (x) => 'k' + x)", 20);
+    bench("list_filter_10k", "Len(Range(0, 10000) filter (x) => x % 3 = 0)", 20);
+    bench(
+        "kvc_member_access",
+        "{ cfg: { rate: 3; bias: 7; name: 'scoring' }; eval Sum(Range(0, 2000) map (x) => x * cfg.rate + cfg.bias) }",
+        20,
+    );
+    bench(
+        "kvc_build_records_2k",
+        "Len(Range(0, 2000) map (x) => { id: x; score: x * 2; label: 'row' })",
+        20,
+    );
+}
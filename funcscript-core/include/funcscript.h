@@ -2,10 +2,20 @@
 //
 // Ownership:
 // - Any `char*` returned via out params is owned by FuncScript and must be freed with `fs_free_string`.
-// - Any `FsErrorC.message` must be freed with `fs_error_free` (or `fs_free_string` on the message pointer).
+// - Any `FsErrorC` filled by FuncScript must be released with `fs_error_free`, which frees
+//   `message`, `data_json` and the `cause` chain. `kind` points to static storage.
+//
+// Errors:
+// - `FsErrorC.code` is the stable code of the error kind, `kind` its snake_case name:
+//   1000 syntax, 2000 runtime, 2001 type_mismatch, 2002 parameter_count, 2003 undefined,
+//   2004 division_by_zero, 2005 out_of_range, 2006 invalid_value, 2007 limit_exceeded,
+//   2100 api, 2600 host_io, 2601 host_unavailable, 2602 access_denied,
+//   3000 user.
+// - Host callbacks only need to set `code` and `message`; unknown codes are reported as host_io.
 //
 // Threading:
-// - `FsVm*` is not thread-safe. Use one VM per thread or add external synchronization.
+// - `FsVm*` may be moved to another thread but must not be used from two threads at once.
+//   Use one VM per thread or add external synchronization.
 
 #ifndef FUNCSCRIPT_CORE_H
 #define FUNCSCRIPT_CORE_H
@@ -27,10 +37,37 @@ typedef struct FsErrorC {
   int32_t line;
   int32_t column;
   char* message;
+  const char* kind;
+  char* data_json;
+  struct FsErrorC* cause;
 } FsErrorC;
 
+typedef struct FsMemoryStats {
+  uint64_t live_kvcs;
+  uint64_t tracked_kvcs;
+  uint64_t value_handles;
+  uint64_t collections;
+  uint64_t collected_kvcs;
+} FsMemoryStats;
+
+typedef struct FsFileMetadata {
+  int32_t exists;
+  int32_t is_dir;
+  uint64_t size;
+  int64_t modified_ticks; // UTC .NET ticks
+} FsFileMetadata;
+
 typedef void (*FsHostWriteFn)(void* ctx, const uint8_t* bytes, uint64_t len);
 
+// Request passed to the fetch callback; the pointers are only valid during the call.
+typedef struct FsHttpRequest {
+  const char* method; // upper case, e.g. "GET"
+  const char* url;
+  const char* headers; // "Name: value" lines separated by '\n'
+  const uint8_t* body;
+  uint64_t body_len;
+} FsHttpRequest;
+
 typedef struct FsHostCallbacks {
   void* user_data;
 
@@ -42,6 +79,31 @@ typedef struct FsHostCallbacks {
   int32_t (*dir_list)(void* user_data, const char* path, void* out_ctx, FsHostWriteFn out_write, FsErrorC* out_error);
 
   void (*log_line)(void* user_data, const char* text);
+
+  // Current time for now()/utcnow()/today(): UTC .NET ticks and the local UTC offset.
+  // Defaults to the system clock with a zero offset.
+  void (*now)(void* user_data, int64_t* out_utc_ticks, int32_t* out_offset_minutes);
+  // 64 random bits for Random()/guid(). Defaults to ambient entropy.
+  uint64_t (*random)(void* user_data);
+
+  int32_t (*file_read_bytes)(void* user_data, const char* path, void* out_ctx, FsHostWriteFn out_write, FsErrorC* out_error);
+  // Every entry below `path`, relative to it with '/' separators, one per line.
+  int32_t (*dir_walk)(void* user_data, const char* path, void* out_ctx, FsHostWriteFn out_write, FsErrorC* out_error);
+  // Leave `out_metadata->exists` at 0 for a missing path.
+  int32_t (*file_metadata)(void* user_data, const char* path, FsFileMetadata* out_metadata, FsErrorC* out_error);
+
+  // Creates or replaces a file for writefile(). Leave it null unless scripts may write;
+  // a VM from fs_vm_new has no write access.
+  int32_t (*file_write)(void* user_data, const char* path, const uint8_t* bytes, uint64_t len, FsErrorC* out_error);
+
+  // Environment variable for env(): set `*out_found` to 1 and write the value, or leave it
+  // at 0 when the variable is unset.
+  void (*env_var)(void* user_data, const char* name, int32_t* out_found, void* out_ctx, FsHostWriteFn out_write);
+
+  // HTTP client for fetch(). Set `*out_status`, write the response headers as "Name: value"
+  // lines to `out_headers_ctx` and the body to `out_body_ctx`. Leave it null to keep scripts
+  // off the network.
+  int32_t (*fetch)(void* user_data, const FsHttpRequest* request, int32_t* out_status, void* out_headers_ctx, void* out_body_ctx, FsHostWriteFn out_write, FsErrorC* out_error);
 } FsHostCallbacks;
 
 extern const uint32_t FS_CORE_ABI_VERSION;
@@ -54,6 +116,7 @@ extern const uint32_t FS_VALUE_BIGINT;
 extern const uint32_t FS_VALUE_BYTES;
 extern const uint32_t FS_VALUE_GUID;
 extern const uint32_t FS_VALUE_DATETIME;
+extern const uint32_t FS_VALUE_DURATION;
 extern const uint32_t FS_VALUE_STRING;
 extern const uint32_t FS_VALUE_LIST;
 extern const uint32_t FS_VALUE_KVC;
@@ -65,9 +128,26 @@ extern const uint32_t FS_VALUE_ERROR;
 FsVm* fs_vm_new(void);
 void fs_vm_free(FsVm* vm);
 
-
+// Host callbacks belong to the VM and apply to every call on it, including lazy members
+// evaluated through the value accessors. The struct is copied; `user_data` must outlive the VM.
 int32_t fs_vm_set_host_callbacks(FsVm* vm, const FsHostCallbacks* callbacks);
 
+// Parallel `map`/`filter`/`Sum` over collections with at least `min_items` elements.
+// Only lambdas that cannot reach host callbacks run on workers. `workers <= 1` disables it.
+int32_t fs_vm_set_parallelism(FsVm* vm, uint32_t workers, uint64_t min_items);
+
+// Deterministic Random()/guid(): the seed takes precedence over the host random callback.
+// The sequence continues across evaluations; set the seed again to restart it.
+int32_t fs_vm_set_random_seed(FsVm* vm, uint64_t seed);
+int32_t fs_vm_clear_random_seed(FsVm* vm);
+
+// KVC cycle collection. Runs automatically during evaluation unless turned off with
+// `fs_vm_set_auto_collect(vm, 0)`; long-lived VMs can also collect explicitly between
+// evaluations. Returns the number of KVCs freed.
+int32_t fs_vm_set_auto_collect(FsVm* vm, int32_t enabled);
+uint64_t fs_vm_collect_cycles(FsVm* vm);
+int32_t fs_vm_memory_stats(FsVm* vm, FsMemoryStats* out_stats);
+
 int32_t fs_vm_eval(FsVm* vm, const char* source, char** out_json, FsErrorC* out_error);
 
 int32_t fs_vm_eval_value(FsVm* vm, const char* source, FsValue* out_value, FsErrorC* out_error);
@@ -75,6 +155,7 @@ int32_t fs_vm_value_free(FsVm* vm, FsValue value);
 uint32_t fs_vm_value_type(FsVm* vm, FsValue value);
 int32_t fs_vm_value_to_json(FsVm* vm, FsValue value, char** out_json, FsErrorC* out_error);
 
+// Strings report their length in code points; lists and ranges in items; bytes in bytes.
 int32_t fs_vm_value_len(FsVm* vm, FsValue value, uint64_t* out_len, FsErrorC* out_error);
 int32_t fs_vm_value_index(FsVm* vm, FsValue receiver, int64_t index, FsValue* out_value, FsErrorC* out_error);
 int32_t fs_vm_value_get_key(FsVm* vm, FsValue receiver, const char* key, FsValue* out_value, FsErrorC* out_error);
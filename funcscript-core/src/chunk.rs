@@ -1,8 +1,17 @@
 //! Bytecode opcodes and `Chunk` container used by the VM.
+//!
+//! Instructions are stored as a flat byte buffer: a one-byte tag followed by little-endian
+//! operands (`u16` for local slots and argument counts, `u32` for constant indices, element
+//! counts and jump offsets). `OpCode` is the decoded form the compiler emits and the VM
+//! dispatches on.
+//!
+//! `Chunk::write` also fuses the most frequent instruction sequences into
+//! superinstructions (`OpGetLocal2`, `OpGetLocalConst`, `OpLocalConstBinary`,
+//! `OpGetGlobalProp`). Fusion never crosses a jump target.
 
 use crate::value::Value;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OpCode {
     OpConstant(usize),
     OpAdd,
@@ -43,12 +52,122 @@ pub enum OpCode {
     OpFirstWhere,
     OpSort,
     OpReduce(bool),
+    /// `OpGetLocal(a); OpGetLocal(b)`
+    OpGetLocal2(usize, usize),
+    /// `OpGetLocal(slot); OpConstant(idx)`
+    OpGetLocalConst(usize, usize),
+    /// `OpGetLocal(slot); OpConstant(idx); <op>`
+    OpLocalConstBinary(usize, usize, BinaryOp),
+    /// `OpGetGlobal(name); OpGetProp(prop)`
+    OpGetGlobalProp(usize, usize),
+    /// Installs an error handler whose catch block starts `offset` bytes after this instruction.
+    OpTry(usize),
+    /// Removes the handler; skips the catch block by `offset` unless the value is an error.
+    OpEndTry(usize),
+    /// `[error, handler]` -> `handler(error)`, or `handler` itself if it is not callable.
+    OpCatch,
+}
+
+/// Binary operators that can be fused into `OpLocalConstBinary`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Modulo,
+    Equal,
+    Greater,
+    Less,
+}
+
+impl BinaryOp {
+    fn from_op(op: OpCode) -> Option<BinaryOp> {
+        Some(match op {
+            OpCode::OpAdd => BinaryOp::Add,
+            OpCode::OpSubtract => BinaryOp::Subtract,
+            OpCode::OpMultiply => BinaryOp::Multiply,
+            OpCode::OpDivide => BinaryOp::Divide,
+            OpCode::OpModulo => BinaryOp::Modulo,
+            OpCode::OpEqual => BinaryOp::Equal,
+            OpCode::OpGreater => BinaryOp::Greater,
+            OpCode::OpLess => BinaryOp::Less,
+            _ => return None,
+        })
+    }
+
+    fn from_byte(b: u8) -> BinaryOp {
+        match b {
+            0 => BinaryOp::Add,
+            1 => BinaryOp::Subtract,
+            2 => BinaryOp::Multiply,
+            3 => BinaryOp::Divide,
+            4 => BinaryOp::Modulo,
+            5 => BinaryOp::Equal,
+            6 => BinaryOp::Greater,
+            7 => BinaryOp::Less,
+            _ => panic!("invalid binary operator {b}"),
+        }
+    }
+}
+
+mod tag {
+    pub const CONSTANT: u8 = 0;
+    pub const ADD: u8 = 1;
+    pub const SUBTRACT: u8 = 2;
+    pub const MULTIPLY: u8 = 3;
+    pub const DIVIDE: u8 = 4;
+    pub const INT_DIV: u8 = 5;
+    pub const MODULO: u8 = 6;
+    pub const POW: u8 = 7;
+    pub const NEGATE: u8 = 8;
+    pub const RETURN: u8 = 9;
+    pub const BUILD_LIST: u8 = 10;
+    pub const CALL: u8 = 11;
+    pub const GET_GLOBAL: u8 = 12;
+    pub const GET_PARENT: u8 = 13;
+    pub const JUMP: u8 = 14;
+    pub const JUMP_IF_FALSE: u8 = 15;
+    pub const JUMP_IF_NIL: u8 = 16;
+    pub const POP: u8 = 17;
+    pub const DUP: u8 = 18;
+    pub const SWAP: u8 = 19;
+    pub const EQUAL: u8 = 20;
+    pub const GREATER: u8 = 21;
+    pub const LESS: u8 = 22;
+    pub const NOT: u8 = 23;
+    pub const BUILD_KVC: u8 = 24;
+    pub const GET_PROP: u8 = 25;
+    pub const CLOSURE: u8 = 26;
+    pub const GET_LOCAL: u8 = 27;
+    pub const INDEX: u8 = 28;
+    pub const MAKE_PROVIDER: u8 = 29;
+    pub const PUSH_PROVIDER: u8 = 30;
+    pub const POP_PROVIDER: u8 = 31;
+    pub const SELECT: u8 = 32;
+    pub const MAP: u8 = 33;
+    pub const FILTER: u8 = 34;
+    pub const ANY: u8 = 35;
+    pub const FIRST_WHERE: u8 = 36;
+    pub const SORT: u8 = 37;
+    pub const REDUCE: u8 = 38;
+    pub const GET_LOCAL2: u8 = 39;
+    pub const GET_LOCAL_CONST: u8 = 40;
+    pub const LOCAL_CONST_BINARY: u8 = 41;
+    pub const GET_GLOBAL_PROP: u8 = 42;
+    pub const TRY: u8 = 43;
+    pub const END_TRY: u8 = 44;
+    pub const CATCH: u8 = 45;
 }
 
 #[derive(Debug, Clone)]
 pub struct Chunk {
-    pub code: Vec<OpCode>,
+    pub code: Vec<u8>,
     pub constants: Vec<Value>,
+    /// Start offset and decoded form of the last instruction written, for fusion.
+    last: Option<(usize, OpCode)>,
+    /// Offset most recently used as a jump target; nothing may be fused into it.
+    label: Option<usize>,
 }
 
 impl Chunk {
@@ -56,15 +175,232 @@ impl Chunk {
         Chunk {
             code: Vec::new(),
             constants: Vec::new(),
+            last: None,
+            label: None,
         }
     }
 
-    pub fn write(&mut self, op: OpCode) {
-        self.code.push(op);
+    /// Appends `op`, fusing it with the previous instruction where possible.
+    ///
+    /// Fails if an operand does not fit its encoding.
+    pub fn write(&mut self, op: OpCode) -> Result<(), &'static str> {
+        if let Some((start, prev)) = self.last {
+            if self.label != Some(self.code.len()) {
+                if let Some(fused) = Self::fuse(prev, op) {
+                    self.code.truncate(start);
+                    self.encode(fused)?;
+                    self.last = Some((start, fused));
+                    return Ok(());
+                }
+            }
+        }
+        let start = self.code.len();
+        self.encode(op)?;
+        self.last = Some((start, op));
+        Ok(())
     }
 
     pub fn add_constant(&mut self, value: Value) -> usize {
         self.constants.push(value);
         self.constants.len() - 1
     }
+
+    /// Writes a jump with a placeholder offset and returns the offset's position for
+    /// `patch_jump`.
+    pub fn write_jump(&mut self, op: OpCode) -> usize {
+        debug_assert!(matches!(
+            op,
+            OpCode::OpJump(_) | OpCode::OpJumpIfFalse(_) | OpCode::OpJumpIfNil(_) | OpCode::OpTry(_) | OpCode::OpEndTry(_)
+        ));
+        // Jump operands are u32; the placeholder always fits.
+        let _ = self.write(op);
+        self.code.len() - 4
+    }
+
+    /// Points the jump whose operand is at `operand` to the current end of the chunk.
+    pub fn patch_jump(&mut self, operand: usize) -> Result<(), &'static str> {
+        let jump = u32::try_from(self.code.len() - (operand + 4)).map_err(|_| "Too much code to jump over.")?;
+        self.code[operand..operand + 4].copy_from_slice(&jump.to_le_bytes());
+        self.label = Some(self.code.len());
+        Ok(())
+    }
+
+    /// Decodes the instruction at `ip`, returning it with the offset of the next one.
+    #[inline]
+    pub fn read(&self, ip: usize) -> (OpCode, usize) {
+        let code = &self.code;
+        let u16_at = |at: usize| u16::from_le_bytes([code[at], code[at + 1]]) as usize;
+        let u32_at = |at: usize| u32::from_le_bytes([code[at], code[at + 1], code[at + 2], code[at + 3]]) as usize;
+        let at = ip + 1;
+        match code[ip] {
+            tag::CONSTANT => (OpCode::OpConstant(u32_at(at)), at + 4),
+            tag::ADD => (OpCode::OpAdd, at),
+            tag::SUBTRACT => (OpCode::OpSubtract, at),
+            tag::MULTIPLY => (OpCode::OpMultiply, at),
+            tag::DIVIDE => (OpCode::OpDivide, at),
+            tag::INT_DIV => (OpCode::OpIntDiv, at),
+            tag::MODULO => (OpCode::OpModulo, at),
+            tag::POW => (OpCode::OpPow, at),
+            tag::NEGATE => (OpCode::OpNegate, at),
+            tag::RETURN => (OpCode::OpReturn, at),
+            tag::BUILD_LIST => (OpCode::OpBuildList(u32_at(at)), at + 4),
+            tag::CALL => (OpCode::OpCall(u16_at(at)), at + 2),
+            tag::GET_GLOBAL => (OpCode::OpGetGlobal(u32_at(at)), at + 4),
+            tag::GET_PARENT => (OpCode::OpGetParent(u32_at(at)), at + 4),
+            tag::JUMP => (OpCode::OpJump(u32_at(at)), at + 4),
+            tag::JUMP_IF_FALSE => (OpCode::OpJumpIfFalse(u32_at(at)), at + 4),
+            tag::JUMP_IF_NIL => (OpCode::OpJumpIfNil(u32_at(at)), at + 4),
+            tag::POP => (OpCode::OpPop, at),
+            tag::DUP => (OpCode::OpDup, at),
+            tag::SWAP => (OpCode::OpSwap, at),
+            tag::EQUAL => (OpCode::OpEqual, at),
+            tag::GREATER => (OpCode::OpGreater, at),
+            tag::LESS => (OpCode::OpLess, at),
+            tag::NOT => (OpCode::OpNot, at),
+            tag::BUILD_KVC => (OpCode::OpBuildKvc(u32_at(at)), at + 4),
+            tag::GET_PROP => (OpCode::OpGetProp(u32_at(at)), at + 4),
+            tag::CLOSURE => (OpCode::OpClosure(u32_at(at)), at + 4),
+            tag::GET_LOCAL => (OpCode::OpGetLocal(u16_at(at)), at + 2),
+            tag::INDEX => (OpCode::OpIndex, at),
+            tag::MAKE_PROVIDER => (OpCode::OpMakeProvider, at),
+            tag::PUSH_PROVIDER => (OpCode::OpPushProvider, at),
+            tag::POP_PROVIDER => (OpCode::OpPopProvider, at),
+            tag::SELECT => (OpCode::OpSelect(u32_at(at)), at + 4),
+            tag::MAP => (OpCode::OpMap, at),
+            tag::FILTER => (OpCode::OpFilter, at),
+            tag::ANY => (OpCode::OpAny, at),
+            tag::FIRST_WHERE => (OpCode::OpFirstWhere, at),
+            tag::SORT => (OpCode::OpSort, at),
+            tag::REDUCE => (OpCode::OpReduce(code[at] != 0), at + 1),
+            tag::GET_LOCAL2 => (OpCode::OpGetLocal2(u16_at(at), u16_at(at + 2)), at + 4),
+            tag::GET_LOCAL_CONST => (OpCode::OpGetLocalConst(u16_at(at), u32_at(at + 2)), at + 6),
+            tag::LOCAL_CONST_BINARY => (
+                OpCode::OpLocalConstBinary(u16_at(at), u32_at(at + 2), BinaryOp::from_byte(code[at + 6])),
+                at + 7,
+            ),
+            tag::GET_GLOBAL_PROP => (OpCode::OpGetGlobalProp(u32_at(at), u32_at(at + 4)), at + 8),
+            tag::TRY => (OpCode::OpTry(u32_at(at)), at + 4),
+            tag::END_TRY => (OpCode::OpEndTry(u32_at(at)), at + 4),
+            tag::CATCH => (OpCode::OpCatch, at),
+            t => panic!("invalid opcode {t} at {ip}"),
+        }
+    }
+
+    /// Iterates over the decoded instructions of the chunk.
+    pub fn ops(&self) -> impl Iterator<Item = OpCode> + '_ {
+        let mut ip = 0;
+        std::iter::from_fn(move || {
+            if ip >= self.code.len() {
+                return None;
+            }
+            let (op, next) = self.read(ip);
+            ip = next;
+            Some(op)
+        })
+    }
+
+    fn fuse(prev: OpCode, op: OpCode) -> Option<OpCode> {
+        Some(match (prev, op) {
+            (OpCode::OpGetLocal(a), OpCode::OpGetLocal(b)) => OpCode::OpGetLocal2(a, b),
+            (OpCode::OpGetLocal(slot), OpCode::OpConstant(idx)) => OpCode::OpGetLocalConst(slot, idx),
+            (OpCode::OpGetLocalConst(slot, idx), op) => OpCode::OpLocalConstBinary(slot, idx, BinaryOp::from_op(op)?),
+            (OpCode::OpGetGlobal(name), OpCode::OpGetProp(prop)) => OpCode::OpGetGlobalProp(name, prop),
+            _ => return None,
+        })
+    }
+
+    fn encode(&mut self, op: OpCode) -> Result<(), &'static str> {
+        match op {
+            OpCode::OpConstant(i) => self.op_u32(tag::CONSTANT, i, "Too many constants in one chunk.")?,
+            OpCode::OpAdd => self.code.push(tag::ADD),
+            OpCode::OpSubtract => self.code.push(tag::SUBTRACT),
+            OpCode::OpMultiply => self.code.push(tag::MULTIPLY),
+            OpCode::OpDivide => self.code.push(tag::DIVIDE),
+            OpCode::OpIntDiv => self.code.push(tag::INT_DIV),
+            OpCode::OpModulo => self.code.push(tag::MODULO),
+            OpCode::OpPow => self.code.push(tag::POW),
+            OpCode::OpNegate => self.code.push(tag::NEGATE),
+            OpCode::OpReturn => self.code.push(tag::RETURN),
+            OpCode::OpBuildList(n) => self.op_u32(tag::BUILD_LIST, n, "Too many list items.")?,
+            OpCode::OpCall(n) => {
+                self.code.push(tag::CALL);
+                self.push_u16(n, "Too many arguments in call.")?;
+            }
+            OpCode::OpGetGlobal(i) => self.op_u32(tag::GET_GLOBAL, i, "Too many constants in one chunk.")?,
+            OpCode::OpGetParent(i) => self.op_u32(tag::GET_PARENT, i, "Too many constants in one chunk.")?,
+            OpCode::OpJump(o) => self.op_u32(tag::JUMP, o, "Too much code to jump over.")?,
+            OpCode::OpJumpIfFalse(o) => self.op_u32(tag::JUMP_IF_FALSE, o, "Too much code to jump over.")?,
+            OpCode::OpJumpIfNil(o) => self.op_u32(tag::JUMP_IF_NIL, o, "Too much code to jump over.")?,
+            OpCode::OpPop => self.code.push(tag::POP),
+            OpCode::OpDup => self.code.push(tag::DUP),
+            OpCode::OpSwap => self.code.push(tag::SWAP),
+            OpCode::OpEqual => self.code.push(tag::EQUAL),
+            OpCode::OpGreater => self.code.push(tag::GREATER),
+            OpCode::OpLess => self.code.push(tag::LESS),
+            OpCode::OpNot => self.code.push(tag::NOT),
+            OpCode::OpBuildKvc(n) => self.op_u32(tag::BUILD_KVC, n, "Too many KVC entries.")?,
+            OpCode::OpGetProp(i) => self.op_u32(tag::GET_PROP, i, "Too many constants in one chunk.")?,
+            OpCode::OpClosure(i) => self.op_u32(tag::CLOSURE, i, "Too many constants in one chunk.")?,
+            OpCode::OpGetLocal(slot) => {
+                self.code.push(tag::GET_LOCAL);
+                self.push_u16(slot, "Too many local variables.")?;
+            }
+            OpCode::OpIndex => self.code.push(tag::INDEX),
+            OpCode::OpMakeProvider => self.code.push(tag::MAKE_PROVIDER),
+            OpCode::OpPushProvider => self.code.push(tag::PUSH_PROVIDER),
+            OpCode::OpPopProvider => self.code.push(tag::POP_PROVIDER),
+            OpCode::OpSelect(i) => self.op_u32(tag::SELECT, i, "Too many constants in one chunk.")?,
+            OpCode::OpMap => self.code.push(tag::MAP),
+            OpCode::OpFilter => self.code.push(tag::FILTER),
+            OpCode::OpAny => self.code.push(tag::ANY),
+            OpCode::OpFirstWhere => self.code.push(tag::FIRST_WHERE),
+            OpCode::OpSort => self.code.push(tag::SORT),
+            OpCode::OpReduce(has_seed) => {
+                self.code.push(tag::REDUCE);
+                self.code.push(has_seed as u8);
+            }
+            OpCode::OpGetLocal2(a, b) => {
+                self.code.push(tag::GET_LOCAL2);
+                self.push_u16(a, "Too many local variables.")?;
+                self.push_u16(b, "Too many local variables.")?;
+            }
+            OpCode::OpGetLocalConst(slot, idx) => {
+                self.code.push(tag::GET_LOCAL_CONST);
+                self.push_u16(slot, "Too many local variables.")?;
+                self.push_u32(idx, "Too many constants in one chunk.")?;
+            }
+            OpCode::OpLocalConstBinary(slot, idx, op) => {
+                self.code.push(tag::LOCAL_CONST_BINARY);
+                self.push_u16(slot, "Too many local variables.")?;
+                self.push_u32(idx, "Too many constants in one chunk.")?;
+                self.code.push(op as u8);
+            }
+            OpCode::OpGetGlobalProp(name, prop) => {
+                self.code.push(tag::GET_GLOBAL_PROP);
+                self.push_u32(name, "Too many constants in one chunk.")?;
+                self.push_u32(prop, "Too many constants in one chunk.")?;
+            }
+            OpCode::OpTry(o) => self.op_u32(tag::TRY, o, "Too much code to jump over.")?,
+            OpCode::OpEndTry(o) => self.op_u32(tag::END_TRY, o, "Too much code to jump over.")?,
+            OpCode::OpCatch => self.code.push(tag::CATCH),
+        }
+        Ok(())
+    }
+
+    fn op_u32(&mut self, tag: u8, v: usize, overflow: &'static str) -> Result<(), &'static str> {
+        self.code.push(tag);
+        self.push_u32(v, overflow)
+    }
+
+    fn push_u16(&mut self, v: usize, overflow: &'static str) -> Result<(), &'static str> {
+        let v = u16::try_from(v).map_err(|_| overflow)?;
+        self.code.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
+
+    fn push_u32(&mut self, v: usize, overflow: &'static str) -> Result<(), &'static str> {
+        let v = u32::try_from(v).map_err(|_| overflow)?;
+        self.code.extend_from_slice(&v.to_le_bytes());
+        Ok(())
+    }
 }
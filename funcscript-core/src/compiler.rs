@@ -5,9 +5,9 @@
 
 use crate::chunk::{Chunk, OpCode};
 use crate::scanner::{Scanner, Token, TokenType};
-use crate::value::{FsError, Value};
+use crate::value::{ErrorKind, FsError, Value};
 use crate::obj::FsFunction;
-use std::rc::Rc;
+use std::sync::Arc;
 
 pub struct Parser<'a> {
     pub current: Token<'a>,
@@ -82,12 +82,7 @@ impl<'a> Compiler<'a> {
         if !self.parser.had_error {
             return Ok(function);
         }
-        Err(self.last_error.clone().unwrap_or(FsError {
-            code: 1000,
-            message: "Compile error".to_string(),
-            line: -1,
-            column: -1,
-        }))
+        Err(self.last_error.clone().unwrap_or(FsError::new(ErrorKind::Syntax, "Compile error")))
     }
 
     fn is_naked_kvc_start(&self) -> bool {
@@ -200,14 +195,13 @@ impl<'a> Compiler<'a> {
     }
 
     fn error_at(&mut self, token: Token, message: &str) {
+        self.error_at_with(token, ErrorKind::Syntax, message);
+    }
+
+    fn error_at_with(&mut self, token: Token, kind: ErrorKind, message: &str) {
         if self.parser.had_error { return; } 
         self.parser.had_error = true;
-        self.last_error = Some(FsError {
-            code: 1000,
-            message: message.to_string(),
-            line: token.line as i32,
-            column: token.column as i32,
-        });
+        self.last_error = Some(FsError::new(kind, message).at(token.line as i32, token.column as i32));
     }
 
     fn expression(&mut self) {
@@ -218,8 +212,8 @@ impl<'a> Compiler<'a> {
         self.logical_and();
         while self.check(TokenType::Identifier) && self.parser.current.start == "or" {
             self.advance(); 
-            let name_obj = crate::obj::Obj::String("Or".to_string());
-            let name_val = Value::Obj(std::rc::Rc::new(name_obj));
+            let name_obj = crate::obj::Obj::String("Or".into());
+            let name_val = Value::Obj(std::sync::Arc::new(name_obj));
             let idx = self.current_chunk().add_constant(name_val);
             self.emit_byte(OpCode::OpGetGlobal(idx));
             self.emit_byte(OpCode::OpSwap);
@@ -232,8 +226,8 @@ impl<'a> Compiler<'a> {
         self.in_expression();
         while self.check(TokenType::Identifier) && self.parser.current.start == "and" {
             self.advance(); 
-            let name_obj = crate::obj::Obj::String("And".to_string());
-            let name_val = Value::Obj(std::rc::Rc::new(name_obj));
+            let name_obj = crate::obj::Obj::String("And".into());
+            let name_val = Value::Obj(std::sync::Arc::new(name_obj));
             let idx = self.current_chunk().add_constant(name_val);
             self.emit_byte(OpCode::OpGetGlobal(idx)); 
             self.emit_byte(OpCode::OpSwap);
@@ -246,8 +240,8 @@ impl<'a> Compiler<'a> {
         self.map_expression();
         while self.check(TokenType::Identifier) && self.parser.current.start == "in" {
             self.advance(); 
-            let name_obj = crate::obj::Obj::String("In".to_string());
-            let name_val = Value::Obj(std::rc::Rc::new(name_obj));
+            let name_obj = crate::obj::Obj::String("In".into());
+            let name_val = Value::Obj(std::sync::Arc::new(name_obj));
             let idx = self.current_chunk().add_constant(name_val);
             self.emit_byte(OpCode::OpGetGlobal(idx)); 
             self.emit_byte(OpCode::OpSwap); 
@@ -378,8 +372,8 @@ impl<'a> Compiler<'a> {
             }
             if self.check(TokenType::Identifier) && self.parser.current.start.eq_ignore_ascii_case("join") {
                 self.advance();
-                let name_obj = crate::obj::Obj::String("join".to_string());
-                let name_val = Value::Obj(std::rc::Rc::new(name_obj));
+                let name_obj = crate::obj::Obj::String("join".into());
+                let name_val = Value::Obj(std::sync::Arc::new(name_obj));
                 let idx = self.current_chunk().add_constant(name_val);
                 self.emit_byte(OpCode::OpGetGlobal(idx));
                 self.emit_byte(OpCode::OpSwap);
@@ -416,6 +410,24 @@ impl<'a> Compiler<'a> {
         }
     }
 
+    /// Compiles the arguments after a consumed `(` and the call itself.
+    fn call_arguments(&mut self) {
+        let mut arg_count = 0;
+        if !self.check(TokenType::RightParen) {
+            loop {
+                self.expression();
+                arg_count += 1;
+                if !self.match_token(TokenType::Comma) { break; }
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after arguments.");
+        self.emit_byte(OpCode::OpCall(arg_count));
+    }
+
+    fn is_local(&self, name: &str) -> bool {
+        self.compilers.last().unwrap().locals.iter().any(|local| local.name == name)
+    }
+
     fn unary(&mut self) {
          if self.match_token(TokenType::Bang) {
              self.unary();
@@ -436,28 +448,19 @@ impl<'a> Compiler<'a> {
         self.primary();
         loop {
             if self.match_token(TokenType::LeftParen) {
-                let mut arg_count = 0;
-                if !self.check(TokenType::RightParen) {
-                    loop {
-                        self.expression();
-                        arg_count += 1;
-                        if !self.match_token(TokenType::Comma) { break; }
-                    }
-                }
-                self.consume(TokenType::RightParen, "Expect ')' after arguments.");
-                self.emit_byte(OpCode::OpCall(arg_count));
+                self.call_arguments();
             } else if self.match_token(TokenType::Dot) {
                 self.consume(TokenType::Identifier, "Expect property name after '.'.");
                 let name = self.parser.previous.start;
-                let obj = crate::obj::Obj::String(name.to_string());
-                let val = Value::Obj(std::rc::Rc::new(obj));
+                let obj = crate::obj::Obj::String(name.into());
+                let val = Value::Obj(std::sync::Arc::new(obj));
                 let idx = self.current_chunk().add_constant(val);
                 self.emit_byte(OpCode::OpGetProp(idx));
             } else if self.match_token(TokenType::SafeDot) {
                 self.consume(TokenType::Identifier, "Expect property name after '?.'.");
                 let name = self.parser.previous.start;
-                let obj = crate::obj::Obj::String(name.to_string());
-                let val = Value::Obj(std::rc::Rc::new(obj));
+                let obj = crate::obj::Obj::String(name.into());
+                let val = Value::Obj(std::sync::Arc::new(obj));
                 let idx = self.current_chunk().add_constant(val);
                 self.emit_byte(OpCode::OpGetProp(idx));
             } else if self.match_token(TokenType::LeftBracket) {
@@ -509,7 +512,7 @@ impl<'a> Compiler<'a> {
         
         let function = self.end_compiler();
         
-        let val = Value::Obj(Rc::new(crate::obj::Obj::Function(Rc::new(function))));
+        let val = Value::Obj(Arc::new(crate::obj::Obj::Function(Arc::new(function))));
         let idx = self.current_chunk().add_constant(val);
         self.emit_byte(OpCode::OpClosure(idx));
     }
@@ -534,7 +537,7 @@ impl<'a> Compiler<'a> {
                     self.emit_constant(Value::Int(n));
                 } else {
                     match num_bigint::BigInt::parse_bytes(lexeme.as_bytes(), 10) {
-                        Some(bi) => self.emit_constant(Value::BigInt(bi)),
+                        Some(bi) => self.emit_constant(Value::bigint(bi)),
                         None => self.error_at(self.parser.previous, "Invalid integer format."),
                     }
                 }
@@ -550,8 +553,8 @@ impl<'a> Compiler<'a> {
                 let inner = &s[1..s.len()-1];
                 if q == '\'' || q == '"' { inner.to_string() } else { inner.to_string() }
             };
-            let obj = crate::obj::Obj::String(content);
-            let value = Value::Obj(std::rc::Rc::new(obj));
+            let obj = crate::obj::Obj::String(content.into());
+            let value = Value::Obj(std::sync::Arc::new(obj));
             self.emit_constant(value);
         } else if self.match_token(TokenType::TemplateStart) {
             self.template_string_expression();
@@ -607,6 +610,15 @@ impl<'a> Compiler<'a> {
                      }
                  }
 
+                 if name.eq_ignore_ascii_case("try") && !self.is_local(name) {
+                     let mut look = self.scanner.clone();
+                     if look.scan_token().kind == TokenType::LeftParen {
+                         self.advance();
+                         self.try_or_call(name);
+                         return;
+                     }
+                 }
+
                  if name.eq_ignore_ascii_case("map") {
                      let mut look = self.scanner.clone();
                      if look.scan_token().kind == TokenType::LeftParen {
@@ -718,7 +730,7 @@ impl<'a> Compiler<'a> {
                      self.emit_byte(OpCode::OpGetLocal(idx));
                  } else {
                      
-                     let val = Value::Obj(std::rc::Rc::new(crate::obj::Obj::String(name_str)));
+                     let val = Value::Obj(std::sync::Arc::new(crate::obj::Obj::String(name_str.into())));
                      let idx = self.current_chunk().add_constant(val); 
                      self.emit_byte(OpCode::OpGetGlobal(idx));
                  }
@@ -754,8 +766,8 @@ impl<'a> Compiler<'a> {
         let mut part_count = 0usize;
 
        
-        let name_obj = crate::obj::Obj::String("TemplateMerge".to_string());
-        let name_val = Value::Obj(std::rc::Rc::new(name_obj));
+        let name_obj = crate::obj::Obj::String("TemplateMerge".into());
+        let name_val = Value::Obj(std::sync::Arc::new(name_obj));
         let idx = self.current_chunk().add_constant(name_val);
         self.emit_byte(OpCode::OpGetGlobal(idx));
 
@@ -766,8 +778,8 @@ impl<'a> Compiler<'a> {
             if self.match_token(TokenType::TemplateText) {
                 let raw = self.parser.previous.start;
                 let s = Self::unescape_template_text(raw);
-                let obj = crate::obj::Obj::String(s);
-                let value = Value::Obj(std::rc::Rc::new(obj));
+                let obj = crate::obj::Obj::String(s.into());
+                let value = Value::Obj(std::sync::Arc::new(obj));
                 self.emit_constant(value);
                 part_count += 1;
                 continue;
@@ -880,8 +892,8 @@ impl<'a> Compiler<'a> {
 
             
             if self.check(TokenType::LeftParen) && self.check_is_lambda() {
-                let key_obj = crate::obj::Obj::String(key.clone());
-                let key_val = Value::Obj(std::rc::Rc::new(key_obj));
+                let key_obj = crate::obj::Obj::String(key.clone().into());
+                let key_val = Value::Obj(std::sync::Arc::new(key_obj));
                 self.emit_constant(key_val);
                 self.lambda_expression();
                 count += 1;
@@ -890,16 +902,16 @@ impl<'a> Compiler<'a> {
             }
 
             if self.check(TokenType::LeftBrace) {
-                let key_obj = crate::obj::Obj::String(key.clone());
-                let key_val = Value::Obj(std::rc::Rc::new(key_obj));
+                let key_obj = crate::obj::Obj::String(key.clone().into());
+                let key_val = Value::Obj(std::sync::Arc::new(key_obj));
                 self.emit_constant(key_val);
 
                 self.advance(); 
                 let selector_val = self.compile_selector_function_value();
                 let name_for_parent = key.clone();
                 let thunk_idx = self.compile_thunk_const(format!("kvc_sel_{}", key), 0, |c| {
-                    let name_obj = crate::obj::Obj::String(name_for_parent.clone());
-                    let name_val = Value::Obj(std::rc::Rc::new(name_obj));
+                    let name_obj = crate::obj::Obj::String(name_for_parent.clone().into());
+                    let name_val = Value::Obj(std::sync::Arc::new(name_obj));
                     let name_idx = c.current_chunk().add_constant(name_val);
                     c.emit_byte(OpCode::OpGetParent(name_idx));
                     let selector_idx = c.current_chunk().add_constant(selector_val.clone());
@@ -913,8 +925,8 @@ impl<'a> Compiler<'a> {
             }
             if self.match_token(TokenType::Colon) {
                 
-                let key_obj = crate::obj::Obj::String(key.clone());
-                let key_val = Value::Obj(std::rc::Rc::new(key_obj));
+                let key_obj = crate::obj::Obj::String(key.clone().into());
+                let key_val = Value::Obj(std::sync::Arc::new(key_obj));
                 self.emit_constant(key_val);
 
                 let is_simple_self_ref = self.check(TokenType::Identifier)
@@ -934,8 +946,8 @@ impl<'a> Compiler<'a> {
                 count += 1;
                 self.consume_kvc_separator();
             } else {
-                let key_obj = crate::obj::Obj::String(key.clone());
-                let key_val = Value::Obj(std::rc::Rc::new(key_obj));
+                let key_obj = crate::obj::Obj::String(key.clone().into());
+                let key_val = Value::Obj(std::sync::Arc::new(key_obj));
                 self.emit_constant(key_val);
 
                 let thunk_idx = self.compile_parent_get_thunk_const(format!("kvc_proj_{}", key), &key);
@@ -1029,8 +1041,8 @@ impl<'a> Compiler<'a> {
 
     fn compile_parent_get_thunk_const(&mut self, name: String, key: &str) -> usize {
         self.compile_thunk_const(name, 0, |c| {
-            let name_obj = crate::obj::Obj::String(key.to_string());
-            let name_val = Value::Obj(std::rc::Rc::new(name_obj));
+            let name_obj = crate::obj::Obj::String(key.into());
+            let name_val = Value::Obj(std::sync::Arc::new(name_obj));
             let name_idx = c.current_chunk().add_constant(name_val);
             c.emit_byte(OpCode::OpGetParent(name_idx));
         })
@@ -1049,7 +1061,7 @@ impl<'a> Compiler<'a> {
         build(self);
 
         let function = self.end_compiler();
-        let val = Value::Obj(Rc::new(crate::obj::Obj::Function(Rc::new(function))));
+        let val = Value::Obj(Arc::new(crate::obj::Obj::Function(Arc::new(function))));
         self.current_chunk().add_constant(val)
     }
 
@@ -1070,7 +1082,7 @@ impl<'a> Compiler<'a> {
         self.emit_byte(OpCode::OpPopProvider);
 
         let function = self.end_compiler();
-        Value::Obj(Rc::new(crate::obj::Obj::Function(Rc::new(function))))
+        Value::Obj(Arc::new(crate::obj::Obj::Function(Arc::new(function))))
     }
 
     fn match_token(&mut self, kind: TokenType) -> bool {
@@ -1084,36 +1096,67 @@ impl<'a> Compiler<'a> {
     }
 
     fn emit_byte(&mut self, op: OpCode) {
-        self.current_chunk().write(op);
+        if let Err(message) = self.current_chunk().write(op) {
+            self.error_at_with(self.parser.previous, ErrorKind::LimitExceeded, message);
+        }
     }
     
     fn emit_constant(&mut self, value: Value) {
         let idx = self.current_chunk().add_constant(value);
-        self.current_chunk().write(OpCode::OpConstant(idx));
+        self.emit_byte(OpCode::OpConstant(idx));
     }
 
     fn emit_jump(&mut self, op: fn(usize) -> OpCode) -> usize {
-        self.emit_byte(op(0xFFFF));
-        self.current_chunk().code.len() - 1
+        self.current_chunk().write_jump(op(0))
     }
 
     fn patch_jump(&mut self, offset: usize) {
-        let jump = self.current_chunk().code.len() - 1 - offset;
+        if let Err(message) = self.current_chunk().patch_jump(offset) {
+            self.error_at_with(self.parser.previous, ErrorKind::LimitExceeded, message);
+        }
+    }
 
-        match self.current_chunk().code[offset] {
-            OpCode::OpJumpIfFalse(_) => {
-                self.current_chunk().code[offset] = OpCode::OpJumpIfFalse(jump);
-            }
-            OpCode::OpJumpIfNil(_) => {
-                self.current_chunk().code[offset] = OpCode::OpJumpIfNil(jump);
-            }
-            OpCode::OpJump(_) => {
-                self.current_chunk().code[offset] = OpCode::OpJump(jump);
-            }
-            _ => { 
-                panic!("Attempted to patch a non-jump instruction."); 
-            }
+    /// `try(...)` calls a KVC member or outer binding named `try` when one is in scope at run
+    /// time, and is the `try` special form otherwise. The arguments are compiled once for
+    /// each case.
+    fn try_or_call(&mut self, name: &str) {
+        let val = Value::Obj(Arc::new(crate::obj::Obj::String(name.into())));
+        let idx = self.current_chunk().add_constant(val);
+        self.emit_byte(OpCode::OpGetGlobal(idx));
+        let to_special = self.emit_jump(OpCode::OpJumpIfNil);
+
+        let (scanner, current, previous) = (self.scanner.clone(), self.parser.current, self.parser.previous);
+        self.consume(TokenType::LeftParen, "Expect '(' after 'try'.");
+        self.call_arguments();
+        let to_end = self.emit_jump(OpCode::OpJump);
+
+        self.patch_jump(to_special);
+        self.emit_byte(OpCode::OpPop);
+        (self.scanner, self.parser.current, self.parser.previous) = (scanner, current, previous);
+        self.try_expression();
+        self.patch_jump(to_end);
+    }
+
+    /// `try(expr, handler?)`: `expr` runs under a handler; if it fails with a runtime error or
+    /// evaluates to an error value, the result is `handler(error)` (or `handler` itself when it
+    /// is not a function, `nil` when omitted).
+    fn try_expression(&mut self) {
+        self.consume(TokenType::LeftParen, "Expect '(' after 'try'.");
+
+        let to_catch = self.emit_jump(OpCode::OpTry);
+        self.expression();
+        let to_end = self.emit_jump(OpCode::OpEndTry);
+
+        self.patch_jump(to_catch);
+        if self.match_token(TokenType::Comma) {
+            self.expression();
+        } else {
+            self.emit_constant(Value::Nil);
         }
+        self.emit_byte(OpCode::OpCatch);
+
+        self.patch_jump(to_end);
+        self.consume(TokenType::RightParen, "Expect ')' after try.");
     }
 
     fn if_expression(&mut self) {
@@ -0,0 +1,563 @@
+//! Calendar math on date values: .NET ticks (100 ns since 0001-01-01, proleptic Gregorian).
+//!
+//! Shared by the date built-ins, member access on dates (`d.year`), `format` and
+//! `Date(text, pattern)` parsing, which accepts the same culture-invariant patterns `format`
+//! writes. A date may carry the UTC offset its clock time is local to, like
+//! `System.DateTimeOffset`. Durations are signed tick counts, like `System.TimeSpan`.
+
+use crate::value::Value;
+use time::{Date, Month};
+
+pub const TICKS_PER_SECOND: i64 = 10_000_000;
+pub const TICKS_PER_MINUTE: i64 = 60 * TICKS_PER_SECOND;
+pub const TICKS_PER_DAY: i64 = 1440 * TICKS_PER_MINUTE;
+/// `DateTime.MaxValue.Ticks` (9999-12-31 23:59:59.9999999).
+pub const MAX_TICKS: i64 = 3_155_378_975_999_999_999;
+/// Julian day number of 0001-01-01, tick zero.
+const JULIAN_DAY_OF_TICK_ZERO: i64 = 1_721_426;
+
+pub(crate) const DAY_NAMES: [&str; 7] = ["Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday", "Sunday"];
+pub(crate) const MONTH_NAMES: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June", "July", "August", "September", "October", "November", "December",
+];
+
+/// Invariant-culture expansion of a one-letter standard date/time pattern.
+pub(crate) fn standard_date_pattern(spec: char) -> Option<&'static str> {
+    Some(match spec {
+        'd' => "MM/dd/yyyy",
+        'D' => "dddd, dd MMMM yyyy",
+        'f' => "dddd, dd MMMM yyyy HH:mm",
+        'F' | 'U' => "dddd, dd MMMM yyyy HH:mm:ss",
+        'g' => "MM/dd/yyyy HH:mm",
+        'G' => "MM/dd/yyyy HH:mm:ss",
+        'm' | 'M' => "MMMM dd",
+        'o' | 'O' => "yyyy'-'MM'-'dd'T'HH':'mm':'ss'.'fffffffK",
+        'r' | 'R' => "ddd, dd MMM yyyy HH':'mm':'ss 'GMT'",
+        's' => "yyyy'-'MM'-'dd'T'HH':'mm':'ss",
+        't' => "HH:mm",
+        'T' => "HH:mm:ss",
+        'u' => "yyyy'-'MM'-'dd HH':'mm':'ss'Z'",
+        'y' | 'Y' => "yyyy MMMM",
+        _ => return None,
+    })
+}
+
+/// Calendar fields of a tick count.
+pub(crate) struct Civil {
+    pub year: i32,
+    pub month: usize,
+    pub day: u8,
+    /// Days since Monday.
+    pub weekday: usize,
+    pub day_of_year: u16,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    /// Ticks within the second, seven digits.
+    pub fraction: i64,
+}
+
+fn date_of(ticks: i64) -> Option<Date> {
+    if !(0..=MAX_TICKS).contains(&ticks) {
+        return None;
+    }
+    Date::from_julian_day((JULIAN_DAY_OF_TICK_ZERO + ticks / TICKS_PER_DAY) as i32).ok()
+}
+
+/// Ticks at midnight of `date`.
+fn ticks_of(date: Date) -> i64 {
+    (date.to_julian_day() as i64 - JULIAN_DAY_OF_TICK_ZERO) * TICKS_PER_DAY
+}
+
+pub(crate) fn civil(ticks: i64) -> Option<Civil> {
+    let date = date_of(ticks)?;
+    let tod = ticks % TICKS_PER_DAY;
+    let secs = tod / TICKS_PER_SECOND;
+    Some(Civil {
+        year: date.year(),
+        month: date.month() as usize,
+        day: date.day(),
+        weekday: date.weekday().number_days_from_monday() as usize,
+        day_of_year: date.ordinal(),
+        hour: (secs / 3600) as u8,
+        minute: (secs / 60 % 60) as u8,
+        second: (secs % 60) as u8,
+        fraction: tod % TICKS_PER_SECOND,
+    })
+}
+
+/// Ticks of a calendar date plus `time_of_day` ticks, if the date exists and is in range.
+pub(crate) fn ticks_from(year: i32, month: u8, day: u8, time_of_day: i64) -> Option<i64> {
+    if !(1..=9999).contains(&year) {
+        return None;
+    }
+    let date = Date::from_calendar_date(year, Month::try_from(month).ok()?, day).ok()?;
+    Some(ticks_of(date) + time_of_day)
+}
+
+/// `ticks` moved by whole months, clamping the day to the end of a shorter month
+/// (Jan 31 + 1 month = Feb 28/29).
+pub(crate) fn add_months(ticks: i64, months: i64) -> Option<i64> {
+    let date = date_of(ticks)?;
+    let index = (date.year() as i64) * 12 + (date.month() as i64 - 1) + months;
+    let year = i32::try_from(index.div_euclid(12)).ok()?;
+    let month = Month::try_from((index.rem_euclid(12) + 1) as u8).ok()?;
+    if !(1..=9999).contains(&year) {
+        return None;
+    }
+    let day = date.day().min(time::util::days_in_month(month, year));
+    ticks_from(year, month as u8, day, ticks % TICKS_PER_DAY)
+}
+
+/// Adds `delta` ticks, staying within 0001-01-01..9999-12-31.
+pub(crate) fn add_ticks(ticks: i64, delta: i64) -> Option<i64> {
+    ticks.checked_add(delta).filter(|t| (0..=MAX_TICKS).contains(t))
+}
+
+/// Largest UTC offset, in minutes, a date can carry (±14:00, as `DateTimeOffset`).
+pub const MAX_OFFSET_MINUTES: i16 = 14 * 60;
+
+/// The instant a date denotes, in UTC ticks; dates without an offset already read as UTC.
+pub(crate) fn utc_ticks(ticks: i64, offset_minutes: Option<i16>) -> i64 {
+    ticks - offset_minutes.unwrap_or(0) as i64 * TICKS_PER_MINUTE
+}
+
+/// Clock ticks of the same instant seen at `offset_minutes`.
+pub(crate) fn to_offset(ticks: i64, from: Option<i16>, offset_minutes: i16) -> Option<i64> {
+    add_ticks(utc_ticks(ticks, from), offset_minutes as i64 * TICKS_PER_MINUTE)
+}
+
+/// `+hh:mm` / `-hh:mm`.
+pub(crate) fn offset_text(offset_minutes: i16) -> String {
+    let sign = if offset_minutes < 0 { '-' } else { '+' };
+    let m = offset_minutes.unsigned_abs();
+    format!("{}{:02}:{:02}", sign, m / 60, m % 60)
+}
+
+/// Parses a UTC offset: `Z`, `UTC`, `+3`, `+03`, `-0530` or `+05:30`, at most ±14:00.
+pub(crate) fn parse_offset(text: &str) -> Option<i16> {
+    let text = text.trim();
+    if text.eq_ignore_ascii_case("z") || text.eq_ignore_ascii_case("utc") {
+        return Some(0);
+    }
+    let sign = match text.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let digits = &text[1..];
+    let (h, m) = match digits.split_once(':') {
+        Some((h, m)) if m.len() == 2 => (h, m),
+        Some(_) => return None,
+        None if digits.len() == 4 => digits.split_at(2),
+        None => (digits, "0"),
+    };
+    if h.is_empty() || h.len() > 2 || !h.bytes().chain(m.bytes()).all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let (h, m): (i16, i16) = (h.parse().ok()?, m.parse().ok()?);
+    let total = h * 60 + m;
+    (m < 60 && total <= MAX_OFFSET_MINUTES).then_some(sign * total)
+}
+
+/// Calendar periods for `startOf`/`endOf`. Weeks start on Monday (ISO 8601).
+#[derive(Clone, Copy)]
+pub(crate) enum Period {
+    Day,
+    Week,
+    Month,
+    Year,
+}
+
+impl Period {
+    pub(crate) fn parse(name: &str) -> Option<Period> {
+        Some(match name.to_ascii_lowercase().as_str() {
+            "day" => Period::Day,
+            "week" => Period::Week,
+            "month" => Period::Month,
+            "year" => Period::Year,
+            _ => return None,
+        })
+    }
+}
+
+/// First tick of the period containing `ticks`.
+pub(crate) fn start_of(ticks: i64, period: Period) -> Option<i64> {
+    let date = date_of(ticks)?;
+    let start = match period {
+        Period::Day => date,
+        Period::Week => date.checked_sub(time::Duration::days(date.weekday().number_days_from_monday() as i64))?,
+        Period::Month => date.replace_day(1).ok()?,
+        Period::Year => date.replace_ordinal(1).ok()?,
+    };
+    Some(ticks_of(start)).filter(|t| *t >= 0)
+}
+
+/// Last tick of the period containing `ticks`.
+pub(crate) fn end_of(ticks: i64, period: Period) -> Option<i64> {
+    let start = start_of(ticks, period)?;
+    let next = match period {
+        Period::Day => start.checked_add(TICKS_PER_DAY),
+        Period::Week => start.checked_add(7 * TICKS_PER_DAY),
+        Period::Month => add_months(start, 1),
+        Period::Year => add_months(start, 12),
+    };
+    // The last period of 9999 has no successor; it ends at the last representable tick.
+    Some(next.map_or(MAX_TICKS, |n| (n - 1).min(MAX_TICKS)))
+}
+
+/// Members of a date value: `year`, `month`, `day`, `hour`, `minute`, `second`, `millisecond`,
+/// `weekday` (0 = Sunday, as .NET's `DayOfWeek`), `dayOfYear` and `ticks` of its clock time,
+/// and `offset`, a duration, or null when the date carries none.
+pub(crate) fn member(ticks: i64, offset_minutes: Option<i16>, key_l: &str) -> Value {
+    if key_l == "offset" {
+        return match offset_minutes {
+            Some(m) => Value::Obj(std::sync::Arc::new(crate::obj::Obj::Duration(m as i64 * TICKS_PER_MINUTE))),
+            None => Value::Nil,
+        };
+    }
+    let Some(c) = civil(ticks) else {
+        return Value::Nil;
+    };
+    match key_l {
+        "year" => Value::Int(c.year as i64),
+        "month" => Value::Int(c.month as i64),
+        "day" => Value::Int(c.day as i64),
+        "hour" => Value::Int(c.hour as i64),
+        "minute" => Value::Int(c.minute as i64),
+        "second" => Value::Int(c.second as i64),
+        "millisecond" => Value::Int(c.fraction / 10_000),
+        "weekday" => Value::Int(((c.weekday + 1) % 7) as i64),
+        "dayofyear" => Value::Int(c.day_of_year as i64),
+        "ticks" => Value::Int(ticks),
+        _ => Value::Nil,
+    }
+}
+
+/// Parses `text` against a custom or one-letter standard pattern, the same ones `format`
+/// writes, into clock ticks and the offset parsed by `z` or `K`, if any. Date parts the
+/// pattern leaves out default to 0001-01-01.
+pub(crate) fn parse(text: &str, pattern: &str) -> Option<(i64, Option<i16>)> {
+    let mut spec_chars = pattern.chars();
+    let pattern = match (spec_chars.next(), spec_chars.next()) {
+        (Some(spec), None) => standard_date_pattern(spec)?,
+        _ => pattern,
+    };
+    let p: Vec<char> = pattern.chars().collect();
+    let mut input = Input { text: text.chars().collect(), pos: 0 };
+
+    let (mut year, mut month, mut day) = (1i32, 1u8, 1u8);
+    let (mut hour, mut minute, mut second, mut fraction) = (0i64, 0i64, 0i64, 0i64);
+    let mut twelve_hour = false;
+    let mut pm: Option<bool> = None;
+    let mut offset_minutes: Option<i16> = None;
+
+    let mut i = 0;
+    while i < p.len() {
+        let ch = p[i];
+        let run = p[i..].iter().take_while(|c| **c == ch).count();
+        match ch {
+            'd' | 'f' | 'F' | 'g' | 'h' | 'H' | 'K' | 'm' | 'M' | 's' | 't' | 'y' | 'z' => {
+                i += run;
+                match (ch, run) {
+                    ('d', 1) => day = input.number(1, 2)? as u8,
+                    ('d', 2) => day = input.number(2, 2)? as u8,
+                    // Day names are checked for shape only; the date decides the weekday.
+                    ('d', _) => {
+                        input.name(&DAY_NAMES, run == 3)?;
+                    }
+                    ('M', 1) => month = input.number(1, 2)? as u8,
+                    ('M', 2) => month = input.number(2, 2)? as u8,
+                    ('M', _) => month = input.name(&MONTH_NAMES, run == 3)? as u8 + 1,
+                    ('y', 1) => year = 2000 + input.number(1, 2)? as i32,
+                    // Two-digit years follow .NET's default window: 00-49 is 20xx, 50-99 is 19xx.
+                    ('y', 2) => {
+                        let yy = input.number(2, 2)? as i32;
+                        year = if yy < 50 { 2000 + yy } else { 1900 + yy };
+                    }
+                    ('y', n) => year = input.number(n.min(4), n.max(4))? as i32,
+                    ('h', n) => {
+                        hour = input.number(n.min(2), 2)?;
+                        twelve_hour = true;
+                    }
+                    ('H', n) => hour = input.number(n.min(2), 2)?,
+                    ('m', n) => minute = input.number(n.min(2), 2)?,
+                    ('s', n) => second = input.number(n.min(2), 2)?,
+                    ('f', n) if n <= 7 => fraction = input.number(n, n)? * 10i64.pow((7 - n) as u32),
+                    ('F', n) if n <= 7 => {
+                        let start = input.pos;
+                        let value = input.number(0, n).unwrap_or(0);
+                        fraction = value * 10i64.pow((7 - (input.pos - start)) as u32);
+                    }
+                    ('t', n) => {
+                        let upper = input.rest().to_ascii_uppercase();
+                        pm = Some(if upper.starts_with('P') { true } else if upper.starts_with('A') { false } else { return None });
+                        input.pos += if n == 1 || !upper[1..].starts_with('M') { 1 } else { 2 };
+                    }
+                    ('g', _) => {
+                        if !input.literal("A.D.") {
+                            input.literal("AD");
+                        }
+                    }
+                    ('z', n) => offset_minutes = Some(input.offset(n >= 3)?),
+                    ('K', _) => {
+                        // `Z` is UTC, which dates carry as no offset.
+                        if input.literal("Z") {
+                            offset_minutes = None;
+                        } else if matches!(input.peek(), Some('+') | Some('-')) {
+                            offset_minutes = Some(input.offset(true)?);
+                        }
+                    }
+                    _ => return None,
+                }
+            }
+            '\'' | '"' => {
+                let end = p[i + 1..].iter().position(|q| *q == ch)?;
+                let lit: String = p[i + 1..i + 1 + end].iter().collect();
+                if !input.literal(&lit) {
+                    return None;
+                }
+                i += end + 2;
+            }
+            '\\' => {
+                if input.next()? != *p.get(i + 1)? {
+                    return None;
+                }
+                i += 2;
+            }
+            '%' => i += 1,
+            _ => {
+                if input.next()? != ch {
+                    return None;
+                }
+                i += 1;
+            }
+        }
+    }
+    if input.pos != input.text.len() || minute > 59 || second > 59 {
+        return None;
+    }
+    if twelve_hour {
+        if !(1..=12).contains(&hour) {
+            return None;
+        }
+        hour %= 12;
+    }
+    if pm == Some(true) && hour < 12 {
+        hour += 12;
+    }
+    if hour > 23 {
+        return None;
+    }
+    let tod = (hour * 3600 + minute * 60 + second) * TICKS_PER_SECOND + fraction;
+    Some((ticks_from(year, month, day, tod)?, offset_minutes))
+}
+
+struct Input {
+    text: Vec<char>,
+    pos: usize,
+}
+
+impl Input {
+    fn peek(&self) -> Option<char> {
+        self.text.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        Some(c)
+    }
+
+    fn rest(&self) -> String {
+        self.text[self.pos..].iter().collect()
+    }
+
+    /// Consumes `lit` if the input continues with it.
+    fn literal(&mut self, lit: &str) -> bool {
+        let lit: Vec<char> = lit.chars().collect();
+        if self.text[self.pos..].starts_with(&lit) {
+            self.pos += lit.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Reads `min..=max` ASCII digits.
+    fn number(&mut self, min: usize, max: usize) -> Option<i64> {
+        let digits = self.text[self.pos..].iter().take(max).take_while(|c| c.is_ascii_digit()).count();
+        if digits < min || digits == 0 {
+            return None;
+        }
+        let value = self.text[self.pos..self.pos + digits].iter().fold(0i64, |acc, c| acc * 10 + (*c as i64 - '0' as i64));
+        self.pos += digits;
+        Some(value)
+    }
+
+    /// Index of the (abbreviated, when `short`) name the input continues with, case-insensitive.
+    fn name(&mut self, names: &[&str], short: bool) -> Option<usize> {
+        let rest = self.rest().to_lowercase();
+        let (index, len) = names.iter().enumerate().find_map(|(i, n)| {
+            let n = if short { &n[..3] } else { n };
+            rest.starts_with(&n.to_lowercase()).then_some((i, n.chars().count()))
+        })?;
+        self.pos += len;
+        Some(index)
+    }
+
+    /// `+hh`, `-h` or, with minutes, `+hh:mm`, in minutes east of UTC.
+    fn offset(&mut self, with_minutes: bool) -> Option<i16> {
+        let sign = match self.next()? {
+            '+' => 1,
+            '-' => -1,
+            _ => return None,
+        };
+        let hours = self.number(1, 2)?;
+        let minutes = if with_minutes {
+            if self.next()? != ':' {
+                return None;
+            }
+            self.number(2, 2)?
+        } else {
+            0
+        };
+        let total = hours * 60 + minutes;
+        if total > MAX_OFFSET_MINUTES as i64 || minutes > 59 {
+            return None;
+        }
+        Some(sign * total as i16)
+    }
+}
+
+/// `TimeSpan`'s constant ("c") format: `[-][d.]hh:mm:ss[.fffffff]`.
+pub(crate) fn duration_text(ticks: i64) -> String {
+    let sign = if ticks < 0 { "-" } else { "" };
+    let t = ticks.unsigned_abs();
+    let (days, rest) = (t / TICKS_PER_DAY as u64, t % TICKS_PER_DAY as u64);
+    let seconds = rest / TICKS_PER_SECOND as u64;
+    let fraction = rest % TICKS_PER_SECOND as u64;
+    let mut out = String::from(sign);
+    if days > 0 {
+        out.push_str(&format!("{}.", days));
+    }
+    out.push_str(&format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60));
+    if fraction > 0 {
+        out.push_str(&format!(".{:07}", fraction));
+    }
+    out
+}
+
+/// ISO 8601 form of a duration (`P1DT2H30M`, `-PT0.5S`); zero is `PT0S`.
+pub(crate) fn duration_iso(ticks: i64) -> String {
+    let sign = if ticks < 0 { "-" } else { "" };
+    let t = ticks.unsigned_abs();
+    let (days, rest) = (t / TICKS_PER_DAY as u64, t % TICKS_PER_DAY as u64);
+    let seconds = rest / TICKS_PER_SECOND as u64;
+    let fraction = rest % TICKS_PER_SECOND as u64;
+    let mut out = format!("{}P", sign);
+    if days > 0 {
+        out.push_str(&format!("{}D", days));
+    }
+    if rest > 0 || days == 0 {
+        out.push('T');
+        let (h, m, s) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+        if h > 0 {
+            out.push_str(&format!("{}H", h));
+        }
+        if m > 0 {
+            out.push_str(&format!("{}M", m));
+        }
+        if s > 0 || fraction > 0 || rest == 0 {
+            out.push_str(&s.to_string());
+            if fraction > 0 {
+                let digits = format!("{:07}", fraction);
+                out.push('.');
+                out.push_str(digits.trim_end_matches('0'));
+            }
+            out.push('S');
+        }
+    }
+    out
+}
+
+/// Parses an ISO 8601 duration: `[-]P[nW][nD][T[nH][nM][nS]]`, with a decimal fraction
+/// allowed on any component. Years and months have no fixed length and are rejected.
+pub(crate) fn parse_iso_duration(text: &str) -> Option<i64> {
+    let text = text.trim();
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text.strip_prefix('+').unwrap_or(text)),
+    };
+    let mut rest = text.strip_prefix(['P', 'p'])?;
+    let units: [(char, bool, i64); 5] = [
+        ('W', false, 7 * TICKS_PER_DAY),
+        ('D', false, TICKS_PER_DAY),
+        ('H', true, 60 * TICKS_PER_MINUTE),
+        ('M', true, TICKS_PER_MINUTE),
+        ('S', true, TICKS_PER_SECOND),
+    ];
+    let mut next_unit = 0;
+    let mut in_time = false;
+    let mut components = 0;
+    let mut total: i128 = 0;
+    while !rest.is_empty() {
+        if let Some(r) = rest.strip_prefix(['T', 't']) {
+            if in_time {
+                return None;
+            }
+            in_time = true;
+            rest = r;
+            continue;
+        }
+        let end = rest.find(|c: char| !(c.is_ascii_digit() || c == '.' || c == ',')).unwrap_or(rest.len());
+        let number = rest[..end].replace(',', ".");
+        let designator = rest[end..].chars().next()?.to_ascii_uppercase();
+        rest = &rest[end + 1..];
+        let index = units
+            .iter()
+            .enumerate()
+            .skip(next_unit)
+            .position(|(_, (d, time_part, _))| *d == designator && *time_part == in_time)?
+            + next_unit;
+        next_unit = index + 1;
+        let unit = units[index].2 as i128;
+        let (whole, fraction) = number.split_once('.').unwrap_or((&number, ""));
+        if whole.is_empty() && fraction.is_empty() {
+            return None;
+        }
+        let whole: i128 = if whole.is_empty() { 0 } else { whole.parse().ok()? };
+        total = total.checked_add(whole.checked_mul(unit)?)?;
+        if !fraction.is_empty() {
+            let fraction: f64 = format!("0.{}", fraction).parse().ok()?;
+            total += (fraction * unit as f64).round() as i128;
+        }
+        components += 1;
+    }
+    // `PT` with nothing after the designator is malformed.
+    if components == 0 || text.ends_with(['T', 't']) {
+        return None;
+    }
+    let total = if negative { -total } else { total };
+    i64::try_from(total).ok()
+}
+
+/// Members of a duration: the `days`, `hours`, `minutes`, `seconds` and `milliseconds`
+/// components (signed, as `TimeSpan`'s), their `total*` counterparts as numbers, and `ticks`.
+pub(crate) fn duration_member(ticks: i64, key_l: &str) -> Value {
+    let total = |unit: i64| Value::Number(ticks as f64 / unit as f64);
+    match key_l {
+        "days" => Value::Int(ticks / TICKS_PER_DAY),
+        "hours" => Value::Int(ticks / (60 * TICKS_PER_MINUTE) % 24),
+        "minutes" => Value::Int(ticks / TICKS_PER_MINUTE % 60),
+        "seconds" => Value::Int(ticks / TICKS_PER_SECOND % 60),
+        "milliseconds" => Value::Int(ticks / 10_000 % 1000),
+        "totaldays" => total(TICKS_PER_DAY),
+        "totalhours" => total(60 * TICKS_PER_MINUTE),
+        "totalminutes" => total(TICKS_PER_MINUTE),
+        "totalseconds" => total(TICKS_PER_SECOND),
+        "totalmilliseconds" => total(10_000),
+        "ticks" => Value::Int(ticks),
+        _ => Value::Nil,
+    }
+}
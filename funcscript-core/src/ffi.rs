@@ -2,15 +2,15 @@
 
 use std::ffi::{CStr, CString};
 use std::os::raw::{c_char, c_void};
-use crate::value::{FsError, Value};
+use crate::value::{ErrorKind, FsError, Value};
 use crate::vm::VM;
+use crate::parallel::ParallelConfig;
 use crate::host;
 use num_traits::ToPrimitive;
 
 #[repr(C)]
 pub struct FsVm {
     inner: VM,
-    host: FsHostCallbacksC,
 }
 
 #[repr(C)]
@@ -25,6 +25,36 @@ pub struct FsErrorC {
     pub line: i32,
     pub column: i32,
     pub message: *mut c_char,
+    /// Static snake_case name of the error kind (never freed), or null.
+    pub kind: *const c_char,
+    /// JSON of the error's `data` payload, or null.
+    pub data_json: *mut c_char,
+    /// The error this one was raised in response to, or null. Freed by `fs_error_free`.
+    pub cause: *mut FsErrorC,
+}
+
+impl Default for FsErrorC {
+    fn default() -> Self {
+        FsErrorC {
+            code: 0,
+            line: 0,
+            column: 0,
+            message: std::ptr::null_mut(),
+            kind: std::ptr::null(),
+            data_json: std::ptr::null_mut(),
+            cause: std::ptr::null_mut(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FsMemoryStatsC {
+    pub live_kvcs: u64,
+    pub tracked_kvcs: u64,
+    pub value_handles: u64,
+    pub collections: u64,
+    pub collected_kvcs: u64,
 }
 
 pub type FsHostWriteFn = Option<extern "C" fn(ctx: *mut c_void, bytes: *const u8, len: u64)>;
@@ -38,16 +68,45 @@ pub struct FsHostCallbacksC {
     pub is_file: Option<extern "C" fn(user_data: *mut c_void, path: *const c_char, out_is_file: *mut i32, out_error: *mut FsErrorC) -> i32>,
     pub dir_list: Option<extern "C" fn(user_data: *mut c_void, path: *const c_char, out_ctx: *mut c_void, out_write: FsHostWriteFn, out_error: *mut FsErrorC) -> i32>,
     pub log_line: Option<extern "C" fn(user_data: *mut c_void, text: *const c_char)>,
+    pub now: Option<extern "C" fn(user_data: *mut c_void, out_utc_ticks: *mut i64, out_offset_minutes: *mut i32)>,
+    pub random: Option<extern "C" fn(user_data: *mut c_void) -> u64>,
+    pub file_read_bytes: Option<extern "C" fn(user_data: *mut c_void, path: *const c_char, out_ctx: *mut c_void, out_write: FsHostWriteFn, out_error: *mut FsErrorC) -> i32>,
+    pub dir_walk: Option<extern "C" fn(user_data: *mut c_void, path: *const c_char, out_ctx: *mut c_void, out_write: FsHostWriteFn, out_error: *mut FsErrorC) -> i32>,
+    pub file_metadata: Option<extern "C" fn(user_data: *mut c_void, path: *const c_char, out_metadata: *mut FsFileMetadataC, out_error: *mut FsErrorC) -> i32>,
+    pub file_write: Option<extern "C" fn(user_data: *mut c_void, path: *const c_char, bytes: *const u8, len: u64, out_error: *mut FsErrorC) -> i32>,
+    pub env_var: Option<extern "C" fn(user_data: *mut c_void, name: *const c_char, out_found: *mut i32, out_ctx: *mut c_void, out_write: FsHostWriteFn)>,
+    pub fetch: Option<extern "C" fn(user_data: *mut c_void, request: *const FsHttpRequestC, out_status: *mut i32, out_headers_ctx: *mut c_void, out_body_ctx: *mut c_void, out_write: FsHostWriteFn, out_error: *mut FsErrorC) -> i32>,
+}
+
+/// Request handed to the `fetch` callback; the pointers are only valid during the call.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FsHttpRequestC {
+    pub method: *const c_char,
+    pub url: *const c_char,
+    /// `Name: value` lines separated by `\n`.
+    pub headers: *const c_char,
+    pub body: *const u8,
+    pub body_len: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FsFileMetadataC {
+    pub exists: i32,
+    pub is_dir: i32,
+    pub size: u64,
+    pub modified_ticks: i64,
 }
 
 impl Default for FsHostCallbacksC {
     fn default() -> Self {
-        Self { user_data: std::ptr::null_mut(), file_read_text: None, file_exists: None, is_file: None, dir_list: None, log_line: None }
+        Self { user_data: std::ptr::null_mut(), file_read_text: None, file_exists: None, is_file: None, dir_list: None, log_line: None, now: None, random: None, file_read_bytes: None, dir_walk: None, file_metadata: None, file_write: None, env_var: None, fetch: None }
     }
 }
 
 #[unsafe(no_mangle)]
-pub static FS_CORE_ABI_VERSION: u32 = 3;
+pub static FS_CORE_ABI_VERSION: u32 = 10;
 
 #[unsafe(no_mangle)]
 pub static FS_VALUE_NIL: u32 = 1;
@@ -66,6 +125,8 @@ pub static FS_VALUE_GUID: u32 = 14;
 #[unsafe(no_mangle)]
 pub static FS_VALUE_DATETIME: u32 = 15;
 #[unsafe(no_mangle)]
+pub static FS_VALUE_DURATION: u32 = 16;
+#[unsafe(no_mangle)]
 pub static FS_VALUE_STRING: u32 = 4;
 #[unsafe(no_mangle)]
 pub static FS_VALUE_LIST: u32 = 5;
@@ -103,7 +164,10 @@ pub extern "C" fn fs_eval_json(source: *const c_char) -> *mut c_char {
 
 #[unsafe(no_mangle)]
 pub extern "C" fn fs_vm_new() -> *mut FsVm {
-    Box::into_raw(Box::new(FsVm { inner: VM::new(), host: FsHostCallbacksC::default() }))
+    // C hosts only reach values through this VM, so collecting during evaluation is safe.
+    let mut inner = VM::new();
+    inner.set_auto_collect(true);
+    Box::into_raw(Box::new(FsVm { inner }))
 }
 
 #[unsafe(no_mangle)]
@@ -117,12 +181,34 @@ pub extern "C" fn fs_vm_free(vm: *mut FsVm) {
 }
 
 fn fs_error_to_c(err: &FsError) -> FsErrorC {
+    fs_error_to_c_in(None, err)
+}
+
+/// Like `fs_error_to_c`, but serializes `data` with the VM so lazy KVC members are evaluated.
+fn fs_error_to_c_in(mut vm: Option<&mut VM>, err: &FsError) -> FsErrorC {
     let msg = CString::new(err.message.clone()).unwrap_or_else(|_| CString::new("error").unwrap());
+    let data_json = match &err.data {
+        Some(d) => {
+            let json = match vm.as_deref_mut() {
+                Some(vm) => vm.value_to_json_string(d),
+                None => crate::native::format_json_value(d),
+            };
+            CString::new(json).map(CString::into_raw).unwrap_or(std::ptr::null_mut())
+        }
+        None => std::ptr::null_mut(),
+    };
+    let cause = match &err.cause {
+        Some(c) => Box::into_raw(Box::new(fs_error_to_c_in(vm, c))),
+        None => std::ptr::null_mut(),
+    };
     FsErrorC {
-        code: err.code,
+        code: err.code(),
         line: err.line,
         column: err.column,
         message: msg.into_raw(),
+        kind: err.kind.c_name().as_ptr(),
+        data_json,
+        cause,
     }
 }
 
@@ -137,16 +223,17 @@ extern "C" fn fs_host_write_vec(ctx: *mut c_void, bytes: *const u8, len: u64) {
     }
 }
 
-fn fs_host_err_to_fs(err: &FsErrorC, fallback_code: u32, fallback_message: &str) -> FsError {
-    FsError {
-        code: if err.code == 0 { fallback_code } else { err.code },
-        message: if err.message.is_null() { fallback_message.to_string() } else {
-            
-            unsafe { CStr::from_ptr(err.message) }.to_string_lossy().to_string()
-        },
-        line: if err.line == 0 { -1 } else { err.line },
-        column: if err.column == 0 { -1 } else { err.column },
-    }
+/// Converts an error reported by a host callback. Codes that are not a known `ErrorKind`
+/// code are reported as `HostIo`.
+fn fs_host_err_to_fs(err: &FsErrorC, fallback_message: &str) -> FsError {
+    let kind = ErrorKind::from_code(err.code).unwrap_or(ErrorKind::HostIo);
+    let message = if err.message.is_null() { fallback_message.to_string() } else {
+        unsafe { CStr::from_ptr(err.message) }.to_string_lossy().to_string()
+    };
+    FsError::new(kind, message).at(
+        if err.line == 0 { -1 } else { err.line },
+        if err.column == 0 { -1 } else { err.column },
+    )
 }
 
 fn fs_build_host_callbacks(c: FsHostCallbacksC) -> host::HostCallbacks {
@@ -155,77 +242,253 @@ fn fs_build_host_callbacks(c: FsHostCallbacksC) -> host::HostCallbacks {
     host::HostCallbacks {
         file_read_text: c.file_read_text.map(|cb| {
             std::sync::Arc::new(move |path: &str| -> Result<String, FsError> {
-                let c_path = CString::new(path).map_err(|_| FsError { code: 2601, message: "file: invalid path".to_string(), line: -1, column: -1 })?;
+                let c_path = CString::new(path).map_err(|_| FsError::new(ErrorKind::InvalidValue, "file: invalid path"))?;
                 let mut out: Vec<u8> = Vec::new();
-                let mut err = FsErrorC { code: 0, line: 0, column: 0, message: std::ptr::null_mut() };
+                let mut err = FsErrorC::default();
                 let rc = cb(user_data as *mut c_void, c_path.as_ptr(), (&mut out as *mut Vec<u8>) as *mut c_void, Some(fs_host_write_vec), &mut err as *mut FsErrorC);
                 if rc == 0 {
-                    String::from_utf8(out).map_err(|_| FsError { code: 2601, message: "file: host returned invalid utf-8".to_string(), line: -1, column: -1 })
+                    String::from_utf8(out).map_err(|_| FsError::new(ErrorKind::HostIo, "file: host returned invalid utf-8"))
                 } else {
-                    Err(fs_host_err_to_fs(&err, 2601, "file: host error"))
+                    Err(fs_host_err_to_fs(&err, "file: host error"))
                 }
-            }) as std::sync::Arc<dyn Fn(&str) -> Result<String, FsError> + Send + Sync>
+            }) as std::sync::Arc<host::ReadTextFn>
         }),
         file_exists: c.file_exists.map(|cb| {
             std::sync::Arc::new(move |path: &str| -> Result<bool, FsError> {
-                let c_path = CString::new(path).map_err(|_| FsError { code: 2602, message: "fileexists: invalid path".to_string(), line: -1, column: -1 })?;
+                let c_path = CString::new(path).map_err(|_| FsError::new(ErrorKind::InvalidValue, "fileexists: invalid path"))?;
                 let mut out_exists: i32 = 0;
-                let mut err = FsErrorC { code: 0, line: 0, column: 0, message: std::ptr::null_mut() };
+                let mut err = FsErrorC::default();
                 let rc = cb(user_data as *mut c_void, c_path.as_ptr(), &mut out_exists as *mut i32, &mut err as *mut FsErrorC);
-                if rc == 0 { Ok(out_exists != 0) } else { Err(fs_host_err_to_fs(&err, 2602, "fileexists: host error")) }
-            }) as std::sync::Arc<dyn Fn(&str) -> Result<bool, FsError> + Send + Sync>
+                if rc == 0 { Ok(out_exists != 0) } else { Err(fs_host_err_to_fs(&err, "fileexists: host error")) }
+            }) as std::sync::Arc<host::PathCheckFn>
         }),
         is_file: c.is_file.map(|cb| {
             std::sync::Arc::new(move |path: &str| -> Result<bool, FsError> {
-                let c_path = CString::new(path).map_err(|_| FsError { code: 2603, message: "isfile: invalid path".to_string(), line: -1, column: -1 })?;
+                let c_path = CString::new(path).map_err(|_| FsError::new(ErrorKind::InvalidValue, "isfile: invalid path"))?;
                 let mut out_is_file: i32 = 0;
-                let mut err = FsErrorC { code: 0, line: 0, column: 0, message: std::ptr::null_mut() };
+                let mut err = FsErrorC::default();
                 let rc = cb(user_data as *mut c_void, c_path.as_ptr(), &mut out_is_file as *mut i32, &mut err as *mut FsErrorC);
-                if rc == 0 { Ok(out_is_file != 0) } else { Err(fs_host_err_to_fs(&err, 2603, "isfile: host error")) }
-            }) as std::sync::Arc<dyn Fn(&str) -> Result<bool, FsError> + Send + Sync>
+                if rc == 0 { Ok(out_is_file != 0) } else { Err(fs_host_err_to_fs(&err, "isfile: host error")) }
+            }) as std::sync::Arc<host::PathCheckFn>
         }),
         dir_list: c.dir_list.map(|cb| {
             std::sync::Arc::new(move |path: &str| -> Result<Vec<String>, FsError> {
-                let c_path = CString::new(path).map_err(|_| FsError { code: 2604, message: "dirlist: invalid path".to_string(), line: -1, column: -1 })?;
+                let c_path = CString::new(path).map_err(|_| FsError::new(ErrorKind::InvalidValue, "dirlist: invalid path"))?;
                 let mut out: Vec<u8> = Vec::new();
-                let mut err = FsErrorC { code: 0, line: 0, column: 0, message: std::ptr::null_mut() };
+                let mut err = FsErrorC::default();
                 let rc = cb(user_data as *mut c_void, c_path.as_ptr(), (&mut out as *mut Vec<u8>) as *mut c_void, Some(fs_host_write_vec), &mut err as *mut FsErrorC);
                 if rc != 0 {
-                    return Err(fs_host_err_to_fs(&err, 2604, "dirlist: host error"));
+                    return Err(fs_host_err_to_fs(&err, "dirlist: host error"));
                 }
-                let s = String::from_utf8(out).map_err(|_| FsError { code: 2604, message: "dirlist: host returned invalid utf-8".to_string(), line: -1, column: -1 })?;
+                let s = String::from_utf8(out).map_err(|_| FsError::new(ErrorKind::HostIo, "dirlist: host returned invalid utf-8"))?;
                 let items = s.split('\n').filter(|x| !x.is_empty()).map(|x| x.to_string()).collect();
                 Ok(items)
-            }) as std::sync::Arc<dyn Fn(&str) -> Result<Vec<String>, FsError> + Send + Sync>
+            }) as std::sync::Arc<host::DirListFn>
         }),
         log_line: c.log_line.map(|cb| {
             std::sync::Arc::new(move |text: &str| {
                 if let Ok(c_text) = CString::new(text) {
                     cb(user_data as *mut c_void, c_text.as_ptr());
                 }
-            }) as std::sync::Arc<dyn Fn(&str) + Send + Sync>
+            }) as std::sync::Arc<host::LogLineFn>
+        }),
+        now: c.now.map(|cb| {
+            std::sync::Arc::new(move || {
+                let mut utc_ticks: i64 = 0;
+                let mut offset_minutes: i32 = 0;
+                cb(user_data as *mut c_void, &mut utc_ticks as *mut i64, &mut offset_minutes as *mut i32);
+                host::HostTime { utc_ticks, offset_minutes }
+            }) as std::sync::Arc<host::NowFn>
+        }),
+        random: c.random.map(|cb| {
+            std::sync::Arc::new(move || cb(user_data as *mut c_void)) as std::sync::Arc<host::RandomFn>
+        }),
+        file_read_bytes: c.file_read_bytes.map(|cb| {
+            std::sync::Arc::new(move |path: &str| -> Result<Vec<u8>, FsError> {
+                let c_path = CString::new(path).map_err(|_| FsError::new(ErrorKind::InvalidValue, "fileBytes: invalid path"))?;
+                let mut out: Vec<u8> = Vec::new();
+                let mut err = FsErrorC::default();
+                let rc = cb(user_data as *mut c_void, c_path.as_ptr(), (&mut out as *mut Vec<u8>) as *mut c_void, Some(fs_host_write_vec), &mut err as *mut FsErrorC);
+                if rc == 0 { Ok(out) } else { Err(fs_host_err_to_fs(&err, "fileBytes: host error")) }
+            }) as std::sync::Arc<host::ReadBytesFn>
+        }),
+        dir_walk: c.dir_walk.map(|cb| {
+            std::sync::Arc::new(move |path: &str| -> Result<Vec<String>, FsError> {
+                let c_path = CString::new(path).map_err(|_| FsError::new(ErrorKind::InvalidValue, "walk: invalid path"))?;
+                let mut out: Vec<u8> = Vec::new();
+                let mut err = FsErrorC::default();
+                let rc = cb(user_data as *mut c_void, c_path.as_ptr(), (&mut out as *mut Vec<u8>) as *mut c_void, Some(fs_host_write_vec), &mut err as *mut FsErrorC);
+                if rc != 0 {
+                    return Err(fs_host_err_to_fs(&err, "walk: host error"));
+                }
+                let s = String::from_utf8(out).map_err(|_| FsError::new(ErrorKind::HostIo, "walk: host returned invalid utf-8"))?;
+                Ok(s.split('\n').filter(|x| !x.is_empty()).map(|x| x.to_string()).collect())
+            }) as std::sync::Arc<host::DirWalkFn>
+        }),
+        file_metadata: c.file_metadata.map(|cb| {
+            std::sync::Arc::new(move |path: &str| -> Result<Option<host::FileMetadata>, FsError> {
+                let c_path = CString::new(path).map_err(|_| FsError::new(ErrorKind::InvalidValue, "metadata: invalid path"))?;
+                let mut out = FsFileMetadataC::default();
+                let mut err = FsErrorC::default();
+                let rc = cb(user_data as *mut c_void, c_path.as_ptr(), &mut out as *mut FsFileMetadataC, &mut err as *mut FsErrorC);
+                if rc != 0 {
+                    return Err(fs_host_err_to_fs(&err, "metadata: host error"));
+                }
+                Ok((out.exists != 0).then_some(host::FileMetadata { size: out.size, modified_ticks: out.modified_ticks, is_dir: out.is_dir != 0 }))
+            }) as std::sync::Arc<host::MetadataFn>
         }),
+        file_write: c.file_write.map(|cb| {
+            std::sync::Arc::new(move |path: &str, bytes: &[u8]| -> Result<(), FsError> {
+                let c_path = CString::new(path).map_err(|_| FsError::new(ErrorKind::InvalidValue, "writefile: invalid path"))?;
+                let mut err = FsErrorC::default();
+                let rc = cb(user_data as *mut c_void, c_path.as_ptr(), bytes.as_ptr(), bytes.len() as u64, &mut err as *mut FsErrorC);
+                if rc == 0 { Ok(()) } else { Err(fs_host_err_to_fs(&err, "writefile: host error")) }
+            }) as std::sync::Arc<host::WriteFn>
+        }),
+        env_var: c.env_var.map(|cb| {
+            std::sync::Arc::new(move |name: &str| -> Option<String> {
+                let c_name = CString::new(name).ok()?;
+                let mut found: i32 = 0;
+                let mut out: Vec<u8> = Vec::new();
+                cb(user_data as *mut c_void, c_name.as_ptr(), &mut found as *mut i32, (&mut out as *mut Vec<u8>) as *mut c_void, Some(fs_host_write_vec));
+                if found == 0 { None } else { String::from_utf8(out).ok() }
+            }) as std::sync::Arc<host::EnvVarFn>
+        }),
+        fetch: c.fetch.map(|cb| {
+            std::sync::Arc::new(move |request: &host::HttpRequest| -> Result<host::HttpResponse, FsError> {
+                let invalid = || FsError::new(ErrorKind::InvalidValue, "fetch: invalid request");
+                let method = CString::new(request.method.as_str()).map_err(|_| invalid())?;
+                let url = CString::new(request.url.as_str()).map_err(|_| invalid())?;
+                let header_lines: String = request.headers.iter().map(|(n, v)| format!("{n}: {v}\n")).collect();
+                let headers = CString::new(header_lines).map_err(|_| invalid())?;
+                let c_request = FsHttpRequestC {
+                    method: method.as_ptr(),
+                    url: url.as_ptr(),
+                    headers: headers.as_ptr(),
+                    body: request.body.as_ptr(),
+                    body_len: request.body.len() as u64,
+                };
+                let mut status: i32 = 0;
+                let mut out_headers: Vec<u8> = Vec::new();
+                let mut out_body: Vec<u8> = Vec::new();
+                let mut err = FsErrorC::default();
+                let rc = cb(
+                    user_data as *mut c_void,
+                    &c_request as *const FsHttpRequestC,
+                    &mut status as *mut i32,
+                    (&mut out_headers as *mut Vec<u8>) as *mut c_void,
+                    (&mut out_body as *mut Vec<u8>) as *mut c_void,
+                    Some(fs_host_write_vec),
+                    &mut err as *mut FsErrorC,
+                );
+                if rc != 0 {
+                    return Err(fs_host_err_to_fs(&err, "fetch: host error"));
+                }
+                let status = u16::try_from(status).map_err(|_| FsError::new(ErrorKind::HostIo, "fetch: host returned an invalid status"))?;
+                let out_headers = String::from_utf8(out_headers).map_err(|_| FsError::new(ErrorKind::HostIo, "fetch: host returned invalid utf-8"))?;
+                let headers = out_headers
+                    .split('\n')
+                    .filter_map(|line| line.split_once(':'))
+                    .map(|(n, v)| (n.trim().to_string(), v.trim().to_string()))
+                    .collect();
+                Ok(host::HttpResponse { status, headers, body: out_body })
+            }) as std::sync::Arc<host::FetchFn>
+        }),
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn fs_vm_set_host_callbacks(vm: *mut FsVm, callbacks: *const FsHostCallbacksC) -> i32 {
+    if vm.is_null() {
+        return 1;
+    }
+    unsafe {
+        let c = if callbacks.is_null() { FsHostCallbacksC::default() } else { *callbacks };
+        (*vm).inner.set_host(fs_build_host_callbacks(c));
     }
+    0
 }
 
-fn fs_with_host<T>(host_c: FsHostCallbacksC, f: impl FnOnce() -> T) -> T {
-    let callbacks = fs_build_host_callbacks(host_c);
-    let _g = host::push(callbacks);
-    f()
+/// Enables parallel `map`/`filter`/`Sum` on this VM. `workers <= 1` turns it off.
+#[unsafe(no_mangle)]
+pub extern "C" fn fs_vm_set_parallelism(vm: *mut FsVm, workers: u32, min_items: u64) -> i32 {
+    if vm.is_null() {
+        return 1;
+    }
+    let config = if workers <= 1 {
+        None
+    } else {
+        Some(ParallelConfig { workers: workers as usize, min_items: min_items as usize })
+    };
+    unsafe {
+        (*vm).inner.set_parallel(config);
+    }
+    0
 }
 
+/// Seeds `Random()` and `guid()` on this VM so evaluations are reproducible.
 #[unsafe(no_mangle)]
-pub extern "C" fn fs_vm_set_host_callbacks(vm: *mut FsVm, callbacks: *const FsHostCallbacksC) -> i32 {
+pub extern "C" fn fs_vm_set_random_seed(vm: *mut FsVm, seed: u64) -> i32 {
+    if vm.is_null() {
+        return 1;
+    }
+    unsafe {
+        (*vm).inner.set_random_seed(Some(seed));
+    }
+    0
+}
+
+/// Goes back to the host random callback (or ambient entropy) after `fs_vm_set_random_seed`.
+#[unsafe(no_mangle)]
+pub extern "C" fn fs_vm_clear_random_seed(vm: *mut FsVm) -> i32 {
+    if vm.is_null() {
+        return 1;
+    }
+    unsafe {
+        (*vm).inner.set_random_seed(None);
+    }
+    0
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn fs_vm_memory_stats(vm: *mut FsVm, out_stats: *mut FsMemoryStatsC) -> i32 {
+    if vm.is_null() || out_stats.is_null() {
+        return 1;
+    }
+    unsafe {
+        let stats = (*vm).inner.memory_stats();
+        *out_stats = FsMemoryStatsC {
+            live_kvcs: stats.live_kvcs as u64,
+            tracked_kvcs: stats.tracked_kvcs as u64,
+            value_handles: stats.value_handles as u64,
+            collections: stats.collections,
+            collected_kvcs: stats.collected_kvcs,
+        };
+    }
+    0
+}
+
+/// Turns automatic cycle collection during evaluation on (`enabled != 0`) or off.
+#[unsafe(no_mangle)]
+pub extern "C" fn fs_vm_set_auto_collect(vm: *mut FsVm, enabled: i32) -> i32 {
     if vm.is_null() {
         return 1;
     }
     unsafe {
-        let vm_ref = &mut *vm;
-        vm_ref.host = if callbacks.is_null() { FsHostCallbacksC::default() } else { *callbacks };
+        (*vm).inner.set_auto_collect(enabled != 0);
     }
     0
 }
 
+/// Frees unreachable KVC cycles now; returns the number of KVCs freed.
+#[unsafe(no_mangle)]
+pub extern "C" fn fs_vm_collect_cycles(vm: *mut FsVm) -> u64 {
+    if vm.is_null() {
+        return 0;
+    }
+    unsafe { (*vm).inner.collect_cycles() as u64 }
+}
+
 fn fs_json_escape(s: &str) -> String {
     let mut out = String::with_capacity(s.len() + 2);
     for ch in s.chars() {
@@ -252,6 +515,16 @@ pub extern "C" fn fs_error_free(err: *mut FsErrorC) {
             fs_free_string((*err).message);
             (*err).message = std::ptr::null_mut();
         }
+        if !(*err).data_json.is_null() {
+            fs_free_string((*err).data_json);
+            (*err).data_json = std::ptr::null_mut();
+        }
+        if !(*err).cause.is_null() {
+            let mut cause = Box::from_raw((*err).cause);
+            fs_error_free(&mut *cause);
+            (*err).cause = std::ptr::null_mut();
+        }
+        (*err).kind = std::ptr::null();
     }
 }
 
@@ -267,19 +540,16 @@ pub extern "C" fn fs_vm_eval(
     }
     unsafe {
         *out_json = std::ptr::null_mut();
-        (*out_error).code = 0;
-        (*out_error).line = 0;
-        (*out_error).column = 0;
-        (*out_error).message = std::ptr::null_mut();
     }
+    fs_reset_out_error(out_error);
 
     if vm.is_null() {
-        let err = FsError { code: 2001, message: "vm is null".to_string(), line: -1, column: -1 };
+        let err = FsError::new(ErrorKind::Api, "vm is null");
         unsafe { *out_error = fs_error_to_c(&err); }
         return 1;
     }
     if source.is_null() {
-        let err = FsError { code: 2002, message: "source is null".to_string(), line: -1, column: -1 };
+        let err = FsError::new(ErrorKind::Api, "source is null");
         unsafe { *out_error = fs_error_to_c(&err); }
         return 1;
     }
@@ -288,19 +558,18 @@ pub extern "C" fn fs_vm_eval(
     let r_str = match c_str.to_str() {
         Ok(s) => s,
         Err(_) => {
-            let err = FsError { code: 2003, message: "source is not valid UTF-8".to_string(), line: -1, column: -1 };
+            let err = FsError::new(ErrorKind::Api, "source is not valid UTF-8");
             unsafe { *out_error = fs_error_to_c(&err); }
             return 1;
         }
     };
 
     let vm_box = unsafe { &mut (*vm) };
-    let host_c = vm_box.host;
     let vm_inner = &mut vm_box.inner;
-    let res = fs_with_host(host_c, || vm_inner.interpret(r_str));
+    let res = vm_inner.interpret(r_str);
     match res {
         Ok(Value::Error(e)) => {
-            unsafe { *out_error = fs_error_to_c(&e); }
+            fs_set_vm_error(out_error, vm_inner, &e);
             1
         }
         Ok(v) => {
@@ -322,10 +591,7 @@ pub extern "C" fn fs_vm_eval(
 
 fn fs_reset_out_error(out_error: *mut FsErrorC) {
     unsafe {
-        (*out_error).code = 0;
-        (*out_error).line = 0;
-        (*out_error).column = 0;
-        (*out_error).message = std::ptr::null_mut();
+        *out_error = FsErrorC::default();
     }
 }
 
@@ -333,9 +599,13 @@ fn fs_set_error(out_error: *mut FsErrorC, err: &FsError) {
     unsafe { *out_error = fs_error_to_c(err); }
 }
 
+fn fs_set_vm_error(out_error: *mut FsErrorC, vm: &mut VM, err: &FsError) {
+    unsafe { *out_error = fs_error_to_c_in(Some(vm), err); }
+}
+
 fn fs_read_source(source: *const c_char, out_error: *mut FsErrorC) -> Option<&'static str> {
     if source.is_null() {
-        let err = FsError { code: 2002, message: "source is null".to_string(), line: -1, column: -1 };
+        let err = FsError::new(ErrorKind::Api, "source is null");
         fs_set_error(out_error, &err);
         return None;
     }
@@ -343,7 +613,7 @@ fn fs_read_source(source: *const c_char, out_error: *mut FsErrorC) -> Option<&'s
     match c_str.to_str() {
         Ok(s) => Some(unsafe { std::mem::transmute::<&str, &'static str>(s) }),
         Err(_) => {
-            let err = FsError { code: 2003, message: "source is not valid UTF-8".to_string(), line: -1, column: -1 };
+            let err = FsError::new(ErrorKind::Api, "source is not valid UTF-8");
             fs_set_error(out_error, &err);
             None
         }
@@ -364,7 +634,7 @@ pub extern "C" fn fs_vm_eval_value(
     fs_reset_out_error(out_error);
 
     if vm.is_null() {
-        let err = FsError { code: 2001, message: "vm is null".to_string(), line: -1, column: -1 };
+        let err = FsError::new(ErrorKind::Api, "vm is null");
         fs_set_error(out_error, &err);
         return 1;
     }
@@ -374,12 +644,11 @@ pub extern "C" fn fs_vm_eval_value(
     };
 
     let vm_box = unsafe { &mut (*vm) };
-    let host_c = vm_box.host;
     let vm_inner = &mut vm_box.inner;
-    let res = fs_with_host(host_c, || vm_inner.interpret(src));
+    let res = vm_inner.interpret(src);
     match res {
         Ok(Value::Error(e)) => {
-            fs_set_error(out_error, &e);
+            fs_set_vm_error(out_error, vm_inner, &e);
             1
         }
         Ok(v) => {
@@ -431,9 +700,10 @@ pub extern "C" fn fs_vm_value_type(vm: *mut FsVm, value: FsValue) -> u32 {
             crate::obj::Obj::Range(_) => FS_VALUE_RANGE,
             crate::obj::Obj::Bytes(_) => FS_VALUE_BYTES,
             crate::obj::Obj::Guid(_) => FS_VALUE_GUID,
-            crate::obj::Obj::DateTimeTicks(_) => FS_VALUE_DATETIME,
+            crate::obj::Obj::DateTimeTicks(..) => FS_VALUE_DATETIME,
+            crate::obj::Obj::Duration(_) => FS_VALUE_DURATION,
             crate::obj::Obj::Function(_) => FS_VALUE_FUNCTION,
-            crate::obj::Obj::NativeFn(_) => FS_VALUE_NATIVE,
+            crate::obj::Obj::NativeFn(_) | crate::obj::Obj::HostFn(_) | crate::obj::Obj::VmFn(_) => FS_VALUE_NATIVE,
             crate::obj::Obj::Provider(_) => FS_VALUE_KVC,
         },
     }
@@ -453,7 +723,7 @@ pub extern "C" fn fs_vm_value_to_json(
     fs_reset_out_error(out_error);
 
     if vm.is_null() {
-        let err = FsError { code: 2001, message: "vm is null".to_string(), line: -1, column: -1 };
+        let err = FsError::new(ErrorKind::Api, "vm is null");
         fs_set_error(out_error, &err);
         return 1;
     }
@@ -461,7 +731,7 @@ pub extern "C" fn fs_vm_value_to_json(
     let v = match vm.get_value(value.id) {
         Some(v) => v.clone(),
         None => {
-            let err = FsError { code: 2006, message: "invalid value handle".to_string(), line: -1, column: -1 };
+            let err = FsError::new(ErrorKind::Api, "invalid value handle");
             fs_set_error(out_error, &err);
             return 1;
         }
@@ -489,7 +759,7 @@ pub extern "C" fn fs_vm_value_range_info(
     }
     fs_reset_out_error(out_error);
     if vm.is_null() {
-        let err = FsError { code: 2001, message: "vm is null".to_string(), line: -1, column: -1 };
+        let err = FsError::new(ErrorKind::Api, "vm is null");
         fs_set_error(out_error, &err);
         return 1;
     }
@@ -497,7 +767,7 @@ pub extern "C" fn fs_vm_value_range_info(
     let v = match vm.get_value(value.id) {
         Some(v) => v,
         None => {
-            let err = FsError { code: 2006, message: "invalid value handle".to_string(), line: -1, column: -1 };
+            let err = FsError::new(ErrorKind::Api, "invalid value handle");
             fs_set_error(out_error, &err);
             return 1;
         }
@@ -512,13 +782,13 @@ pub extern "C" fn fs_vm_value_range_info(
                 0
             }
             _ => {
-                let err = FsError { code: 2007, message: "value is not a range".to_string(), line: -1, column: -1 };
+                let err = FsError::new(ErrorKind::TypeMismatch, "value is not a range");
                 fs_set_error(out_error, &err);
                 1
             }
         },
         _ => {
-            let err = FsError { code: 2007, message: "value is not a range".to_string(), line: -1, column: -1 };
+            let err = FsError::new(ErrorKind::TypeMismatch, "value is not a range");
             fs_set_error(out_error, &err);
             1
         }
@@ -538,7 +808,7 @@ pub extern "C" fn fs_vm_value_len(
     unsafe { *out_len = 0; }
     fs_reset_out_error(out_error);
     if vm.is_null() {
-        let err = FsError { code: 2001, message: "vm is null".to_string(), line: -1, column: -1 };
+        let err = FsError::new(ErrorKind::Api, "vm is null");
         fs_set_error(out_error, &err);
         return 1;
     }
@@ -546,7 +816,7 @@ pub extern "C" fn fs_vm_value_len(
     let v = match vm.get_value(value.id) {
         Some(v) => v.clone(),
         None => {
-            let err = FsError { code: 2006, message: "invalid value handle".to_string(), line: -1, column: -1 };
+            let err = FsError::new(ErrorKind::Api, "invalid value handle");
             fs_set_error(out_error, &err);
             return 1;
         }
@@ -566,17 +836,17 @@ pub extern "C" fn fs_vm_value_len(
                 0
             }
             None => {
-                let err = FsError { code: 2008, message: "len result is out of range".to_string(), line: -1, column: -1 };
+                let err = FsError::new(ErrorKind::OutOfRange, "len result is out of range");
                 fs_set_error(out_error, &err);
                 1
             }
         },
         Value::Error(e) => {
-            fs_set_error(out_error, &e);
+            fs_set_vm_error(out_error, vm, &e);
             1
         }
         _ => {
-            let err = FsError { code: 2008, message: "len not supported for this value".to_string(), line: -1, column: -1 };
+            let err = FsError::new(ErrorKind::TypeMismatch, "len not supported for this value");
             fs_set_error(out_error, &err);
             1
         }
@@ -597,7 +867,7 @@ pub extern "C" fn fs_vm_value_index(
     unsafe { (*out_value).id = 0; }
     fs_reset_out_error(out_error);
     if vm.is_null() {
-        let err = FsError { code: 2001, message: "vm is null".to_string(), line: -1, column: -1 };
+        let err = FsError::new(ErrorKind::Api, "vm is null");
         fs_set_error(out_error, &err);
         return 1;
     }
@@ -605,14 +875,14 @@ pub extern "C" fn fs_vm_value_index(
     let recv = match vm.get_value(receiver.id) {
         Some(v) => v.clone(),
         None => {
-            let err = FsError { code: 2006, message: "invalid value handle".to_string(), line: -1, column: -1 };
+            let err = FsError::new(ErrorKind::Api, "invalid value handle");
             fs_set_error(out_error, &err);
             return 1;
         }
     };
     let v = vm.value_index(&recv, index);
     if let Value::Error(e) = &v {
-        fs_set_error(out_error, e);
+        fs_set_vm_error(out_error, vm, e);
         return 1;
     }
     let id = vm.store_value(v);
@@ -634,12 +904,12 @@ pub extern "C" fn fs_vm_value_get_key(
     unsafe { (*out_value).id = 0; }
     fs_reset_out_error(out_error);
     if vm.is_null() {
-        let err = FsError { code: 2001, message: "vm is null".to_string(), line: -1, column: -1 };
+        let err = FsError::new(ErrorKind::Api, "vm is null");
         fs_set_error(out_error, &err);
         return 1;
     }
     if key.is_null() {
-        let err = FsError { code: 2009, message: "key is null".to_string(), line: -1, column: -1 };
+        let err = FsError::new(ErrorKind::Api, "key is null");
         fs_set_error(out_error, &err);
         return 1;
     }
@@ -647,7 +917,7 @@ pub extern "C" fn fs_vm_value_get_key(
     let key_s = match key_c.to_str() {
         Ok(s) => s,
         Err(_) => {
-            let err = FsError { code: 2010, message: "key is not valid UTF-8".to_string(), line: -1, column: -1 };
+            let err = FsError::new(ErrorKind::Api, "key is not valid UTF-8");
             fs_set_error(out_error, &err);
             return 1;
         }
@@ -656,14 +926,14 @@ pub extern "C" fn fs_vm_value_get_key(
     let recv = match vm.get_value(receiver.id) {
         Some(v) => v.clone(),
         None => {
-            let err = FsError { code: 2006, message: "invalid value handle".to_string(), line: -1, column: -1 };
+            let err = FsError::new(ErrorKind::Api, "invalid value handle");
             fs_set_error(out_error, &err);
             return 1;
         }
     };
     let v = vm.value_get_prop(&recv, key_s);
     if let Value::Error(e) = &v {
-        fs_set_error(out_error, e);
+        fs_set_vm_error(out_error, vm, e);
         return 1;
     }
     let id = vm.store_value(v);
@@ -684,7 +954,7 @@ pub extern "C" fn fs_vm_value_keys_json(
     unsafe { *out_json = std::ptr::null_mut(); }
     fs_reset_out_error(out_error);
     if vm.is_null() {
-        let err = FsError { code: 2001, message: "vm is null".to_string(), line: -1, column: -1 };
+        let err = FsError::new(ErrorKind::Api, "vm is null");
         fs_set_error(out_error, &err);
         return 1;
     }
@@ -692,17 +962,17 @@ pub extern "C" fn fs_vm_value_keys_json(
     let recv = match vm.get_value(receiver.id) {
         Some(v) => v.clone(),
         None => {
-            let err = FsError { code: 2006, message: "invalid value handle".to_string(), line: -1, column: -1 };
+            let err = FsError::new(ErrorKind::Api, "invalid value handle");
             fs_set_error(out_error, &err);
             return 1;
         }
     };
     let keys = match recv {
         Value::Obj(o) => match &*o {
-            crate::obj::Obj::Kvc(k) => vm.kvc_keys(std::rc::Rc::clone(k)),
+            crate::obj::Obj::Kvc(k) => vm.kvc_keys(std::sync::Arc::clone(k)),
             crate::obj::Obj::Provider(p) => match &p.current {
                 Value::Obj(o2) => match &**o2 {
-                    crate::obj::Obj::Kvc(k) => vm.kvc_keys(std::rc::Rc::clone(k)),
+                    crate::obj::Obj::Kvc(k) => vm.kvc_keys(std::sync::Arc::clone(k)),
                     _ => Vec::new(),
                 },
                 _ => Vec::new(),
@@ -736,22 +1006,21 @@ pub extern "C" fn fs_vm_value_call(
     unsafe { (*out_value).id = 0; }
     fs_reset_out_error(out_error);
     if vm.is_null() {
-        let err = FsError { code: 2001, message: "vm is null".to_string(), line: -1, column: -1 };
+        let err = FsError::new(ErrorKind::Api, "vm is null");
         fs_set_error(out_error, &err);
         return 1;
     }
     if argc > 0 && argv.is_null() {
-        let err = FsError { code: 2011, message: "argv is null".to_string(), line: -1, column: -1 };
+        let err = FsError::new(ErrorKind::Api, "argv is null");
         fs_set_error(out_error, &err);
         return 1;
     }
     let vm_box = unsafe { &mut (*vm) };
-    let host_c = vm_box.host;
     let vm = &mut vm_box.inner;
     let callee_v = match vm.get_value(callee.id) {
         Some(v) => v.clone(),
         None => {
-            let err = FsError { code: 2006, message: "invalid value handle".to_string(), line: -1, column: -1 };
+            let err = FsError::new(ErrorKind::Api, "invalid value handle");
             fs_set_error(out_error, &err);
             return 1;
         }
@@ -763,17 +1032,17 @@ pub extern "C" fn fs_vm_value_call(
             match vm.get_value(a.id) {
                 Some(v) => args.push(v.clone()),
                 None => {
-                    let err = FsError { code: 2006, message: "invalid value handle".to_string(), line: -1, column: -1 };
+                    let err = FsError::new(ErrorKind::Api, "invalid value handle");
                     fs_set_error(out_error, &err);
                     return 1;
                 }
             }
         }
     }
-    let res = fs_with_host(host_c, || vm.call_value_direct(callee_v, args));
+    let res = vm.call_value_direct(callee_v, args);
     match res {
         Ok(Value::Error(e)) => {
-            fs_set_error(out_error, &e);
+            fs_set_vm_error(out_error, vm, &e);
             1
         }
         Ok(v) => {
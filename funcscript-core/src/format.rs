@@ -0,0 +1,656 @@
+//! .NET-compatible `format(value, pattern)`, culture invariant.
+//!
+//! Numbers take standard (`N2`, `P1`, `X8`, `E3`, ...) and custom (`#,##0.00;(#,##0.00)`)
+//! patterns, `DateTimeTicks` take standard (`d`, `o`, `u`, ...) and custom (`yyyy-MM-dd HH:mm`)
+//! date/time patterns and GUIDs take `N`, `D`, `B`, `P` and `X`. Custom numeric patterns follow
+//! the C# runtime's formatter where it departs from .NET: at least one integer digit is always
+//! written. Both kinds of numeric pattern round midpoints away from zero, as .NET does.
+
+use crate::datetime::{civil, offset_text, standard_date_pattern, utc_ticks, DAY_NAMES, MONTH_NAMES};
+use crate::obj::Obj;
+use crate::value::{ErrorKind, FsError, Value};
+use num_traits::Signed;
+use uuid::Uuid;
+
+/// Largest precision accepted by standard numeric formats.
+const MAX_PRECISION: usize = 1000;
+
+/// Pattern used for dates when `format` is given none, as the C# runtime does.
+pub const DEFAULT_DATE_PATTERN: &str = "yyy-MM-dd HH:mm:ss";
+
+/// Formats `v` with `pattern`; values that take no pattern (text, lists, ...) use their
+/// display form.
+pub fn format_value(v: &Value, pattern: &str) -> Result<String, FsError> {
+    match v {
+        Value::Nil => Ok("null".to_string()),
+        Value::Int(_) | Value::BigInt(_) | Value::Number(_) => format_number(v, pattern),
+        Value::Obj(o) => match &**o {
+            Obj::DateTimeTicks(t, offset) => format_ticks(*t, *offset, pattern),
+            Obj::Guid(g) => format_guid(g, pattern),
+            _ => Ok(v.to_string()),
+        },
+        _ => Ok(v.to_string()),
+    }
+}
+
+fn invalid(message: String) -> FsError {
+    FsError::new(ErrorKind::InvalidValue, message)
+}
+
+// ---------------------------------------------------------------------------------------------
+// Numbers
+// ---------------------------------------------------------------------------------------------
+
+/// A number as decimal digits (values 0-9). `int` has no leading zeros, so zero has an empty
+/// integer part.
+#[derive(Clone, Debug)]
+struct Decimal {
+    neg: bool,
+    int: Vec<u8>,
+    frac: Vec<u8>,
+}
+
+fn digits_of(s: &str) -> Vec<u8> {
+    s.bytes().map(|b| b - b'0').collect()
+}
+
+fn digits_to_string(d: &[u8]) -> String {
+    d.iter().map(|d| (b'0' + d) as char).collect()
+}
+
+impl Decimal {
+    fn from_value(v: &Value) -> Option<Decimal> {
+        let (neg, text) = match v {
+            Value::Int(n) => (*n < 0, n.unsigned_abs().to_string()),
+            Value::BigInt(n) => (n.is_negative(), n.abs().to_string()),
+            Value::Number(n) if n.is_finite() => (n.is_sign_negative(), n.abs().to_string()),
+            _ => return None,
+        };
+        let (int, frac) = text.split_once('.').unwrap_or((&text, ""));
+        let mut d = Decimal { neg, int: digits_of(int), frac: digits_of(frac) };
+        d.trim();
+        Some(d)
+    }
+
+    fn trim(&mut self) {
+        let lead = self.int.iter().take_while(|d| **d == 0).count();
+        self.int.drain(..lead);
+        while self.frac.last() == Some(&0) {
+            self.frac.pop();
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.int.iter().chain(self.frac.iter()).all(|d| *d == 0)
+    }
+
+    /// Multiplies by `10^k`.
+    fn shift(&mut self, k: i32) {
+        if k > 0 {
+            for _ in 0..k {
+                let d = if self.frac.is_empty() { 0 } else { self.frac.remove(0) };
+                self.int.push(d);
+            }
+        } else {
+            for _ in 0..-k {
+                let d = self.int.pop().unwrap_or(0);
+                self.frac.insert(0, d);
+            }
+        }
+        self.trim();
+    }
+
+    /// Rounds to `places` fraction digits, midpoints away from zero.
+    fn round(&mut self, places: usize) {
+        if self.frac.len() <= places {
+            return;
+        }
+        let next = self.frac[places];
+        self.frac.truncate(places);
+        if next >= 5 {
+            let mut carry = true;
+            for d in self.frac.iter_mut().rev().chain(self.int.iter_mut().rev()) {
+                if *d == 9 {
+                    *d = 0;
+                } else {
+                    *d += 1;
+                    carry = false;
+                    break;
+                }
+            }
+            if carry {
+                self.int.insert(0, 1);
+            }
+        }
+        self.trim();
+    }
+
+    /// Significant digits without leading or trailing zeros, and the power of ten of the first
+    /// one. Zero has no digits.
+    fn significant(&self) -> (Vec<u8>, i32) {
+        let all: Vec<u8> = self.int.iter().chain(self.frac.iter()).copied().collect();
+        let Some(first) = all.iter().position(|d| *d != 0) else {
+            return (Vec::new(), 0);
+        };
+        let mut digits = all[first..].to_vec();
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+        (digits, self.int.len() as i32 - 1 - first as i32)
+    }
+}
+
+/// Rounds significant digits to `n`, midpoints away from zero.
+fn round_significant(digits: &mut Vec<u8>, exp: &mut i32, n: usize) {
+    if digits.len() <= n {
+        return;
+    }
+    let up = digits[n] >= 5;
+    digits.truncate(n);
+    if up {
+        let mut carry = true;
+        for d in digits.iter_mut().rev() {
+            if *d == 9 {
+                *d = 0;
+            } else {
+                *d += 1;
+                carry = false;
+                break;
+            }
+        }
+        if carry {
+            digits.insert(0, 1);
+            *exp += 1;
+        }
+    }
+    while digits.last() == Some(&0) {
+        digits.pop();
+    }
+}
+
+fn group_thousands(int: &str) -> String {
+    let mut out = String::with_capacity(int.len() + int.len() / 3);
+    for (i, c) in int.chars().enumerate() {
+        if i > 0 && (int.len() - i) % 3 == 0 {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// `d` rounded to `places` as fixed point text without sign, optionally grouped.
+fn fixed(d: &Decimal, places: usize, grouped: bool) -> String {
+    let int = if d.int.is_empty() { "0".to_string() } else { digits_to_string(&d.int) };
+    let mut out = if grouped { group_thousands(&int) } else { int };
+    if places > 0 {
+        out.push('.');
+        out.push_str(&digits_to_string(&d.frac));
+        out.extend(std::iter::repeat_n('0', places - d.frac.len()));
+    }
+    out
+}
+
+fn exponent_text(marker: char, exp: i32, always_sign: bool, min_digits: usize) -> String {
+    let sign = if exp < 0 { "-" } else if always_sign { "+" } else { "" };
+    format!("{marker}{sign}{:0min_digits$}", exp.unsigned_abs())
+}
+
+fn format_number(v: &Value, pattern: &str) -> Result<String, FsError> {
+    let pattern = pattern.trim();
+    let Some(mut d) = Decimal::from_value(v) else {
+        // NaN and the infinities print the same under every pattern.
+        return Ok(match v {
+            Value::Number(n) if n.is_nan() => "NaN".to_string(),
+            Value::Number(n) if *n < 0.0 => "-Infinity".to_string(),
+            _ => "Infinity".to_string(),
+        });
+    };
+    let mut chars = pattern.chars();
+    if let Some(spec) = chars.next().filter(|c| c.is_ascii_alphabetic()) {
+        let rest = chars.as_str();
+        if rest.len() <= 9 && rest.bytes().all(|b| b.is_ascii_digit()) {
+            let precision = if rest.is_empty() { None } else { rest.parse::<usize>().ok() };
+            if precision.is_some_and(|p| p > MAX_PRECISION) {
+                return Err(FsError::new(ErrorKind::OutOfRange, format!("format: precision of '{pattern}' is too large")));
+            }
+            return standard_number(&mut d, v, spec, precision, pattern);
+        }
+    }
+    custom_number(d, pattern)
+}
+
+fn standard_number(d: &mut Decimal, v: &Value, spec: char, precision: Option<usize>, pattern: &str) -> Result<String, FsError> {
+    let integral = matches!(v, Value::Int(_) | Value::BigInt(_));
+    let out = match spec {
+        'F' | 'f' | 'N' | 'n' => {
+            let p = precision.unwrap_or(2);
+            d.round(p);
+            let sign = if d.neg && !d.is_zero() { "-" } else { "" };
+            format!("{sign}{}", fixed(d, p, matches!(spec, 'N' | 'n')))
+        }
+        'P' | 'p' => {
+            let p = precision.unwrap_or(2);
+            d.shift(2);
+            d.round(p);
+            let sign = if d.neg && !d.is_zero() { "-" } else { "" };
+            format!("{sign}{} %", fixed(d, p, true))
+        }
+        'C' | 'c' => {
+            let p = precision.unwrap_or(2);
+            d.round(p);
+            let body = fixed(d, p, true);
+            if d.neg && !d.is_zero() { format!("(¤{body})") } else { format!("¤{body}") }
+        }
+        'D' | 'd' if integral => {
+            let int = if d.int.is_empty() { "0".to_string() } else { digits_to_string(&d.int) };
+            let sign = if d.neg { "-" } else { "" };
+            format!("{sign}{int:0>width$}", width = precision.unwrap_or(0))
+        }
+        'X' | 'x' if integral => {
+            let hex = match v {
+                Value::Int(n) => match i32::try_from(*n) {
+                    Ok(small) => format!("{:x}", small as u32),
+                    Err(_) => format!("{:x}", *n as u64),
+                },
+                Value::BigInt(n) if !n.is_negative() => n.to_str_radix(16),
+                _ => return Err(invalid("format: hexadecimal formats need a value that fits 64 bits".to_string())),
+            };
+            let hex = if spec == 'X' { hex.to_uppercase() } else { hex };
+            format!("{hex:0>width$}", width = precision.unwrap_or(0))
+        }
+        'E' | 'e' => {
+            let p = precision.unwrap_or(6);
+            let (mut digits, mut exp) = d.significant();
+            round_significant(&mut digits, &mut exp, p + 1);
+            let mut mantissa = digits_to_string(if digits.is_empty() { &[0] } else { &digits[..1] });
+            if p > 0 {
+                mantissa.push('.');
+                mantissa.push_str(&digits_to_string(digits.get(1..).unwrap_or(&[])));
+                let have = digits.len().saturating_sub(1);
+                mantissa.extend(std::iter::repeat_n('0', p - have));
+            }
+            let sign = if d.neg && !digits.is_empty() { "-" } else { "" };
+            let marker = if spec == 'E' { 'E' } else { 'e' };
+            format!("{sign}{mantissa}{}", exponent_text(marker, exp, true, 3))
+        }
+        'G' | 'g' | 'R' | 'r' => {
+            let precision = if matches!(spec, 'R' | 'r') { None } else { precision.filter(|p| *p > 0) };
+            let (mut digits, mut exp) = d.significant();
+            if let Some(p) = precision {
+                round_significant(&mut digits, &mut exp, p);
+            }
+            let threshold = precision.unwrap_or(if integral { usize::MAX } else { 15 });
+            general(d.neg, &digits, exp, threshold, if spec.is_ascii_uppercase() { 'E' } else { 'e' })
+        }
+        _ => return Err(invalid(format!("format: '{pattern}' is not a supported numeric format"))),
+    };
+    Ok(out)
+}
+
+/// The `G` layout: fixed point unless the exponent reaches `threshold` or drops below -5.
+fn general(neg: bool, digits: &[u8], exp: i32, threshold: usize, marker: char) -> String {
+    if digits.is_empty() {
+        return "0".to_string();
+    }
+    let sign = if neg { "-" } else { "" };
+    if exp < -5 || (exp >= 0 && exp as usize >= threshold) {
+        let mut mantissa = digits_to_string(&digits[..1]);
+        if digits.len() > 1 {
+            mantissa.push('.');
+            mantissa.push_str(&digits_to_string(&digits[1..]));
+        }
+        return format!("{sign}{mantissa}{}", exponent_text(marker, exp, true, 2));
+    }
+    let text = digits_to_string(digits);
+    let body = if exp < 0 {
+        format!("0.{}{text}", "0".repeat((-exp - 1) as usize))
+    } else if (exp as usize) + 1 >= text.len() {
+        format!("{text}{}", "0".repeat(exp as usize + 1 - text.len()))
+    } else {
+        let (int, frac) = text.split_at(exp as usize + 1);
+        format!("{int}.{frac}")
+    };
+    format!("{sign}{body}")
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum NumTok {
+    Zero,
+    Hash,
+    Point,
+    Comma,
+    Percent,
+    Permille,
+    Exponent { marker: char, always_sign: bool, digits: usize },
+    Literal(String),
+}
+
+/// Splits a custom numeric pattern into its (up to three) `;`-separated sections.
+fn numeric_sections(pattern: &str) -> Result<Vec<Vec<NumTok>>, FsError> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut sections = vec![Vec::new()];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+        let tok = match c {
+            '0' => NumTok::Zero,
+            '#' => NumTok::Hash,
+            '.' => NumTok::Point,
+            ',' => NumTok::Comma,
+            '%' => NumTok::Percent,
+            '‰' => NumTok::Permille,
+            ';' => {
+                sections.push(Vec::new());
+                continue;
+            }
+            'E' | 'e' => {
+                let mut j = i;
+                let always_sign = chars.get(j) == Some(&'+');
+                if matches!(chars.get(j), Some('+') | Some('-')) {
+                    j += 1;
+                }
+                let zeros = chars[j..].iter().take_while(|c| **c == '0').count();
+                if zeros == 0 {
+                    NumTok::Literal(c.to_string())
+                } else {
+                    i = j + zeros;
+                    NumTok::Exponent { marker: c, always_sign, digits: zeros }
+                }
+            }
+            '\\' => match chars.get(i) {
+                Some(next) => {
+                    i += 1;
+                    NumTok::Literal(next.to_string())
+                }
+                None => return Err(invalid("format: pattern ends with an escape".to_string())),
+            },
+            '\'' | '"' => {
+                let end = chars[i..].iter().position(|q| *q == c).ok_or_else(|| invalid("format: unterminated quote in pattern".to_string()))?;
+                let text: String = chars[i..i + end].iter().collect();
+                i += end + 1;
+                NumTok::Literal(text)
+            }
+            other => NumTok::Literal(other.to_string()),
+        };
+        sections.last_mut().expect("at least one section").push(tok);
+    }
+    if sections.len() > 3 {
+        return Err(invalid("format: a numeric pattern has at most three sections".to_string()));
+    }
+    Ok(sections)
+}
+
+/// Layout facts of one custom numeric section.
+struct NumLayout {
+    int_places: usize,
+    min_int: usize,
+    max_frac: usize,
+    min_frac: usize,
+    grouped: bool,
+    /// Power of ten applied before formatting (`%`, `‰` and scaling commas).
+    scale: i32,
+    exponent: bool,
+}
+
+fn numeric_layout(toks: &[NumTok]) -> NumLayout {
+    let end = toks.iter().position(|t| matches!(t, NumTok::Exponent { .. })).unwrap_or(toks.len());
+    let number = &toks[..end];
+    let point = number.iter().position(|t| *t == NumTok::Point).unwrap_or(number.len());
+    let int_part = &number[..point];
+    let frac_part = number.get(point + 1..).unwrap_or(&[]);
+
+    let places: Vec<&NumTok> = int_part.iter().filter(|t| matches!(t, NumTok::Zero | NumTok::Hash)).collect();
+    let first_zero = places.iter().position(|t| **t == NumTok::Zero).unwrap_or(places.len());
+    let frac_places: Vec<&NumTok> = frac_part.iter().filter(|t| matches!(t, NumTok::Zero | NumTok::Hash)).collect();
+    let min_frac = frac_places.iter().rposition(|t| **t == NumTok::Zero).map_or(0, |p| p + 1);
+
+    let last_place = int_part.iter().rposition(|t| matches!(t, NumTok::Zero | NumTok::Hash));
+    let first_place = int_part.iter().position(|t| matches!(t, NumTok::Zero | NumTok::Hash));
+    let mut grouped = false;
+    let mut scaling = 0;
+    if let (Some(first), Some(last)) = (first_place, last_place) {
+        grouped = int_part[first..last].contains(&NumTok::Comma);
+        scaling = int_part[last + 1..].iter().take_while(|t| **t == NumTok::Comma).count() as i32;
+    }
+    let percent = toks.iter().filter(|t| **t == NumTok::Percent).count() as i32;
+    let permille = toks.iter().filter(|t| **t == NumTok::Permille).count() as i32;
+
+    NumLayout {
+        int_places: places.len(),
+        min_int: (places.len() - first_zero).max(1),
+        max_frac: frac_places.len(),
+        min_frac,
+        grouped,
+        scale: 2 * percent + 3 * permille - 3 * scaling,
+        exponent: end < toks.len(),
+    }
+}
+
+fn custom_number(d: Decimal, pattern: &str) -> Result<String, FsError> {
+    let sections = numeric_sections(pattern)?;
+    let negative = d.neg && !d.is_zero();
+    let (toks, minus) = match sections.len() {
+        n if negative && n >= 2 && !sections[1].is_empty() => (&sections[1], false),
+        3 if d.is_zero() && !sections[2].is_empty() => (&sections[2], false),
+        // As in .NET, a negative value that shows as zero keeps its sign.
+        _ => (&sections[0], d.neg),
+    };
+    let layout = numeric_layout(toks);
+    let mut d = Decimal { neg: false, ..d };
+    d.shift(layout.scale);
+
+    let mut exp = 0;
+    if layout.exponent {
+        let (mut digits, mut e) = d.significant();
+        round_significant(&mut digits, &mut e, layout.min_int + layout.max_frac);
+        exp = if digits.is_empty() { 0 } else { e - (layout.min_int as i32 - 1) };
+        let mut int = digits.clone();
+        int.resize(digits.len().max(layout.min_int), 0);
+        let frac = int.split_off(layout.min_int);
+        d = Decimal { neg: false, int, frac };
+        d.trim();
+    } else {
+        d.round(layout.max_frac);
+    }
+
+    let mut int = d.int.clone();
+    while int.len() < layout.min_int {
+        int.insert(0, 0);
+    }
+    let mut frac = d.frac.clone();
+    frac.resize(frac.len().max(layout.min_frac), 0);
+
+    let mut out = String::new();
+    if minus {
+        out.push('-');
+    }
+    let mut seen_places = 0usize;
+    let mut in_frac = false;
+    let mut frac_pos = 0usize;
+    let mut int_written = layout.int_places > 0;
+    let write_digit = |out: &mut String, idx: usize| {
+        out.push((b'0' + int[idx]) as char);
+        let from_right = int.len() - 1 - idx;
+        if layout.grouped && from_right > 0 && from_right % 3 == 0 {
+            out.push(',');
+        }
+    };
+    for tok in toks {
+        match tok {
+            NumTok::Zero | NumTok::Hash if !in_frac => {
+                let idx = int.len() as isize - layout.int_places as isize + seen_places as isize;
+                if seen_places == 0 {
+                    for i in 0..=idx.max(-1) {
+                        write_digit(&mut out, i as usize);
+                    }
+                } else if idx >= 0 {
+                    write_digit(&mut out, idx as usize);
+                }
+                seen_places += 1;
+            }
+            NumTok::Zero | NumTok::Hash => {
+                if let Some(digit) = frac.get(frac_pos) {
+                    out.push((b'0' + digit) as char);
+                }
+                frac_pos += 1;
+            }
+            NumTok::Point if !in_frac => {
+                if !int_written {
+                    for i in 0..int.len() {
+                        write_digit(&mut out, i);
+                    }
+                    int_written = true;
+                }
+                in_frac = true;
+                if !frac.is_empty() {
+                    out.push('.');
+                }
+            }
+            // With no digit placeholders or point, the first comma places the integer part.
+            NumTok::Comma if !int_written && !in_frac => {
+                for i in 0..int.len() {
+                    write_digit(&mut out, i);
+                }
+                int_written = true;
+            }
+            NumTok::Point | NumTok::Comma => {}
+            NumTok::Percent => out.push('%'),
+            NumTok::Permille => out.push('‰'),
+            NumTok::Exponent { marker, always_sign, digits } => out.push_str(&exponent_text(*marker, exp, *always_sign, *digits)),
+            NumTok::Literal(text) => out.push_str(text),
+        }
+    }
+    Ok(out)
+}
+
+// ---------------------------------------------------------------------------------------------
+// Dates
+// ---------------------------------------------------------------------------------------------
+
+/// Formats clock `ticks`; `offset_minutes` feeds `z` and `K`, and the UTC standard formats
+/// (`r`, `u`) convert to UTC first, as they do for a `DateTimeOffset`.
+pub(crate) fn format_ticks(ticks: i64, offset_minutes: Option<i16>, pattern: &str) -> Result<String, FsError> {
+    let mut spec_chars = pattern.chars();
+    let (pattern, ticks) = match (spec_chars.next(), spec_chars.next()) {
+        (Some(spec), None) => {
+            let expanded = standard_date_pattern(spec).ok_or_else(|| invalid(format!("format: '{pattern}' is not a supported date format")))?;
+            let utc = matches!(spec, 'r' | 'R' | 'u');
+            (expanded, if utc { utc_ticks(ticks, offset_minutes) } else { ticks })
+        }
+        _ => (pattern, ticks),
+    };
+    let c = civil(ticks).ok_or_else(|| FsError::new(ErrorKind::OutOfRange, "format: date is outside 0001-01-01..9999-12-31"))?;
+
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut out = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let ch = chars[i];
+        let run = chars[i..].iter().take_while(|c| **c == ch).count();
+        match ch {
+            'd' | 'f' | 'F' | 'g' | 'h' | 'H' | 'K' | 'm' | 'M' | 's' | 't' | 'y' | 'z' => {
+                i += run;
+                match ch {
+                    'd' => match run {
+                        1 => out.push_str(&c.day.to_string()),
+                        2 => out.push_str(&format!("{:02}", c.day)),
+                        3 => out.push_str(&DAY_NAMES[c.weekday][..3]),
+                        _ => out.push_str(DAY_NAMES[c.weekday]),
+                    },
+                    'f' | 'F' => {
+                        if run > 7 {
+                            return Err(invalid(format!("format: '{}' has too many fraction digits", ch.to_string().repeat(run))));
+                        }
+                        let digits = format!("{:07}", c.fraction);
+                        let mut digits = &digits[..run];
+                        if ch == 'F' {
+                            digits = digits.trim_end_matches('0');
+                            if digits.is_empty() && out.ends_with('.') {
+                                out.pop();
+                            }
+                        }
+                        out.push_str(digits);
+                    }
+                    'g' => out.push_str("A.D."),
+                    'h' => {
+                        let h = if c.hour % 12 == 0 { 12 } else { c.hour % 12 };
+                        out.push_str(&if run == 1 { h.to_string() } else { format!("{h:02}") });
+                    }
+                    'H' => out.push_str(&if run == 1 { c.hour.to_string() } else { format!("{:02}", c.hour) }),
+                    // A date without an offset is UTC, written `Z` like a UTC `DateTime`.
+                    'K' => match offset_minutes {
+                        Some(offset) => out.push_str(&offset_text(offset)),
+                        None => out.push('Z'),
+                    },
+                    'z' => {
+                        let offset = offset_minutes.unwrap_or(0);
+                        let sign = if offset < 0 { '-' } else { '+' };
+                        let hours = offset.unsigned_abs() / 60;
+                        match run {
+                            1 => out.push_str(&format!("{sign}{hours}")),
+                            2 => out.push_str(&format!("{sign}{hours:02}")),
+                            _ => out.push_str(&offset_text(offset)),
+                        }
+                    }
+                    'm' => out.push_str(&if run == 1 { c.minute.to_string() } else { format!("{:02}", c.minute) }),
+                    's' => out.push_str(&if run == 1 { c.second.to_string() } else { format!("{:02}", c.second) }),
+                    'M' => match run {
+                        1 => out.push_str(&c.month.to_string()),
+                        2 => out.push_str(&format!("{:02}", c.month)),
+                        3 => out.push_str(&MONTH_NAMES[c.month - 1][..3]),
+                        _ => out.push_str(MONTH_NAMES[c.month - 1]),
+                    },
+                    't' => out.push_str(match (run, c.hour < 12) {
+                        (1, true) => "A",
+                        (1, false) => "P",
+                        (_, true) => "AM",
+                        (_, false) => "PM",
+                    }),
+                    _ => match run {
+                        1 => out.push_str(&(c.year % 100).to_string()),
+                        2 => out.push_str(&format!("{:02}", c.year % 100)),
+                        n => out.push_str(&format!("{:0n$}", c.year)),
+                    },
+                }
+            }
+            '\'' | '"' => {
+                let end = chars[i + 1..].iter().position(|q| *q == ch).ok_or_else(|| invalid("format: unterminated quote in pattern".to_string()))?;
+                out.extend(&chars[i + 1..i + 1 + end]);
+                i += end + 2;
+            }
+            '\\' => {
+                let next = chars.get(i + 1).ok_or_else(|| invalid("format: pattern ends with an escape".to_string()))?;
+                out.push(*next);
+                i += 2;
+            }
+            // `%d` marks a lone custom specifier; the specifier itself is handled next.
+            '%' => i += 1,
+            _ => {
+                out.push(ch);
+                i += 1;
+            }
+        }
+    }
+    Ok(out)
+}
+
+// ---------------------------------------------------------------------------------------------
+// GUIDs
+// ---------------------------------------------------------------------------------------------
+
+fn format_guid(g: &Uuid, pattern: &str) -> Result<String, FsError> {
+    Ok(match pattern.trim() {
+        "" | "D" | "d" => g.hyphenated().to_string(),
+        "N" | "n" => g.simple().to_string(),
+        "B" | "b" => g.braced().to_string(),
+        "P" | "p" => format!("({})", g.hyphenated()),
+        "X" | "x" => {
+            let (a, b, c, d) = g.as_fields();
+            let tail: Vec<String> = d.iter().map(|x| format!("0x{x:02x}")).collect();
+            format!("{{0x{a:08x},0x{b:04x},0x{c:04x},{{{}}}}}", tail.join(","))
+        }
+        other => return Err(invalid(format!("format: '{other}' is not a supported GUID format"))),
+    })
+}
@@ -0,0 +1,224 @@
+//! Cycle collection for KVC graphs created by a VM.
+//!
+//! A nested KVC keeps its enclosing scope alive through `KvcObject.parent`, while the
+//! enclosing KVC caches the nested one as a member value. These `Arc` cycles are never
+//! freed by reference counting alone, so each VM tracks the KVCs it creates and
+//! periodically runs trial deletion over them:
+//!
+//! 1. Walk everything reachable from the tracked KVCs (lists, KVC parents/caches, providers).
+//! 2. Subtract references that come from inside that graph from each node's strong count.
+//!    Whatever is left is held from outside (host values, handles, globals, the VM stack).
+//! 3. Nodes not reachable from such externally held nodes are garbage; their KVCs are
+//!    emptied, which breaks the cycles and lets `Arc` free them.
+//!
+//! Strong counts are read one node at a time and nothing stops other threads from cloning
+//! or dropping references meanwhile, so a collection is only sound while no other thread
+//! holds values from the VM. Rust values are `Send`, so a `VM` only collects when the host
+//! calls `VM::collect_cycles` or opts into automatic collection with `VM::set_auto_collect`.
+//! The C ABI and the CLI keep every value inside the VM and collect automatically.
+
+use crate::obj::{KvcObject, Obj, ProviderObject};
+use crate::value::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+
+const MIN_COLLECT_THRESHOLD: usize = 1024;
+
+/// Memory counters for a VM, see `VM::memory_stats`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// KVCs created by this VM that are still allocated.
+    pub live_kvcs: usize,
+    /// Entries in the VM's KVC registry, including ones freed since the last collection.
+    pub tracked_kvcs: usize,
+    /// Live value handles (`VM::store_value`).
+    pub value_handles: usize,
+    /// Number of cycle collections run so far.
+    pub collections: u64,
+    /// Total number of KVCs freed by cycle collection.
+    pub collected_kvcs: u64,
+}
+
+pub(crate) struct KvcRegistry {
+    tracked: Vec<Weak<Mutex<KvcObject>>>,
+    threshold: usize,
+    pub(crate) automatic: bool,
+    pub(crate) collections: u64,
+    pub(crate) collected: u64,
+}
+
+impl Default for KvcRegistry {
+    fn default() -> Self {
+        KvcRegistry { tracked: Vec::new(), threshold: MIN_COLLECT_THRESHOLD, automatic: false, collections: 0, collected: 0 }
+    }
+}
+
+impl KvcRegistry {
+    pub(crate) fn track(&mut self, kvc: &Arc<Mutex<KvcObject>>) {
+        self.tracked.push(Arc::downgrade(kvc));
+    }
+
+    /// Takes over the KVCs tracked by another (worker) registry.
+    pub(crate) fn absorb(&mut self, other: KvcRegistry) {
+        self.tracked.extend(other.tracked);
+    }
+
+    pub(crate) fn should_collect(&self) -> bool {
+        self.automatic && self.tracked.len() >= self.threshold
+    }
+
+    pub(crate) fn tracked(&self) -> usize {
+        self.tracked.len()
+    }
+
+    pub(crate) fn live(&self) -> usize {
+        self.tracked.iter().filter(|w| w.strong_count() > 0).count()
+    }
+
+    /// Frees unreachable KVC cycles and returns how many KVCs were emptied.
+    pub(crate) fn collect(&mut self) -> usize {
+        self.tracked.retain(|w| w.strong_count() > 0);
+        let roots: Vec<Arc<Mutex<KvcObject>>> = self.tracked.iter().filter_map(|w| w.upgrade()).collect();
+        let freed = collect_cycles(roots);
+
+        self.tracked.retain(|w| w.strong_count() > 0);
+        self.threshold = MIN_COLLECT_THRESHOLD.max(self.tracked.len() * 2);
+        self.collections += 1;
+        self.collected += freed as u64;
+        freed
+    }
+}
+
+enum Node {
+    Obj(Arc<Obj>),
+    Kvc(Arc<Mutex<KvcObject>>),
+    Provider(Arc<ProviderObject>),
+}
+
+impl Node {
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Obj(o) => Arc::strong_count(o),
+            Node::Kvc(k) => Arc::strong_count(k),
+            Node::Provider(p) => Arc::strong_count(p),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Graph {
+    nodes: Vec<Node>,
+    edges: Vec<Vec<usize>>,
+    index: HashMap<usize, usize>,
+}
+
+impl Graph {
+    /// Index of the node for `ptr`, inserting it (and holding exactly one clone) if new.
+    fn node(&mut self, ptr: usize, make: impl FnOnce() -> Node, pending: &mut Vec<usize>) -> usize {
+        if let Some(&i) = self.index.get(&ptr) {
+            return i;
+        }
+        let i = self.nodes.len();
+        self.nodes.push(make());
+        self.edges.push(Vec::new());
+        self.index.insert(ptr, i);
+        pending.push(i);
+        i
+    }
+
+    fn value_edge(&mut self, from: usize, v: &Value, pending: &mut Vec<usize>) {
+        if let Value::Obj(o) = v {
+            // Only containers can take part in a cycle.
+            if matches!(&**o, Obj::List(_) | Obj::Kvc(_) | Obj::Provider(_)) {
+                let to = self.node(Arc::as_ptr(o) as *const () as usize, || Node::Obj(Arc::clone(o)), pending);
+                self.edges[from].push(to);
+            }
+        }
+    }
+}
+
+fn collect_cycles(roots: Vec<Arc<Mutex<KvcObject>>>) -> usize {
+    let mut g = Graph::default();
+    let mut pending: Vec<usize> = Vec::new();
+    for k in roots {
+        let ptr = Arc::as_ptr(&k) as *const () as usize;
+        if !g.index.contains_key(&ptr) {
+            g.node(ptr, || Node::Kvc(k), &mut pending);
+        }
+    }
+
+    while let Some(i) = pending.pop() {
+        match &g.nodes[i] {
+            Node::Obj(o) => {
+                let o = Arc::clone(o);
+                match &*o {
+                    Obj::List(items) => {
+                        for v in items.iter() {
+                            g.value_edge(i, v, &mut pending);
+                        }
+                    }
+                    Obj::Kvc(k) => {
+                        let to = g.node(Arc::as_ptr(k) as *const () as usize, || Node::Kvc(Arc::clone(k)), &mut pending);
+                        g.edges[i].push(to);
+                    }
+                    Obj::Provider(p) => {
+                        let to = g.node(Arc::as_ptr(p) as *const () as usize, || Node::Provider(Arc::clone(p)), &mut pending);
+                        g.edges[i].push(to);
+                    }
+                    _ => {}
+                }
+            }
+            Node::Kvc(k) => {
+                let k = Arc::clone(k);
+                let b = k.lock().unwrap();
+                if let Some(p) = &b.parent {
+                    g.value_edge(i, p, &mut pending);
+                }
+                for v in b.cache.values() {
+                    g.value_edge(i, v, &mut pending);
+                }
+            }
+            Node::Provider(p) => {
+                let p = Arc::clone(p);
+                g.value_edge(i, &p.current, &mut pending);
+                if let Some(parent) = &p.parent {
+                    g.value_edge(i, parent, &mut pending);
+                }
+            }
+        }
+    }
+
+    // References from inside the graph; the graph itself holds one more per node.
+    let mut internal = vec![0usize; g.nodes.len()];
+    for targets in g.edges.iter() {
+        for &t in targets {
+            internal[t] += 1;
+        }
+    }
+    let counts: Vec<usize> = g.nodes.iter().map(Node::strong_count).collect();
+    let mut alive = vec![false; g.nodes.len()];
+    let mut stack: Vec<usize> = (0..g.nodes.len()).filter(|&i| counts[i] > internal[i] + 1).collect();
+    while let Some(i) = stack.pop() {
+        if alive[i] {
+            continue;
+        }
+        alive[i] = true;
+        stack.extend(g.edges[i].iter().copied().filter(|&t| !alive[t]));
+    }
+
+    let mut freed = 0;
+    for (i, node) in g.nodes.iter().enumerate() {
+        if alive[i] {
+            continue;
+        }
+        if let Node::Kvc(k) = node {
+            let mut b = k.lock().unwrap();
+            b.cache.clear();
+            b.entries.clear();
+            b.order.clear();
+            b.parent = None;
+            freed += 1;
+        }
+    }
+    freed
+}
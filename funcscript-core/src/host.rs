@@ -2,108 +2,405 @@
 //!
 //! The core runtime avoids direct IO/logging. Embedders can provide callbacks
 //! (via the C ABI or other bindings) to enable filesystem operations and logging.
+//!
+//! Callbacks belong to a VM (`VM::with_host` / `VM::set_host`); natives that need them
+//! receive the VM's `HostContext` as an argument.
+//!
+//! The clock and the random source are host callbacks too, so embedders (and golden tests)
+//! can pin `now()` and make `Random()`/`guid()` reproducible. Without callbacks the system
+//! clock and ambient entropy are used.
+//!
+//! `fetch(...)` is handed to the `fetch` callback as an `HttpRequest`; the core never opens
+//! a connection itself.
+//!
+//! `recording_callbacks` captures every callback invocation into a `HostRecording`, and
+//! `replay_callbacks` answers from such a recording, so a failing evaluation can be re-run
+//! without the host it ran against.
+
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{BuildHasher, Hasher};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use base64::{engine::general_purpose, Engine as _};
+
+use crate::json::Json;
+use crate::value::{ErrorKind, FsError};
+
+const UNIX_EPOCH_TICKS: i64 = 621_355_968_000_000_000;
+
+/// Reads a whole file as text for `file(...)`.
+pub type ReadTextFn = dyn Fn(&str) -> Result<String, FsError> + Send + Sync;
+
+/// Answers a yes/no question about a path, for `fileexists(...)` and `isfile(...)`.
+pub type PathCheckFn = dyn Fn(&str) -> Result<bool, FsError> + Send + Sync;
+
+/// Lists the entries directly inside a directory for `dirlist(...)`.
+pub type DirListFn = dyn Fn(&str) -> Result<Vec<String>, FsError> + Send + Sync;
 
-use std::cell::RefCell;
-use std::sync::Arc;
+/// Prints one line of `log(...)` output.
+pub type LogLineFn = dyn Fn(&str) + Send + Sync;
 
-use crate::value::FsError;
+/// Current time for `now()`, `utcnow()` and `today()`.
+pub type NowFn = dyn Fn() -> HostTime + Send + Sync;
+
+/// 64 random bits for `guid()` and `Random()`.
+pub type RandomFn = dyn Fn() -> u64 + Send + Sync;
+
+/// Reads a whole file as bytes for `fileBytes(...)`.
+pub type ReadBytesFn = dyn Fn(&str) -> Result<Vec<u8>, FsError> + Send + Sync;
+
+/// Lists every entry below a directory for `walk(...)`.
+pub type DirWalkFn = dyn Fn(&str) -> Result<Vec<String>, FsError> + Send + Sync;
+
+/// Size, modification time and kind of a path for `metadata(...)`.
+pub type MetadataFn = dyn Fn(&str) -> Result<Option<FileMetadata>, FsError> + Send + Sync;
+
+/// Creates or replaces a file for `writefile(...)`.
+pub type WriteFn = dyn Fn(&str, &[u8]) -> Result<(), FsError> + Send + Sync;
+
+/// Looks up an environment variable; see `env_from_map` and `process_env`.
+pub type EnvVarFn = dyn Fn(&str) -> Option<String> + Send + Sync;
+
+/// Performs an HTTP request for `fetch(...)`.
+pub type FetchFn = dyn Fn(&HttpRequest) -> Result<HttpResponse, FsError> + Send + Sync;
 
 #[derive(Clone, Default)]
 pub struct HostCallbacks {
-    pub file_read_text: Option<Arc<dyn Fn(&str) -> Result<String, FsError> + Send + Sync>>,
-    pub file_exists: Option<Arc<dyn Fn(&str) -> Result<bool, FsError> + Send + Sync>>,
-    pub is_file: Option<Arc<dyn Fn(&str) -> Result<bool, FsError> + Send + Sync>>,
-    pub dir_list: Option<Arc<dyn Fn(&str) -> Result<Vec<String>, FsError> + Send + Sync>>,
-    pub log_line: Option<Arc<dyn Fn(&str) + Send + Sync>>,
+    pub file_read_text: Option<Arc<ReadTextFn>>,
+    pub file_exists: Option<Arc<PathCheckFn>>,
+    pub is_file: Option<Arc<PathCheckFn>>,
+    pub dir_list: Option<Arc<DirListFn>>,
+    pub log_line: Option<Arc<LogLineFn>>,
+    pub now: Option<Arc<NowFn>>,
+    pub random: Option<Arc<RandomFn>>,
+    pub file_read_bytes: Option<Arc<ReadBytesFn>>,
+    /// Every entry below a directory, as paths relative to it with `/` separators.
+    pub dir_walk: Option<Arc<DirWalkFn>>,
+    /// `Ok(None)` when the path does not exist.
+    pub file_metadata: Option<Arc<MetadataFn>>,
+    /// Creates or replaces a file. Hosts leave it unset unless scripts may write.
+    pub file_write: Option<Arc<WriteFn>>,
+    /// Value of an environment variable for `env(...)`, `None` when unset.
+    pub env_var: Option<Arc<EnvVarFn>>,
+    /// HTTP client for `fetch(...)`. Unset means scripts have no network access.
+    pub fetch: Option<Arc<FetchFn>>,
 }
 
-thread_local! {
-    static STACK: RefCell<Vec<HostCallbacks>> = const { RefCell::new(Vec::new()) };
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HttpRequest {
+    /// Upper-case method, `GET` unless the script asks for another one.
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
 }
 
-pub struct HostGuard;
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
 
-impl Drop for HostGuard {
-    fn drop(&mut self) {
-        STACK.with(|s| {
-            let mut s = s.borrow_mut();
-            let _ = s.pop();
-        });
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileMetadata {
+    pub size: u64,
+    /// Last modification time in UTC .NET ticks.
+    pub modified_ticks: i64,
+    pub is_dir: bool,
+}
+
+/// The current instant as seen by scripts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HostTime {
+    /// UTC time in .NET ticks (100ns units since 0001-01-01), like `DateTime` values.
+    pub utc_ticks: i64,
+    /// Offset of local time from UTC, used by `now()` and `today()`.
+    pub offset_minutes: i32,
 }
 
-pub fn push(callbacks: HostCallbacks) -> HostGuard {
-    STACK.with(|s| s.borrow_mut().push(callbacks));
-    HostGuard
+/// SplitMix64 generator behind `VM::set_random_seed`; it takes precedence over the host
+/// random callback.
+#[derive(Debug)]
+struct SeededRandom(AtomicU64);
+
+impl SeededRandom {
+    fn new(seed: u64) -> Self {
+        SeededRandom(AtomicU64::new(seed))
+    }
+
+    fn next(&self) -> u64 {
+        const GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+        let mut z = self.0.fetch_add(GAMMA, Ordering::Relaxed).wrapping_add(GAMMA);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
 }
 
-fn current() -> Option<HostCallbacks> {
-    STACK.with(|s| s.borrow().last().cloned())
+/// Host services of one VM. Each VM owns its context and hands it to host natives
+/// (`Obj::HostFn`) on every call, so VMs with different callbacks can share a thread.
+#[derive(Clone, Default)]
+pub struct HostContext {
+    pub callbacks: HostCallbacks,
+    seeded: Option<Arc<SeededRandom>>,
 }
 
-pub fn file_read_text(path: &str) -> Result<String, FsError> {
-    if let Some(cb) = current().and_then(|c| c.file_read_text) {
-        cb(path)
-    } else {
-        Err(FsError { code: 2601, message: "file: host callback not set".to_string(), line: -1, column: -1 })
+impl HostContext {
+    pub fn new(callbacks: HostCallbacks) -> Self {
+        HostContext { callbacks, seeded: None }
+    }
+
+    pub(crate) fn set_seed(&mut self, seed: Option<u64>) {
+        self.seeded = seed.map(|s| Arc::new(SeededRandom::new(s)));
+    }
+
+    pub fn file_read_text(&self, path: &str) -> Result<String, FsError> {
+        match &self.callbacks.file_read_text {
+            Some(cb) => cb(path),
+            None => Err(FsError::new(ErrorKind::HostUnavailable, "file: host callback not set")),
+        }
+    }
+
+    pub fn file_exists(&self, path: &str) -> Result<bool, FsError> {
+        match &self.callbacks.file_exists {
+            Some(cb) => cb(path),
+            None => Err(FsError::new(ErrorKind::HostUnavailable, "fileexists: host callback not set")),
+        }
+    }
+
+    pub fn is_file(&self, path: &str) -> Result<bool, FsError> {
+        match &self.callbacks.is_file {
+            Some(cb) => cb(path),
+            None => Err(FsError::new(ErrorKind::HostUnavailable, "isfile: host callback not set")),
+        }
+    }
+
+    pub fn dir_list(&self, path: &str) -> Result<Vec<String>, FsError> {
+        match &self.callbacks.dir_list {
+            Some(cb) => cb(path),
+            None => Err(FsError::new(ErrorKind::HostUnavailable, "dirlist: host callback not set")),
+        }
+    }
+
+    pub fn file_read_bytes(&self, path: &str) -> Result<Vec<u8>, FsError> {
+        match &self.callbacks.file_read_bytes {
+            Some(cb) => cb(path),
+            None => Err(FsError::new(ErrorKind::HostUnavailable, "fileBytes: host callback not set")),
+        }
+    }
+
+    pub fn dir_walk(&self, path: &str) -> Result<Vec<String>, FsError> {
+        match &self.callbacks.dir_walk {
+            Some(cb) => cb(path),
+            None => Err(FsError::new(ErrorKind::HostUnavailable, "walk: host callback not set")),
+        }
+    }
+
+    pub fn file_metadata(&self, path: &str) -> Result<Option<FileMetadata>, FsError> {
+        match &self.callbacks.file_metadata {
+            Some(cb) => cb(path),
+            None => Err(FsError::new(ErrorKind::HostUnavailable, "metadata: host callback not set")),
+        }
     }
+
+    pub fn file_write(&self, path: &str, bytes: &[u8]) -> Result<(), FsError> {
+        match &self.callbacks.file_write {
+            Some(cb) => cb(path, bytes),
+            None => Err(FsError::new(ErrorKind::HostUnavailable, "writefile: writing files is not enabled")),
+        }
+    }
+
+    /// Unset variables and hosts without an environment both read as `None`.
+    pub fn env_var(&self, name: &str) -> Option<String> {
+        self.callbacks.env_var.as_ref().and_then(|cb| cb(name))
+    }
+
+    pub fn fetch(&self, request: &HttpRequest) -> Result<HttpResponse, FsError> {
+        match &self.callbacks.fetch {
+            Some(cb) => cb(request),
+            None => Err(FsError::new(ErrorKind::HostUnavailable, "fetch: network access is not enabled")),
+        }
+    }
+
+    pub fn log_line(&self, text: &str) {
+        if let Some(cb) = &self.callbacks.log_line {
+            cb(text);
+        }
+    }
+
+    pub fn now(&self) -> HostTime {
+        match &self.callbacks.now {
+            Some(cb) => cb(),
+            None => system_now(),
+        }
+    }
+
+    /// 64 random bits from the VM seed, the host random callback or ambient entropy, in that order.
+    pub fn random_u64(&self) -> u64 {
+        if let Some(seeded) = &self.seeded {
+            return seeded.next();
+        }
+        match &self.callbacks.random {
+            Some(cb) => cb(),
+            None => ambient_random(),
+        }
+    }
+}
+
+fn system_now() -> HostTime {
+    let since_epoch = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+    let ticks = i64::try_from(since_epoch.as_nanos() / 100).unwrap_or(i64::MAX - UNIX_EPOCH_TICKS);
+    HostTime { utc_ticks: UNIX_EPOCH_TICKS + ticks, offset_minutes: 0 }
 }
 
-pub fn file_exists(path: &str) -> Result<bool, FsError> {
-    if let Some(cb) = current().and_then(|c| c.file_exists) {
-        cb(path)
-    } else {
-        Err(FsError { code: 2602, message: "fileexists: host callback not set".to_string(), line: -1, column: -1 })
+fn ambient_random() -> u64 {
+    // Every `RandomState` gets fresh keys, which is enough for non-cryptographic use.
+    let mut h = RandomState::new().build_hasher();
+    h.write_u128(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_nanos());
+    h.finish()
+}
+
+/// Limits what the standard filesystem callbacks may read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsSandbox {
+    /// Directories scripts may read below; `None` allows the whole file system.
+    pub read_roots: Option<Vec<PathBuf>>,
+    /// Largest file `file(...)` will read, in bytes.
+    pub max_file_bytes: u64,
+    /// Directories `writefile` may create or replace files below. Without any, the
+    /// callbacks offer no write access at all.
+    pub write_roots: Vec<PathBuf>,
+}
+
+impl FsSandbox {
+    /// A sandbox without any readable directory.
+    pub fn new() -> Self {
+        FsSandbox { read_roots: Some(Vec::new()), max_file_bytes: 1_000_000, write_roots: Vec::new() }
+    }
+
+    pub fn unrestricted() -> Self {
+        FsSandbox { read_roots: None, ..FsSandbox::new() }
+    }
+
+    pub fn allow_read(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.read_roots.get_or_insert_with(Vec::new).push(dir.into());
+        self
+    }
+
+    pub fn allow_write(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.write_roots.push(dir.into());
+        self
     }
 }
 
-pub fn is_file(path: &str) -> Result<bool, FsError> {
-    if let Some(cb) = current().and_then(|c| c.is_file) {
-        cb(path)
-    } else {
-        Err(FsError { code: 2603, message: "isfile: host callback not set".to_string(), line: -1, column: -1 })
+impl Default for FsSandbox {
+    fn default() -> Self {
+        FsSandbox::new()
     }
 }
 
-pub fn dir_list(path: &str) -> Result<Vec<String>, FsError> {
-    if let Some(cb) = current().and_then(|c| c.dir_list) {
-        cb(path)
-    } else {
-        Err(FsError { code: 2604, message: "dirlist: host callback not set".to_string(), line: -1, column: -1 })
+/// `FsSandbox` with its roots canonicalized once, when the callbacks are built.
+struct ResolvedSandbox {
+    roots: Option<Vec<PathBuf>>,
+    max_file_bytes: u64,
+    write_roots: Vec<PathBuf>,
+}
+
+impl ResolvedSandbox {
+    fn new(sandbox: &FsSandbox) -> Self {
+        let canonical = |roots: &[PathBuf]| roots.iter().filter_map(|r| std::fs::canonicalize(r).ok()).collect::<Vec<_>>();
+        ResolvedSandbox {
+            roots: sandbox.read_roots.as_deref().map(canonical),
+            max_file_bytes: sandbox.max_file_bytes,
+            write_roots: canonical(&sandbox.write_roots),
+        }
+    }
+
+    /// Where `writefile` may write `path`: inside a write root, and not through a symlink
+    /// (an existing one could point anywhere).
+    fn check_write(&self, path: &str) -> Result<PathBuf, FsError> {
+        let target = std::fs::canonicalize(path).unwrap_or_else(|_| resolve_missing(Path::new(path)));
+        let is_link = std::fs::symlink_metadata(&target).is_ok_and(|m| m.file_type().is_symlink());
+        if is_link || !self.write_roots.iter().any(|r| target.starts_with(r)) {
+            return Err(FsError::new(ErrorKind::AccessDenied, format!("writefile: Writing '{path}' is denied")));
+        }
+        Ok(target)
+    }
+
+    /// Resolves `path` and checks it against the roots. Symlinks and `..` are resolved first,
+    /// so links leading out of a root are refused. Returns `None` for a missing path.
+    fn check(&self, op: &str, path: &str) -> Result<Option<PathBuf>, FsError> {
+        let resolved = std::fs::canonicalize(path).ok();
+        if let Some(roots) = &self.roots {
+            let target = resolved.clone().unwrap_or_else(|| resolve_missing(Path::new(path)));
+            if !roots.iter().any(|r| target.starts_with(r)) {
+                return Err(FsError::new(ErrorKind::AccessDenied, format!("{op}: Access to '{path}' is denied")));
+            }
+        }
+        Ok(resolved)
     }
 }
 
-pub fn log_line(text: &str) {
-    if let Some(cb) = current().and_then(|c| c.log_line) {
-        cb(text);
+/// Where a path that does not exist would be: its deepest existing ancestor, canonicalized,
+/// followed by the remaining components.
+fn resolve_missing(path: &Path) -> PathBuf {
+    let mut rest: Vec<Component> = Vec::new();
+    let mut cur = path;
+    let mut base = loop {
+        if let Ok(c) = std::fs::canonicalize(if cur.as_os_str().is_empty() { Path::new(".") } else { cur }) {
+            break c;
+        }
+        match (cur.components().next_back(), cur.parent()) {
+            (Some(last), Some(parent)) => {
+                rest.push(last);
+                cur = parent;
+            }
+            _ => break std::env::current_dir().unwrap_or_default(),
+        }
+    };
+    for comp in rest.into_iter().rev() {
+        match comp {
+            Component::ParentDir => {
+                base.pop();
+            }
+            Component::Normal(name) => base.push(name),
+            _ => {}
+        }
     }
+    base
 }
 
-pub fn std_fs_callbacks() -> HostCallbacks {
+/// Filesystem callbacks backed by `std::fs`, restricted to `sandbox`.
+pub fn sandboxed_fs_callbacks(sandbox: &FsSandbox) -> HostCallbacks {
+    let sb = Arc::new(ResolvedSandbox::new(sandbox));
+    let (read_sb, exists_sb, is_file_sb, dir_sb) = (Arc::clone(&sb), Arc::clone(&sb), Arc::clone(&sb), Arc::clone(&sb));
+    let (bytes_sb, walk_sb, meta_sb) = (Arc::clone(&sb), Arc::clone(&sb), Arc::clone(&sb));
+    let write_sb = (!sb.write_roots.is_empty()).then_some(sb);
     HostCallbacks {
-        file_read_text: Some(Arc::new(|path| {
-            let meta = std::fs::metadata(path).map_err(|_| FsError {
-                code: 1,
-                message: format!("file: File '{path}' doesn't exist"),
-                line: -1,
-                column: -1,
-            })?;
-            if meta.len() > 1_000_000 {
-                return Err(FsError { code: 1, message: format!("file: File '{path}' is too big"), line: -1, column: -1 });
+        file_read_text: Some(Arc::new(move |path| {
+            let resolved = read_sb.check("file", path)?;
+            let resolved = resolved.ok_or_else(|| FsError::new(ErrorKind::HostIo, format!("file: File '{path}' doesn't exist")))?;
+            let meta = std::fs::metadata(&resolved).map_err(|_| FsError::new(ErrorKind::HostIo, format!("file: File '{path}' doesn't exist")))?;
+            if meta.len() > read_sb.max_file_bytes {
+                return Err(FsError::new(ErrorKind::OutOfRange, format!("file: File '{path}' is too big")));
             }
-            std::fs::read_to_string(path).map_err(|e| FsError { code: 1, message: format!("file: Error reading '{path}': {e}"), line: -1, column: -1 })
+            std::fs::read_to_string(&resolved).map_err(|e| FsError::new(ErrorKind::HostIo, format!("file: Error reading '{path}': {e}")))
         })),
-        file_exists: Some(Arc::new(|path| Ok(std::path::Path::new(path).exists()))),
-        is_file: Some(Arc::new(|path| Ok(std::path::Path::new(path).is_file()))),
-        dir_list: Some(Arc::new(|path| {
-            let p = std::path::Path::new(path);
-            if !p.is_dir() {
-                return Err(FsError { code: 1, message: format!("dirlist: Directory '{path}' does not exist"), line: -1, column: -1 });
-            }
+        file_exists: Some(Arc::new(move |path| Ok(exists_sb.check("fileexists", path)?.is_some()))),
+        is_file: Some(Arc::new(move |path| Ok(is_file_sb.check("isfile", path)?.is_some_and(|p| p.is_file())))),
+        dir_list: Some(Arc::new(move |path| {
+            let resolved = match dir_sb.check("dirlist", path)? {
+                Some(p) if p.is_dir() => p,
+                _ => return Err(FsError::new(ErrorKind::HostIo, format!("dirlist: Directory '{path}' does not exist"))),
+            };
             let mut out: Vec<String> = Vec::new();
-            for ent in std::fs::read_dir(p).map_err(|e| FsError { code: 1, message: format!("dirlist: Error retrieving files from '{path}': {e}"), line: -1, column: -1 })? {
+            for ent in std::fs::read_dir(&resolved).map_err(|e| FsError::new(ErrorKind::HostIo, format!("dirlist: Error retrieving files from '{path}': {e}")))? {
                 if let Ok(e) = ent {
-                    if let Ok(s) = e.path().into_os_string().into_string() {
+                    if let Ok(s) = Path::new(path).join(e.file_name()).into_os_string().into_string() {
                         out.push(s);
                     }
                 }
@@ -112,6 +409,466 @@ pub fn std_fs_callbacks() -> HostCallbacks {
             Ok(out)
         })),
         log_line: None,
+        now: None,
+        random: None,
+        env_var: None,
+        fetch: None,
+        file_read_bytes: Some(Arc::new(move |path| {
+            let resolved = bytes_sb.check("fileBytes", path)?;
+            let resolved = resolved.ok_or_else(|| FsError::new(ErrorKind::HostIo, format!("fileBytes: File '{path}' doesn't exist")))?;
+            let meta = std::fs::metadata(&resolved).map_err(|_| FsError::new(ErrorKind::HostIo, format!("fileBytes: File '{path}' doesn't exist")))?;
+            if meta.len() > bytes_sb.max_file_bytes {
+                return Err(FsError::new(ErrorKind::OutOfRange, format!("fileBytes: File '{path}' is too big")));
+            }
+            std::fs::read(&resolved).map_err(|e| FsError::new(ErrorKind::HostIo, format!("fileBytes: Error reading '{path}': {e}")))
+        })),
+        dir_walk: Some(Arc::new(move |path| {
+            let resolved = match walk_sb.check("walk", path)? {
+                Some(p) if p.is_dir() => p,
+                _ => return Err(FsError::new(ErrorKind::HostIo, format!("walk: Directory '{path}' does not exist"))),
+            };
+            let mut out = Vec::new();
+            walk_dir(&resolved, "", &mut out).map_err(|e| FsError::new(ErrorKind::HostIo, format!("walk: Error retrieving files from '{path}': {e}")))?;
+            out.sort();
+            Ok(out)
+        })),
+        file_metadata: Some(Arc::new(move |path| {
+            let resolved = match meta_sb.check("metadata", path)? {
+                Some(p) => p,
+                None => return Ok(None),
+            };
+            let meta = std::fs::metadata(&resolved).map_err(|e| FsError::new(ErrorKind::HostIo, format!("metadata: Error reading '{path}': {e}")))?;
+            let modified_ticks = meta
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+                .map_or(UNIX_EPOCH_TICKS, |d| UNIX_EPOCH_TICKS + (d.as_nanos() / 100) as i64);
+            Ok(Some(FileMetadata { size: meta.len(), modified_ticks, is_dir: meta.is_dir() }))
+        })),
+        file_write: write_sb.map(|write_sb| {
+            Arc::new(move |path: &str, bytes: &[u8]| {
+                let target = write_sb.check_write(path)?;
+                if let Some(parent) = target.parent() {
+                    std::fs::create_dir_all(parent).map_err(|e| FsError::new(ErrorKind::HostIo, format!("writefile: Error creating the directory of '{path}': {e}")))?;
+                }
+                std::fs::write(&target, bytes).map_err(|e| FsError::new(ErrorKind::HostIo, format!("writefile: Error writing '{path}': {e}")))
+            }) as Arc<WriteFn>
+        }),
     }
 }
 
+/// Appends the entries below `dir` to `out`, relative to the walk root. Symlinks are listed
+/// but never followed, so a walk cannot leave the directory it started in.
+fn walk_dir(dir: &Path, prefix: &str, out: &mut Vec<String>) -> std::io::Result<()> {
+    for ent in std::fs::read_dir(dir)? {
+        let ent = ent?;
+        let name = match ent.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        let rel = if prefix.is_empty() { name } else { format!("{prefix}/{name}") };
+        let is_dir = ent.file_type()?.is_dir();
+        out.push(rel.clone());
+        if is_dir {
+            walk_dir(&ent.path(), &rel, out)?;
+        }
+    }
+    Ok(())
+}
+
+/// Filesystem callbacks with unrestricted read access; prefer `sandboxed_fs_callbacks` for
+/// scripts that are not trusted.
+pub fn std_fs_callbacks() -> HostCallbacks {
+    sandboxed_fs_callbacks(&FsSandbox::unrestricted())
+}
+
+/// `env_var` callback serving a fixed set of variables.
+pub fn env_from_map(vars: HashMap<String, String>) -> Arc<EnvVarFn> {
+    Arc::new(move |name| vars.get(name).cloned())
+}
+
+/// `env_var` callback reading the process environment, limited to the names in `allowed`.
+/// A trailing `*` allows every name with that prefix (`APP_*`).
+pub fn process_env(allowed: Vec<String>) -> Arc<EnvVarFn> {
+    Arc::new(move |name| {
+        let permitted = allowed.iter().any(|a| match a.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => a == name,
+        });
+        if permitted { std::env::var(name).ok() } else { None }
+    })
+}
+
+/// Host callback invocations captured by `recording_callbacks`, in call order. Saved with
+/// `to_json` and loaded again with `from_json`, a recording lets `replay_callbacks` re-run an
+/// evaluation without the files, environment or network it originally saw.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HostRecording {
+    calls: Vec<RecordedCall>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct RecordedCall {
+    callback: String,
+    args: Vec<Json>,
+    result: Result<Json, FsError>,
+}
+
+impl HostRecording {
+    pub fn len(&self) -> usize {
+        self.calls.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    /// Names of the recorded callbacks in call order (`file_read_text`, `now`, ...).
+    pub fn callbacks(&self) -> impl Iterator<Item = &str> {
+        self.calls.iter().map(|c| c.callback.as_str())
+    }
+
+    /// `{"version":1,"calls":[...]}` with one call per line. Bytes are base64 text, and failed
+    /// calls have an `error` with the error code and message instead of a `result`.
+    pub fn to_json(&self) -> String {
+        let calls: Vec<String> = self
+            .calls
+            .iter()
+            .map(|c| {
+                let mut members = vec![
+                    ("callback".to_string(), Json::String(c.callback.clone())),
+                    ("args".to_string(), Json::Array(c.args.clone())),
+                ];
+                match &c.result {
+                    Ok(v) => members.push(("result".to_string(), v.clone())),
+                    Err(e) => members.push((
+                        "error".to_string(),
+                        Json::Object(vec![
+                            ("code".to_string(), Json::Int(i64::from(e.code()))),
+                            ("kind".to_string(), Json::String(e.kind.name().to_string())),
+                            ("message".to_string(), Json::String(e.message.clone())),
+                        ]),
+                    )),
+                }
+                Json::Object(members).to_string()
+            })
+            .collect();
+        format!("{{\"version\":1,\"calls\":[\n{}\n]}}\n", calls.join(",\n"))
+    }
+
+    pub fn from_json(text: &str) -> Result<HostRecording, FsError> {
+        let invalid = |what: &str| FsError::new(ErrorKind::InvalidValue, format!("replay: invalid recording: {what}"));
+        let doc = Json::parse(text).map_err(|e| invalid(&e))?;
+        if doc.get("version").and_then(Json::as_i64) != Some(1) {
+            return Err(invalid("unsupported version"));
+        }
+        let mut calls = Vec::new();
+        for call in doc.get("calls").and_then(Json::as_array).ok_or_else(|| invalid("missing calls"))? {
+            let callback = call.get("callback").and_then(Json::as_str).ok_or_else(|| invalid("call without a callback"))?;
+            let args = call.get("args").and_then(Json::as_array).ok_or_else(|| invalid("call without args"))?;
+            let result = match (call.get("result"), call.get("error")) {
+                (_, Some(e)) => {
+                    let kind = e.get("code").and_then(Json::as_i64).and_then(|c| u32::try_from(c).ok()).and_then(ErrorKind::from_code);
+                    let message = e.get("message").and_then(Json::as_str).unwrap_or_default();
+                    Err(FsError::new(kind.unwrap_or(ErrorKind::HostIo), message))
+                }
+                (Some(v), None) => Ok(v.clone()),
+                (None, None) => return Err(invalid("call without a result")),
+            };
+            calls.push(RecordedCall { callback: callback.to_string(), args: args.to_vec(), result });
+        }
+        Ok(HostRecording { calls })
+    }
+}
+
+fn json_str(s: &str) -> Json {
+    Json::String(s.to_string())
+}
+
+fn json_bytes(bytes: &[u8]) -> Json {
+    Json::String(general_purpose::STANDARD.encode(bytes))
+}
+
+fn json_strings(items: &[String]) -> Json {
+    Json::Array(items.iter().map(|s| json_str(s)).collect())
+}
+
+fn json_headers(headers: &[(String, String)]) -> Json {
+    Json::Array(headers.iter().map(|(n, v)| Json::Array(vec![json_str(n), json_str(v)])).collect())
+}
+
+fn json_time(t: &HostTime) -> Json {
+    Json::Object(vec![
+        ("utcTicks".to_string(), Json::Int(t.utc_ticks)),
+        ("offsetMinutes".to_string(), Json::Int(i64::from(t.offset_minutes))),
+    ])
+}
+
+fn json_metadata(m: &Option<FileMetadata>) -> Json {
+    match m {
+        Some(m) => Json::Object(vec![
+            ("size".to_string(), Json::Int(i64::try_from(m.size).unwrap_or(i64::MAX))),
+            ("modifiedTicks".to_string(), Json::Int(m.modified_ticks)),
+            ("isDir".to_string(), Json::Bool(m.is_dir)),
+        ]),
+        None => Json::Null,
+    }
+}
+
+fn json_request(r: &HttpRequest) -> Json {
+    Json::Object(vec![
+        ("method".to_string(), json_str(&r.method)),
+        ("url".to_string(), json_str(&r.url)),
+        ("headers".to_string(), json_headers(&r.headers)),
+        ("body".to_string(), json_bytes(&r.body)),
+    ])
+}
+
+fn json_response(r: &HttpResponse) -> Json {
+    Json::Object(vec![
+        ("status".to_string(), Json::Int(i64::from(r.status))),
+        ("headers".to_string(), json_headers(&r.headers)),
+        ("body".to_string(), json_bytes(&r.body)),
+    ])
+}
+
+/// Appends calls to a shared `HostRecording`.
+#[derive(Clone)]
+struct Recorder(Arc<Mutex<HostRecording>>);
+
+impl Recorder {
+    fn push<T>(&self, callback: &str, args: Vec<Json>, result: &Result<T, FsError>, encode: impl Fn(&T) -> Json) {
+        let result = result.as_ref().map(encode).map_err(Clone::clone);
+        self.0.lock().unwrap().calls.push(RecordedCall { callback: callback.to_string(), args, result });
+    }
+}
+
+/// Wraps `inner` so that every callback invocation is appended to `recording`. The clock and
+/// the random source are always recorded, also when `inner` leaves them to the defaults.
+pub fn recording_callbacks(inner: HostCallbacks, recording: Arc<Mutex<HostRecording>>) -> HostCallbacks {
+    let rec = Recorder(recording);
+    let HostCallbacks {
+        file_read_text,
+        file_exists,
+        is_file,
+        dir_list,
+        log_line,
+        now,
+        random,
+        file_read_bytes,
+        dir_walk,
+        file_metadata,
+        file_write,
+        env_var,
+        fetch,
+    } = inner;
+    let (r1, r2, r3, r4, r5, r6, r7) = (rec.clone(), rec.clone(), rec.clone(), rec.clone(), rec.clone(), rec.clone(), rec.clone());
+    let (r8, r9, r10, r11, r12) = (rec.clone(), rec.clone(), rec.clone(), rec.clone(), rec.clone());
+    HostCallbacks {
+        file_read_text: file_read_text.map(|cb| {
+            Arc::new(move |path: &str| {
+                let r = cb(path);
+                r1.push("file_read_text", vec![json_str(path)], &r, |s| json_str(s));
+                r
+            }) as Arc<ReadTextFn>
+        }),
+        file_exists: file_exists.map(|cb| {
+            Arc::new(move |path: &str| {
+                let r = cb(path);
+                r2.push("file_exists", vec![json_str(path)], &r, |b| Json::Bool(*b));
+                r
+            }) as Arc<PathCheckFn>
+        }),
+        is_file: is_file.map(|cb| {
+            Arc::new(move |path: &str| {
+                let r = cb(path);
+                r3.push("is_file", vec![json_str(path)], &r, |b| Json::Bool(*b));
+                r
+            }) as Arc<PathCheckFn>
+        }),
+        dir_list: dir_list.map(|cb| {
+            Arc::new(move |path: &str| {
+                let r = cb(path);
+                r4.push("dir_list", vec![json_str(path)], &r, |v| json_strings(v));
+                r
+            }) as Arc<DirListFn>
+        }),
+        log_line: log_line.map(|cb| {
+            Arc::new(move |text: &str| {
+                cb(text);
+                r5.push("log_line", vec![json_str(text)], &Ok(()), |_| Json::Null);
+            }) as Arc<LogLineFn>
+        }),
+        now: Some(Arc::new(move || {
+            let t = now.as_ref().map_or_else(system_now, |cb| cb());
+            r6.push("now", Vec::new(), &Ok(t), json_time);
+            t
+        })),
+        random: Some(Arc::new(move || {
+            let n = random.as_ref().map_or_else(ambient_random, |cb| cb());
+            // As text, since JSON readers commonly lose precision above 2^53.
+            r7.push("random", Vec::new(), &Ok(n), |n| Json::String(n.to_string()));
+            n
+        })),
+        file_read_bytes: file_read_bytes.map(|cb| {
+            Arc::new(move |path: &str| {
+                let r = cb(path);
+                r8.push("file_read_bytes", vec![json_str(path)], &r, |b| json_bytes(b));
+                r
+            }) as Arc<ReadBytesFn>
+        }),
+        dir_walk: dir_walk.map(|cb| {
+            Arc::new(move |path: &str| {
+                let r = cb(path);
+                r9.push("dir_walk", vec![json_str(path)], &r, |v| json_strings(v));
+                r
+            }) as Arc<DirWalkFn>
+        }),
+        file_metadata: file_metadata.map(|cb| {
+            Arc::new(move |path: &str| {
+                let r = cb(path);
+                r10.push("file_metadata", vec![json_str(path)], &r, json_metadata);
+                r
+            }) as Arc<MetadataFn>
+        }),
+        file_write: file_write.map(|cb| {
+            Arc::new(move |path: &str, bytes: &[u8]| {
+                let r = cb(path, bytes);
+                r11.push("file_write", vec![json_str(path), json_bytes(bytes)], &r, |_| Json::Null);
+                r
+            }) as Arc<WriteFn>
+        }),
+        env_var: env_var.map(|cb| {
+            Arc::new(move |name: &str| {
+                let v = cb(name);
+                r12.push("env_var", vec![json_str(name)], &Ok(v.clone()), |v| v.as_deref().map_or(Json::Null, json_str));
+                v
+            }) as Arc<EnvVarFn>
+        }),
+        fetch: fetch.map(|cb| {
+            Arc::new(move |request: &HttpRequest| {
+                let r = cb(request);
+                rec.push("fetch", vec![json_request(request)], &r, json_response);
+                r
+            }) as Arc<FetchFn>
+        }),
+    }
+}
+
+/// Recorded results queued per call, keyed like `file_read_text("a.txt")`.
+struct Replayer {
+    queues: Mutex<HashMap<String, VecDeque<Result<Json, FsError>>>>,
+}
+
+fn call_key(callback: &str, args: Vec<Json>) -> String {
+    let args = Json::Array(args).to_string();
+    format!("{callback}({})", &args[1..args.len() - 1])
+}
+
+impl Replayer {
+    fn new(recording: &HostRecording) -> Self {
+        let mut queues: HashMap<String, VecDeque<Result<Json, FsError>>> = HashMap::new();
+        for call in &recording.calls {
+            queues.entry(call_key(&call.callback, call.args.clone())).or_default().push_back(call.result.clone());
+        }
+        Replayer { queues: Mutex::new(queues) }
+    }
+
+    /// The next recorded result of `callback(args)`. Clock and random reads past the end of
+    /// the recording repeat the last value.
+    fn take(&self, callback: &str, args: Vec<Json>) -> Result<Json, FsError> {
+        let key = call_key(callback, args);
+        let missing = || FsError::new(ErrorKind::HostUnavailable, format!("replay: no recorded call {key}"));
+        let mut queues = self.queues.lock().unwrap();
+        let queue = queues.get_mut(&key).ok_or_else(missing)?;
+        if queue.len() == 1 && matches!(callback, "now" | "random") {
+            return queue[0].clone();
+        }
+        queue.pop_front().ok_or_else(missing)?
+    }
+
+    fn decode<T>(&self, callback: &str, args: Vec<Json>, decode: impl Fn(&Json) -> Option<T>) -> Result<T, FsError> {
+        let v = self.take(callback, args)?;
+        decode(&v).ok_or_else(|| FsError::new(ErrorKind::InvalidValue, format!("replay: malformed result for {callback}")))
+    }
+}
+
+fn decode_strings(v: &Json) -> Option<Vec<String>> {
+    v.as_array()?.iter().map(|s| s.as_str().map(str::to_string)).collect()
+}
+
+fn decode_bytes(v: &Json) -> Option<Vec<u8>> {
+    general_purpose::STANDARD.decode(v.as_str()?).ok()
+}
+
+fn decode_headers(v: &Json) -> Option<Vec<(String, String)>> {
+    v.as_array()?
+        .iter()
+        .map(|h| match h.as_array()? {
+            [n, v] => Some((n.as_str()?.to_string(), v.as_str()?.to_string())),
+            _ => None,
+        })
+        .collect()
+}
+
+fn decode_time(v: &Json) -> Option<HostTime> {
+    let offset_minutes = i32::try_from(v.get("offsetMinutes")?.as_i64()?).ok()?;
+    Some(HostTime { utc_ticks: v.get("utcTicks")?.as_i64()?, offset_minutes })
+}
+
+fn decode_metadata(v: &Json) -> Option<Option<FileMetadata>> {
+    if *v == Json::Null {
+        return Some(None);
+    }
+    Some(Some(FileMetadata {
+        size: u64::try_from(v.get("size")?.as_i64()?).ok()?,
+        modified_ticks: v.get("modifiedTicks")?.as_i64()?,
+        is_dir: v.get("isDir")?.as_bool()?,
+    }))
+}
+
+fn decode_response(v: &Json) -> Option<HttpResponse> {
+    Some(HttpResponse {
+        status: u16::try_from(v.get("status")?.as_i64()?).ok()?,
+        headers: decode_headers(v.get("headers")?)?,
+        body: decode_bytes(v.get("body")?)?,
+    })
+}
+
+fn replayed<T>(result: Result<T, FsError>) -> T {
+    result.unwrap_or_else(|e| panic!("replay exhausted: {}", e.message))
+}
+
+/// Callbacks that answer from `recording` instead of a real host: nothing is read, written or
+/// fetched. Calls that were not recorded fail with `host_unavailable`; `now` and `random`
+/// cannot fail, so they panic when the recording has no usable value for them. `log_line` is
+/// left unset so the host can keep printing log output.
+pub fn replay_callbacks(recording: &HostRecording) -> HostCallbacks {
+    let rp = Arc::new(Replayer::new(recording));
+    let (p1, p2, p3, p4, p5, p6) = (Arc::clone(&rp), Arc::clone(&rp), Arc::clone(&rp), Arc::clone(&rp), Arc::clone(&rp), Arc::clone(&rp));
+    let (p7, p8, p9, p10, p11) = (Arc::clone(&rp), Arc::clone(&rp), Arc::clone(&rp), Arc::clone(&rp), Arc::clone(&rp));
+    HostCallbacks {
+        file_read_text: Some(Arc::new(move |path| p1.decode("file_read_text", vec![json_str(path)], |v| v.as_str().map(str::to_string)))),
+        file_exists: Some(Arc::new(move |path| p2.decode("file_exists", vec![json_str(path)], Json::as_bool))),
+        is_file: Some(Arc::new(move |path| p3.decode("is_file", vec![json_str(path)], Json::as_bool))),
+        dir_list: Some(Arc::new(move |path| p4.decode("dir_list", vec![json_str(path)], decode_strings))),
+        log_line: None,
+        now: Some(Arc::new(move || replayed(p5.decode("now", Vec::new(), decode_time)))),
+        random: Some(Arc::new(move || replayed(p6.decode("random", Vec::new(), |v| v.as_str()?.parse::<u64>().ok())))),
+        file_read_bytes: Some(Arc::new(move |path| p7.decode("file_read_bytes", vec![json_str(path)], decode_bytes))),
+        dir_walk: Some(Arc::new(move |path| p8.decode("dir_walk", vec![json_str(path)], decode_strings))),
+        file_metadata: Some(Arc::new(move |path| p9.decode("file_metadata", vec![json_str(path)], decode_metadata))),
+        file_write: Some(Arc::new(move |path, bytes| {
+            p10.decode("file_write", vec![json_str(path), json_bytes(bytes)], |v| (*v == Json::Null).then_some(()))
+        })),
+        env_var: Some(Arc::new(move |name| {
+            p11.decode("env_var", vec![json_str(name)], |v| match v {
+                Json::Null => Some(None),
+                v => v.as_str().map(|s| Some(s.to_string())),
+            })
+            .ok()
+            .flatten()
+        })),
+        fetch: Some(Arc::new(move |request| rp.decode("fetch", vec![json_request(request)], decode_response))),
+    }
+}
@@ -2,11 +2,17 @@
 
 pub mod chunk;
 pub mod compiler;
+pub mod datetime;
 pub mod ffi;
+pub mod format;
+pub mod gc;
 pub mod host;
+pub mod json;
 pub mod native;
 pub mod obj;
+pub mod parallel;
 pub mod scanner;
+pub mod small_string;
 pub mod value;
 pub mod vm;
 pub mod wasm;
@@ -2,60 +2,271 @@
 //!
 //! - `fs 'code'` evaluates one expression and exits
 //! - `fs` starts an interactive REPL
+//! - `--parallel <n>` / `-j <n>` runs side-effect-free `map`/`filter`/`Sum` on `n` workers
+//! - `--seed <n>` makes `Random()` and `guid()` reproducible
+//! - `--allow-read <dir>` lets scripts read below `dir` (repeatable); scripts cannot read
+//!   any file without it
+//! - `--allow-write <dir>` lets `writefile` create files below `dir` (repeatable); scripts
+//!   cannot write anywhere without it
+//! - `--no-fs` disables the file built-ins entirely
+//! - `--allow-env <NAME,...>` exposes these environment variables to `env(...)` (repeatable;
+//!   `APP_*` allows a prefix); no variables are visible without it
+//! - `--allow-net` lets `fetch(...)` make plain `http://` requests; scripts have no network
+//!   access without it
+//! - `--record <file>` saves every host callback call (files, environment, clock, network,
+//!   logs) to `file` as JSON; `--replay <file>` answers them from such a recording instead of
+//!   touching the host
 
 use funcscript::host;
+use funcscript::parallel::ParallelConfig;
 use funcscript::scanner::{Scanner, TokenType};
+use funcscript::value::{ErrorKind, FsError};
 use funcscript::vm::{InterpretResult, VM};
-use std::io::{self, IsTerminal, Read, Write};
-use std::sync::Arc;
+use std::io::{self, BufRead, BufReader, IsTerminal, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
 
 fn main() {
+    let mut args = std::env::args().skip(1).collect::<Vec<_>>();
+    let mut vm = VM::new();
+    vm.set_auto_collect(true);
+    let mut read_roots: Vec<String> = Vec::new();
+    let mut write_roots: Vec<String> = Vec::new();
+    let mut env_names: Vec<String> = Vec::new();
+    let mut no_fs = false;
+    let mut allow_net = false;
+    let mut record_path: Option<String> = None;
+    let mut replay_path: Option<String> = None;
 
-    let mut callbacks = host::std_fs_callbacks();
-    callbacks.log_line = Some(Arc::new(|line| eprintln!("{line}")));
-    let _guard = host::push(callbacks);
+    // Leading options; everything after them is the expression / mode flag.
+    while let Some(opt) = args.first().cloned() {
+        match opt.as_str() {
+            "--parallel" | "-j" => {
+                let workers = match args.get(1).map(|n| n.parse::<usize>()) {
+                    Some(Ok(n)) => n,
+                    _ => {
+                        eprintln!("{opt} expects a worker count");
+                        std::process::exit(2);
+                    }
+                };
+                vm.set_parallel(if workers > 1 { Some(ParallelConfig::new(workers)) } else { None });
+                args.drain(..2);
+            }
+            "--seed" => {
+                match args.get(1).map(|n| n.parse::<u64>()) {
+                    Some(Ok(seed)) => vm.set_random_seed(Some(seed)),
+                    _ => {
+                        eprintln!("{opt} expects an unsigned integer");
+                        std::process::exit(2);
+                    }
+                }
+                args.drain(..2);
+            }
+            "--allow-read" => {
+                match args.get(1) {
+                    Some(dir) => read_roots.push(dir.clone()),
+                    None => {
+                        eprintln!("{opt} expects a directory");
+                        std::process::exit(2);
+                    }
+                }
+                args.drain(..2);
+            }
+            "--allow-write" => {
+                match args.get(1) {
+                    Some(dir) => write_roots.push(dir.clone()),
+                    None => {
+                        eprintln!("{opt} expects a directory");
+                        std::process::exit(2);
+                    }
+                }
+                args.drain(..2);
+            }
+            "--allow-env" => {
+                match args.get(1) {
+                    Some(names) => env_names.extend(names.split(',').filter(|n| !n.is_empty()).map(str::to_string)),
+                    None => {
+                        eprintln!("{opt} expects a comma-separated list of variable names");
+                        std::process::exit(2);
+                    }
+                }
+                args.drain(..2);
+            }
+            "--no-fs" => {
+                no_fs = true;
+                args.drain(..1);
+            }
+            "--allow-net" => {
+                allow_net = true;
+                args.drain(..1);
+            }
+            "--record" | "--replay" => {
+                match args.get(1) {
+                    Some(file) if opt == "--record" => record_path = Some(file.clone()),
+                    Some(file) => replay_path = Some(file.clone()),
+                    None => {
+                        eprintln!("{opt} expects a file");
+                        std::process::exit(2);
+                    }
+                }
+                args.drain(..2);
+            }
+            _ => break,
+        }
+    }
 
-    let args = std::env::args().skip(1).collect::<Vec<_>>();
-    let mut vm = VM::new();
+    let mut callbacks = if no_fs {
+        host::HostCallbacks::default()
+    } else {
+        let sandbox = read_roots.iter().fold(host::FsSandbox::new(), |sb, dir| sb.allow_read(dir));
+        let sandbox = write_roots.iter().fold(sandbox, |sb, dir| sb.allow_write(dir));
+        host::sandboxed_fs_callbacks(&sandbox)
+    };
+    callbacks.log_line = Some(Arc::new(|line| eprintln!("{line}")));
+    callbacks.env_var = Some(host::process_env(env_names));
+    if allow_net {
+        callbacks.fetch = Some(Arc::new(http_fetch));
+    }
+    if record_path.is_some() && replay_path.is_some() {
+        eprintln!("--record and --replay cannot be combined");
+        std::process::exit(2);
+    }
+    let recording = record_path.as_ref().map(|_| Arc::new(Mutex::new(host::HostRecording::default())));
+    if let Some(recording) = &recording {
+        callbacks = host::recording_callbacks(callbacks, Arc::clone(recording));
+    }
+    if let Some(path) = &replay_path {
+        let recorded = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read {path}: {e}"))
+            .and_then(|text| host::HostRecording::from_json(&text).map_err(|e| e.message));
+        match recorded {
+            Ok(recorded) => {
+                callbacks = host::replay_callbacks(&recorded);
+                callbacks.log_line = Some(Arc::new(|line| eprintln!("{line}")));
+            }
+            Err(message) => {
+                eprintln!("{message}");
+                std::process::exit(2);
+            }
+        }
+    }
+    vm.set_host(callbacks);
 
     if args.len() == 1 && (args[0] == "--repl" || args[0] == "-i") {
         repl(&mut vm);
-        return;
-    }
-
-    if args.len() >= 2 && (args[0] == "--eval" || args[0] == "-e") {
+    } else if args.len() >= 2 && (args[0] == "--eval" || args[0] == "-e") {
         let source = args[1..].join(" ");
         run_once(&mut vm, &source);
-        return;
-    }
-
-    if !args.is_empty() {
+    } else if !args.is_empty() {
         let source = args.join(" ");
         run_once(&mut vm, &source);
-        return;
-    }
-
-    if !io::stdin().is_terminal() {
+    } else if !io::stdin().is_terminal() {
         let mut buf = String::new();
         if io::stdin().read_to_string(&mut buf).is_ok() && !buf.trim().is_empty() {
             run_once(&mut vm, &buf);
         }
-        return;
+    } else {
+        repl(&mut vm);
     }
 
-    repl(&mut vm);
+    if let (Some(path), Some(recording)) = (&record_path, &recording) {
+        if let Err(e) = std::fs::write(path, recording.lock().unwrap().to_json()) {
+            eprintln!("cannot write {path}: {e}");
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Minimal HTTP/1.1 client for `fetch(...)`: one request per connection, no TLS and no
+/// redirects.
+fn http_fetch(request: &host::HttpRequest) -> Result<host::HttpResponse, FsError> {
+    let io_err = |e: io::Error| FsError::new(ErrorKind::HostIo, format!("fetch: {} failed: {e}", request.url));
+    let rest = match request.url.strip_prefix("http://") {
+        Some(rest) => rest,
+        None if request.url.starts_with("https://") => {
+            return Err(FsError::new(ErrorKind::HostUnavailable, "fetch: https is not supported by the CLI"));
+        }
+        None => return Err(FsError::new(ErrorKind::InvalidValue, format!("fetch: '{}' is not an http URL", request.url))),
+    };
+    let (authority, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let address = if authority.contains(':') { authority.to_string() } else { format!("{authority}:80") };
+    let timeout = std::time::Duration::from_secs(30);
+    let socket = address
+        .to_socket_addrs()
+        .map_err(io_err)?
+        .next()
+        .ok_or_else(|| FsError::new(ErrorKind::HostIo, format!("fetch: cannot resolve '{authority}'")))?;
+    let mut stream = TcpStream::connect_timeout(&socket, timeout).map_err(io_err)?;
+    stream.set_read_timeout(Some(timeout)).map_err(io_err)?;
+
+    let mut head = format!("{} {path} HTTP/1.1\r\nHost: {authority}\r\nConnection: close\r\n", request.method);
+    for (name, value) in &request.headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    if !request.body.is_empty() || request.method != "GET" {
+        head.push_str(&format!("Content-Length: {}\r\n", request.body.len()));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes()).map_err(io_err)?;
+    stream.write_all(&request.body).map_err(io_err)?;
+
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).map_err(io_err)?;
+    let status = line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|s| s.parse::<u16>().ok())
+        .ok_or_else(|| FsError::new(ErrorKind::HostIo, format!("fetch: invalid response from {}", request.url)))?;
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line).map_err(io_err)?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    let chunked = headers.iter().any(|(n, v)| n.eq_ignore_ascii_case("transfer-encoding") && v.eq_ignore_ascii_case("chunked"));
+    let mut body = Vec::new();
+    if chunked {
+        loop {
+            line.clear();
+            reader.read_line(&mut line).map_err(io_err)?;
+            let size_text = line.trim().split(';').next().unwrap_or_default();
+            let size = usize::from_str_radix(size_text, 16)
+                .map_err(|_| FsError::new(ErrorKind::HostIo, format!("fetch: invalid chunked response from {}", request.url)))?;
+            if size == 0 {
+                break;
+            }
+            let start = body.len();
+            body.resize(start + size, 0);
+            reader.read_exact(&mut body[start..]).map_err(io_err)?;
+            line.clear();
+            reader.read_line(&mut line).map_err(io_err)?;
+        }
+    } else {
+        reader.read_to_end(&mut body).map_err(io_err)?;
+    }
+    Ok(host::HttpResponse { status, headers, body })
 }
 
 fn run_once(vm: &mut VM, source: &str) {
     match vm.interpret(source) {
         Ok(value) => println!("Result: {}", value),
         Err(InterpretResult::CompileError(e)) => eprintln!(
-            "CompileError[{}] (line {}, col {}): {}",
-            e.code, e.line, e.column, e.message
+            "CompileError[{} {}] (line {}, col {}): {}",
+            e.code(), e.kind, e.line, e.column, e.message
         ),
         Err(InterpretResult::RuntimeError(e)) => eprintln!(
-            "RuntimeError[{}] (line {}, col {}): {}",
-            e.code, e.line, e.column, e.message
+            "RuntimeError[{} {}] (line {}, col {}): {}",
+            e.code(), e.kind, e.line, e.column, e.message
         ),
     }
 }
@@ -115,12 +326,12 @@ fn repl(vm: &mut VM) {
         match vm.interpret(src) {
             Ok(value) => println!("=> {}", value),
             Err(InterpretResult::CompileError(e)) => eprintln!(
-                "CompileError[{}] (line {}, col {}): {}",
-                e.code, e.line, e.column, e.message
+                "CompileError[{} {}] (line {}, col {}): {}",
+                e.code(), e.kind, e.line, e.column, e.message
             ),
             Err(InterpretResult::RuntimeError(e)) => eprintln!(
-                "RuntimeError[{}] (line {}, col {}): {}",
-                e.code, e.line, e.column, e.message
+                "RuntimeError[{} {}] (line {}, col {}): {}",
+                e.code(), e.kind, e.line, e.column, e.message
             ),
         }
 
@@ -3,63 +3,123 @@
 //! These are registered in the VM global scope (e.g. `Range`, `Len`, `First`, `And`/`Or`/`In`).
 
 use crate::value::Value;
-use crate::value::FsError;
-use std::rc::Rc;
+use crate::value::{ErrorKind, FsError};
+use std::sync::Arc;
 use crate::obj::Obj;
+use crate::small_string::SmallString;
 use num_bigint::BigInt;
 use num_traits::{Signed, ToPrimitive, Zero};
 use base64::{engine::general_purpose, Engine as _};
 use uuid::Uuid;
-use std::cell::RefCell;
+use std::sync::Mutex;
 use std::collections::{HashMap, HashSet};
 use crate::obj::KvcObject;
 use regex::Regex;
-use crate::host;
+use crate::host::{HostContext, HttpRequest};
+use crate::json::Json;
+use crate::vm::VM;
+use crate::datetime::{Period, TICKS_PER_DAY, TICKS_PER_MINUTE, TICKS_PER_SECOND};
 
 pub fn define_natives(globals: &mut std::collections::HashMap<String, Value>) {
     let mut insert = |name: &str, v: Value| {
         globals.insert(name.to_ascii_lowercase(), v);
     };
 
-    insert("Range", Value::Obj(Rc::new(Obj::NativeFn(fs_range))));
+    insert("Range", Value::Obj(Arc::new(Obj::NativeFn(fs_range))));
     insert("true", Value::Bool(true));
     insert("false", Value::Bool(false));
     
-    insert("Abs", Value::Obj(Rc::new(Obj::NativeFn(math_abs))));
-    insert("Max", Value::Obj(Rc::new(Obj::NativeFn(math_max))));
-    insert("Min", Value::Obj(Rc::new(Obj::NativeFn(math_min))));
-    insert("Sqrt", Value::Obj(Rc::new(Obj::NativeFn(math_sqrt))));
-
-    insert("Len", Value::Obj(Rc::new(Obj::NativeFn(fs_len))));
-    insert("First", Value::Obj(Rc::new(Obj::NativeFn(fs_first))));
-
-    insert("And", Value::Obj(Rc::new(Obj::NativeFn(fs_and))));
-    insert("Or", Value::Obj(Rc::new(Obj::NativeFn(fs_or))));
-    insert("In", Value::Obj(Rc::new(Obj::NativeFn(fs_in))));
-
-    insert("TemplateMerge", Value::Obj(Rc::new(Obj::NativeFn(fs_template_merge))));
-
-    insert("Sum", Value::Obj(Rc::new(Obj::NativeFn(fs_sum))));
-    insert("SumApprox", Value::Obj(Rc::new(Obj::NativeFn(fs_sum_approx))));
-
-    insert("Date", Value::Obj(Rc::new(Obj::NativeFn(fs_date))));
-    insert("TicksToDate", Value::Obj(Rc::new(Obj::NativeFn(fs_ticks_to_date))));
-    insert("guid", Value::Obj(Rc::new(Obj::NativeFn(fs_guid))));
-    insert("ChangeType", Value::Obj(Rc::new(Obj::NativeFn(fs_change_type))));
-
-    insert("lower", Value::Obj(Rc::new(Obj::NativeFn(text_lower))));
-    insert("upper", Value::Obj(Rc::new(Obj::NativeFn(text_upper))));
-    insert("endswith", Value::Obj(Rc::new(Obj::NativeFn(text_endswith))));
-    insert("substring", Value::Obj(Rc::new(Obj::NativeFn(text_substring))));
-    insert("find", Value::Obj(Rc::new(Obj::NativeFn(text_find))));
-    insert("isBlank", Value::Obj(Rc::new(Obj::NativeFn(text_is_blank))));
-    insert("join", Value::Obj(Rc::new(Obj::NativeFn(text_join))));
-
-    insert("Take", Value::Obj(Rc::new(Obj::NativeFn(list_take))));
-    insert("Skip", Value::Obj(Rc::new(Obj::NativeFn(list_skip))));
-    insert("Reverse", Value::Obj(Rc::new(Obj::NativeFn(list_reverse))));
-    insert("Distinct", Value::Obj(Rc::new(Obj::NativeFn(list_distinct))));
-    insert("Contains", Value::Obj(Rc::new(Obj::NativeFn(list_contains))));
+    insert("Abs", Value::Obj(Arc::new(Obj::NativeFn(math_abs))));
+    insert("Max", Value::Obj(Arc::new(Obj::NativeFn(math_max))));
+    insert("Min", Value::Obj(Arc::new(Obj::NativeFn(math_min))));
+    insert("Sqrt", Value::Obj(Arc::new(Obj::NativeFn(math_sqrt))));
+
+    insert("Len", Value::Obj(Arc::new(Obj::NativeFn(fs_len))));
+    insert("First", Value::Obj(Arc::new(Obj::NativeFn(fs_first))));
+
+    insert("And", Value::Obj(Arc::new(Obj::NativeFn(fs_and))));
+    insert("Or", Value::Obj(Arc::new(Obj::NativeFn(fs_or))));
+    insert("In", Value::Obj(Arc::new(Obj::NativeFn(fs_in))));
+
+    insert("TemplateMerge", Value::Obj(Arc::new(Obj::NativeFn(fs_template_merge))));
+
+    insert("Sum", Value::Obj(Arc::new(Obj::VmFn(fs_sum))));
+    insert("SumApprox", Value::Obj(Arc::new(Obj::VmFn(fs_sum_approx))));
+    insert("Avg", Value::Obj(Arc::new(Obj::VmFn(stat_avg))));
+    insert("Median", Value::Obj(Arc::new(Obj::VmFn(stat_median))));
+    insert("Percentile", Value::Obj(Arc::new(Obj::VmFn(stat_percentile))));
+    insert("Variance", Value::Obj(Arc::new(Obj::VmFn(stat_variance))));
+    insert("StdDev", Value::Obj(Arc::new(Obj::VmFn(stat_std_dev))));
+    insert("Mode", Value::Obj(Arc::new(Obj::VmFn(stat_mode))));
+    insert("Product", Value::Obj(Arc::new(Obj::VmFn(stat_product))));
+
+    insert("Date", Value::Obj(Arc::new(Obj::NativeFn(fs_date))));
+    insert("TicksToDate", Value::Obj(Arc::new(Obj::NativeFn(fs_ticks_to_date))));
+    insert("addDays", Value::Obj(Arc::new(Obj::NativeFn(date_add_days))));
+    insert("addMonths", Value::Obj(Arc::new(Obj::NativeFn(date_add_months))));
+    insert("addYears", Value::Obj(Arc::new(Obj::NativeFn(date_add_years))));
+    insert("startOf", Value::Obj(Arc::new(Obj::NativeFn(date_start_of))));
+    insert("endOf", Value::Obj(Arc::new(Obj::NativeFn(date_end_of))));
+    insert("toOffset", Value::Obj(Arc::new(Obj::NativeFn(date_to_offset))));
+    insert("utc", Value::Obj(Arc::new(Obj::NativeFn(date_utc))));
+    insert("duration", Value::Obj(Arc::new(Obj::NativeFn(fs_duration))));
+    insert("guid", Value::Obj(Arc::new(Obj::HostFn(fs_guid))));
+    insert("now", Value::Obj(Arc::new(Obj::HostFn(fs_now))));
+    insert("utcnow", Value::Obj(Arc::new(Obj::HostFn(fs_utc_now))));
+    insert("today", Value::Obj(Arc::new(Obj::HostFn(fs_today))));
+    insert("ChangeType", Value::Obj(Arc::new(Obj::NativeFn(fs_change_type))));
+
+    insert("lower", Value::Obj(Arc::new(Obj::NativeFn(text_lower))));
+    insert("upper", Value::Obj(Arc::new(Obj::NativeFn(text_upper))));
+    insert("endswith", Value::Obj(Arc::new(Obj::NativeFn(text_endswith))));
+    insert("substring", Value::Obj(Arc::new(Obj::NativeFn(text_substring))));
+    insert("find", Value::Obj(Arc::new(Obj::NativeFn(text_find))));
+    insert("isBlank", Value::Obj(Arc::new(Obj::NativeFn(text_is_blank))));
+    insert("join", Value::Obj(Arc::new(Obj::NativeFn(text_join))));
+    insert("split", Value::Obj(Arc::new(Obj::NativeFn(text_split))));
+    insert("replace", Value::Obj(Arc::new(Obj::NativeFn(text_replace))));
+    insert("trim", Value::Obj(Arc::new(Obj::NativeFn(text_trim))));
+    insert("trimStart", Value::Obj(Arc::new(Obj::NativeFn(text_trim_start))));
+    insert("trimEnd", Value::Obj(Arc::new(Obj::NativeFn(text_trim_end))));
+    insert("startswith", Value::Obj(Arc::new(Obj::NativeFn(text_startswith))));
+    insert("padLeft", Value::Obj(Arc::new(Obj::NativeFn(text_pad_left))));
+    insert("padRight", Value::Obj(Arc::new(Obj::NativeFn(text_pad_right))));
+    insert("repeat", Value::Obj(Arc::new(Obj::NativeFn(text_repeat))));
+    insert("indexOf", Value::Obj(Arc::new(Obj::NativeFn(fs_index_of))));
+    insert("lastIndexOf", Value::Obj(Arc::new(Obj::NativeFn(text_last_index_of))));
+    insert("chars", Value::Obj(Arc::new(Obj::NativeFn(text_chars))));
+    #[cfg(feature = "unicode")]
+    {
+        insert("graphemes", Value::Obj(Arc::new(Obj::NativeFn(text_graphemes))));
+        insert("normalize", Value::Obj(Arc::new(Obj::NativeFn(text_normalize))));
+    }
+
+    insert("Take", Value::Obj(Arc::new(Obj::NativeFn(list_take))));
+    insert("Skip", Value::Obj(Arc::new(Obj::NativeFn(list_skip))));
+    insert("Reverse", Value::Obj(Arc::new(Obj::NativeFn(list_reverse))));
+    insert("Distinct", Value::Obj(Arc::new(Obj::NativeFn(list_distinct))));
+    insert("Contains", Value::Obj(Arc::new(Obj::NativeFn(list_contains))));
+    insert("last", Value::Obj(Arc::new(Obj::NativeFn(list_last))));
+    insert("zip", Value::Obj(Arc::new(Obj::NativeFn(list_zip))));
+    insert("flatten", Value::Obj(Arc::new(Obj::NativeFn(list_flatten))));
+    insert("chunk", Value::Obj(Arc::new(Obj::NativeFn(list_chunk))));
+    insert("window", Value::Obj(Arc::new(Obj::NativeFn(list_window))));
+    insert("flatMap", Value::Obj(Arc::new(Obj::VmFn(list_flat_map))));
+    insert("groupBy", Value::Obj(Arc::new(Obj::VmFn(list_group_by))));
+    insert("count", Value::Obj(Arc::new(Obj::VmFn(list_count))));
+    insert("sortBy", Value::Obj(Arc::new(Obj::VmFn(list_sort_by))));
+    insert("minBy", Value::Obj(Arc::new(Obj::VmFn(list_min_by))));
+    insert("maxBy", Value::Obj(Arc::new(Obj::VmFn(list_max_by))));
+
+    insert("keys", Value::Obj(Arc::new(Obj::VmFn(kvc_keys))));
+    insert("values", Value::Obj(Arc::new(Obj::VmFn(kvc_values))));
+    insert("entries", Value::Obj(Arc::new(Obj::VmFn(kvc_entries))));
+    insert("fromEntries", Value::Obj(Arc::new(Obj::VmFn(kvc_from_entries))));
+    insert("hasKey", Value::Obj(Arc::new(Obj::NativeFn(kvc_has_key))));
+    insert("pick", Value::Obj(Arc::new(Obj::VmFn(kvc_pick))));
+    insert("omit", Value::Obj(Arc::new(Obj::VmFn(kvc_omit))));
+    insert("getPath", Value::Obj(Arc::new(Obj::VmFn(kvc_get_path))));
+    insert("setPath", Value::Obj(Arc::new(Obj::VmFn(kvc_set_path))));
 
     // Provider collections (C# parity): `math.*`, `text.*`, `float.*`
     insert("math", build_math_provider());
@@ -67,58 +127,78 @@ pub fn define_natives(globals: &mut std::collections::HashMap<String, Value>) {
     insert("float", build_float_provider());
 
     // Common math globals (C# function names)
-    insert("Pow", Value::Obj(Rc::new(Obj::NativeFn(math_pow))));
-    insert("Sin", Value::Obj(Rc::new(Obj::NativeFn(math_sin))));
-    insert("Cos", Value::Obj(Rc::new(Obj::NativeFn(math_cos))));
-    insert("Tan", Value::Obj(Rc::new(Obj::NativeFn(math_tan))));
-    insert("Asin", Value::Obj(Rc::new(Obj::NativeFn(math_asin))));
-    insert("Acos", Value::Obj(Rc::new(Obj::NativeFn(math_acos))));
-    insert("Atan", Value::Obj(Rc::new(Obj::NativeFn(math_atan))));
-    insert("Atan2", Value::Obj(Rc::new(Obj::NativeFn(math_atan2))));
-    insert("Exp", Value::Obj(Rc::new(Obj::NativeFn(math_exp))));
-    insert("Ln", Value::Obj(Rc::new(Obj::NativeFn(math_ln))));
-    insert("Log10", Value::Obj(Rc::new(Obj::NativeFn(math_log10))));
-    insert("Log2", Value::Obj(Rc::new(Obj::NativeFn(math_log2))));
-    insert("Ceiling", Value::Obj(Rc::new(Obj::NativeFn(math_ceil))));
-    insert("Floor", Value::Obj(Rc::new(Obj::NativeFn(math_floor))));
-    insert("Round", Value::Obj(Rc::new(Obj::NativeFn(math_round))));
-    insert("Trunc", Value::Obj(Rc::new(Obj::NativeFn(math_trunc))));
-    insert("Sign", Value::Obj(Rc::new(Obj::NativeFn(math_sign))));
-    insert("Clamp", Value::Obj(Rc::new(Obj::NativeFn(math_clamp))));
-    insert("Random", Value::Obj(Rc::new(Obj::NativeFn(math_random))));
-    insert("Cbrt", Value::Obj(Rc::new(Obj::NativeFn(math_cbrt))));
-    insert("DegToRad", Value::Obj(Rc::new(Obj::NativeFn(math_deg_to_rad))));
-    insert("RadToDeg", Value::Obj(Rc::new(Obj::NativeFn(math_rad_to_deg))));
-
-    insert("IsNaN", Value::Obj(Rc::new(Obj::NativeFn(float_is_nan))));
-    insert("IsInfinity", Value::Obj(Rc::new(Obj::NativeFn(float_is_infinity))));
-    insert("IsNormal", Value::Obj(Rc::new(Obj::NativeFn(float_is_normal))));
+    insert("Pow", Value::Obj(Arc::new(Obj::NativeFn(math_pow))));
+    insert("Sin", Value::Obj(Arc::new(Obj::NativeFn(math_sin))));
+    insert("Cos", Value::Obj(Arc::new(Obj::NativeFn(math_cos))));
+    insert("Tan", Value::Obj(Arc::new(Obj::NativeFn(math_tan))));
+    insert("Asin", Value::Obj(Arc::new(Obj::NativeFn(math_asin))));
+    insert("Acos", Value::Obj(Arc::new(Obj::NativeFn(math_acos))));
+    insert("Atan", Value::Obj(Arc::new(Obj::NativeFn(math_atan))));
+    insert("Atan2", Value::Obj(Arc::new(Obj::NativeFn(math_atan2))));
+    insert("Exp", Value::Obj(Arc::new(Obj::NativeFn(math_exp))));
+    insert("Ln", Value::Obj(Arc::new(Obj::NativeFn(math_ln))));
+    insert("Log10", Value::Obj(Arc::new(Obj::NativeFn(math_log10))));
+    insert("Log2", Value::Obj(Arc::new(Obj::NativeFn(math_log2))));
+    insert("Ceiling", Value::Obj(Arc::new(Obj::NativeFn(math_ceil))));
+    insert("Floor", Value::Obj(Arc::new(Obj::NativeFn(math_floor))));
+    insert("Round", Value::Obj(Arc::new(Obj::NativeFn(math_round))));
+    insert("Trunc", Value::Obj(Arc::new(Obj::NativeFn(math_trunc))));
+    insert("Sign", Value::Obj(Arc::new(Obj::NativeFn(math_sign))));
+    insert("Clamp", Value::Obj(Arc::new(Obj::NativeFn(math_clamp))));
+    insert("Random", Value::Obj(Arc::new(Obj::HostFn(math_random))));
+    insert("Cbrt", Value::Obj(Arc::new(Obj::NativeFn(math_cbrt))));
+    insert("DegToRad", Value::Obj(Arc::new(Obj::NativeFn(math_deg_to_rad))));
+    insert("RadToDeg", Value::Obj(Arc::new(Obj::NativeFn(math_rad_to_deg))));
+
+    insert("IsNaN", Value::Obj(Arc::new(Obj::NativeFn(float_is_nan))));
+    insert("IsInfinity", Value::Obj(Arc::new(Obj::NativeFn(float_is_infinity))));
+    insert("IsNormal", Value::Obj(Arc::new(Obj::NativeFn(float_is_normal))));
 
     // Remaining C# built-ins
-    insert("regex", Value::Obj(Rc::new(Obj::NativeFn(text_regex))));
-    insert("parse", Value::Obj(Rc::new(Obj::NativeFn(text_parse))));
-    insert("format", Value::Obj(Rc::new(Obj::NativeFn(text_format))));
-    insert("_templatemerge", Value::Obj(Rc::new(Obj::NativeFn(text_templatemerge))));
-    insert("HEncode", Value::Obj(Rc::new(Obj::NativeFn(html_encode))));
+    insert("regex", Value::Obj(Arc::new(Obj::VmFn(text_regex))));
+    insert("parse", Value::Obj(Arc::new(Obj::NativeFn(text_parse))));
+    insert("format", Value::Obj(Arc::new(Obj::NativeFn(text_format))));
+    insert("_templatemerge", Value::Obj(Arc::new(Obj::NativeFn(text_templatemerge))));
+    insert("HEncode", Value::Obj(Arc::new(Obj::NativeFn(html_encode))));
+
+    insert("error", Value::Obj(Arc::new(Obj::NativeFn(misc_error))));
+    insert("iserror", Value::Obj(Arc::new(Obj::NativeFn(misc_is_error))));
+    insert("log", Value::Obj(Arc::new(Obj::HostFn(misc_log))));
+
+    insert("file", Value::Obj(Arc::new(Obj::HostFn(os_file_text))));
+    insert("fileexists", Value::Obj(Arc::new(Obj::HostFn(os_file_exists))));
+    insert("isfile", Value::Obj(Arc::new(Obj::HostFn(os_is_file))));
+    insert("dirlist", Value::Obj(Arc::new(Obj::HostFn(os_dir_list))));
+    insert("fileBytes", Value::Obj(Arc::new(Obj::HostFn(os_file_bytes))));
+    insert("walk", Value::Obj(Arc::new(Obj::HostFn(os_walk))));
+    insert("metadata", Value::Obj(Arc::new(Obj::HostFn(os_metadata))));
+    insert("writefile", Value::Obj(Arc::new(Obj::HostFn(os_write_file))));
+    insert("env", Value::Obj(Arc::new(Obj::HostFn(os_env))));
+    insert("fetch", Value::Obj(Arc::new(Obj::HostFn(net_fetch))));
+}
 
-    insert("error", Value::Obj(Rc::new(Obj::NativeFn(misc_error))));
-    insert("log", Value::Obj(Rc::new(Obj::NativeFn(misc_log))));
+pub(crate) fn is_sum(f: fn(&mut VM, &[Value]) -> Value) -> bool {
+    std::ptr::eq(fs_sum as *const (), f as *const ())
+}
 
-    insert("file", Value::Obj(Rc::new(Obj::NativeFn(os_file_text))));
-    insert("fileexists", Value::Obj(Rc::new(Obj::NativeFn(os_file_exists))));
-    insert("isfile", Value::Obj(Rc::new(Obj::NativeFn(os_is_file))));
-    insert("dirlist", Value::Obj(Rc::new(Obj::NativeFn(os_dir_list))));
+/// Natives whose KVC arguments the VM evaluates up front, since they read members.
+pub(crate) fn reads_kvc_args(f: fn(&HostContext, &[Value]) -> Value) -> bool {
+    std::ptr::eq(net_fetch as *const (), f as *const ())
 }
 
-fn kvc_from_cache(display_names_in_order: Vec<(&str, Value)>) -> Value {
+/// A KVC of already-evaluated members. A repeated key keeps its first position and display
+/// name and takes the last value.
+fn kvc_from_cache<S: AsRef<str>>(display_names_in_order: Vec<(S, Value)>) -> Value {
     let mut cache: HashMap<String, Value> = HashMap::new();
     let mut order: Vec<String> = Vec::with_capacity(display_names_in_order.len());
     let mut display_names: HashMap<String, String> = HashMap::new();
     for (display, v) in display_names_in_order {
+        let display = display.as_ref();
         let key_l = display.to_lowercase();
-        cache.insert(key_l.clone(), v);
-        order.push(key_l.clone());
-        display_names.insert(key_l, display.to_string());
+        if cache.insert(key_l.clone(), v).is_none() {
+            order.push(key_l.clone());
+            display_names.insert(key_l, display.to_string());
+        }
     }
     let kvc = KvcObject {
         entries: HashMap::new(),
@@ -128,7 +208,7 @@ fn kvc_from_cache(display_names_in_order: Vec<(&str, Value)>) -> Value {
         order,
         display_names,
     };
-    Value::Obj(Rc::new(Obj::Kvc(Rc::new(RefCell::new(kvc)))))
+    Value::Obj(Arc::new(Obj::Kvc(Arc::new(Mutex::new(kvc)))))
 }
 
 fn build_math_provider() -> Value {
@@ -136,60 +216,80 @@ fn build_math_provider() -> Value {
         ("Pi", Value::Number(std::f64::consts::PI)),
         ("E", Value::Number(std::f64::consts::E)),
 
-        ("Abs", Value::Obj(Rc::new(Obj::NativeFn(math_abs)))),
-        ("Min", Value::Obj(Rc::new(Obj::NativeFn(math_min)))),
-        ("Max", Value::Obj(Rc::new(Obj::NativeFn(math_max)))),
-        ("Sqrt", Value::Obj(Rc::new(Obj::NativeFn(math_sqrt)))),
-        ("Pow", Value::Obj(Rc::new(Obj::NativeFn(math_pow)))),
-
-        ("Sin", Value::Obj(Rc::new(Obj::NativeFn(math_sin)))),
-        ("Cos", Value::Obj(Rc::new(Obj::NativeFn(math_cos)))),
-        ("Tan", Value::Obj(Rc::new(Obj::NativeFn(math_tan)))),
-        ("Asin", Value::Obj(Rc::new(Obj::NativeFn(math_asin)))),
-        ("Acos", Value::Obj(Rc::new(Obj::NativeFn(math_acos)))),
-        ("Atan", Value::Obj(Rc::new(Obj::NativeFn(math_atan)))),
-        ("Atan2", Value::Obj(Rc::new(Obj::NativeFn(math_atan2)))),
-
-        ("Exp", Value::Obj(Rc::new(Obj::NativeFn(math_exp)))),
-        ("Ln", Value::Obj(Rc::new(Obj::NativeFn(math_ln)))),
-        ("Log10", Value::Obj(Rc::new(Obj::NativeFn(math_log10)))),
-        ("Log2", Value::Obj(Rc::new(Obj::NativeFn(math_log2)))),
-
-        ("Ceiling", Value::Obj(Rc::new(Obj::NativeFn(math_ceil)))),
-        ("Floor", Value::Obj(Rc::new(Obj::NativeFn(math_floor)))),
-        ("Round", Value::Obj(Rc::new(Obj::NativeFn(math_round)))),
-        ("Trunc", Value::Obj(Rc::new(Obj::NativeFn(math_trunc)))),
-        ("Sign", Value::Obj(Rc::new(Obj::NativeFn(math_sign)))),
-        ("Clamp", Value::Obj(Rc::new(Obj::NativeFn(math_clamp)))),
-
-        ("Random", Value::Obj(Rc::new(Obj::NativeFn(math_random)))),
-        ("Cbrt", Value::Obj(Rc::new(Obj::NativeFn(math_cbrt)))),
-        ("DegToRad", Value::Obj(Rc::new(Obj::NativeFn(math_deg_to_rad)))),
-        ("RadToDeg", Value::Obj(Rc::new(Obj::NativeFn(math_rad_to_deg)))),
+        ("Abs", Value::Obj(Arc::new(Obj::NativeFn(math_abs)))),
+        ("Min", Value::Obj(Arc::new(Obj::NativeFn(math_min)))),
+        ("Max", Value::Obj(Arc::new(Obj::NativeFn(math_max)))),
+        ("Sqrt", Value::Obj(Arc::new(Obj::NativeFn(math_sqrt)))),
+        ("Pow", Value::Obj(Arc::new(Obj::NativeFn(math_pow)))),
+
+        ("Sin", Value::Obj(Arc::new(Obj::NativeFn(math_sin)))),
+        ("Cos", Value::Obj(Arc::new(Obj::NativeFn(math_cos)))),
+        ("Tan", Value::Obj(Arc::new(Obj::NativeFn(math_tan)))),
+        ("Asin", Value::Obj(Arc::new(Obj::NativeFn(math_asin)))),
+        ("Acos", Value::Obj(Arc::new(Obj::NativeFn(math_acos)))),
+        ("Atan", Value::Obj(Arc::new(Obj::NativeFn(math_atan)))),
+        ("Atan2", Value::Obj(Arc::new(Obj::NativeFn(math_atan2)))),
+
+        ("Exp", Value::Obj(Arc::new(Obj::NativeFn(math_exp)))),
+        ("Ln", Value::Obj(Arc::new(Obj::NativeFn(math_ln)))),
+        ("Log10", Value::Obj(Arc::new(Obj::NativeFn(math_log10)))),
+        ("Log2", Value::Obj(Arc::new(Obj::NativeFn(math_log2)))),
+
+        ("Ceiling", Value::Obj(Arc::new(Obj::NativeFn(math_ceil)))),
+        ("Floor", Value::Obj(Arc::new(Obj::NativeFn(math_floor)))),
+        ("Round", Value::Obj(Arc::new(Obj::NativeFn(math_round)))),
+        ("Trunc", Value::Obj(Arc::new(Obj::NativeFn(math_trunc)))),
+        ("Sign", Value::Obj(Arc::new(Obj::NativeFn(math_sign)))),
+        ("Clamp", Value::Obj(Arc::new(Obj::NativeFn(math_clamp)))),
+
+        ("Random", Value::Obj(Arc::new(Obj::HostFn(math_random)))),
+        ("Cbrt", Value::Obj(Arc::new(Obj::NativeFn(math_cbrt)))),
+        ("DegToRad", Value::Obj(Arc::new(Obj::NativeFn(math_deg_to_rad)))),
+        ("RadToDeg", Value::Obj(Arc::new(Obj::NativeFn(math_rad_to_deg)))),
     ])
 }
 
 fn build_text_provider() -> Value {
-    kvc_from_cache(vec![
-        ("lower", Value::Obj(Rc::new(Obj::NativeFn(text_lower)))),
-        ("upper", Value::Obj(Rc::new(Obj::NativeFn(text_upper)))),
-        ("endswith", Value::Obj(Rc::new(Obj::NativeFn(text_endswith)))),
-        ("substring", Value::Obj(Rc::new(Obj::NativeFn(text_substring)))),
-        ("find", Value::Obj(Rc::new(Obj::NativeFn(text_find)))),
-        ("isBlank", Value::Obj(Rc::new(Obj::NativeFn(text_is_blank)))),
-        ("join", Value::Obj(Rc::new(Obj::NativeFn(text_join)))),
-        ("regex", Value::Obj(Rc::new(Obj::NativeFn(text_regex)))),
-        ("parse", Value::Obj(Rc::new(Obj::NativeFn(text_parse)))),
-        ("format", Value::Obj(Rc::new(Obj::NativeFn(text_format)))),
-        ("_templatemerge", Value::Obj(Rc::new(Obj::NativeFn(text_templatemerge)))),
-    ])
+    #[allow(unused_mut)]
+    let mut members = vec![
+        ("lower", Value::Obj(Arc::new(Obj::NativeFn(text_lower)))),
+        ("upper", Value::Obj(Arc::new(Obj::NativeFn(text_upper)))),
+        ("endswith", Value::Obj(Arc::new(Obj::NativeFn(text_endswith)))),
+        ("substring", Value::Obj(Arc::new(Obj::NativeFn(text_substring)))),
+        ("find", Value::Obj(Arc::new(Obj::NativeFn(text_find)))),
+        ("isBlank", Value::Obj(Arc::new(Obj::NativeFn(text_is_blank)))),
+        ("join", Value::Obj(Arc::new(Obj::NativeFn(text_join)))),
+        ("split", Value::Obj(Arc::new(Obj::NativeFn(text_split)))),
+        ("replace", Value::Obj(Arc::new(Obj::NativeFn(text_replace)))),
+        ("trim", Value::Obj(Arc::new(Obj::NativeFn(text_trim)))),
+        ("trimStart", Value::Obj(Arc::new(Obj::NativeFn(text_trim_start)))),
+        ("trimEnd", Value::Obj(Arc::new(Obj::NativeFn(text_trim_end)))),
+        ("startswith", Value::Obj(Arc::new(Obj::NativeFn(text_startswith)))),
+        ("padLeft", Value::Obj(Arc::new(Obj::NativeFn(text_pad_left)))),
+        ("padRight", Value::Obj(Arc::new(Obj::NativeFn(text_pad_right)))),
+        ("repeat", Value::Obj(Arc::new(Obj::NativeFn(text_repeat)))),
+        ("indexOf", Value::Obj(Arc::new(Obj::NativeFn(text_index_of)))),
+        ("lastIndexOf", Value::Obj(Arc::new(Obj::NativeFn(text_last_index_of)))),
+        ("chars", Value::Obj(Arc::new(Obj::NativeFn(text_chars)))),
+        ("contains", Value::Obj(Arc::new(Obj::NativeFn(list_contains)))),
+        ("regex", Value::Obj(Arc::new(Obj::VmFn(text_regex)))),
+        ("parse", Value::Obj(Arc::new(Obj::NativeFn(text_parse)))),
+        ("format", Value::Obj(Arc::new(Obj::NativeFn(text_format)))),
+        ("_templatemerge", Value::Obj(Arc::new(Obj::NativeFn(text_templatemerge)))),
+    ];
+    #[cfg(feature = "unicode")]
+    members.extend([
+        ("graphemes", Value::Obj(Arc::new(Obj::NativeFn(text_graphemes)))),
+        ("normalize", Value::Obj(Arc::new(Obj::NativeFn(text_normalize)))),
+    ]);
+    kvc_from_cache(members)
 }
 
 fn build_float_provider() -> Value {
     kvc_from_cache(vec![
-        ("IsNormal", Value::Obj(Rc::new(Obj::NativeFn(float_is_normal)))),
-        ("IsNaN", Value::Obj(Rc::new(Obj::NativeFn(float_is_nan)))),
-        ("IsInfinity", Value::Obj(Rc::new(Obj::NativeFn(float_is_infinity)))),
+        ("IsNormal", Value::Obj(Arc::new(Obj::NativeFn(float_is_normal)))),
+        ("IsNaN", Value::Obj(Arc::new(Obj::NativeFn(float_is_nan)))),
+        ("IsInfinity", Value::Obj(Arc::new(Obj::NativeFn(float_is_infinity)))),
     ])
 }
 
@@ -199,9 +299,9 @@ fn math_abs(args: &[Value]) -> Value {
         Value::Number(n) => Value::Number(n.abs()),
         Value::Int(n) => match n.checked_abs() {
             Some(v) => Value::Int(v),
-            None => Value::BigInt(BigInt::from(*n).abs()),
+            None => Value::bigint(BigInt::from(*n).abs()),
         },
-        Value::BigInt(n) => Value::BigInt(n.abs()),
+        Value::BigInt(n) => Value::bigint(n.abs()),
         _ => Value::Nil,
     }
 }
@@ -213,17 +313,17 @@ fn math_max(args: &[Value]) -> Value {
         (a, b) => {
             let ai = match a {
                 Value::Int(n) => Some(BigInt::from(*n)),
-                Value::BigInt(n) => Some(n.clone()),
+                Value::BigInt(n) => Some((**n).clone()),
                 _ => None,
             };
             let bi = match b {
                 Value::Int(n) => Some(BigInt::from(*n)),
-                Value::BigInt(n) => Some(n.clone()),
+                Value::BigInt(n) => Some((**n).clone()),
                 _ => None,
             };
             if let (Some(ai), Some(bi)) = (ai, bi) {
                 let m = if ai >= bi { ai } else { bi };
-                if let Some(v) = m.to_i64() { Value::Int(v) } else { Value::BigInt(m) }
+                if let Some(v) = m.to_i64() { Value::Int(v) } else { Value::bigint(m) }
             } else {
                 Value::Nil
             }
@@ -238,17 +338,17 @@ fn math_min(args: &[Value]) -> Value {
         (a, b) => {
             let ai = match a {
                 Value::Int(n) => Some(BigInt::from(*n)),
-                Value::BigInt(n) => Some(n.clone()),
+                Value::BigInt(n) => Some((**n).clone()),
                 _ => None,
             };
             let bi = match b {
                 Value::Int(n) => Some(BigInt::from(*n)),
-                Value::BigInt(n) => Some(n.clone()),
+                Value::BigInt(n) => Some((**n).clone()),
                 _ => None,
             };
             if let (Some(ai), Some(bi)) = (ai, bi) {
                 let m = if ai <= bi { ai } else { bi };
-                if let Some(v) = m.to_i64() { Value::Int(v) } else { Value::BigInt(m) }
+                if let Some(v) = m.to_i64() { Value::Int(v) } else { Value::bigint(m) }
             } else {
                 Value::Nil
             }
@@ -271,20 +371,20 @@ fn math_sqrt(args: &[Value]) -> Value {
 
 fn math_num1(args: &[Value], name: &str) -> Result<f64, Value> {
     if args.len() != 1 {
-        return Err(Value::Error(FsError { code: 1, message: format!("{name}: number expected"), line: -1, column: -1 }));
+        return Err(Value::error(FsError::new(ErrorKind::TypeMismatch, format!("{name}: number expected"))));
     }
     match &args[0] {
         Value::Error(e) => Err(Value::Error(e.clone())),
         Value::Int(n) => Ok(*n as f64),
-        Value::BigInt(n) => n.to_f64().ok_or_else(|| Value::Error(FsError { code: 1, message: format!("{name}: number out of range"), line: -1, column: -1 })),
+        Value::BigInt(n) => n.to_f64().ok_or_else(|| Value::error(FsError::new(ErrorKind::OutOfRange, format!("{name}: number out of range")))),
         Value::Number(n) if n.is_finite() => Ok(*n),
-        _ => Err(Value::Error(FsError { code: 2, message: format!("{name}: number expected"), line: -1, column: -1 })),
+        _ => Err(Value::error(FsError::new(ErrorKind::TypeMismatch, format!("{name}: number expected")))),
     }
 }
 
 fn math_num2(args: &[Value], name: &str) -> Result<(f64, f64), Value> {
     if args.len() != 2 {
-        return Err(Value::Error(FsError { code: 1, message: format!("{name}: Expected 2 parameters"), line: -1, column: -1 }));
+        return Err(Value::error(FsError::new(ErrorKind::ParameterCount, format!("{name}: Expected 2 parameters"))));
     }
     let a = math_num1(&args[0..1], name)?;
     let b = math_num1(&args[1..2], name)?;
@@ -315,14 +415,14 @@ fn math_exp(args: &[Value]) -> Value { math_num1(args, "Exp").map(|n| Value::Num
 
 fn math_ln(args: &[Value]) -> Value {
     if args.is_empty() || args.len() > 2 {
-        return Value::Error(FsError { code: 1, message: "Ln: Expecting 1 or 2 parameters".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "Ln: Expecting 1 or 2 parameters"));
     }
     let v = match math_num1(&args[0..1], "Ln") {
         Ok(v) => v,
         Err(e) => return e,
     };
     if v <= 0.0 {
-        return Value::Error(FsError { code: 2, message: "Ln: value must be greater than 0.".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::InvalidValue, "Ln: value must be greater than 0."));
     }
     if args.len() == 1 {
         return Value::Number(v.ln());
@@ -332,7 +432,7 @@ fn math_ln(args: &[Value]) -> Value {
         Err(e) => return e,
     };
     if base <= 0.0 || (base - 1.0).abs() < f64::EPSILON {
-        return Value::Error(FsError { code: 2, message: "Ln: base must be greater than 0 and not equal to 1.".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::InvalidValue, "Ln: base must be greater than 0 and not equal to 1."));
     }
     Value::Number(v.log(base))
 }
@@ -340,7 +440,7 @@ fn math_ln(args: &[Value]) -> Value {
 fn math_log10(args: &[Value]) -> Value {
     match math_num1(args, "Log10") {
         Ok(v) if v > 0.0 => Value::Number(v.log10()),
-        Ok(_) => Value::Error(FsError { code: 2, message: "Log10: value must be greater than 0.".to_string(), line: -1, column: -1 }),
+        Ok(_) => Value::error(FsError::new(ErrorKind::InvalidValue, "Log10: value must be greater than 0.")),
         Err(e) => e,
     }
 }
@@ -348,7 +448,7 @@ fn math_log10(args: &[Value]) -> Value {
 fn math_log2(args: &[Value]) -> Value {
     match math_num1(args, "Log2") {
         Ok(v) if v > 0.0 => Value::Number(v.log2()),
-        Ok(_) => Value::Error(FsError { code: 2, message: "Log2: value must be greater than 0.".to_string(), line: -1, column: -1 }),
+        Ok(_) => Value::error(FsError::new(ErrorKind::InvalidValue, "Log2: value must be greater than 0.")),
         Err(e) => e,
     }
 }
@@ -361,7 +461,7 @@ fn math_sign(args: &[Value]) -> Value { math_num1(args, "Sign").map(|n| Value::I
 
 fn math_clamp(args: &[Value]) -> Value {
     if args.len() != 3 {
-        return Value::Error(FsError { code: 1, message: "Clamp: Expected 3 parameters".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "Clamp: Expected 3 parameters"));
     }
     let x = match math_num1(&args[0..1], "Clamp") { Ok(v) => v, Err(e) => return e };
     let lo = match math_num1(&args[1..2], "Clamp") { Ok(v) => v, Err(e) => return e };
@@ -369,10 +469,10 @@ fn math_clamp(args: &[Value]) -> Value {
     Value::Number(x.clamp(lo, hi))
 }
 
-fn math_random(args: &[Value]) -> Value {
-    // Deterministic seed → [0,1)
+fn math_random(host: &HostContext, args: &[Value]) -> Value {
+    // Without a seed, draw from the host random source; with one, hash the seed → [0,1)
     let seed = match args.get(0) {
-        None => 0.0,
+        None => return Value::Number((host.random_u64() >> 11) as f64 / ((1u64 << 53) as f64)),
         Some(v) => match math_num1(std::slice::from_ref(v), "Random") {
             Ok(x) => x,
             Err(e) => return e,
@@ -395,66 +495,166 @@ fn float_is_nan(args: &[Value]) -> Value { math_num1(args, "IsNaN").map(|n| Valu
 fn float_is_infinity(args: &[Value]) -> Value { math_num1(args, "IsInfinity").map(|n| Value::Bool(n.is_infinite())).unwrap_or_else(|e| e) }
 fn float_is_normal(args: &[Value]) -> Value { math_num1(args, "IsNormal").map(|n| Value::Bool(n.is_normal())).unwrap_or_else(|e| e) }
 
-fn text_regex(args: &[Value]) -> Value {
-    if args.len() < 2 || args.len() > 3 {
-        return Value::Error(FsError { code: 1, message: "regex: two or three parameters expected".to_string(), line: -1, column: -1 });
-    }
-    let text = match &args[0] {
-        Value::Error(e) => return Value::Error(e.clone()),
-        Value::Obj(o) => match &**o {
-            Obj::String(s) => s.clone(),
-            _ => return Value::Error(FsError { code: 2, message: "regex: text parameter must be string".to_string(), line: -1, column: -1 }),
-        },
-        _ => return Value::Error(FsError { code: 2, message: "regex: text parameter must be string".to_string(), line: -1, column: -1 }),
-    };
-    let pattern = match &args[1] {
-        Value::Error(e) => return Value::Error(e.clone()),
+fn regex_string_arg(name: &str, what: &str, v: &Value) -> Result<SmallString, Value> {
+    match v {
+        Value::Error(e) => Err(Value::Error(e.clone())),
         Value::Obj(o) => match &**o {
-            Obj::String(s) => s.clone(),
-            _ => return Value::Error(FsError { code: 2, message: "regex: pattern parameter must be string".to_string(), line: -1, column: -1 }),
+            Obj::String(s) => Ok(s.clone()),
+            _ => Err(Value::error(FsError::new(ErrorKind::TypeMismatch, format!("{name}: {what} parameter must be string")))),
         },
-        _ => return Value::Error(FsError { code: 2, message: "regex: pattern parameter must be string".to_string(), line: -1, column: -1 }),
-    };
-    let flags = if args.len() == 3 {
-        match &args[2] {
-            Value::Nil => None,
-            Value::Error(e) => return Value::Error(e.clone()),
-            Value::Obj(o) => match &**o {
-                Obj::String(s) => Some(s.clone()),
-                _ => return Value::Error(FsError { code: 2, message: "regex: flags parameter must be string".to_string(), line: -1, column: -1 }),
-            },
-            _ => return Value::Error(FsError { code: 2, message: "regex: flags parameter must be string".to_string(), line: -1, column: -1 }),
-        }
-    } else {
-        None
-    };
+        _ => Err(Value::error(FsError::new(ErrorKind::TypeMismatch, format!("{name}: {what} parameter must be string")))),
+    }
+}
 
+/// Compiles `pattern` with the optional flags argument (`i`, `m`, `s`, `x`) through the VM's
+/// pattern cache.
+fn compile_regex(vm: &mut VM, name: &str, pattern: &str, flags: Option<&Value>) -> Result<Regex, Value> {
     let mut prefix = String::new();
-    if let Some(f) = flags {
-        for ch in f.chars() {
-            if ch.is_whitespace() || ch == ',' || ch == '|' { continue; }
-            match ch.to_ascii_lowercase() {
-                'i' => prefix.push_str("(?i)"),
-                'm' => prefix.push_str("(?m)"),
-                's' => prefix.push_str("(?s)"),
-                'x' => prefix.push_str("(?x)"),
-                other => {
-                    return Value::Error(FsError { code: 1, message: format!("regex: unsupported regex option '{other}'"), line: -1, column: -1 });
+    match flags {
+        None | Some(Value::Nil) => {}
+        Some(v) => {
+            for ch in regex_string_arg(name, "flags", v)?.chars() {
+                if ch.is_whitespace() || ch == ',' || ch == '|' { continue; }
+                match ch.to_ascii_lowercase() {
+                    'i' => prefix.push_str("(?i)"),
+                    'm' => prefix.push_str("(?m)"),
+                    's' => prefix.push_str("(?s)"),
+                    'x' => prefix.push_str("(?x)"),
+                    other => {
+                        return Err(Value::error(FsError::new(ErrorKind::InvalidValue, format!("{name}: unsupported regex option '{other}'"))));
+                    }
                 }
             }
         }
     }
+    vm.cached_regex(&format!("{prefix}{pattern}"))
+        .map_err(|e| Value::error(FsError::new(ErrorKind::InvalidValue, format!("{name}: invalid pattern: {e}"))))
+}
 
-    let pat = format!("{prefix}{pattern}");
-    match Regex::new(&pat) {
+/// `regex(text, pattern, flags?)`: whether `pattern` matches anywhere in `text`. Also the
+/// namespace of `regex.match`, `regex.matches`, `regex.replace` and `regex.split`.
+fn text_regex(vm: &mut VM, args: &[Value]) -> Value {
+    if args.len() < 2 || args.len() > 3 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "regex: two or three parameters expected"));
+    }
+    let text = match regex_string_arg("regex", "text", &args[0]) { Ok(t) => t, Err(e) => return e };
+    let pattern = match regex_string_arg("regex", "pattern", &args[1]) { Ok(p) => p, Err(e) => return e };
+    match compile_regex(vm, "regex", &pattern, args.get(2)) {
         Ok(re) => Value::Bool(re.is_match(&text)),
-        Err(e) => Value::Error(FsError { code: 1, message: format!("regex: invalid pattern: {e}"), line: -1, column: -1 }),
+        Err(e) => e,
+    }
+}
+
+/// Members reachable through a VM native used as a namespace (`regex.match`).
+pub(crate) fn native_member(f: fn(&mut VM, &[Value]) -> Value, key_l: &str) -> Value {
+    if !std::ptr::eq(text_regex as *const (), f as *const ()) {
+        return Value::Nil;
+    }
+    let member: fn(&mut VM, &[Value]) -> Value = match key_l {
+        "match" => regex_match,
+        "matches" => regex_matches,
+        "replace" => regex_replace,
+        "split" => regex_split,
+        _ => return Value::Nil,
+    };
+    Value::Obj(Arc::new(Obj::VmFn(member)))
+}
+
+/// `{ value, index, groups, named }` for one match. `index` counts characters like
+/// `substring`; `groups` holds the numbered groups from 1 (nil when a group did not take part)
+/// and `named` the named ones.
+fn regex_match_kvc(re: &Regex, text: &str, caps: &regex::Captures) -> Value {
+    let whole = caps.get(0).expect("group 0 is always present");
+    let text_value = |m: Option<regex::Match>| m.map_or(Value::Nil, |m| Value::Obj(Arc::new(Obj::String(m.as_str().into()))));
+    let groups: Vec<Value> = (1..caps.len()).map(|i| text_value(caps.get(i))).collect();
+    let named: Vec<(&str, Value)> = re.capture_names().flatten().map(|n| (n, text_value(caps.name(n)))).collect();
+    kvc_from_cache(vec![
+        ("value", text_value(Some(whole))),
+        ("index", Value::Int(text[..whole.start()].chars().count() as i64)),
+        ("groups", Value::Obj(Arc::new(Obj::List(groups)))),
+        ("named", kvc_from_cache(named)),
+    ])
+}
+
+/// Text, pattern and compiled regex for `regex.<name>(text, pattern, ..., flags?)`, where
+/// `flags` is the argument at `flags_at`.
+fn regex_args(vm: &mut VM, name: &str, args: &[Value], flags_at: usize) -> Result<(SmallString, Regex), Value> {
+    if args.len() < flags_at || args.len() > flags_at + 1 {
+        let expected = format!("{} or {}", flags_at, flags_at + 1);
+        return Err(Value::error(FsError::new(ErrorKind::ParameterCount, format!("{name}: invalid parameter count. {expected} expected"))));
+    }
+    let text = regex_string_arg(name, "text", &args[0])?;
+    let pattern = regex_string_arg(name, "pattern", &args[1])?;
+    let re = compile_regex(vm, name, &pattern, args.get(flags_at))?;
+    Ok((text, re))
+}
+
+/// `regex.match(text, pattern, flags?)`: the first match, or nil.
+fn regex_match(vm: &mut VM, args: &[Value]) -> Value {
+    let (text, re) = match regex_args(vm, "regex.match", args, 2) { Ok(a) => a, Err(e) => return e };
+    match re.captures(&text) {
+        Some(caps) => regex_match_kvc(&re, &text, &caps),
+        None => Value::Nil,
     }
 }
 
+/// `regex.matches(text, pattern, flags?)`: every non-overlapping match, in order.
+fn regex_matches(vm: &mut VM, args: &[Value]) -> Value {
+    let (text, re) = match regex_args(vm, "regex.matches", args, 2) { Ok(a) => a, Err(e) => return e };
+    let out: Vec<Value> = re.captures_iter(&text).map(|caps| regex_match_kvc(&re, &text, &caps)).collect();
+    Value::Obj(Arc::new(Obj::List(out)))
+}
+
+/// `regex.replace(text, pattern, replacement, flags?)`: replaces every match. `replacement` is
+/// either text, where `$1`/`${1}` and `${name}` insert groups and `$$` a literal `$`, or a
+/// function that receives the match (shaped like `regex.match`) and returns the new text.
+fn regex_replace(vm: &mut VM, args: &[Value]) -> Value {
+    let (text, re) = match regex_args(vm, "regex.replace", args, 3) { Ok(a) => a, Err(e) => return e };
+    let replaced = match &args[2] {
+        Value::Error(e) => return Value::Error(e.clone()),
+        Value::Obj(o) => match &**o {
+            Obj::String(template) => re.replace_all(&text, template.as_str()).into_owned(),
+            Obj::Function(_) | Obj::NativeFn(_) | Obj::HostFn(_) | Obj::VmFn(_) => {
+                let mut out = String::with_capacity(text.len());
+                let mut last = 0;
+                for caps in re.captures_iter(&text) {
+                    let whole = caps.get(0).expect("group 0 is always present");
+                    let m = regex_match_kvc(&re, &text, &caps);
+                    let piece = vm.call_function(&args[2], &[m]);
+                    if let Value::Error(e) = piece {
+                        return Value::Error(e);
+                    }
+                    out.push_str(&text[last..whole.start()]);
+                    match &piece {
+                        Value::Nil => {}
+                        Value::Obj(p) => match &**p {
+                            Obj::String(p) => out.push_str(p),
+                            _ => out.push_str(&piece.to_string()),
+                        },
+                        _ => out.push_str(&piece.to_string()),
+                    }
+                    last = whole.end();
+                }
+                out.push_str(&text[last..]);
+                out
+            }
+            _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "regex.replace: replacement must be text or a function")),
+        },
+        _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "regex.replace: replacement must be text or a function")),
+    };
+    Value::Obj(Arc::new(Obj::String(replaced.into())))
+}
+
+/// `regex.split(text, pattern, flags?)`: the pieces of `text` between matches.
+fn regex_split(vm: &mut VM, args: &[Value]) -> Value {
+    let (text, re) = match regex_args(vm, "regex.split", args, 2) { Ok(a) => a, Err(e) => return e };
+    let out: Vec<Value> = re.split(&text).map(|piece| Value::Obj(Arc::new(Obj::String(piece.into())))).collect();
+    Value::Obj(Arc::new(Obj::List(out)))
+}
+
 fn text_parse(args: &[Value]) -> Value {
     if args.is_empty() {
-        return Value::Error(FsError { code: 1, message: "parse requires at least one parameter".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "parse requires at least one parameter"));
     }
     if let Value::Error(e) = &args[0] { return Value::Error(e.clone()); }
     if matches!(args[0], Value::Nil) { return Value::Nil; }
@@ -467,7 +667,7 @@ fn text_parse(args: &[Value]) -> Value {
     };
     let fmt = fmt.unwrap_or_default();
     if fmt.trim().is_empty() {
-        return Value::Obj(Rc::new(Obj::String(s)));
+        return Value::Obj(Arc::new(Obj::String(s.into())));
     }
     match fmt.to_lowercase().as_str() {
         "hex" => {
@@ -475,12 +675,12 @@ fn text_parse(args: &[Value]) -> Value {
             let t = t.strip_prefix("0x").unwrap_or(t);
             match i64::from_str_radix(t, 16) {
                 Ok(v) => Value::Int(v),
-                Err(_) => Value::Error(FsError { code: 1, message: "parse: invalid hex".to_string(), line: -1, column: -1 }),
+                Err(_) => Value::error(FsError::new(ErrorKind::InvalidValue, "parse: invalid hex")),
             }
         }
         "l" => match s.trim().parse::<i64>() {
             Ok(v) => Value::Int(v),
-            Err(_) => Value::Error(FsError { code: 1, message: "parse: invalid int64".to_string(), line: -1, column: -1 }),
+            Err(_) => Value::error(FsError::new(ErrorKind::InvalidValue, "parse: invalid int64")),
         },
         "fs" => {
             let mut vm = crate::vm::VM::new();
@@ -491,17 +691,17 @@ fn text_parse(args: &[Value]) -> Value {
                         crate::vm::InterpretResult::CompileError(err) => err,
                         crate::vm::InterpretResult::RuntimeError(err) => err,
                     };
-                    Value::Error(err)
+                    Value::error(err)
                 }
             }
         }
-        _ => Value::Obj(Rc::new(Obj::String(s))),
+        _ => Value::Obj(Arc::new(Obj::String(s.into()))),
     }
 }
 
 fn text_format(args: &[Value]) -> Value {
     if args.is_empty() {
-        return Value::Error(FsError { code: 1, message: "format requires at least one parameter.".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "format requires at least one parameter."));
     }
     let value = &args[0];
     if let Value::Error(e) = value { return Value::Error(e.clone()); }
@@ -510,7 +710,7 @@ fn text_format(args: &[Value]) -> Value {
         match &args[1] {
             Value::Nil => None,
             Value::Obj(o) => match &**o {
-                Obj::String(s) => Some(s.clone()),
+                Obj::String(s) => Some(s.to_string()),
                 _ => Some(args[1].to_string()),
             },
             _ => Some(args[1].to_string()),
@@ -518,15 +718,22 @@ fn text_format(args: &[Value]) -> Value {
     } else {
         None
     };
-    if let Some(f) = fmt {
-        if f.eq_ignore_ascii_case("json") {
+    let text = match fmt.as_deref() {
+        Some(f) if f.eq_ignore_ascii_case("json") => {
             // Best-effort JSON formatting (does not force-evaluate lazy KVC entries).
-            let json = format_json_value(value);
-            return Value::Obj(Rc::new(Obj::String(json)));
+            return Value::Obj(Arc::new(Obj::String(format_json_value(value).into())));
         }
-        // For now, non-json uses Display formatting (Rust core doesn't implement .NET format patterns yet).
+        Some(f) if !f.trim().is_empty() => crate::format::format_value(value, f),
+        _ => match value {
+            Value::Obj(o) if matches!(&**o, Obj::DateTimeTicks(..)) => crate::format::format_value(value, crate::format::DEFAULT_DATE_PATTERN),
+            Value::Nil => Ok("null".to_string()),
+            _ => Ok(value.to_string()),
+        },
+    };
+    match text {
+        Ok(text) => Value::Obj(Arc::new(Obj::String(text.into()))),
+        Err(e) => Value::error(e),
     }
-    Value::Obj(Rc::new(Obj::String(value.to_string())))
 }
 
 fn format_json_escape(s: &str) -> String {
@@ -545,15 +752,22 @@ fn format_json_escape(s: &str) -> String {
     out
 }
 
-fn format_json_value(v: &Value) -> String {
+fn format_json_error(e: &FsError) -> String {
+    let user_type = e.user_type.as_ref().map(|t| format!("\"{}\"", format_json_escape(t))).unwrap_or_else(|| "null".to_string());
+    let data = e.data.as_ref().map(format_json_value).unwrap_or_else(|| "null".to_string());
+    let cause = e.cause.as_deref().map(format_json_error).unwrap_or_else(|| "null".to_string());
+    format!("{{\"kind\":\"value\",\"code\":{},\"errorKind\":\"{}\",\"message\":\"{}\",\"type\":{},\"data\":{},\"cause\":{},\"line\":{},\"column\":{}}}",
+        e.code(), e.kind.name(), format_json_escape(&e.message), user_type, data, cause, e.line, e.column)
+}
+
+pub(crate) fn format_json_value(v: &Value) -> String {
     match v {
         Value::Nil => "null".to_string(),
         Value::Bool(b) => if *b { "true".to_string() } else { "false".to_string() },
         Value::Int(n) => n.to_string(),
         Value::BigInt(n) => n.to_string(),
         Value::Number(n) => if n.is_finite() { n.to_string() } else { "null".to_string() },
-        Value::Error(e) => format!("{{\"kind\":\"value\",\"code\":{},\"message\":\"{}\",\"line\":{},\"column\":{}}}",
-            e.code, format_json_escape(&e.message), e.line, e.column),
+        Value::Error(e) => format_json_error(e),
         Value::Obj(o) => match &**o {
             Obj::String(s) => format!("\"{}\"", format_json_escape(s)),
             Obj::List(items) => {
@@ -563,20 +777,21 @@ fn format_json_value(v: &Value) -> String {
             Obj::Range(r) => format!("{{\"type\":\"range\",\"start\":{},\"count\":{}}}", r.start, r.count),
             Obj::Bytes(b) => format!("{{\"type\":\"bytes\",\"base64\":\"{}\"}}", format_json_escape(&general_purpose::STANDARD.encode(b))),
             Obj::Guid(g) => format!("{{\"type\":\"guid\",\"value\":\"{}\"}}", format_json_escape(&g.to_string())),
-            Obj::DateTimeTicks(t) => format!("{{\"type\":\"datetime\",\"ticks\":{}}}", t),
+            Obj::DateTimeTicks(t, None) => format!("{{\"type\":\"datetime\",\"ticks\":{}}}", t),
+            Obj::DateTimeTicks(t, Some(offset)) => format!("{{\"type\":\"datetime\",\"ticks\":{},\"offset\":{}}}", t, offset),
+            Obj::Duration(t) => format!("{{\"type\":\"duration\",\"ticks\":{},\"iso\":\"{}\"}}", t, crate::datetime::duration_iso(*t)),
             Obj::Kvc(k) => {
-                let b = k.borrow();
+                let entries = k.lock().unwrap().cached_entries();
                 let mut parts: Vec<String> = Vec::new();
-                for key_l in b.order.iter() {
-                    let display = b.display_names.get(key_l).cloned().unwrap_or_else(|| key_l.clone());
-                    let val = b.cache.get(key_l).cloned().unwrap_or(Value::Nil);
+                for (display, val) in entries {
+                    let val = val.unwrap_or(Value::Nil);
                     parts.push(format!("\"{}\":{}", format_json_escape(&display), format_json_value(&val)));
                 }
                 format!("{{{}}}", parts.join(","))
             }
             Obj::Provider(p) => format_json_value(&p.current),
             Obj::Function(f) => format!("{{\"type\":\"function\",\"name\":\"{}\",\"arity\":{}}}", format_json_escape(&f.name), f.arity),
-            Obj::NativeFn(_) => "{\"type\":\"native\"}".to_string(),
+            Obj::NativeFn(_) | Obj::HostFn(_) | Obj::VmFn(_) => "{\"type\":\"native\"}".to_string(),
         }
     }
 }
@@ -599,7 +814,7 @@ fn text_templatemerge(args: &[Value]) -> Value {
         if let Value::Error(e) = v { return Value::Error(e.clone()); }
         push_val(&mut out, v);
     }
-    Value::Obj(Rc::new(Obj::String(out)))
+    Value::Obj(Arc::new(Obj::String(out.into())))
 }
 
 fn html_encode(args: &[Value]) -> Value {
@@ -618,131 +833,456 @@ fn html_encode(args: &[Value]) -> Value {
             _ => out.push(ch),
         }
     }
-    Value::Obj(Rc::new(Obj::String(out)))
+    Value::Obj(Arc::new(Obj::String(out.into())))
 }
 
 fn misc_error(args: &[Value]) -> Value {
-    if args.is_empty() || args.len() > 2 {
-        return Value::Error(FsError { code: 1, message: "error: message and optional type expected".to_string(), line: -1, column: -1 });
+    if args.is_empty() || args.len() > 4 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "error: message and optional type, data and cause expected"));
     }
     let msg = match &args[0] {
         Value::Error(e) => return Value::Error(e.clone()),
         Value::Obj(o) => match &**o {
             Obj::String(s) => s.clone(),
-            _ => return Value::Error(FsError { code: 2, message: "error: message must be a string".to_string(), line: -1, column: -1 }),
+            _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "error: message must be a string")),
         },
-        _ => return Value::Error(FsError { code: 2, message: "error: message must be a string".to_string(), line: -1, column: -1 }),
+        _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "error: message must be a string")),
     };
-    let typ = if args.len() == 2 {
-        match &args[1] {
-            Value::Nil => None,
+    let mut err = FsError::new(ErrorKind::User, msg.as_str());
+    if let Some(t) = args.get(1) {
+        match t {
+            Value::Nil => {}
             Value::Error(e) => return Value::Error(e.clone()),
             Value::Obj(o) => match &**o {
-                Obj::String(s) => Some(s.clone()),
-                _ => return Value::Error(FsError { code: 2, message: "error: optional type must be a string".to_string(), line: -1, column: -1 }),
+                Obj::String(s) => err.user_type = Some(s.to_string()),
+                _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "error: optional type must be a string")),
             },
-            _ => return Value::Error(FsError { code: 2, message: "error: optional type must be a string".to_string(), line: -1, column: -1 }),
+            _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "error: optional type must be a string")),
         }
-    } else { None };
-    let message = if let Some(t) = typ { format!("{t}: {msg}") } else { msg };
-    Value::Error(FsError { code: 3000, message, line: -1, column: -1 })
+    }
+    match args.get(2) {
+        None | Some(Value::Nil) => {}
+        Some(Value::Error(e)) => return Value::Error(e.clone()),
+        Some(data) => err.data = Some(data.clone()),
+    }
+    match args.get(3) {
+        None | Some(Value::Nil) => {}
+        Some(Value::Error(cause)) => err.cause = Some(cause.clone()),
+        Some(_) => return Value::error(FsError::new(ErrorKind::TypeMismatch, "error: cause must be an error")),
+    }
+    Value::error(err)
+}
+
+fn misc_is_error(args: &[Value]) -> Value {
+    if args.len() != 1 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "iserror: single parameter expected"));
+    }
+    Value::Bool(matches!(args[0], Value::Error(_)))
 }
 
-fn misc_log(args: &[Value]) -> Value {
+fn misc_log(host: &HostContext, args: &[Value]) -> Value {
     if args.is_empty() {
-        return Value::Error(FsError { code: 1, message: "log: value expected".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "log: value expected"));
     }
     if args.len() > 2 {
-        return Value::Error(FsError { code: 1, message: "log: invalid parameter count".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "log: invalid parameter count"));
     }
     if let Value::Error(e) = &args[0] { return Value::Error(e.clone()); }
     if args.len() == 1 {
-        host::log_line(&format_json_value(&args[0]));
+        host.log_line(&format_json_value(&args[0]));
         return args[0].clone();
     }
     if let Value::Error(e) = &args[1] { return Value::Error(e.clone()); }
     match &args[1] {
         Value::Obj(o) => match &**o {
-            Obj::Function(_) | Obj::NativeFn(_) => {
-                host::log_line("<handler>");
+            Obj::Function(_) | Obj::NativeFn(_) | Obj::HostFn(_) | Obj::VmFn(_) => {
+                host.log_line("<handler>");
             }
-            _ => host::log_line(&args[1].to_string()),
+            _ => host.log_line(&args[1].to_string()),
         },
-        _ => host::log_line(&args[1].to_string()),
+        _ => host.log_line(&args[1].to_string()),
     }
     args[0].clone()
 }
 
-fn os_file_text(args: &[Value]) -> Value {
+fn os_file_text(host: &HostContext, args: &[Value]) -> Value {
     if args.len() != 1 {
-        return Value::Error(FsError { code: 1, message: "file: invalid parameter count. 1 expected".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "file: invalid parameter count. 1 expected"));
     }
     if let Value::Error(e) = &args[0] { return Value::Error(e.clone()); }
     if matches!(args[0], Value::Nil) { return Value::Nil; }
     let path = match &args[0] {
         Value::Obj(o) => match &**o {
             Obj::String(s) => s.clone(),
-            _ => return Value::Error(FsError { code: 2, message: "file: expected string".to_string(), line: -1, column: -1 }),
+            _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "file: expected string")),
         },
-        _ => return Value::Error(FsError { code: 2, message: "file: expected string".to_string(), line: -1, column: -1 }),
+        _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "file: expected string")),
     };
-    match host::file_read_text(&path) {
-        Ok(s) => Value::Obj(Rc::new(Obj::String(s))),
-        Err(e) => Value::Error(e),
+    match host.file_read_text(&path) {
+        Ok(s) => Value::Obj(Arc::new(Obj::String(s.into()))),
+        Err(e) => Value::error(e),
     }
 }
 
-fn os_file_exists(args: &[Value]) -> Value {
+fn os_file_exists(host: &HostContext, args: &[Value]) -> Value {
     if args.len() != 1 {
-        return Value::Error(FsError { code: 1, message: "fileexists: invalid parameter count. 1 expected".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "fileexists: invalid parameter count. 1 expected"));
     }
     let path = match &args[0] {
         Value::Obj(o) => match &**o {
             Obj::String(s) => s.as_str(),
-            _ => return Value::Error(FsError { code: 2, message: "fileexists: expected a string".to_string(), line: -1, column: -1 }),
+            _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "fileexists: expected a string")),
         },
-        _ => return Value::Error(FsError { code: 2, message: "fileexists: expected a string".to_string(), line: -1, column: -1 }),
+        _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "fileexists: expected a string")),
     };
-    match host::file_exists(path) {
+    match host.file_exists(path) {
         Ok(b) => Value::Bool(b),
-        Err(e) => Value::Error(e),
+        Err(e) => Value::error(e),
     }
 }
 
-fn os_is_file(args: &[Value]) -> Value {
+fn os_is_file(host: &HostContext, args: &[Value]) -> Value {
     if args.len() != 1 {
-        return Value::Error(FsError { code: 1, message: "isfile: invalid parameter count. 1 expected".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "isfile: invalid parameter count. 1 expected"));
     }
     let path = match &args[0] {
         Value::Obj(o) => match &**o {
             Obj::String(s) => s.as_str(),
-            _ => return Value::Error(FsError { code: 2, message: "isfile: expected a string".to_string(), line: -1, column: -1 }),
+            _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "isfile: expected a string")),
         },
-        _ => return Value::Error(FsError { code: 2, message: "isfile: expected a string".to_string(), line: -1, column: -1 }),
+        _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "isfile: expected a string")),
     };
-    match host::is_file(path) {
+    match host.is_file(path) {
         Ok(b) => Value::Bool(b),
-        Err(e) => Value::Error(e),
+        Err(e) => Value::error(e),
     }
 }
 
-fn os_dir_list(args: &[Value]) -> Value {
+fn os_dir_list(host: &HostContext, args: &[Value]) -> Value {
     if args.len() != 1 {
-        return Value::Error(FsError { code: 1, message: "dirlist: invalid parameter count. 1 expected".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "dirlist: invalid parameter count. 1 expected"));
     }
     let path = match &args[0] {
         Value::Obj(o) => match &**o {
             Obj::String(s) => s.clone(),
-            _ => return Value::Error(FsError { code: 2, message: "dirlist: expected a string".to_string(), line: -1, column: -1 }),
+            _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "dirlist: expected a string")),
         },
-        _ => return Value::Error(FsError { code: 2, message: "dirlist: expected a string".to_string(), line: -1, column: -1 }),
+        _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "dirlist: expected a string")),
+    };
+    match host.dir_list(&path) {
+        Ok(entries) => {
+            let out: Vec<Value> = entries.into_iter().map(|s| Value::Obj(Arc::new(Obj::String(s.into())))).collect();
+            Value::Obj(Arc::new(Obj::List(out)))
+        }
+        Err(e) => Value::error(e),
+    }
+}
+
+fn os_path_arg(name: &str, v: &Value) -> Result<SmallString, Value> {
+    match v {
+        Value::Error(e) => Err(Value::Error(e.clone())),
+        Value::Obj(o) => match &**o {
+            Obj::String(s) => Ok(s.clone()),
+            _ => Err(Value::error(FsError::new(ErrorKind::TypeMismatch, format!("{name}: expected a string")))),
+        },
+        _ => Err(Value::error(FsError::new(ErrorKind::TypeMismatch, format!("{name}: expected a string")))),
+    }
+}
+
+fn os_file_bytes(host: &HostContext, args: &[Value]) -> Value {
+    if args.len() != 1 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "fileBytes: invalid parameter count. 1 expected"));
+    }
+    if matches!(args[0], Value::Nil) { return Value::Nil; }
+    let path = match os_path_arg("fileBytes", &args[0]) { Ok(p) => p, Err(e) => return e };
+    match host.file_read_bytes(&path) {
+        Ok(bytes) => Value::Obj(Arc::new(Obj::Bytes(bytes))),
+        Err(e) => Value::error(e),
+    }
+}
+
+/// `walk(dir, pattern?)`: every entry below `dir`, optionally filtered by a glob matched
+/// against the path relative to `dir` (`*` and `?` stay within one segment, `**` spans any).
+fn os_walk(host: &HostContext, args: &[Value]) -> Value {
+    if args.is_empty() || args.len() > 2 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "walk: invalid parameter count. 1 or 2 expected"));
+    }
+    let path = match os_path_arg("walk", &args[0]) { Ok(p) => p, Err(e) => return e };
+    let pattern: Option<Vec<char>> = match args.get(1) {
+        None | Some(Value::Nil) => None,
+        Some(v) => match os_path_arg("walk", v) { Ok(p) => Some(p.chars().collect()), Err(e) => return e },
     };
-    match host::dir_list(&path) {
+    match host.dir_walk(&path) {
         Ok(entries) => {
-            let out: Vec<Value> = entries.into_iter().map(|s| Value::Obj(Rc::new(Obj::String(s)))).collect();
-            Value::Obj(Rc::new(Obj::List(out)))
+            let base = std::path::Path::new(path.as_str());
+            let out: Vec<Value> = entries
+                .into_iter()
+                .filter(|rel| pattern.as_ref().is_none_or(|p| glob_match(p, &rel.chars().collect::<Vec<_>>())))
+                .filter_map(|rel| base.join(rel).into_os_string().into_string().ok())
+                .map(|s| Value::Obj(Arc::new(Obj::String(s.into()))))
+                .collect();
+            Value::Obj(Arc::new(Obj::List(out)))
+        }
+        Err(e) => Value::error(e),
+    }
+}
+
+/// Iterative wildcard match: on a mismatch it goes back to the latest `*`, which may not
+/// swallow a `/`, and failing that to the latest `**`, so the work stays linear in the
+/// number of retries instead of exponential.
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    let (mut p, mut t) = (0, 0);
+    // (pattern index after the star, text index it resumes from)
+    let mut star: Option<(usize, usize)> = None;
+    let mut double_star: Option<(usize, usize)> = None;
+    // `**/` only ever swallows whole directories, so it resumes after the next `/`.
+    let mut double_star_dirs = false;
+    while t < text.len() {
+        if pattern.get(p) == Some(&'*') && pattern.get(p + 1) == Some(&'*') {
+            double_star_dirs = pattern.get(p + 2) == Some(&'/');
+            p += if double_star_dirs { 3 } else { 2 };
+            double_star = Some((p, t));
+            star = None;
+            continue;
+        }
+        match pattern.get(p) {
+            Some('*') => {
+                p += 1;
+                star = Some((p, t));
+                continue;
+            }
+            Some('?') if text[t] != '/' => {
+                p += 1;
+                t += 1;
+                continue;
+            }
+            Some(c) if *c != '?' && *c == text[t] => {
+                p += 1;
+                t += 1;
+                continue;
+            }
+            _ => {}
+        }
+        if let Some((sp, st)) = star.filter(|&(_, st)| text[st] != '/') {
+            star = Some((sp, st + 1));
+            (p, t) = (sp, st + 1);
+        } else if let Some((dp, dt)) = double_star {
+            let next = if double_star_dirs {
+                match text[dt..].iter().position(|c| *c == '/') {
+                    Some(slash) => dt + slash + 1,
+                    None => return false,
+                }
+            } else {
+                dt + 1
+            };
+            double_star = Some((dp, next));
+            star = None;
+            (p, t) = (dp, next);
+        } else {
+            return false;
+        }
+    }
+    // What is left of the pattern has to match nothing: `*`, `**` and `**/` all can.
+    let mut rest = &pattern[p..];
+    loop {
+        match rest {
+            [] => return true,
+            ['*', '*', '/', tail @ ..] | ['*', tail @ ..] => rest = tail,
+            _ => return false,
+        }
+    }
+}
+
+fn os_metadata(host: &HostContext, args: &[Value]) -> Value {
+    if args.len() != 1 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "metadata: invalid parameter count. 1 expected"));
+    }
+    if matches!(args[0], Value::Nil) { return Value::Nil; }
+    let path = match os_path_arg("metadata", &args[0]) { Ok(p) => p, Err(e) => return e };
+    match host.file_metadata(&path) {
+        Ok(Some(m)) => kvc_from_cache(vec![
+            ("size", Value::Int(i64::try_from(m.size).unwrap_or(i64::MAX))),
+            ("modified", Value::Obj(Arc::new(Obj::DateTimeTicks(m.modified_ticks, None)))),
+            ("isDirectory", Value::Bool(m.is_dir)),
+        ]),
+        Ok(None) => Value::Nil,
+        Err(e) => Value::error(e),
+    }
+}
+
+/// `writefile(path, content)`: writes text (as UTF-8) or bytes and returns `path`.
+fn os_write_file(host: &HostContext, args: &[Value]) -> Value {
+    if args.len() != 2 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "writefile: invalid parameter count. 2 expected"));
+    }
+    let path = match os_path_arg("writefile", &args[0]) { Ok(p) => p, Err(e) => return e };
+    let result = match &args[1] {
+        Value::Error(e) => return Value::Error(e.clone()),
+        Value::Obj(o) => match &**o {
+            Obj::String(s) => host.file_write(&path, s.as_bytes()),
+            Obj::Bytes(b) => host.file_write(&path, b),
+            _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "writefile: text or bytes expected")),
+        },
+        _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "writefile: text or bytes expected")),
+    };
+    match result {
+        Ok(()) => args[0].clone(),
+        Err(e) => Value::error(e),
+    }
+}
+
+/// `env(name, default?)`: never fails for unset variables, it returns `default` (or nil).
+fn os_env(host: &HostContext, args: &[Value]) -> Value {
+    if args.is_empty() || args.len() > 2 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "env: invalid parameter count. 1 or 2 expected"));
+    }
+    let name = match os_path_arg("env", &args[0]) { Ok(n) => n, Err(e) => return e };
+    match host.env_var(&name) {
+        Some(v) => Value::Obj(Arc::new(Obj::String(v.into()))),
+        None => args.get(1).cloned().unwrap_or(Value::Nil),
+    }
+}
+
+/// `fetch(url, options?)`: sends the request through the host and returns
+/// `{ status, ok, headers, body }`. Options: `method`, `headers` (a KVC), `body` (text, bytes,
+/// or a list/KVC sent as JSON) and `json: true` to add the body parsed as `json`.
+/// Hosts may write the request head out as text, so the method and header names must be
+/// HTTP tokens, and header values and the URL must not contain line breaks; otherwise a
+/// script could add headers or a second request of its own.
+fn check_request_head(request: &HttpRequest) -> Result<(), Value> {
+    let is_token = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+    let invalid = |msg: String| Err(Value::error(FsError::new(ErrorKind::InvalidValue, msg)));
+    if !is_token(&request.method) {
+        return invalid(format!("fetch: {:?} is not a valid HTTP method", request.method));
+    }
+    if request.url.chars().any(|c| c.is_control() || c.is_whitespace()) {
+        return invalid(format!("fetch: URL {:?} contains whitespace or control characters", request.url));
+    }
+    for (name, value) in &request.headers {
+        if !is_token(name) {
+            return invalid(format!("fetch: {name:?} is not a valid header name"));
+        }
+        if value.chars().any(|c| c == '\r' || c == '\n' || c == '\0') {
+            return invalid(format!("fetch: the value of header {name:?} contains a line break"));
+        }
+    }
+    Ok(())
+}
+
+fn net_fetch(host: &HostContext, args: &[Value]) -> Value {
+    if args.is_empty() || args.len() > 2 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "fetch: invalid parameter count. 1 or 2 expected"));
+    }
+    let url = match os_path_arg("fetch", &args[0]) { Ok(u) => u, Err(e) => return e };
+    let mut request = HttpRequest { method: "GET".to_string(), url: url.to_string(), headers: Vec::new(), body: Vec::new() };
+    let mut parse_body = false;
+    match args.get(1) {
+        None | Some(Value::Nil) => {}
+        Some(Value::Error(e)) => return Value::Error(e.clone()),
+        Some(Value::Obj(o)) if matches!(&**o, Obj::Kvc(_)) => {
+            let Obj::Kvc(k) = &**o else { unreachable!() };
+            let options = k.lock().unwrap().cache.clone();
+            match options.get("method") {
+                None | Some(Value::Nil) => {}
+                Some(v) => match os_path_arg("fetch", v) {
+                    Ok(m) => request.method = m.to_ascii_uppercase(),
+                    Err(e) => return e,
+                },
+            }
+            match options.get("headers") {
+                None | Some(Value::Nil) => {}
+                Some(Value::Obj(h)) if matches!(&**h, Obj::Kvc(_)) => {
+                    let Obj::Kvc(h) = &**h else { unreachable!() };
+                    for (name, v) in h.lock().unwrap().cached_entries() {
+                        match v {
+                            None | Some(Value::Nil) => {}
+                            Some(Value::Error(e)) => return Value::Error(e),
+                            Some(v) => request.headers.push((name, fetch_text(&v))),
+                        }
+                    }
+                }
+                Some(Value::Error(e)) => return Value::Error(e.clone()),
+                Some(_) => return Value::error(FsError::new(ErrorKind::TypeMismatch, "fetch: headers must be a key-value collection")),
+            }
+            match options.get("body") {
+                None | Some(Value::Nil) => {}
+                Some(Value::Error(e)) => return Value::Error(e.clone()),
+                Some(Value::Obj(b)) => match &**b {
+                    Obj::String(s) => request.body = s.as_bytes().to_vec(),
+                    Obj::Bytes(b) => request.body = b.clone(),
+                    Obj::List(_) | Obj::Kvc(_) => {
+                        request.body = format_json_value(&options["body"]).into_bytes();
+                        if !request.headers.iter().any(|(n, _)| n.eq_ignore_ascii_case("content-type")) {
+                            request.headers.push(("Content-Type".to_string(), "application/json".to_string()));
+                        }
+                    }
+                    _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "fetch: body must be text, bytes, a list or a key-value collection")),
+                },
+                Some(_) => return Value::error(FsError::new(ErrorKind::TypeMismatch, "fetch: body must be text, bytes, a list or a key-value collection")),
+            }
+            match options.get("json") {
+                None | Some(Value::Nil) => {}
+                Some(Value::Bool(b)) => parse_body = *b,
+                Some(Value::Error(e)) => return Value::Error(e.clone()),
+                Some(_) => return Value::error(FsError::new(ErrorKind::TypeMismatch, "fetch: json must be a boolean")),
+            }
+        }
+        Some(_) => return Value::error(FsError::new(ErrorKind::TypeMismatch, "fetch: options must be a key-value collection")),
+    };
+    if let Err(e) = check_request_head(&request) {
+        return e;
+    }
+    let response = match host.fetch(&request) {
+        Ok(r) => r,
+        Err(e) => return Value::error(e),
+    };
+    let body = String::from_utf8_lossy(&response.body).into_owned();
+    let headers = kvc_from_cache(
+        response.headers.iter().map(|(n, v)| (n.as_str(), Value::Obj(Arc::new(Obj::String(v.as_str().into()))))).collect(),
+    );
+    let mut fields = vec![
+        ("status", Value::Int(i64::from(response.status))),
+        ("ok", Value::Bool((200..300).contains(&response.status))),
+        ("headers", headers),
+    ];
+    if parse_body {
+        let json = Json::parse(&body)
+            .map(|j| json_to_value(&j))
+            .unwrap_or_else(|msg| Value::error(FsError::new(ErrorKind::InvalidValue, format!("fetch: response body is not valid JSON: {msg}"))));
+        fields.push(("json", json));
+    }
+    fields.push(("body", Value::Obj(Arc::new(Obj::String(body.into())))));
+    kvc_from_cache(fields)
+}
+
+fn fetch_text(v: &Value) -> String {
+    match v {
+        Value::Obj(o) => match &**o {
+            Obj::String(s) => s.to_string(),
+            _ => v.to_string(),
+        },
+        _ => v.to_string(),
+    }
+}
+
+/// Converts parsed JSON into FuncScript values: objects become KVCs, integers that fit
+/// become `Int`s and other numbers `Number`s.
+fn json_to_value(json: &Json) -> Value {
+    match json {
+        Json::Null => Value::Nil,
+        Json::Bool(b) => Value::Bool(*b),
+        Json::Int(n) => Value::Int(*n),
+        Json::Number(n) => Value::Number(*n),
+        Json::String(s) => Value::Obj(Arc::new(Obj::String(s.as_str().into()))),
+        Json::Array(items) => Value::Obj(Arc::new(Obj::List(items.iter().map(json_to_value).collect()))),
+        Json::Object(members) => {
+            let mut fields: Vec<(&str, Value)> = Vec::with_capacity(members.len());
+            for (name, v) in members {
+                fields.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+                fields.push((name, json_to_value(v)));
+            }
+            kvc_from_cache(fields)
         }
-        Err(e) => Value::Error(e),
     }
 }
 
@@ -753,11 +1293,11 @@ fn fs_len(args: &[Value]) -> Value {
     }
     match &args[0] {
         Value::Obj(o) => match &**o {
-            Obj::String(s) => Value::Int(s.len() as i64),
+            Obj::String(s) => Value::Int(s.chars().count() as i64),
             Obj::List(l) => Value::Int(l.len() as i64),
             Obj::Range(r) => Value::Int(r.count as i64),
             Obj::Bytes(b) => Value::Int(b.len() as i64),
-            Obj::Kvc(k) => Value::Int(k.borrow().order.len() as i64),
+            Obj::Kvc(k) => Value::Int(k.lock().unwrap().order.len() as i64),
             _ => Value::Nil,
         },
         _ => Value::Nil,
@@ -779,7 +1319,7 @@ fn fs_first(args: &[Value]) -> Value {
                  if b.is_empty() { Value::Nil } else { Value::Int(b[0] as i64) }
              }
              Obj::String(s) => if !s.is_empty() { 
-                 Value::Obj(Rc::new(Obj::String(s[0..1].to_string()))) 
+                 Value::Obj(Arc::new(Obj::String(s[0..1].to_string().into()))) 
              } else { Value::Nil },
              _ => Value::Nil,
         },
@@ -826,7 +1366,7 @@ fn fs_range(args: &[Value]) -> Value {
         || matches!(&args[1], Value::Number(n) if n.is_finite() && *n < 0.0)
         || matches!(&args[1], Value::BigInt(n) if n.sign() == num_bigint::Sign::Minus)
     {
-        return Value::Error(FsError { code: 1, message: "Range: count must be >= 0".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::InvalidValue, "Range: count must be >= 0"));
     }
     let count = match as_usize_exact(&args[1]) {
         Some(n) => n,
@@ -834,14 +1374,14 @@ fn fs_range(args: &[Value]) -> Value {
     };
 
     if count == 0 {
-        return Value::Obj(Rc::new(Obj::Range(crate::obj::RangeObject { start: start_i, count: 0 })));
+        return Value::Obj(Arc::new(Obj::Range(crate::obj::RangeObject { start: start_i, count: 0 })));
     }
 
     if start_i.checked_add((count - 1) as i64).is_none() {
-        return Value::Error(FsError { code: 1, message: "Range: overflow".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::OutOfRange, "Range: overflow"));
     }
 
-    Value::Obj(Rc::new(Obj::Range(crate::obj::RangeObject { start: start_i, count })))
+    Value::Obj(Arc::new(Obj::Range(crate::obj::RangeObject { start: start_i, count })))
 }
 
 fn fs_and(args: &[Value]) -> Value {
@@ -855,7 +1395,7 @@ fn fs_and(args: &[Value]) -> Value {
                 has_bool = true;
                 if !*b { return Value::Bool(false); }
             }
-            _ => return Value::Error(FsError { code: 2, message: "and doesn't apply to this type".to_string(), line: -1, column: -1 }),
+            _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "and doesn't apply to this type")),
         }
     }
     if !has_bool { Value::Nil } else { Value::Bool(true) }
@@ -870,23 +1410,23 @@ fn fs_or(args: &[Value]) -> Value {
             Value::Nil => continue,
             Value::Error(e) => {
                 if first_error.is_none() {
-                    first_error = Some(e.clone());
+                    first_error = Some((**e).clone());
                 }
             }
             Value::Bool(b) => {
                 has_bool = true;
                 if *b { return Value::Bool(true); }
             }
-            _ => return Value::Error(FsError { code: 2, message: "or doesn't apply to this type".to_string(), line: -1, column: -1 }),
+            _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "or doesn't apply to this type")),
         }
     }
-    if let Some(e) = first_error { return Value::Error(e); }
+    if let Some(e) = first_error { return Value::error(e); }
     if !has_bool { Value::Nil } else { Value::Bool(false) }
 }
 
 fn fs_in(args: &[Value]) -> Value {
     if args.len() != 2 {
-        return Value::Error(FsError { code: 3, message: "in: invalid parameter count".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "in: invalid parameter count"));
     }
     let needle = &args[0];
     let hay = &args[1];
@@ -917,9 +1457,9 @@ fn fs_in(args: &[Value]) -> Value {
                 }
                 return Value::Bool(true);
             }
-            _ => return Value::Error(FsError { code: 2, message: "in: list expected".to_string(), line: -1, column: -1 }),
+            _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "in: list expected")),
         },
-        _ => return Value::Error(FsError { code: 2, message: "in: list expected".to_string(), line: -1, column: -1 }),
+        _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "in: list expected")),
     };
     for v in list.iter() {
         if matches!(v, Value::Nil) {
@@ -942,25 +1482,34 @@ fn fs_template_merge(args: &[Value]) -> Value {
             _ => out.push_str(&v.to_string()),
         }
     }
-    Value::Obj(Rc::new(Obj::String(out)))
+    Value::Obj(Arc::new(Obj::String(out.into())))
 }
 
-fn fs_sum(args: &[Value]) -> Value {
-    if args.len() != 1 {
+/// `Sum(list, selector?)`: exact over integers, floating point once a `Number` is seen.
+/// Ranges use the closed form, so they are never expanded.
+fn fs_sum(vm: &mut VM, args: &[Value]) -> Value {
+    if args.is_empty() || args.len() > 2 {
         return Value::Nil;
     }
-    if let Value::Error(e) = &args[0] {
+    match aggregate_input(vm, "Sum", args, 0) {
+        Ok(v) => sum_of(&v),
+        Err(e) => e,
+    }
+}
+
+fn sum_of(v: &Value) -> Value {
+    if let Value::Error(e) = v {
         return Value::Error(e.clone());
     }
-    if matches!(args[0], Value::Nil) {
+    if matches!(v, Value::Nil) {
         return Value::Nil;
     }
 
-    fn err(msg: &str) -> Value {
-        Value::Error(FsError { code: 4, message: msg.to_string(), line: -1, column: -1 })
+    fn err(kind: ErrorKind, msg: &str) -> Value {
+        Value::error(FsError::new(kind, msg))
     }
 
-    match &args[0] {
+    match v {
         Value::Obj(o) => match &**o {
             Obj::Range(r) => {
                 if r.count == 0 {
@@ -970,7 +1519,7 @@ fn fs_sum(args: &[Value]) -> Value {
                 let a0 = BigInt::from(r.start);
                 let a_last = BigInt::from(r.start + (r.count as i64) - 1);
                 let sum = (n.clone() * (a0 + a_last)) / BigInt::from(2);
-                if let Some(v) = sum.to_i64() { Value::Int(v) } else { Value::BigInt(sum) }
+                if let Some(v) = sum.to_i64() { Value::Int(v) } else { Value::bigint(sum) }
             }
             Obj::List(items) => {
                 let mut sum_i = BigInt::from(0);
@@ -988,11 +1537,11 @@ fn fs_sum(args: &[Value]) -> Value {
                             if let Some(sf) = sum_f.as_mut() {
                                 let nf = match n.to_f64() {
                                     Some(x) => x,
-                                    None => return err("Sum: bigint too large for float sum"),
+                                    None => return err(ErrorKind::OutOfRange, "Sum: bigint too large for float sum"),
                                 };
                                 *sf += nf;
                             } else {
-                                sum_i += n.clone();
+                                sum_i += &**n;
                             }
                         }
                         Value::Number(n) if n.is_finite() => {
@@ -1002,7 +1551,7 @@ fn fs_sum(args: &[Value]) -> Value {
                         }
                         Value::Nil => {}
                         Value::Error(e) => return Value::Error(e.clone()),
-                        _ => return err("Sum: expects list/range of numbers"),
+                        _ => return err(ErrorKind::TypeMismatch, "Sum: expects list/range of numbers"),
                     }
                 }
                 if let Some(sf) = sum_f {
@@ -1010,7 +1559,7 @@ fn fs_sum(args: &[Value]) -> Value {
                 } else if let Some(v) = sum_i.to_i64() {
                     Value::Int(v)
                 } else {
-                    Value::BigInt(sum_i)
+                    Value::bigint(sum_i)
                 }
             }
             _ => Value::Nil,
@@ -1019,18 +1568,25 @@ fn fs_sum(args: &[Value]) -> Value {
     }
 }
 
-fn fs_sum_approx(args: &[Value]) -> Value {
-    if args.len() != 1 {
+fn fs_sum_approx(vm: &mut VM, args: &[Value]) -> Value {
+    if args.is_empty() || args.len() > 2 {
         return Value::Nil;
     }
-    if let Value::Error(e) = &args[0] {
+    match aggregate_input(vm, "SumApprox", args, 0) {
+        Ok(v) => sum_approx_of(&v),
+        Err(e) => e,
+    }
+}
+
+fn sum_approx_of(v: &Value) -> Value {
+    if let Value::Error(e) = v {
         return Value::Error(e.clone());
     }
-    if matches!(args[0], Value::Nil) {
+    if matches!(v, Value::Nil) {
         return Value::Nil;
     }
 
-    match &args[0] {
+    match v {
         Value::Obj(o) => match &**o {
             Obj::Range(r) => {
                 let n = r.count as f64;
@@ -1049,14 +1605,14 @@ fn fs_sum_approx(args: &[Value]) -> Value {
                         Value::BigInt(n) => {
                             let nf = match n.to_f64() {
                                 Some(x) => x,
-                                None => return Value::Error(FsError { code: 4, message: "SumApprox: bigint too large".to_string(), line: -1, column: -1 }),
+                                None => return Value::error(FsError::new(ErrorKind::OutOfRange, "SumApprox: bigint too large")),
                             };
                             sum += nf;
                         }
                         Value::Number(n) if n.is_finite() => sum += *n,
                         Value::Nil => {}
                         Value::Error(e) => return Value::Error(e.clone()),
-                        _ => return Value::Error(FsError { code: 4, message: "SumApprox: expects list/range of numbers".to_string(), line: -1, column: -1 }),
+                        _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "SumApprox: expects list/range of numbers")),
                     }
                 }
                 Value::Number(sum)
@@ -1067,47 +1623,75 @@ fn fs_sum_approx(args: &[Value]) -> Value {
     }
 }
 
-fn fs_guid(args: &[Value]) -> Value {
+fn fs_guid(host: &HostContext, args: &[Value]) -> Value {
+    if args.is_empty() {
+        let bytes = ((host.random_u64() as u128) << 64 | host.random_u64() as u128).to_le_bytes();
+        return Value::Obj(Arc::new(Obj::Guid(uuid::Builder::from_random_bytes(bytes).into_uuid())));
+    }
     if args.len() != 1 { return Value::Nil; }
     if let Value::Error(e) = &args[0] { return Value::Error(e.clone()); }
     if matches!(args[0], Value::Nil) { return Value::Nil; }
     let s = match &args[0] {
         Value::Obj(o) => match &**o {
             Obj::String(s) => s.as_str(),
-            _ => return Value::Error(FsError { code: 2, message: "guid: string expected".to_string(), line: -1, column: -1 }),
+            _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "guid: string expected")),
         },
-        _ => return Value::Error(FsError { code: 2, message: "guid: string expected".to_string(), line: -1, column: -1 }),
+        _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "guid: string expected")),
     };
     match Uuid::parse_str(s) {
-        Ok(u) => Value::Obj(Rc::new(Obj::Guid(u))),
-        Err(_) => Value::Error(FsError { code: 1, message: format!("guid: '{s}' is not a valid GUID"), line: -1, column: -1 }),
+        Ok(u) => Value::Obj(Arc::new(Obj::Guid(u))),
+        Err(_) => Value::error(FsError::new(ErrorKind::InvalidValue, format!("guid: '{s}' is not a valid GUID"))),
     }
 }
 
+
+fn fs_now(host: &HostContext, args: &[Value]) -> Value {
+    if !args.is_empty() { return Value::error(FsError::new(ErrorKind::ParameterCount, "now: no parameters expected")); }
+    let t = host.now();
+    Value::Obj(Arc::new(Obj::DateTimeTicks(t.utc_ticks + t.offset_minutes as i64 * TICKS_PER_MINUTE, host_offset(t.offset_minutes))))
+}
+
+/// The host's local offset as a date offset; out-of-range offsets leave the date without one.
+fn host_offset(offset_minutes: i32) -> Option<i16> {
+    i16::try_from(offset_minutes).ok().filter(|m| (-crate::datetime::MAX_OFFSET_MINUTES..=crate::datetime::MAX_OFFSET_MINUTES).contains(m))
+}
+
+fn fs_utc_now(host: &HostContext, args: &[Value]) -> Value {
+    if !args.is_empty() { return Value::error(FsError::new(ErrorKind::ParameterCount, "utcnow: no parameters expected")); }
+    Value::Obj(Arc::new(Obj::DateTimeTicks(host.now().utc_ticks, None)))
+}
+
+fn fs_today(host: &HostContext, args: &[Value]) -> Value {
+    if !args.is_empty() { return Value::error(FsError::new(ErrorKind::ParameterCount, "today: no parameters expected")); }
+    let t = host.now();
+    let local = t.utc_ticks + t.offset_minutes as i64 * TICKS_PER_MINUTE;
+    Value::Obj(Arc::new(Obj::DateTimeTicks(local - local.rem_euclid(TICKS_PER_DAY), host_offset(t.offset_minutes))))
+}
+
 fn fs_ticks_to_date(args: &[Value]) -> Value {
-    if args.len() > 1 { return Value::Error(FsError { code: 1, message: "TicksToDate: invalid parameter count".to_string(), line: -1, column: -1 }); }
+    if args.len() > 1 { return Value::error(FsError::new(ErrorKind::ParameterCount, "TicksToDate: invalid parameter count")); }
     if args.is_empty() { return Value::Nil; }
     if let Value::Error(e) = &args[0] { return Value::Error(e.clone()); }
     if matches!(args[0], Value::Nil) { return Value::Nil; }
     let ticks = match as_i64_exact(&args[0]) {
         Some(t) => t,
-        None => return Value::Error(FsError { code: 2, message: "TicksToDate: integer ticks expected".to_string(), line: -1, column: -1 }),
+        None => return Value::error(FsError::new(ErrorKind::TypeMismatch, "TicksToDate: integer ticks expected")),
     };
-    Value::Obj(Rc::new(Obj::DateTimeTicks(ticks)))
+    Value::Obj(Arc::new(Obj::DateTimeTicks(ticks, None)))
 }
 
 fn fs_date(args: &[Value]) -> Value {
     if args.is_empty() || args.len() > 2 {
-        return Value::Error(FsError { code: 1, message: "Date: invalid parameter count".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "Date: invalid parameter count"));
     }
     if let Value::Error(e) = &args[0] { return Value::Error(e.clone()); }
     if matches!(args[0], Value::Nil) { return Value::Nil; }
     let s = match &args[0] {
         Value::Obj(o) => match &**o {
             Obj::String(s) => s.clone(),
-            _ => return Value::Error(FsError { code: 2, message: "Date: string expected".to_string(), line: -1, column: -1 }),
+            _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "Date: string expected")),
         },
-        _ => return Value::Error(FsError { code: 2, message: "Date: string expected".to_string(), line: -1, column: -1 }),
+        _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "Date: string expected")),
     };
     let format = if args.len() == 2 {
         match &args[1] {
@@ -1115,9 +1699,9 @@ fn fs_date(args: &[Value]) -> Value {
             Value::Error(e) => return Value::Error(e.clone()),
             Value::Obj(o) => match &**o {
                 Obj::String(f) => Some(f.clone()),
-                _ => return Value::Error(FsError { code: 2, message: "Date: format must be a string".to_string(), line: -1, column: -1 }),
+                _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "Date: format must be a string")),
             },
-            _ => return Value::Error(FsError { code: 2, message: "Date: format must be a string".to_string(), line: -1, column: -1 }),
+            _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "Date: format must be a string")),
         }
     } else {
         None
@@ -1126,103 +1710,284 @@ fn fs_date(args: &[Value]) -> Value {
     const UNIX_EPOCH_TICKS: i64 = 621_355_968_000_000_000;
     const TICKS_PER_SEC: i64 = 10_000_000;
 
-    let parse_iso = || -> Option<i64> {
+    // Keeps the clock time and numeric offset as written, so the value round-trips; `Z` is UTC.
+    let parse_iso = || -> Option<(i64, Option<i16>)> {
         let dt = time::OffsetDateTime::parse(&s, &time::format_description::well_known::Rfc3339).ok()?;
-        let unix_seconds = dt.unix_timestamp();
+        let offset_seconds = dt.offset().whole_seconds() as i64;
+        let unix_seconds = dt.unix_timestamp().checked_add(offset_seconds)?;
         let nanos = dt.nanosecond() as i64;
         let ticks = UNIX_EPOCH_TICKS
             .checked_add(unix_seconds.checked_mul(TICKS_PER_SEC)?)
             .and_then(|base| base.checked_add(nanos / 100))?;
-        Some(ticks)
+        let offset = (!s.ends_with(['Z', 'z'])).then(|| i16::try_from(offset_seconds / 60)).transpose().ok()?;
+        Some((ticks, offset))
     };
 
-    let ticks = match format.as_deref() {
+    let parsed = match format.as_deref() {
         None | Some("") => parse_iso(),
         Some("o") | Some("O") => parse_iso(),
-        Some("yyyy-MM-dd") => {
-            time::Date::parse(&s, &time::macros::format_description!("[year]-[month]-[day]"))
-                .ok()
-                .map(|d| {
-                    let dt = d.with_time(time::Time::MIDNIGHT).assume_utc();
-                    let unix_seconds = dt.unix_timestamp();
-                    UNIX_EPOCH_TICKS + unix_seconds * TICKS_PER_SEC
-                })
-        }
-        _ => None,
+        Some(pattern) => crate::datetime::parse(&s, pattern),
     };
 
+    match parsed {
+        Some((t, offset)) => Value::Obj(Arc::new(Obj::DateTimeTicks(t, offset))),
+        None => Value::error(FsError::new(ErrorKind::InvalidValue, format!("Date: String '{s}' can't be converted to date"))),
+    }
+}
+
+/// A date's clock ticks and offset.
+type DateParts = (i64, Option<i16>);
+
+/// Reads the date subject of a date function; `Ok(None)` when it is null.
+fn date_arg(name: &str, v: &Value) -> Result<Option<DateParts>, Value> {
+    match v {
+        Value::Nil => Ok(None),
+        Value::Error(e) => Err(Value::Error(e.clone())),
+        Value::Obj(o) => match &**o {
+            Obj::DateTimeTicks(t, offset) => Ok(Some((*t, *offset))),
+            _ => Err(Value::error(FsError::new(ErrorKind::TypeMismatch, format!("{name}: first parameter must be a date")))),
+        },
+        _ => Err(Value::error(FsError::new(ErrorKind::TypeMismatch, format!("{name}: first parameter must be a date")))),
+    }
+}
+
+/// The date at clock `ticks` with `offset`, or an out-of-range error when there is none.
+pub(crate) fn date_result(name: &str, ticks: Option<i64>, offset: Option<i16>) -> Value {
     match ticks {
-        Some(t) => Value::Obj(Rc::new(Obj::DateTimeTicks(t))),
-        None => Value::Error(FsError { code: 1, message: format!("Date: String '{s}' can't be converted to date"), line: -1, column: -1 }),
+        Some(t) => Value::Obj(Arc::new(Obj::DateTimeTicks(t, offset))),
+        None => Value::error(FsError::new(ErrorKind::OutOfRange, format!("{name}: result is outside 0001-01-01..9999-12-31"))),
     }
 }
 
-fn fs_change_type(args: &[Value]) -> Value {
+fn date_add_days(args: &[Value]) -> Value {
     if args.len() != 2 {
-        return Value::Error(FsError { code: 1, message: "ChangeType: invalid parameter count".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "addDays: two parameters expected"));
     }
-    if let Value::Error(e) = &args[0] { return Value::Error(e.clone()); }
-    if matches!(args[0], Value::Nil) { return Value::Nil; }
-
-    let type_name = match &args[1] {
+    let (ticks, offset) = match date_arg("addDays", &args[0]) {
+        Ok(Some(t)) => t,
+        Ok(None) => return Value::Nil,
+        Err(e) => return e,
+    };
+    let delta = match &args[1] {
         Value::Error(e) => return Value::Error(e.clone()),
-        Value::Obj(o) => match &**o {
-            Obj::String(s) if !s.trim().is_empty() => s.trim().to_string(),
-            _ => return Value::Error(FsError { code: 2, message: "ChangeType: Type name must be a string.".to_string(), line: -1, column: -1 }),
+        Value::Number(n) if n.is_finite() => (n * TICKS_PER_DAY as f64).round(),
+        v => match as_i64_exact(v) {
+            Some(n) => n as f64 * TICKS_PER_DAY as f64,
+            None => return Value::error(FsError::new(ErrorKind::TypeMismatch, "addDays: second parameter must be a number")),
         },
-        _ => return Value::Error(FsError { code: 2, message: "ChangeType: Type name must be a string.".to_string(), line: -1, column: -1 }),
     };
-    let tn = type_name.to_lowercase();
+    if delta.abs() > crate::datetime::MAX_TICKS as f64 {
+        return date_result("addDays", None, offset);
+    }
+    date_result("addDays", crate::datetime::add_ticks(ticks, delta as i64), offset)
+}
 
-    match tn.as_str() {
-        "string" => {
-            match &args[0] {
-                Value::Obj(o) => match &**o {
-                    Obj::Bytes(b) => {
-                        let s = general_purpose::STANDARD.encode(b);
-                        Value::Obj(Rc::new(Obj::String(s)))
-                    }
-                    Obj::Guid(g) => Value::Obj(Rc::new(Obj::String(g.to_string()))),
-                    Obj::DateTimeTicks(t) => Value::Obj(Rc::new(Obj::String(t.to_string()))),
-                    _ => Value::Obj(Rc::new(Obj::String(args[0].to_string()))),
-                },
-                _ => Value::Obj(Rc::new(Obj::String(args[0].to_string()))),
-            }
-        }
-        "integer" => match &args[0] {
-            Value::Int(n) => Value::Int(*n),
-            Value::BigInt(n) => n.to_i64().map(Value::Int).unwrap_or(Value::Error(FsError { code: 1, message: "ChangeType: overflow converting to Integer".to_string(), line: -1, column: -1 })),
-            Value::Number(n) if n.is_finite() && n.fract() == 0.0 => Value::Int(*n as i64),
-            Value::Bool(b) => Value::Int(if *b { 1 } else { 0 }),
-            Value::Obj(o) => match &**o {
-                Obj::String(s) => s.parse::<i64>().map(Value::Int).unwrap_or(Value::Error(FsError { code: 1, message: "ChangeType: invalid Integer".to_string(), line: -1, column: -1 })),
-                _ => Value::Error(FsError { code: 2, message: "ChangeType: Can't convert to Integer.".to_string(), line: -1, column: -1 }),
-            },
-            _ => Value::Error(FsError { code: 2, message: "ChangeType: Can't convert to Integer.".to_string(), line: -1, column: -1 }),
-        },
-        "biginteger" => match &args[0] {
-            Value::Int(n) => Value::BigInt(BigInt::from(*n)),
-            Value::BigInt(n) => Value::BigInt(n.clone()),
-            Value::Number(n) if n.is_finite() && n.fract() == 0.0 => Value::BigInt(BigInt::from(*n as i64)),
-            Value::Bool(b) => Value::BigInt(BigInt::from(if *b { 1 } else { 0 })),
-            Value::Obj(o) => match &**o {
-                Obj::String(s) => BigInt::parse_bytes(s.trim().as_bytes(), 10)
-                    .map(Value::BigInt)
-                    .unwrap_or(Value::Error(FsError { code: 1, message: "ChangeType: invalid BigInteger".to_string(), line: -1, column: -1 })),
-                _ => Value::Error(FsError { code: 2, message: "ChangeType: Can't convert to BigInteger.".to_string(), line: -1, column: -1 }),
-            },
-            _ => Value::Error(FsError { code: 2, message: "ChangeType: Can't convert to BigInteger.".to_string(), line: -1, column: -1 }),
+fn add_months_with(name: &str, months_per_unit: i64, args: &[Value]) -> Value {
+    if args.len() != 2 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, format!("{name}: two parameters expected")));
+    }
+    let (ticks, offset) = match date_arg(name, &args[0]) {
+        Ok(Some(t)) => t,
+        Ok(None) => return Value::Nil,
+        Err(e) => return e,
+    };
+    let units = match &args[1] {
+        Value::Error(e) => return Value::Error(e.clone()),
+        v => match as_i64_exact(v) {
+            Some(n) => n,
+            None => return Value::error(FsError::new(ErrorKind::TypeMismatch, format!("{name}: second parameter must be a whole number"))),
         },
-        "float" => match &args[0] {
-            Value::Number(n) => Value::Number(*n),
-            Value::Int(n) => Value::Number(*n as f64),
-            Value::BigInt(n) => n.to_f64().map(Value::Number).unwrap_or(Value::Error(FsError { code: 1, message: "ChangeType: overflow converting to Float".to_string(), line: -1, column: -1 })),
-            Value::Bool(b) => Value::Number(if *b { 1.0 } else { 0.0 }),
-            Value::Obj(o) => match &**o {
-                Obj::String(s) => s.parse::<f64>().map(Value::Number).unwrap_or(Value::Error(FsError { code: 1, message: "ChangeType: invalid Float".to_string(), line: -1, column: -1 })),
-                _ => Value::Error(FsError { code: 2, message: "ChangeType: Can't convert to Float.".to_string(), line: -1, column: -1 }),
+    };
+    let months = units.checked_mul(months_per_unit).filter(|m| m.unsigned_abs() <= 12 * 10_000);
+    date_result(name, months.and_then(|m| crate::datetime::add_months(ticks, m)), offset)
+}
+
+fn date_add_months(args: &[Value]) -> Value {
+    add_months_with("addMonths", 1, args)
+}
+
+fn date_add_years(args: &[Value]) -> Value {
+    add_months_with("addYears", 12, args)
+}
+
+fn period_bound(name: &str, args: &[Value], bound: fn(i64, Period) -> Option<i64>) -> Value {
+    if args.len() != 2 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, format!("{name}: two parameters expected")));
+    }
+    let (ticks, offset) = match date_arg(name, &args[0]) {
+        Ok(Some(t)) => t,
+        Ok(None) => return Value::Nil,
+        Err(e) => return e,
+    };
+    let period = match regex_string_arg(name, "second", &args[1]) {
+        Ok(p) => p,
+        Err(e) => return e,
+    };
+    match Period::parse(&period) {
+        Some(p) => date_result(name, bound(ticks, p), offset),
+        None => Value::error(FsError::new(ErrorKind::InvalidValue, format!("{name}: unknown period '{period}', expected day, week, month or year"))),
+    }
+}
+
+fn date_start_of(args: &[Value]) -> Value {
+    period_bound("startOf", args, crate::datetime::start_of)
+}
+
+fn date_end_of(args: &[Value]) -> Value {
+    period_bound("endOf", args, crate::datetime::end_of)
+}
+
+/// `toOffset(date, offset)`: the same instant at `offset`, given as text (`"+03:00"`, `"Z"`)
+/// or a duration of whole minutes.
+fn date_to_offset(args: &[Value]) -> Value {
+    if args.len() != 2 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "toOffset: two parameters expected"));
+    }
+    let (ticks, offset) = match date_arg("toOffset", &args[0]) {
+        Ok(Some(t)) => t,
+        Ok(None) => return Value::Nil,
+        Err(e) => return e,
+    };
+    let invalid = |what: String| {
+        Value::error(FsError::new(ErrorKind::InvalidValue, format!("toOffset: {what} is not a UTC offset such as +03:00 (at most ±14:00)")))
+    };
+    let target = match &args[1] {
+        Value::Error(e) => return Value::Error(e.clone()),
+        Value::Obj(o) => match &**o {
+            Obj::String(s) => match crate::datetime::parse_offset(s) {
+                Some(m) => m,
+                None => return invalid(format!("'{s}'")),
+            },
+            Obj::Duration(t) => {
+                let minutes = t / TICKS_PER_MINUTE;
+                if t % TICKS_PER_MINUTE != 0 || minutes.abs() > crate::datetime::MAX_OFFSET_MINUTES as i64 {
+                    return invalid(crate::datetime::duration_text(*t));
+                }
+                minutes as i16
+            }
+            _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "toOffset: second parameter must be text or a duration")),
+        },
+        _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "toOffset: second parameter must be text or a duration")),
+    };
+    date_result("toOffset", crate::datetime::to_offset(ticks, offset, target), Some(target))
+}
+
+fn date_utc(args: &[Value]) -> Value {
+    if args.len() != 1 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "utc: one parameter expected"));
+    }
+    match date_arg("utc", &args[0]) {
+        Ok(Some((ticks, offset))) => date_result("utc", crate::datetime::to_offset(ticks, offset, 0), None),
+        Ok(None) => Value::Nil,
+        Err(e) => e,
+    }
+}
+
+/// `duration("P1DT2H")`, `duration(hours, minutes, seconds)` or
+/// `duration(days, hours, minutes, seconds)`; numeric parts may be fractional or negative.
+fn fs_duration(args: &[Value]) -> Value {
+    if let Some(Value::Error(e)) = args.iter().find(|a| matches!(a, Value::Error(_))) {
+        return Value::Error(e.clone());
+    }
+    let units: &[i64] = match args.len() {
+        1 => {
+            let text = match text_subject("duration", &args[0]) {
+                Ok(Some(t)) => t,
+                Ok(None) => return Value::Nil,
+                Err(e) => return e,
+            };
+            return match crate::datetime::parse_iso_duration(&text) {
+                Some(t) => Value::Obj(Arc::new(Obj::Duration(t))),
+                None => Value::error(FsError::new(ErrorKind::InvalidValue, format!("duration: '{text}' is not an ISO 8601 duration such as P1DT2H30M"))),
+            };
+        }
+        3 => &[60 * TICKS_PER_MINUTE, TICKS_PER_MINUTE, TICKS_PER_SECOND],
+        4 => &[TICKS_PER_DAY, 60 * TICKS_PER_MINUTE, TICKS_PER_MINUTE, TICKS_PER_SECOND],
+        _ => return Value::error(FsError::new(ErrorKind::ParameterCount, "duration: expected an ISO 8601 string, (hours, minutes, seconds) or (days, hours, minutes, seconds)")),
+    };
+    let mut total: i128 = 0;
+    for (v, unit) in args.iter().zip(units) {
+        let part = match v {
+            Value::Number(n) if n.is_finite() && n.abs() < 1e19 => (n * *unit as f64).round() as i128,
+            v => match as_i64_exact(v) {
+                Some(n) => n as i128 * *unit as i128,
+                None => return Value::error(FsError::new(ErrorKind::TypeMismatch, "duration: parts must be numbers")),
+            },
+        };
+        total += part;
+    }
+    match i64::try_from(total) {
+        Ok(t) => Value::Obj(Arc::new(Obj::Duration(t))),
+        Err(_) => Value::error(FsError::new(ErrorKind::OutOfRange, "duration: value is too large")),
+    }
+}
+
+fn fs_change_type(args: &[Value]) -> Value {
+    if args.len() != 2 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "ChangeType: invalid parameter count"));
+    }
+    if let Value::Error(e) = &args[0] { return Value::Error(e.clone()); }
+    if matches!(args[0], Value::Nil) { return Value::Nil; }
+
+    let type_name = match &args[1] {
+        Value::Error(e) => return Value::Error(e.clone()),
+        Value::Obj(o) => match &**o {
+            Obj::String(s) if !s.trim().is_empty() => s.trim().to_string(),
+            _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "ChangeType: Type name must be a string.")),
+        },
+        _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "ChangeType: Type name must be a string.")),
+    };
+    let tn = type_name.to_lowercase();
+
+    match tn.as_str() {
+        "string" => {
+            match &args[0] {
+                Value::Obj(o) => match &**o {
+                    Obj::Bytes(b) => {
+                        let s = general_purpose::STANDARD.encode(b);
+                        Value::Obj(Arc::new(Obj::String(s.into())))
+                    }
+                    Obj::Guid(g) => Value::Obj(Arc::new(Obj::String(g.to_string().into()))),
+                    Obj::DateTimeTicks(t, _) => Value::Obj(Arc::new(Obj::String(t.to_string().into()))),
+                    _ => Value::Obj(Arc::new(Obj::String(args[0].to_string().into()))),
+                },
+                _ => Value::Obj(Arc::new(Obj::String(args[0].to_string().into()))),
+            }
+        }
+        "integer" => match &args[0] {
+            Value::Int(n) => Value::Int(*n),
+            Value::BigInt(n) => n.to_i64().map(Value::Int).unwrap_or(Value::error(FsError::new(ErrorKind::OutOfRange, "ChangeType: overflow converting to Integer"))),
+            Value::Number(n) if n.is_finite() && n.fract() == 0.0 => Value::Int(*n as i64),
+            Value::Bool(b) => Value::Int(if *b { 1 } else { 0 }),
+            Value::Obj(o) => match &**o {
+                Obj::String(s) => s.parse::<i64>().map(Value::Int).unwrap_or(Value::error(FsError::new(ErrorKind::InvalidValue, "ChangeType: invalid Integer"))),
+                _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "ChangeType: Can't convert to Integer.")),
             },
-            _ => Value::Error(FsError { code: 2, message: "ChangeType: Can't convert to Float.".to_string(), line: -1, column: -1 }),
+            _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "ChangeType: Can't convert to Integer.")),
+        },
+        "biginteger" => match &args[0] {
+            Value::Int(n) => Value::bigint(BigInt::from(*n)),
+            Value::BigInt(n) => Value::BigInt(n.clone()),
+            Value::Number(n) if n.is_finite() && n.fract() == 0.0 => Value::bigint(BigInt::from(*n as i64)),
+            Value::Bool(b) => Value::bigint(BigInt::from(if *b { 1 } else { 0 })),
+            Value::Obj(o) => match &**o {
+                Obj::String(s) => BigInt::parse_bytes(s.trim().as_bytes(), 10)
+                    .map(Value::bigint)
+                    .unwrap_or(Value::error(FsError::new(ErrorKind::InvalidValue, "ChangeType: invalid BigInteger"))),
+                _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "ChangeType: Can't convert to BigInteger.")),
+            },
+            _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "ChangeType: Can't convert to BigInteger.")),
+        },
+        "float" => match &args[0] {
+            Value::Number(n) => Value::Number(*n),
+            Value::Int(n) => Value::Number(*n as f64),
+            Value::BigInt(n) => n.to_f64().map(Value::Number).unwrap_or(Value::error(FsError::new(ErrorKind::OutOfRange, "ChangeType: overflow converting to Float"))),
+            Value::Bool(b) => Value::Number(if *b { 1.0 } else { 0.0 }),
+            Value::Obj(o) => match &**o {
+                Obj::String(s) => s.parse::<f64>().map(Value::Number).unwrap_or(Value::error(FsError::new(ErrorKind::InvalidValue, "ChangeType: invalid Float"))),
+                _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "ChangeType: Can't convert to Float.")),
+            },
+            _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "ChangeType: Can't convert to Float.")),
         },
         "boolean" => match &args[0] {
             Value::Bool(b) => Value::Bool(*b),
@@ -1230,80 +1995,80 @@ fn fs_change_type(args: &[Value]) -> Value {
             Value::BigInt(n) => Value::Bool(!n.is_zero()),
             Value::Number(n) => Value::Bool(*n != 0.0),
             Value::Obj(o) => match &**o {
-                Obj::String(s) => s.parse::<bool>().map(Value::Bool).unwrap_or(Value::Error(FsError { code: 1, message: "ChangeType: invalid Boolean".to_string(), line: -1, column: -1 })),
-                _ => Value::Error(FsError { code: 2, message: "ChangeType: Can't convert to Boolean.".to_string(), line: -1, column: -1 }),
+                Obj::String(s) => s.parse::<bool>().map(Value::Bool).unwrap_or(Value::error(FsError::new(ErrorKind::InvalidValue, "ChangeType: invalid Boolean"))),
+                _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "ChangeType: Can't convert to Boolean.")),
             },
-            _ => Value::Error(FsError { code: 2, message: "ChangeType: Can't convert to Boolean.".to_string(), line: -1, column: -1 }),
+            _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "ChangeType: Can't convert to Boolean.")),
         },
         "guid" => match &args[0] {
             Value::Obj(o) => match &**o {
-                Obj::Guid(g) => Value::Obj(Rc::new(Obj::Guid(*g))),
+                Obj::Guid(g) => Value::Obj(Arc::new(Obj::Guid(*g))),
                 Obj::String(s) => match Uuid::parse_str(s) {
-                    Ok(u) => Value::Obj(Rc::new(Obj::Guid(u))),
-                    Err(_) => Value::Error(FsError { code: 1, message: "ChangeType: invalid Guid".to_string(), line: -1, column: -1 }),
+                    Ok(u) => Value::Obj(Arc::new(Obj::Guid(u))),
+                    Err(_) => Value::error(FsError::new(ErrorKind::InvalidValue, "ChangeType: invalid Guid")),
                 },
-                _ => Value::Error(FsError { code: 2, message: "ChangeType: Can't convert to Guid.".to_string(), line: -1, column: -1 }),
+                _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "ChangeType: Can't convert to Guid.")),
             },
-            _ => Value::Error(FsError { code: 2, message: "ChangeType: Can't convert to Guid.".to_string(), line: -1, column: -1 }),
+            _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "ChangeType: Can't convert to Guid.")),
         },
         "datetime" => match &args[0] {
             Value::Obj(o) => match &**o {
-                Obj::DateTimeTicks(t) => Value::Obj(Rc::new(Obj::DateTimeTicks(*t))),
-                Obj::String(s) => fs_date(&[Value::Obj(Rc::new(Obj::String(s.clone()))) ]),
-                _ => Value::Error(FsError { code: 2, message: "ChangeType: Can't convert to DateTime.".to_string(), line: -1, column: -1 }),
+                Obj::DateTimeTicks(t, offset) => Value::Obj(Arc::new(Obj::DateTimeTicks(*t, *offset))),
+                Obj::String(s) => fs_date(&[Value::Obj(Arc::new(Obj::String(s.clone()))) ]),
+                _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "ChangeType: Can't convert to DateTime.")),
             },
-            Value::Int(t) => Value::Obj(Rc::new(Obj::DateTimeTicks(*t))),
-            Value::BigInt(t) => t.to_i64().map(|x| Value::Obj(Rc::new(Obj::DateTimeTicks(x)))).unwrap_or(Value::Error(FsError { code: 1, message: "ChangeType: overflow converting to DateTime".to_string(), line: -1, column: -1 })),
-            _ => Value::Error(FsError { code: 2, message: "ChangeType: Can't convert to DateTime.".to_string(), line: -1, column: -1 }),
+            Value::Int(t) => Value::Obj(Arc::new(Obj::DateTimeTicks(*t, None))),
+            Value::BigInt(t) => t.to_i64().map(|x| Value::Obj(Arc::new(Obj::DateTimeTicks(x, None)))).unwrap_or(Value::error(FsError::new(ErrorKind::OutOfRange, "ChangeType: overflow converting to DateTime"))),
+            _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "ChangeType: Can't convert to DateTime.")),
         },
         "bytearray" => match &args[0] {
             Value::Obj(o) => match &**o {
-                Obj::Bytes(b) => Value::Obj(Rc::new(Obj::Bytes(b.clone()))),
+                Obj::Bytes(b) => Value::Obj(Arc::new(Obj::Bytes(b.clone()))),
                 Obj::String(s) => match general_purpose::STANDARD.decode(s.trim()) {
-                    Ok(bytes) => Value::Obj(Rc::new(Obj::Bytes(bytes))),
-                    Err(_) => Value::Error(FsError { code: 1, message: "ChangeType: invalid base64 for ByteArray".to_string(), line: -1, column: -1 }),
+                    Ok(bytes) => Value::Obj(Arc::new(Obj::Bytes(bytes))),
+                    Err(_) => Value::error(FsError::new(ErrorKind::InvalidValue, "ChangeType: invalid base64 for ByteArray")),
                 },
-                _ => Value::Error(FsError { code: 2, message: "ChangeType: Can't convert to ByteArray.".to_string(), line: -1, column: -1 }),
+                _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "ChangeType: Can't convert to ByteArray.")),
             },
-            _ => Value::Error(FsError { code: 2, message: "ChangeType: Can't convert to ByteArray.".to_string(), line: -1, column: -1 }),
+            _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "ChangeType: Can't convert to ByteArray.")),
         },
-        _ => Value::Error(FsError { code: 1, message: format!("ChangeType: Unknown target type '{type_name}'."), line: -1, column: -1 }),
+        _ => Value::error(FsError::new(ErrorKind::InvalidValue, format!("ChangeType: Unknown target type '{type_name}'."))),
     }
 }
 
 fn text_lower(args: &[Value]) -> Value {
     if args.len() != 1 {
-        return Value::Error(FsError { code: 1, message: "lower: single string parameter expected".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "lower: single string parameter expected"));
     }
     match &args[0] {
         Value::Error(e) => Value::Error(e.clone()),
         Value::Nil => Value::Nil,
         Value::Obj(o) => match &**o {
-            Obj::String(s) => Value::Obj(Rc::new(Obj::String(s.to_lowercase()))),
-            _ => Value::Error(FsError { code: 2, message: "lower: string parameter expected".to_string(), line: -1, column: -1 }),
+            Obj::String(s) => Value::Obj(Arc::new(Obj::String(s.to_lowercase().into()))),
+            _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "lower: string parameter expected")),
         },
-        _ => Value::Error(FsError { code: 2, message: "lower: string parameter expected".to_string(), line: -1, column: -1 }),
+        _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "lower: string parameter expected")),
     }
 }
 
 fn text_upper(args: &[Value]) -> Value {
     if args.len() != 1 {
-        return Value::Error(FsError { code: 1, message: "upper: single string parameter expected".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "upper: single string parameter expected"));
     }
     match &args[0] {
         Value::Error(e) => Value::Error(e.clone()),
         Value::Nil => Value::Nil,
         Value::Obj(o) => match &**o {
-            Obj::String(s) => Value::Obj(Rc::new(Obj::String(s.to_uppercase()))),
-            _ => Value::Error(FsError { code: 2, message: "upper: string parameter expected".to_string(), line: -1, column: -1 }),
+            Obj::String(s) => Value::Obj(Arc::new(Obj::String(s.to_uppercase().into()))),
+            _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "upper: string parameter expected")),
         },
-        _ => Value::Error(FsError { code: 2, message: "upper: string parameter expected".to_string(), line: -1, column: -1 }),
+        _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "upper: string parameter expected")),
     }
 }
 
 fn text_endswith(args: &[Value]) -> Value {
     if args.len() != 2 {
-        return Value::Error(FsError { code: 1, message: "endswith: two parameters expected".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "endswith: two parameters expected"));
     }
     if matches!(&args[0], Value::Nil) || matches!(&args[1], Value::Nil) {
         return Value::Bool(false);
@@ -1311,10 +2076,10 @@ fn text_endswith(args: &[Value]) -> Value {
     match (&args[0], &args[1]) {
         (Value::Error(e), _) | (_, Value::Error(e)) => Value::Error(e.clone()),
         (Value::Obj(a), Value::Obj(b)) => match (&**a, &**b) {
-            (Obj::String(s1), Obj::String(s2)) => Value::Bool(s1.ends_with(s2)),
-            _ => Value::Error(FsError { code: 2, message: "endswith: both parameters must be strings".to_string(), line: -1, column: -1 }),
+            (Obj::String(s1), Obj::String(s2)) => Value::Bool(s1.ends_with(s2.as_str())),
+            _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "endswith: both parameters must be strings")),
         },
-        _ => Value::Error(FsError { code: 2, message: "endswith: both parameters must be strings".to_string(), line: -1, column: -1 }),
+        _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "endswith: both parameters must be strings")),
     }
 }
 
@@ -1323,14 +2088,14 @@ fn value_to_i64_default(v: &Value, default: i64) -> Result<i64, Value> {
         Value::Nil => Ok(default),
         Value::Error(e) => Err(Value::Error(e.clone())),
         Value::Int(n) => Ok(*n),
-        Value::BigInt(n) => n.to_i64().ok_or_else(|| Value::Error(FsError { code: 1, message: "numeric value is out of range".to_string(), line: -1, column: -1 })),
+        Value::BigInt(n) => n.to_i64().ok_or_else(|| Value::error(FsError::new(ErrorKind::OutOfRange, "numeric value is out of range"))),
         Value::Number(n) if n.is_finite() => Ok(*n as i64),
         Value::Bool(b) => Ok(if *b { 1 } else { 0 }),
         Value::Obj(o) => match &**o {
-            Obj::String(s) => s.trim().parse::<i64>().map_err(|_| Value::Error(FsError { code: 1, message: "invalid numeric string".to_string(), line: -1, column: -1 })),
-            _ => Err(Value::Error(FsError { code: 2, message: "number expected".to_string(), line: -1, column: -1 })),
+            Obj::String(s) => s.trim().parse::<i64>().map_err(|_| Value::error(FsError::new(ErrorKind::InvalidValue, "invalid numeric string"))),
+            _ => Err(Value::error(FsError::new(ErrorKind::TypeMismatch, "number expected"))),
         },
-        _ => Err(Value::Error(FsError { code: 2, message: "number expected".to_string(), line: -1, column: -1 })),
+        _ => Err(Value::error(FsError::new(ErrorKind::TypeMismatch, "number expected"))),
     }
 }
 
@@ -1354,7 +2119,7 @@ fn substring_by_char_indices(s: &str, index: i64, count: i64) -> String {
 
 fn text_substring(args: &[Value]) -> Value {
     if args.is_empty() {
-        return Value::Error(FsError { code: 1, message: "substring requires at least one parameter.".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "substring requires at least one parameter."));
     }
     let s = match &args[0] {
         Value::Nil => return Value::Nil,
@@ -1374,28 +2139,28 @@ fn text_substring(args: &[Value]) -> Value {
         Ok(v) => v,
         Err(e) => return e,
     };
-    Value::Obj(Rc::new(Obj::String(substring_by_char_indices(&s, index, count))))
+    Value::Obj(Arc::new(Obj::String(substring_by_char_indices(&s, index, count).into())))
 }
 
 fn text_find(args: &[Value]) -> Value {
     if args.len() < 2 || args.len() > 3 {
-        return Value::Error(FsError { code: 1, message: "find: two or three parameters expected".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "find: two or three parameters expected"));
     }
     let text = match &args[0] {
         Value::Error(e) => return Value::Error(e.clone()),
         Value::Obj(o) => match &**o {
             Obj::String(s) => s.clone(),
-            _ => return Value::Error(FsError { code: 2, message: "find: first parameter should be string".to_string(), line: -1, column: -1 }),
+            _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "find: first parameter should be string")),
         },
-        _ => return Value::Error(FsError { code: 2, message: "find: first parameter should be string".to_string(), line: -1, column: -1 }),
+        _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "find: first parameter should be string")),
     };
     let search = match &args[1] {
         Value::Error(e) => return Value::Error(e.clone()),
         Value::Obj(o) => match &**o {
             Obj::String(s) => s.clone(),
-            _ => return Value::Error(FsError { code: 2, message: "find: second parameter should be string".to_string(), line: -1, column: -1 }),
+            _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "find: second parameter should be string")),
         },
-        _ => return Value::Error(FsError { code: 2, message: "find: second parameter should be string".to_string(), line: -1, column: -1 }),
+        _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "find: second parameter should be string")),
     };
 
     let start_index = if args.len() == 3 {
@@ -1408,7 +2173,7 @@ fn text_find(args: &[Value]) -> Value {
     };
     let len = text.chars().count() as i64;
     if start_index < 0 || start_index >= len {
-        return Value::Error(FsError { code: 1, message: "find: index is out of range".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::OutOfRange, "find: index is out of range"));
     }
 
     let hay: Vec<char> = text.chars().collect();
@@ -1427,35 +2192,35 @@ fn text_find(args: &[Value]) -> Value {
 
 fn text_is_blank(args: &[Value]) -> Value {
     if args.is_empty() {
-        return Value::Error(FsError { code: 1, message: "isBlank: argument expected".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "isBlank: argument expected"));
     }
     match &args[0] {
         Value::Error(e) => Value::Error(e.clone()),
         Value::Nil => Value::Bool(true),
         Value::Obj(o) => match &**o {
             Obj::String(s) => Value::Bool(s.trim().is_empty()),
-            _ => Value::Error(FsError { code: 2, message: "isBlank: string expected".to_string(), line: -1, column: -1 }),
+            _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "isBlank: string expected")),
         },
-        _ => Value::Error(FsError { code: 2, message: "isBlank: string expected".to_string(), line: -1, column: -1 }),
+        _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "isBlank: string expected")),
     }
 }
 
 fn text_join(args: &[Value]) -> Value {
     if args.len() != 2 {
-        return Value::Error(FsError { code: 1, message: "join: Two parameters expected".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "join: Two parameters expected"));
     }
     let list_val = &args[0];
     let sep = match &args[1] {
         Value::Error(e) => return Value::Error(e.clone()),
         Value::Obj(o) => match &**o {
             Obj::String(s) => s.clone(),
-            _ => return Value::Error(FsError { code: 2, message: "join: second parameter should be string".to_string(), line: -1, column: -1 }),
+            _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "join: second parameter should be string")),
         },
-        _ => return Value::Error(FsError { code: 2, message: "join: second parameter should be string".to_string(), line: -1, column: -1 }),
+        _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "join: second parameter should be string")),
     };
 
     if matches!(list_val, Value::Nil) {
-        return Value::Error(FsError { code: 2, message: "join: first parameter should be list".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::TypeMismatch, "join: first parameter should be list"));
     }
     if let Value::Error(e) = list_val {
         return Value::Error(e.clone());
@@ -1480,53 +2245,353 @@ fn text_join(args: &[Value]) -> Value {
                     out.push_str(&(r.start + i as i64).to_string());
                 }
             }
-            _ => return Value::Error(FsError { code: 2, message: "join: first parameter should be list".to_string(), line: -1, column: -1 }),
+            _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "join: first parameter should be list")),
+        },
+        _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "join: first parameter should be list")),
+    }
+    Value::Obj(Arc::new(Obj::String(out.into())))
+}
+
+/// Longest string (in bytes) `repeat` and the pad functions will build.
+const TEXT_MAX_LEN: usize = 1 << 28;
+
+fn text_value(s: impl Into<SmallString>) -> Value {
+    Value::Obj(Arc::new(Obj::String(s.into())))
+}
+
+/// Reads the string subject of a text function; `Ok(None)` when it is null.
+fn text_subject(name: &str, v: &Value) -> Result<Option<SmallString>, Value> {
+    match v {
+        Value::Nil => Ok(None),
+        _ => regex_string_arg(name, "first", v).map(Some),
+    }
+}
+
+fn text_split(args: &[Value]) -> Value {
+    if args.is_empty() || args.len() > 2 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "split: one or two parameters expected"));
+    }
+    let text = match text_subject("split", &args[0]) {
+        Ok(Some(s)) => s,
+        Ok(None) => return Value::Nil,
+        Err(e) => return e,
+    };
+    let parts: Vec<Value> = match args.get(1) {
+        None | Some(Value::Nil) => text.split_whitespace().map(text_value).collect(),
+        Some(sep) => match regex_string_arg("split", "second", sep) {
+            Ok(sep) if sep.is_empty() => vec![text_value(text.clone())],
+            Ok(sep) => text.split(sep.as_str()).map(text_value).collect(),
+            Err(e) => return e,
+        },
+    };
+    Value::Obj(Arc::new(Obj::List(parts)))
+}
+
+fn text_replace(args: &[Value]) -> Value {
+    if args.len() != 3 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "replace: three parameters expected"));
+    }
+    let text = match text_subject("replace", &args[0]) {
+        Ok(Some(s)) => s,
+        Ok(None) => return Value::Nil,
+        Err(e) => return e,
+    };
+    let old = match regex_string_arg("replace", "second", &args[1]) {
+        Ok(s) => s,
+        Err(e) => return e,
+    };
+    let new = match &args[2] {
+        Value::Nil => SmallString::from(""),
+        v => match regex_string_arg("replace", "third", v) {
+            Ok(s) => s,
+            Err(e) => return e,
+        },
+    };
+    if old.is_empty() {
+        return text_value(text);
+    }
+    text_value(text.replace(old.as_str(), new.as_str()))
+}
+
+#[derive(Clone, Copy)]
+enum TrimSide {
+    Both,
+    Start,
+    End,
+}
+
+fn trim_with(name: &str, side: TrimSide, args: &[Value]) -> Value {
+    if args.is_empty() || args.len() > 2 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, format!("{name}: one or two parameters expected")));
+    }
+    let text = match text_subject(name, &args[0]) {
+        Ok(Some(s)) => s,
+        Ok(None) => return Value::Nil,
+        Err(e) => return e,
+    };
+    let set: Option<Vec<char>> = match args.get(1) {
+        None | Some(Value::Nil) => None,
+        Some(v) => match regex_string_arg(name, "second", v) {
+            Ok(s) => Some(s.chars().collect()),
+            Err(e) => return e,
+        },
+    };
+    let strip = |c: char| match &set {
+        Some(set) => set.contains(&c),
+        None => c.is_whitespace(),
+    };
+    let out = match side {
+        TrimSide::Both => text.trim_matches(strip),
+        TrimSide::Start => text.trim_start_matches(strip),
+        TrimSide::End => text.trim_end_matches(strip),
+    };
+    text_value(out)
+}
+
+fn text_trim(args: &[Value]) -> Value {
+    trim_with("trim", TrimSide::Both, args)
+}
+
+fn text_trim_start(args: &[Value]) -> Value {
+    trim_with("trimStart", TrimSide::Start, args)
+}
+
+fn text_trim_end(args: &[Value]) -> Value {
+    trim_with("trimEnd", TrimSide::End, args)
+}
+
+fn text_startswith(args: &[Value]) -> Value {
+    if args.len() != 2 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "startswith: two parameters expected"));
+    }
+    if matches!(&args[0], Value::Nil) || matches!(&args[1], Value::Nil) {
+        return Value::Bool(false);
+    }
+    match (&args[0], &args[1]) {
+        (Value::Error(e), _) | (_, Value::Error(e)) => Value::Error(e.clone()),
+        (Value::Obj(a), Value::Obj(b)) => match (&**a, &**b) {
+            (Obj::String(s1), Obj::String(s2)) => Value::Bool(s1.starts_with(s2.as_str())),
+            _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "startswith: both parameters must be strings")),
+        },
+        _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "startswith: both parameters must be strings")),
+    }
+}
+
+fn pad_with(name: &str, left: bool, args: &[Value]) -> Value {
+    if args.len() < 2 || args.len() > 3 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, format!("{name}: two or three parameters expected")));
+    }
+    let text = match text_subject(name, &args[0]) {
+        Ok(Some(s)) => s,
+        Ok(None) => return Value::Nil,
+        Err(e) => return e,
+    };
+    let width = match value_to_i64_default(&args[1], 0) {
+        Ok(n) => n,
+        Err(e) => return e,
+    };
+    let pad = match args.get(2) {
+        None | Some(Value::Nil) => ' ',
+        Some(v) => match regex_string_arg(name, "third", v) {
+            Ok(s) => {
+                let mut it = s.chars();
+                match (it.next(), it.next()) {
+                    (Some(c), None) => c,
+                    _ => return Value::error(FsError::new(ErrorKind::InvalidValue, format!("{name}: padding must be a single character"))),
+                }
+            }
+            Err(e) => return e,
         },
-        _ => return Value::Error(FsError { code: 2, message: "join: first parameter should be list".to_string(), line: -1, column: -1 }),
+    };
+    let len = text.chars().count() as i64;
+    if width <= len {
+        return text_value(text);
+    }
+    let fill = (width - len) as usize;
+    if text.len().saturating_add(fill.saturating_mul(pad.len_utf8())) > TEXT_MAX_LEN {
+        return Value::error(FsError::new(ErrorKind::OutOfRange, format!("{name}: result is too long")));
+    }
+    let padding: String = std::iter::repeat_n(pad, fill).collect();
+    if left {
+        text_value(padding + text.as_str())
+    } else {
+        text_value(text.to_string() + &padding)
+    }
+}
+
+fn text_pad_left(args: &[Value]) -> Value {
+    pad_with("padLeft", true, args)
+}
+
+fn text_pad_right(args: &[Value]) -> Value {
+    pad_with("padRight", false, args)
+}
+
+fn text_repeat(args: &[Value]) -> Value {
+    if args.len() != 2 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "repeat: two parameters expected"));
+    }
+    let text = match text_subject("repeat", &args[0]) {
+        Ok(Some(s)) => s,
+        Ok(None) => return Value::Nil,
+        Err(e) => return e,
+    };
+    let count = match value_to_i64_default(&args[1], 0) {
+        Ok(n) => n,
+        Err(e) => return e,
+    };
+    if count < 0 {
+        return Value::error(FsError::new(ErrorKind::OutOfRange, "repeat: count must not be negative"));
+    }
+    if text.len().saturating_mul(count as usize) > TEXT_MAX_LEN {
+        return Value::error(FsError::new(ErrorKind::OutOfRange, "repeat: result is too long"));
+    }
+    text_value(text.repeat(count as usize))
+}
+
+/// Text and needle as chars, plus the start position of the search.
+type CharSearch = (Vec<char>, Vec<char>, usize);
+
+/// Shared argument handling for `indexOf`/`lastIndexOf`; the optional start position is checked
+/// to lie within `0..=len`.
+fn index_args(name: &str, args: &[Value], default_start: fn(usize) -> i64) -> Result<Option<CharSearch>, Value> {
+    if args.len() < 2 || args.len() > 3 {
+        return Err(Value::error(FsError::new(ErrorKind::ParameterCount, format!("{name}: two or three parameters expected"))));
+    }
+    let text = match text_subject(name, &args[0])? {
+        Some(s) => s,
+        None => return Ok(None),
+    };
+    let needle = regex_string_arg(name, "second", &args[1])?;
+    let hay: Vec<char> = text.chars().collect();
+    let start = value_to_i64_default(args.get(2).unwrap_or(&Value::Nil), default_start(hay.len()))?;
+    if start < 0 || start > hay.len() as i64 {
+        return Err(Value::error(FsError::new(ErrorKind::OutOfRange, format!("{name}: index is out of range"))));
     }
-    Value::Obj(Rc::new(Obj::String(out)))
+    Ok(Some((hay, needle.chars().collect(), start as usize)))
+}
+
+fn text_index_of(args: &[Value]) -> Value {
+    let (hay, needle, start) = match index_args("indexOf", args, |_| 0) {
+        Ok(Some(a)) => a,
+        Ok(None) => return Value::Nil,
+        Err(e) => return e,
+    };
+    if needle.len() > hay.len() {
+        return Value::Int(-1);
+    }
+    (start..=hay.len() - needle.len())
+        .find(|&i| hay[i..i + needle.len()] == needle[..])
+        .map_or(Value::Int(-1), |i| Value::Int(i as i64))
+}
+
+fn text_last_index_of(args: &[Value]) -> Value {
+    let (hay, needle, start) = match index_args("lastIndexOf", args, |len| len as i64) {
+        Ok(Some(a)) => a,
+        Ok(None) => return Value::Nil,
+        Err(e) => return e,
+    };
+    if needle.len() > hay.len() {
+        return Value::Int(-1);
+    }
+    (0..=start.min(hay.len() - needle.len()))
+        .rev()
+        .find(|&i| hay[i..i + needle.len()] == needle[..])
+        .map_or(Value::Int(-1), |i| Value::Int(i as i64))
+}
+
+fn text_chars(args: &[Value]) -> Value {
+    if args.len() != 1 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "chars: single string parameter expected"));
+    }
+    match text_subject("chars", &args[0]) {
+        Ok(Some(s)) => Value::Obj(Arc::new(Obj::List(s.chars().map(|c| text_value(c.to_string())).collect()))),
+        Ok(None) => Value::Nil,
+        Err(e) => e,
+    }
+}
+
+#[cfg(feature = "unicode")]
+fn text_graphemes(args: &[Value]) -> Value {
+    use unicode_segmentation::UnicodeSegmentation;
+    if args.len() != 1 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "graphemes: single string parameter expected"));
+    }
+    match text_subject("graphemes", &args[0]) {
+        Ok(Some(s)) => Value::Obj(Arc::new(Obj::List(s.graphemes(true).map(text_value).collect()))),
+        Ok(None) => Value::Nil,
+        Err(e) => e,
+    }
+}
+
+#[cfg(feature = "unicode")]
+fn text_normalize(args: &[Value]) -> Value {
+    use unicode_normalization::UnicodeNormalization;
+    if args.is_empty() || args.len() > 2 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "normalize: one or two parameters expected"));
+    }
+    let text = match text_subject("normalize", &args[0]) {
+        Ok(Some(s)) => s,
+        Ok(None) => return Value::Nil,
+        Err(e) => return e,
+    };
+    let form = match args.get(1) {
+        None | Some(Value::Nil) => SmallString::from("NFC"),
+        Some(v) => match regex_string_arg("normalize", "second", v) {
+            Ok(s) => s,
+            Err(e) => return e,
+        },
+    };
+    let out: String = match form.to_ascii_uppercase().as_str() {
+        "NFC" => text.nfc().collect(),
+        "NFD" => text.nfd().collect(),
+        "NFKC" => text.nfkc().collect(),
+        "NFKD" => text.nfkd().collect(),
+        _ => return Value::error(FsError::new(ErrorKind::InvalidValue, format!("normalize: unknown form '{form}', expected NFC, NFD, NFKC or NFKD"))),
+    };
+    text_value(out)
 }
 
 fn list_take(args: &[Value]) -> Value {
     if args.len() != 2 {
-        return Value::Error(FsError { code: 1, message: "Take: Invalid parameter count. Expected 2.".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "Take: Invalid parameter count. Expected 2."));
     }
     if let Value::Error(e) = &args[0] { return Value::Error(e.clone()); }
     if matches!(args[0], Value::Nil) { return Value::Nil; }
     let n = match &args[1] {
         Value::Error(e) => return Value::Error(e.clone()),
         Value::Int(i) => *i,
-        _ => return Value::Error(FsError { code: 2, message: "Take: second parameter should be Number".to_string(), line: -1, column: -1 }),
+        _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "Take: second parameter should be Number")),
     };
     if n <= 0 {
-        return Value::Obj(Rc::new(Obj::List(vec![])));
+        return Value::Obj(Arc::new(Obj::List(vec![])));
     }
     match &args[0] {
         Value::Obj(o) => match &**o {
             Obj::List(items) => {
                 let take_n = (n as usize).min(items.len());
-                Value::Obj(Rc::new(Obj::List(items.iter().take(take_n).cloned().collect())))
+                Value::Obj(Arc::new(Obj::List(items.iter().take(take_n).cloned().collect())))
             }
             Obj::Range(r) => {
                 let take_n = (n as usize).min(r.count);
-                Value::Obj(Rc::new(Obj::Range(crate::obj::RangeObject { start: r.start, count: take_n })))
+                Value::Obj(Arc::new(Obj::Range(crate::obj::RangeObject { start: r.start, count: take_n })))
             }
-            _ => Value::Error(FsError { code: 2, message: "Take: first parameter should be List".to_string(), line: -1, column: -1 }),
+            _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "Take: first parameter should be List")),
         },
-        _ => Value::Error(FsError { code: 2, message: "Take: first parameter should be List".to_string(), line: -1, column: -1 }),
+        _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "Take: first parameter should be List")),
     }
 }
 
 fn list_skip(args: &[Value]) -> Value {
     if args.len() != 2 {
-        return Value::Error(FsError { code: 1, message: "Skip: Invalid parameter count. Expected 2.".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "Skip: Invalid parameter count. Expected 2."));
     }
     if let Value::Error(e) = &args[0] { return Value::Error(e.clone()); }
     if matches!(args[0], Value::Nil) { return Value::Nil; }
     let n = match &args[1] {
         Value::Error(e) => return Value::Error(e.clone()),
         Value::Int(i) => *i,
-        _ => return Value::Error(FsError { code: 2, message: "Skip: second parameter should be Number".to_string(), line: -1, column: -1 }),
+        _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "Skip: second parameter should be Number")),
     };
     if n <= 0 {
         return args[0].clone();
@@ -1535,26 +2600,26 @@ fn list_skip(args: &[Value]) -> Value {
         Value::Obj(o) => match &**o {
             Obj::List(items) => {
                 if (n as usize) >= items.len() {
-                    return Value::Obj(Rc::new(Obj::List(vec![])));
+                    return Value::Obj(Arc::new(Obj::List(vec![])));
                 }
-                Value::Obj(Rc::new(Obj::List(items.iter().skip(n as usize).cloned().collect())))
+                Value::Obj(Arc::new(Obj::List(items.iter().skip(n as usize).cloned().collect())))
             }
             Obj::Range(r) => {
                 let skip_n = (n as usize).min(r.count);
                 if skip_n >= r.count {
-                    return Value::Obj(Rc::new(Obj::List(vec![])));
+                    return Value::Obj(Arc::new(Obj::List(vec![])));
                 }
-                Value::Obj(Rc::new(Obj::Range(crate::obj::RangeObject { start: r.start + skip_n as i64, count: r.count - skip_n })))
+                Value::Obj(Arc::new(Obj::Range(crate::obj::RangeObject { start: r.start + skip_n as i64, count: r.count - skip_n })))
             }
-            _ => Value::Error(FsError { code: 2, message: "Skip: first parameter should be List".to_string(), line: -1, column: -1 }),
+            _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "Skip: first parameter should be List")),
         },
-        _ => Value::Error(FsError { code: 2, message: "Skip: first parameter should be List".to_string(), line: -1, column: -1 }),
+        _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "Skip: first parameter should be List")),
     }
 }
 
 fn list_reverse(args: &[Value]) -> Value {
     if args.len() != 1 {
-        return Value::Error(FsError { code: 1, message: "Reverse: Invalid parameter count. Expected 1.".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "Reverse: Invalid parameter count. Expected 1."));
     }
     if let Value::Error(e) = &args[0] { return Value::Error(e.clone()); }
     if matches!(args[0], Value::Nil) { return Value::Nil; }
@@ -1563,30 +2628,30 @@ fn list_reverse(args: &[Value]) -> Value {
             Obj::List(items) => {
                 let mut out = items.clone();
                 out.reverse();
-                Value::Obj(Rc::new(Obj::List(out)))
+                Value::Obj(Arc::new(Obj::List(out)))
             }
             Obj::Range(r) => {
                 let mut out: Vec<Value> = Vec::with_capacity(r.count);
                 for i in 0..r.count {
                     out.push(Value::Int(r.start + (r.count - 1 - i) as i64));
                 }
-                Value::Obj(Rc::new(Obj::List(out)))
+                Value::Obj(Arc::new(Obj::List(out)))
             }
-            _ => Value::Error(FsError { code: 2, message: "Reverse: parameter should be List".to_string(), line: -1, column: -1 }),
+            _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "Reverse: parameter should be List")),
         },
-        _ => Value::Error(FsError { code: 2, message: "Reverse: parameter should be List".to_string(), line: -1, column: -1 }),
+        _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "Reverse: parameter should be List")),
     }
 }
 
 fn list_distinct(args: &[Value]) -> Value {
     if args.len() != 1 {
-        return Value::Error(FsError { code: 1, message: "Distinct: Invalid parameter count. Expected 1.".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "Distinct: Invalid parameter count. Expected 1."));
     }
     if let Value::Error(e) = &args[0] { return Value::Error(e.clone()); }
     if matches!(args[0], Value::Nil) { return Value::Nil; }
     match &args[0] {
         Value::Obj(o) => match &**o {
-            Obj::Range(r) => Value::Obj(Rc::new(Obj::Range(r.clone()))),
+            Obj::Range(r) => Value::Obj(Arc::new(Obj::Range(r.clone()))),
             Obj::List(items) => {
                 let mut out: Vec<Value> = Vec::new();
                 'outer: for v in items.iter().cloned() {
@@ -1597,17 +2662,17 @@ fn list_distinct(args: &[Value]) -> Value {
                     }
                     out.push(v);
                 }
-                Value::Obj(Rc::new(Obj::List(out)))
+                Value::Obj(Arc::new(Obj::List(out)))
             }
-            _ => Value::Error(FsError { code: 2, message: "Distinct: parameter should be List".to_string(), line: -1, column: -1 }),
+            _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "Distinct: parameter should be List")),
         },
-        _ => Value::Error(FsError { code: 2, message: "Distinct: parameter should be List".to_string(), line: -1, column: -1 }),
+        _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "Distinct: parameter should be List")),
     }
 }
 
 fn list_contains(args: &[Value]) -> Value {
     if args.len() != 2 {
-        return Value::Error(FsError { code: 1, message: "Contains: Invalid parameter count. Expected 2.".to_string(), line: -1, column: -1 });
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "Contains: Invalid parameter count. Expected 2."));
     }
     let container = &args[0];
     let item = &args[1];
@@ -1629,14 +2694,954 @@ fn list_contains(args: &[Value]) -> Value {
                     if let Obj::String(sub) = &**o2 {
                         Value::Bool(s.to_lowercase().contains(&sub.to_lowercase()))
                     } else {
-                        Value::Error(FsError { code: 2, message: "Contains: Invalid types for parameters".to_string(), line: -1, column: -1 })
+                        Value::error(FsError::new(ErrorKind::TypeMismatch, "Contains: Invalid types for parameters"))
                     }
                 } else {
-                    Value::Error(FsError { code: 2, message: "Contains: Invalid types for parameters".to_string(), line: -1, column: -1 })
+                    Value::error(FsError::new(ErrorKind::TypeMismatch, "Contains: Invalid types for parameters"))
                 }
             }
-            _ => Value::Error(FsError { code: 2, message: "Contains: Invalid types for parameters".to_string(), line: -1, column: -1 }),
+            _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "Contains: Invalid types for parameters")),
         },
-        _ => Value::Error(FsError { code: 2, message: "Contains: Invalid types for parameters".to_string(), line: -1, column: -1 }),
+        _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "Contains: Invalid types for parameters")),
+    }
+}
+
+/// Elements of a list or range; `Ok(None)` when the value is null.
+fn list_items(name: &str, v: &Value) -> Result<Option<Vec<Value>>, Value> {
+    match v {
+        Value::Nil => Ok(None),
+        Value::Error(e) => Err(Value::Error(e.clone())),
+        Value::Obj(o) => match &**o {
+            Obj::List(items) => Ok(Some(items.clone())),
+            Obj::Range(r) => Ok(Some((0..r.count).map(|i| Value::Int(r.start + i as i64)).collect())),
+            _ => Err(Value::error(FsError::new(ErrorKind::TypeMismatch, format!("{name}: first parameter should be List")))),
+        },
+        _ => Err(Value::error(FsError::new(ErrorKind::TypeMismatch, format!("{name}: first parameter should be List")))),
+    }
+}
+
+fn list_value(items: Vec<Value>) -> Value {
+    Value::Obj(Arc::new(Obj::List(items)))
+}
+
+/// Checks that the `which` parameter of `name` is something callable.
+fn function_arg(name: &str, which: &str, v: &Value) -> Result<(), Value> {
+    match v {
+        Value::Error(e) => Err(Value::Error(e.clone())),
+        Value::Obj(o) if matches!(&**o, Obj::Function(_) | Obj::NativeFn(_) | Obj::HostFn(_) | Obj::VmFn(_)) => Ok(()),
+        _ => Err(Value::error(FsError::new(ErrorKind::TypeMismatch, format!("{name}: {which} parameter should be a function")))),
+    }
+}
+
+/// Positive size parameter of `chunk`/`window`.
+fn size_arg(name: &str, v: &Value) -> Result<usize, Value> {
+    match v {
+        Value::Error(e) => Err(Value::Error(e.clone())),
+        v => match as_i64_exact(v) {
+            Some(n) if n >= 1 => Ok(usize::try_from(n).unwrap_or(usize::MAX)),
+            Some(_) => Err(Value::error(FsError::new(ErrorKind::InvalidValue, format!("{name}: size must be at least 1")))),
+            None => Err(Value::error(FsError::new(ErrorKind::TypeMismatch, format!("{name}: second parameter should be Number")))),
+        },
+    }
+}
+
+/// Calls `f` on every item, stopping at the first error it returns.
+fn map_items(vm: &mut VM, f: &Value, items: &[Value]) -> Result<Vec<Value>, Value> {
+    let mut out = Vec::with_capacity(items.len());
+    for item in items {
+        match vm.call_function(f, std::slice::from_ref(item)) {
+            Value::Error(e) => return Err(Value::Error(e)),
+            v => out.push(v),
+        }
+    }
+    Ok(out)
+}
+
+/// `indexOf(list, value, start?)` finds an element; on text it is `text.indexOf`.
+fn fs_index_of(args: &[Value]) -> Value {
+    let items = match args.first() {
+        Some(Value::Obj(o)) if matches!(&**o, Obj::List(_) | Obj::Range(_)) => &args[0],
+        _ => return text_index_of(args),
+    };
+    if args.len() < 2 || args.len() > 3 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "indexOf: two or three parameters expected"));
+    }
+    if let Value::Error(e) = &args[1] {
+        return Value::Error(e.clone());
+    }
+    let start = match args.get(2) {
+        None | Some(Value::Nil) => 0,
+        Some(Value::Error(e)) => return Value::Error(e.clone()),
+        Some(v) => match as_i64_exact(v) {
+            Some(n) if n >= 0 => n,
+            _ => return Value::error(FsError::new(ErrorKind::InvalidValue, "indexOf: start must be a non-negative whole number")),
+        },
+    };
+    let found = match items {
+        Value::Obj(o) => match &**o {
+            Obj::Range(r) => as_i64_exact(&args[1])
+                .map(|n| n - r.start)
+                .filter(|i| *i >= start && *i < r.count as i64),
+            Obj::List(l) => l.iter().enumerate().skip(start as usize).find(|(_, v)| **v == args[1]).map(|(i, _)| i as i64),
+            _ => None,
+        },
+        _ => None,
+    };
+    Value::Int(found.unwrap_or(-1))
+}
+
+fn list_last(args: &[Value]) -> Value {
+    if args.len() != 1 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "last: Invalid parameter count. Expected 1."));
+    }
+    match &args[0] {
+        Value::Obj(o) => match &**o {
+            Obj::Range(r) if r.count > 0 => Value::Int(r.start + r.count as i64 - 1),
+            Obj::Range(_) => Value::Nil,
+            Obj::List(l) => l.last().cloned().unwrap_or(Value::Nil),
+            _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "last: parameter should be List")),
+        },
+        Value::Nil => Value::Nil,
+        Value::Error(e) => Value::Error(e.clone()),
+        _ => Value::error(FsError::new(ErrorKind::TypeMismatch, "last: parameter should be List")),
+    }
+}
+
+/// `zip(a, b, ...)`: lists of the elements at each position, as long as the shortest input.
+fn list_zip(args: &[Value]) -> Value {
+    if args.len() < 2 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "zip: at least two lists expected"));
+    }
+    let mut lists = Vec::with_capacity(args.len());
+    for a in args {
+        match list_items("zip", a) {
+            Ok(Some(items)) => lists.push(items),
+            Ok(None) => return Value::Nil,
+            Err(e) => return e,
+        }
+    }
+    let len = lists.iter().map(Vec::len).min().unwrap_or(0);
+    list_value((0..len).map(|i| list_value(lists.iter().map(|l| l[i].clone()).collect())).collect())
+}
+
+/// Splices nested lists and ranges into the result, one level deep.
+fn flatten_into(out: &mut Vec<Value>, v: Value) {
+    match &v {
+        Value::Obj(o) => match &**o {
+            Obj::List(items) => out.extend(items.iter().cloned()),
+            Obj::Range(r) => out.extend((0..r.count).map(|i| Value::Int(r.start + i as i64))),
+            _ => out.push(v),
+        },
+        _ => out.push(v),
+    }
+}
+
+fn list_flatten(args: &[Value]) -> Value {
+    if args.len() != 1 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "flatten: Invalid parameter count. Expected 1."));
+    }
+    match list_items("flatten", &args[0]) {
+        Ok(Some(items)) => {
+            let mut out = Vec::with_capacity(items.len());
+            for v in items {
+                flatten_into(&mut out, v);
+            }
+            list_value(out)
+        }
+        Ok(None) => Value::Nil,
+        Err(e) => e,
+    }
+}
+
+fn list_flat_map(vm: &mut VM, args: &[Value]) -> Value {
+    if args.len() != 2 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "flatMap: Invalid parameter count. Expected 2."));
+    }
+    let items = match list_items("flatMap", &args[0]) {
+        Ok(Some(items)) => items,
+        Ok(None) => return Value::Nil,
+        Err(e) => return e,
+    };
+    if let Err(e) = function_arg("flatMap", "second", &args[1]) {
+        return e;
+    }
+    match map_items(vm, &args[1], &items) {
+        Ok(mapped) => {
+            let mut out = Vec::with_capacity(mapped.len());
+            for v in mapped {
+                flatten_into(&mut out, v);
+            }
+            list_value(out)
+        }
+        Err(e) => e,
+    }
+}
+
+fn list_chunk(args: &[Value]) -> Value {
+    if args.len() != 2 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "chunk: Invalid parameter count. Expected 2."));
+    }
+    let items = match list_items("chunk", &args[0]) {
+        Ok(Some(items)) => items,
+        Ok(None) => return Value::Nil,
+        Err(e) => return e,
+    };
+    match size_arg("chunk", &args[1]) {
+        Ok(n) => list_value(items.chunks(n).map(|c| list_value(c.to_vec())).collect()),
+        Err(e) => e,
+    }
+}
+
+/// `window(list, n)`: every run of `n` consecutive elements, sliding by one.
+fn list_window(args: &[Value]) -> Value {
+    if args.len() != 2 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "window: Invalid parameter count. Expected 2."));
+    }
+    let items = match list_items("window", &args[0]) {
+        Ok(Some(items)) => items,
+        Ok(None) => return Value::Nil,
+        Err(e) => return e,
+    };
+    match size_arg("window", &args[1]) {
+        Ok(n) => list_value(items.windows(n).map(|w| list_value(w.to_vec())).collect()),
+        Err(e) => e,
+    }
+}
+
+/// `groupBy(list, keyFn)`: `[{ key, items }]` in order of each key's first appearance.
+fn list_group_by(vm: &mut VM, args: &[Value]) -> Value {
+    if args.len() != 2 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "groupBy: Invalid parameter count. Expected 2."));
+    }
+    let items = match list_items("groupBy", &args[0]) {
+        Ok(Some(items)) => items,
+        Ok(None) => return Value::Nil,
+        Err(e) => return e,
+    };
+    if let Err(e) = function_arg("groupBy", "second", &args[1]) {
+        return e;
+    }
+    let keys = match map_items(vm, &args[1], &items) {
+        Ok(keys) => keys,
+        Err(e) => return e,
+    };
+    let mut groups: Vec<(Value, Vec<Value>)> = Vec::new();
+    for (key, item) in keys.into_iter().zip(items) {
+        match groups.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, members)) => members.push(item),
+            None => groups.push((key, vec![item])),
+        }
+    }
+    list_value(groups.into_iter().map(|(key, members)| kvc_from_cache(vec![("key", key), ("items", list_value(members))])).collect())
+}
+
+/// `count(list, predicate?)`: how many elements satisfy `predicate`, or all of them.
+fn list_count(vm: &mut VM, args: &[Value]) -> Value {
+    if args.is_empty() || args.len() > 2 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "count: one or two parameters expected"));
+    }
+    if let (Value::Obj(o), None) = (&args[0], args.get(1)) {
+        if let Obj::Range(r) = &**o {
+            return Value::Int(r.count as i64);
+        }
+    }
+    let items = match list_items("count", &args[0]) {
+        Ok(Some(items)) => items,
+        Ok(None) => return Value::Int(0),
+        Err(e) => return e,
+    };
+    let Some(pred) = args.get(1) else {
+        return Value::Int(items.len() as i64);
+    };
+    if let Err(e) = function_arg("count", "second", pred) {
+        return e;
+    }
+    match map_items(vm, pred, &items) {
+        Ok(results) => Value::Int(results.iter().filter(|v| matches!(v, Value::Bool(true))).count() as i64),
+        Err(e) => e,
+    }
+}
+
+/// Items of `args[0]` with the key `args[1]` gives each, for `sortBy`/`minBy`/`maxBy`.
+fn keyed_items(vm: &mut VM, name: &str, args: &[Value]) -> Result<Option<Vec<(Value, Value)>>, Value> {
+    let Some(items) = list_items(name, &args[0])? else {
+        return Ok(None);
+    };
+    function_arg(name, "second", &args[1])?;
+    let keys = map_items(vm, &args[1], &items)?;
+    Ok(Some(keys.into_iter().zip(items).collect()))
+}
+
+/// Key order for `sortBy`/`minBy`/`maxBy`: nulls after every other key.
+fn key_order(name: &str, a: &Value, b: &Value) -> Result<std::cmp::Ordering, Value> {
+    use std::cmp::Ordering;
+    match (a, b) {
+        (Value::Nil, Value::Nil) => Ok(Ordering::Equal),
+        (Value::Nil, _) => Ok(Ordering::Greater),
+        (_, Value::Nil) => Ok(Ordering::Less),
+        _ => VM::compare_values(a, b).ok_or_else(|| {
+            Value::error(FsError::new(ErrorKind::TypeMismatch, format!("{name}: keys {a} and {b} can't be compared")))
+        }),
+    }
+}
+
+/// `sortBy(list, keyFn, descending?)`: stable sort by key, so equal keys keep their order.
+fn list_sort_by(vm: &mut VM, args: &[Value]) -> Value {
+    if args.len() < 2 || args.len() > 3 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "sortBy: two or three parameters expected"));
+    }
+    let descending = match args.get(2) {
+        None | Some(Value::Nil) | Some(Value::Bool(false)) => false,
+        Some(Value::Bool(true)) => true,
+        Some(Value::Error(e)) => return Value::Error(e.clone()),
+        Some(_) => return Value::error(FsError::new(ErrorKind::TypeMismatch, "sortBy: third parameter should be Boolean")),
+    };
+    let mut keyed = match keyed_items(vm, "sortBy", args) {
+        Ok(Some(k)) => k,
+        Ok(None) => return Value::Nil,
+        Err(e) => return e,
+    };
+    let mut failure = None;
+    keyed.sort_by(|(a, _), (b, _)| match key_order("sortBy", a, b) {
+        Ok(ord) if descending => ord.reverse(),
+        Ok(ord) => ord,
+        Err(e) => {
+            failure.get_or_insert(e);
+            std::cmp::Ordering::Equal
+        }
+    });
+    match failure {
+        Some(e) => e,
+        None => list_value(keyed.into_iter().map(|(_, item)| item).collect()),
+    }
+}
+
+/// Shared by `minBy`/`maxBy`: the first element whose key is `wanted` relative to the rest.
+fn extreme_by(vm: &mut VM, name: &str, args: &[Value], wanted: std::cmp::Ordering) -> Value {
+    if args.len() != 2 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, format!("{name}: Invalid parameter count. Expected 2.")));
+    }
+    let keyed = match keyed_items(vm, name, args) {
+        Ok(Some(k)) => k,
+        Ok(None) => return Value::Nil,
+        Err(e) => return e,
+    };
+    let mut best: Option<(Value, Value)> = None;
+    for (key, item) in keyed {
+        if matches!(key, Value::Nil) {
+            continue;
+        }
+        let better = match &best {
+            None => true,
+            Some((best_key, _)) => match key_order(name, &key, best_key) {
+                Ok(ord) => ord == wanted,
+                Err(e) => return e,
+            },
+        };
+        if better {
+            best = Some((key, item));
+        }
+    }
+    best.map(|(_, item)| item).unwrap_or(Value::Nil)
+}
+
+fn list_min_by(vm: &mut VM, args: &[Value]) -> Value {
+    extreme_by(vm, "minBy", args, std::cmp::Ordering::Less)
+}
+
+fn list_max_by(vm: &mut VM, args: &[Value]) -> Value {
+    extreme_by(vm, "maxBy", args, std::cmp::Ordering::Greater)
+}
+
+/// The KVC subject of a KVC function; `Ok(None)` when it is null.
+fn kvc_arg(name: &str, v: &Value) -> Result<Option<Arc<Mutex<KvcObject>>>, Value> {
+    match v {
+        Value::Nil => Ok(None),
+        Value::Error(e) => Err(Value::Error(e.clone())),
+        Value::Obj(o) => match &**o {
+            Obj::Kvc(k) => Ok(Some(Arc::clone(k))),
+            _ => Err(Value::error(FsError::new(ErrorKind::TypeMismatch, format!("{name}: first parameter should be a key-value collection")))),
+        },
+        _ => Err(Value::error(FsError::new(ErrorKind::TypeMismatch, format!("{name}: first parameter should be a key-value collection")))),
+    }
+}
+
+/// `(key, display name)` of a KVC's own members in insertion order, without evaluating them.
+fn kvc_own_keys(kvc: &Arc<Mutex<KvcObject>>) -> Vec<(String, String)> {
+    let k = kvc.lock().unwrap();
+    k.order.iter().map(|key_l| (key_l.clone(), k.display_names.get(key_l).cloned().unwrap_or_else(|| key_l.clone()))).collect()
+}
+
+/// A key given as text; the error names the function and what was expected.
+fn key_text(name: &str, v: &Value) -> Result<SmallString, Value> {
+    match v {
+        Value::Error(e) => Err(Value::Error(e.clone())),
+        Value::Obj(o) => match &**o {
+            Obj::String(s) => Ok(s.clone()),
+            _ => Err(Value::error(FsError::new(ErrorKind::TypeMismatch, format!("{name}: keys must be text")))),
+        },
+        _ => Err(Value::error(FsError::new(ErrorKind::TypeMismatch, format!("{name}: keys must be text")))),
+    }
+}
+
+fn kvc_keys(vm: &mut VM, args: &[Value]) -> Value {
+    if args.len() != 1 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "keys: Invalid parameter count. Expected 1."));
+    }
+    match kvc_arg("keys", &args[0]) {
+        Ok(Some(k)) => list_value(vm.kvc_keys(k).into_iter().map(text_value).collect()),
+        Ok(None) => Value::Nil,
+        Err(e) => e,
+    }
+}
+
+/// Evaluated `(display name, value)` members of the KVC argument, in insertion order.
+fn kvc_members(vm: &mut VM, name: &str, args: &[Value]) -> Result<Option<Vec<(String, Value)>>, Value> {
+    if args.len() != 1 {
+        return Err(Value::error(FsError::new(ErrorKind::ParameterCount, format!("{name}: Invalid parameter count. Expected 1."))));
+    }
+    let Some(kvc) = kvc_arg(name, &args[0])? else {
+        return Ok(None);
+    };
+    let members = kvc_own_keys(&kvc)
+        .into_iter()
+        .map(|(key_l, display)| {
+            let v = vm.kvc_get(Arc::clone(&kvc), &key_l, &display);
+            (display, v)
+        })
+        .collect();
+    Ok(Some(members))
+}
+
+fn kvc_values(vm: &mut VM, args: &[Value]) -> Value {
+    match kvc_members(vm, "values", args) {
+        Ok(Some(members)) => list_value(members.into_iter().map(|(_, v)| v).collect()),
+        Ok(None) => Value::Nil,
+        Err(e) => e,
+    }
+}
+
+/// `entries(kvc)`: `[{ key, value }]` in insertion order; `fromEntries` turns it back.
+fn kvc_entries(vm: &mut VM, args: &[Value]) -> Value {
+    match kvc_members(vm, "entries", args) {
+        Ok(Some(members)) => list_value(
+            members.into_iter().map(|(display, v)| kvc_from_cache(vec![("key", text_value(display)), ("value", v)])).collect(),
+        ),
+        Ok(None) => Value::Nil,
+        Err(e) => e,
+    }
+}
+
+/// `fromEntries(list)`: a KVC from `[key, value]` pairs or `{ key, value }` records.
+fn kvc_from_entries(vm: &mut VM, args: &[Value]) -> Value {
+    if args.len() != 1 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "fromEntries: Invalid parameter count. Expected 1."));
+    }
+    let items = match list_items("fromEntries", &args[0]) {
+        Ok(Some(items)) => items,
+        Ok(None) => return Value::Nil,
+        Err(e) => return e,
+    };
+    let shape_error = || Value::error(FsError::new(ErrorKind::TypeMismatch, "fromEntries: each entry should be [key, value] or { key, value }"));
+    let mut pairs = Vec::with_capacity(items.len());
+    for item in &items {
+        let (key, value) = match item {
+            Value::Error(e) => return Value::Error(e.clone()),
+            Value::Obj(o) => match &**o {
+                Obj::List(pair) if pair.len() == 2 => (pair[0].clone(), pair[1].clone()),
+                Obj::Kvc(k) => {
+                    let own = kvc_own_keys(k);
+                    if !own.iter().any(|(key_l, _)| key_l == "key") || own.iter().any(|(key_l, _)| key_l != "key" && key_l != "value") {
+                        return shape_error();
+                    }
+                    let key = vm.kvc_get(Arc::clone(k), "key", "key");
+                    let value = if own.len() == 2 { vm.kvc_get(Arc::clone(k), "value", "value") } else { Value::Nil };
+                    (key, value)
+                }
+                _ => return shape_error(),
+            },
+            _ => return shape_error(),
+        };
+        match key_text("fromEntries", &key) {
+            Ok(key) => pairs.push((key, value)),
+            Err(e) => return e,
+        }
+    }
+    kvc_from_cache(pairs)
+}
+
+fn kvc_has_key(args: &[Value]) -> Value {
+    if args.len() != 2 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "hasKey: Invalid parameter count. Expected 2."));
+    }
+    let kvc = match kvc_arg("hasKey", &args[0]) {
+        Ok(Some(k)) => k,
+        Ok(None) => return Value::Bool(false),
+        Err(e) => return e,
+    };
+    match key_text("hasKey", &args[1]) {
+        Ok(key) => Value::Bool(kvc.lock().unwrap().order.contains(&key.to_lowercase())),
+        Err(e) => e,
+    }
+}
+
+/// A copy of the members of `kvc` that `keep` accepts, in their order. Members not read yet
+/// keep their thunks, so copying evaluates nothing.
+fn kvc_copy(kvc: &Arc<Mutex<KvcObject>>, keep: impl Fn(&str) -> bool, parent: Option<Value>) -> KvcObject {
+    let k = kvc.lock().unwrap();
+    let order: Vec<String> = k.order.iter().filter(|key_l| keep(key_l)).cloned().collect();
+    KvcObject {
+        entries: order.iter().filter_map(|key_l| Some((key_l.clone(), Arc::clone(k.entries.get(key_l)?)))).collect(),
+        cache: order.iter().filter_map(|key_l| Some((key_l.clone(), k.cache.get(key_l)?.clone()))).collect(),
+        evaluating: HashSet::new(),
+        parent,
+        display_names: order.iter().filter_map(|key_l| Some((key_l.clone(), k.display_names.get(key_l)?.clone()))).collect(),
+        order,
+    }
+}
+
+/// Shared by `pick`/`omit`: a new KVC of the members whose inclusion in `args[1]` equals
+/// `listed`. Nothing is evaluated; the copy falls back to `args[0]`, so kept members can
+/// still refer to the ones left out.
+fn kvc_select(vm: &mut VM, name: &str, args: &[Value], listed: bool) -> Value {
+    if args.len() != 2 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, format!("{name}: Invalid parameter count. Expected 2.")));
+    }
+    let kvc = match kvc_arg(name, &args[0]) {
+        Ok(Some(k)) => k,
+        Ok(None) => return Value::Nil,
+        Err(e) => return e,
+    };
+    let wanted = match list_items(name, &args[1]) {
+        Ok(items) => items.unwrap_or_default(),
+        Err(e) => return e,
+    };
+    let mut names = HashSet::with_capacity(wanted.len());
+    for w in &wanted {
+        match key_text(name, w) {
+            Ok(key) => names.insert(key.to_lowercase()),
+            Err(e) => return e,
+        };
+    }
+    let copy = kvc_copy(&kvc, |key_l| names.contains(key_l) == listed, Some(args[0].clone()));
+    Value::Obj(Arc::new(Obj::Kvc(vm.alloc_kvc(copy))))
+}
+
+fn kvc_pick(vm: &mut VM, args: &[Value]) -> Value {
+    kvc_select(vm, "pick", args, true)
+}
+
+fn kvc_omit(vm: &mut VM, args: &[Value]) -> Value {
+    kvc_select(vm, "omit", args, false)
+}
+
+/// `getPath(value, path)`: follows text keys through KVCs and whole-number indexes through
+/// lists; null as soon as a step is missing.
+fn kvc_get_path(vm: &mut VM, args: &[Value]) -> Value {
+    if args.len() != 2 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "getPath: Invalid parameter count. Expected 2."));
+    }
+    let path = match list_items("getPath", &args[1]) {
+        Ok(Some(path)) => path,
+        Ok(None) => return args[0].clone(),
+        Err(e) => return e,
+    };
+    let mut current = args[0].clone();
+    for step in &path {
+        if let Value::Error(e) = step {
+            return Value::Error(e.clone());
+        }
+        let next = match (&current, step) {
+            (Value::Obj(o), Value::Obj(s)) => match (&**o, &**s) {
+                (Obj::Kvc(k), Obj::String(key)) => {
+                    let key_l = key.to_lowercase();
+                    let own = k.lock().unwrap().order.contains(&key_l);
+                    if own { vm.kvc_get(Arc::clone(k), &key_l, key) } else { Value::Nil }
+                }
+                _ => Value::Nil,
+            },
+            (Value::Obj(o), index) => match (&**o, as_i64_exact(index)) {
+                (Obj::List(items), Some(i)) => usize::try_from(i).ok().and_then(|i| items.get(i)).cloned().unwrap_or(Value::Nil),
+                (Obj::Range(r), Some(i)) if i >= 0 && (i as u64) < r.count as u64 => Value::Int(r.start + i),
+                _ => Value::Nil,
+            },
+            _ => Value::Nil,
+        };
+        if let Value::Error(_) = next {
+            return next;
+        }
+        current = next;
+    }
+    current
+}
+
+/// `setPath(kvc, path, value)`: a copy of `kvc` with `value` at `path`, creating KVCs for
+/// missing steps. The original is not changed.
+fn kvc_set_path(vm: &mut VM, args: &[Value]) -> Value {
+    if args.len() != 3 {
+        return Value::error(FsError::new(ErrorKind::ParameterCount, "setPath: Invalid parameter count. Expected 3."));
+    }
+    if let Value::Error(e) = &args[2] {
+        return Value::Error(e.clone());
+    }
+    let kvc = match kvc_arg("setPath", &args[0]) {
+        Ok(k) => k,
+        Err(e) => return e,
+    };
+    let path = match list_items("setPath", &args[1]) {
+        Ok(Some(path)) if !path.is_empty() => path,
+        Ok(_) => return Value::error(FsError::new(ErrorKind::InvalidValue, "setPath: path should list at least one key")),
+        Err(e) => return e,
+    };
+    let mut keys = Vec::with_capacity(path.len());
+    for step in &path {
+        match key_text("setPath", step) {
+            Ok(key) => keys.push(key),
+            Err(e) => return e,
+        }
+    }
+    set_path_in(vm, kvc, &keys, args[2].clone())
+}
+
+/// Only the members along `path` are evaluated; the others are copied as they are.
+fn set_path_in(vm: &mut VM, kvc: Option<Arc<Mutex<KvcObject>>>, path: &[SmallString], value: Value) -> Value {
+    let (key, rest) = path.split_first().expect("setPath checks for an empty path");
+    let key_l = key.to_lowercase();
+    let mut copy = match &kvc {
+        Some(k) => {
+            let parent = k.lock().unwrap().parent.clone();
+            kvc_copy(k, |_| true, parent)
+        }
+        None => KvcObject {
+            entries: HashMap::new(),
+            cache: HashMap::new(),
+            evaluating: HashSet::new(),
+            parent: None,
+            order: Vec::new(),
+            display_names: HashMap::new(),
+        },
+    };
+    let existing = copy.order.contains(&key_l);
+    let v = if rest.is_empty() {
+        value
+    } else {
+        let child = match &kvc {
+            Some(k) if existing => match vm.kvc_get(Arc::clone(k), &key_l, key) {
+                Value::Obj(o) => match &*o {
+                    Obj::Kvc(c) => Some(Arc::clone(c)),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        };
+        set_path_in(vm, child, rest, value)
+    };
+    copy.entries.remove(&key_l);
+    copy.cache.insert(key_l.clone(), v);
+    if !existing {
+        copy.order.push(key_l.clone());
+        copy.display_names.insert(key_l, key.to_string());
+    }
+    Value::Obj(Arc::new(Obj::Kvc(vm.alloc_kvc(copy))))
+}
+
+/// The collection an aggregate works on: `args[0]`, or its items mapped through the
+/// selector that follows the `extra` fixed parameters. Without a selector ranges stay lazy.
+fn aggregate_input(vm: &mut VM, name: &str, args: &[Value], extra: usize) -> Result<Value, Value> {
+    if args.len() < 1 + extra || args.len() > 2 + extra {
+        return Err(Value::error(FsError::new(
+            ErrorKind::ParameterCount,
+            format!("{name}: {} or {} parameters expected", 1 + extra, 2 + extra),
+        )));
+    }
+    let selector = match args.get(1 + extra) {
+        None | Some(Value::Nil) => return Ok(args[0].clone()),
+        Some(f) => f,
+    };
+    function_arg(name, "selector", selector)?;
+    match list_items(name, &args[0])? {
+        Some(items) => Ok(list_value(map_items(vm, selector, &items)?)),
+        None => Ok(Value::Nil),
+    }
+}
+
+fn range_arg(v: &Value) -> Option<&crate::obj::RangeObject> {
+    match v {
+        Value::Obj(o) => match &**o {
+            Obj::Range(r) => Some(r),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The numbers of an aggregate's list, skipping nulls like `Sum` does.
+fn number_items(name: &str, v: &Value) -> Result<Option<Vec<Value>>, Value> {
+    let Some(items) = list_items(name, v)? else {
+        return Ok(None);
+    };
+    let mut out = Vec::with_capacity(items.len());
+    for item in items {
+        match item {
+            Value::Nil => {}
+            Value::Error(e) => return Err(Value::Error(e)),
+            Value::Int(_) | Value::BigInt(_) => out.push(item),
+            Value::Number(n) if n.is_finite() => out.push(item),
+            _ => return Err(Value::error(FsError::new(ErrorKind::TypeMismatch, format!("{name}: expects list/range of numbers")))),
+        }
+    }
+    Ok(Some(out))
+}
+
+fn number_f64(name: &str, v: &Value) -> Result<f64, Value> {
+    match v {
+        Value::Int(n) => Ok(*n as f64),
+        Value::BigInt(n) => n
+            .to_f64()
+            .filter(|f| f.is_finite())
+            .ok_or_else(|| Value::error(FsError::new(ErrorKind::OutOfRange, format!("{name}: bigint too large")))),
+        Value::Number(n) => Ok(*n),
+        _ => Err(Value::error(FsError::new(ErrorKind::TypeMismatch, format!("{name}: expects list/range of numbers")))),
+    }
+}
+
+fn numbers_f64(name: &str, items: &[Value]) -> Result<Vec<f64>, Value> {
+    items.iter().map(|v| number_f64(name, v)).collect()
+}
+
+/// Running total or product: exact while every input is an integer, floating point from
+/// the first `Number` on, the way `+` and `*` promote.
+enum Accumulator {
+    Exact(BigInt),
+    Float(f64),
+}
+
+impl Accumulator {
+    fn fold(name: &str, items: &[Value], seed: i64, multiply: bool) -> Result<Accumulator, Value> {
+        let mut acc = Accumulator::Exact(BigInt::from(seed));
+        for v in items {
+            acc = match (acc, v) {
+                (Accumulator::Exact(a), Value::Int(n)) if multiply => Accumulator::Exact(a * n),
+                (Accumulator::Exact(a), Value::Int(n)) => Accumulator::Exact(a + n),
+                (Accumulator::Exact(a), Value::BigInt(n)) if multiply => Accumulator::Exact(a * &**n),
+                (Accumulator::Exact(a), Value::BigInt(n)) => Accumulator::Exact(a + &**n),
+                (acc, v) => {
+                    let a = match acc {
+                        Accumulator::Exact(a) => number_f64(name, &VM::bigint_to_value(a))?,
+                        Accumulator::Float(f) => f,
+                    };
+                    let n = number_f64(name, v)?;
+                    Accumulator::Float(if multiply { a * n } else { a + n })
+                }
+            };
+        }
+        Ok(acc)
+    }
+
+    fn into_value(self) -> Value {
+        match self {
+            Accumulator::Exact(n) => VM::bigint_to_value(n),
+            Accumulator::Float(f) => Value::Number(f),
+        }
+    }
+}
+
+/// `Avg(list, selector?)`: the mean as a `Number`, or null for an empty list.
+fn stat_avg(vm: &mut VM, args: &[Value]) -> Value {
+    let v = match aggregate_input(vm, "Avg", args, 0) {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+    if let Some(r) = range_arg(&v) {
+        return if r.count == 0 { Value::Nil } else { Value::Number(r.start as f64 + (r.count - 1) as f64 / 2.0) };
+    }
+    let items = match number_items("Avg", &v) {
+        Ok(Some(items)) if !items.is_empty() => items,
+        Ok(_) => return Value::Nil,
+        Err(e) => return e,
+    };
+    let total = match Accumulator::fold("Avg", &items, 0, false) {
+        Ok(Accumulator::Exact(n)) => number_f64("Avg", &VM::bigint_to_value(n)),
+        Ok(Accumulator::Float(f)) => Ok(f),
+        Err(e) => return e,
+    };
+    match total {
+        Ok(t) => Value::Number(t / items.len() as f64),
+        Err(e) => e,
+    }
+}
+
+/// `Median(list, selector?)`: the middle value, or the mean of the two middle values for an
+/// even count.
+fn stat_median(vm: &mut VM, args: &[Value]) -> Value {
+    let v = match aggregate_input(vm, "Median", args, 0) {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+    if let Some(r) = range_arg(&v) {
+        return match r.count {
+            0 => Value::Nil,
+            n if n % 2 == 1 => Value::Int(r.start + (n / 2) as i64),
+            n => Value::Number(r.start as f64 + (n - 1) as f64 / 2.0),
+        };
+    }
+    let mut items = match number_items("Median", &v) {
+        Ok(Some(items)) if !items.is_empty() => items,
+        Ok(_) => return Value::Nil,
+        Err(e) => return e,
+    };
+    items.sort_by(|a, b| VM::compare_values(a, b).unwrap_or(std::cmp::Ordering::Equal));
+    let mid = items.len() / 2;
+    if items.len() % 2 == 1 {
+        return items[mid].clone();
+    }
+    match (number_f64("Median", &items[mid - 1]), number_f64("Median", &items[mid])) {
+        (Ok(a), Ok(b)) => Value::Number(a + (b - a) / 2.0),
+        (Err(e), _) | (_, Err(e)) => e,
+    }
+}
+
+/// `Percentile(list, p, selector?)`: the `p`th percentile (0 to 100), interpolating linearly
+/// between neighbouring values.
+fn stat_percentile(vm: &mut VM, args: &[Value]) -> Value {
+    let v = match aggregate_input(vm, "Percentile", args, 1) {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+    let p = match &args[1] {
+        Value::Error(e) => return Value::Error(e.clone()),
+        Value::Int(_) | Value::BigInt(_) | Value::Number(_) => match number_f64("Percentile", &args[1]) {
+            Ok(p) if (0.0..=100.0).contains(&p) => p,
+            Ok(_) => return Value::error(FsError::new(ErrorKind::InvalidValue, "Percentile: p must be between 0 and 100")),
+            Err(e) => return e,
+        },
+        _ => return Value::error(FsError::new(ErrorKind::TypeMismatch, "Percentile: second parameter should be Number")),
+    };
+    if let Some(r) = range_arg(&v) {
+        return if r.count == 0 { Value::Nil } else { Value::Number(r.start as f64 + p / 100.0 * (r.count - 1) as f64) };
+    }
+    let mut xs = match number_items("Percentile", &v).and_then(|items| items.map(|i| numbers_f64("Percentile", &i)).transpose()) {
+        Ok(Some(xs)) if !xs.is_empty() => xs,
+        Ok(_) => return Value::Nil,
+        Err(e) => return e,
+    };
+    xs.sort_by(f64::total_cmp);
+    let rank = p / 100.0 * (xs.len() - 1) as f64;
+    let (lo, hi) = (rank.floor() as usize, rank.ceil() as usize);
+    Value::Number(xs[lo] + (xs[hi] - xs[lo]) * (rank - lo as f64))
+}
+
+/// `Variance(list, selector?, sample?)`: the population variance, or the sample variance
+/// (dividing by `n - 1`) when `sample` is true; null when there are too few values.
+/// `Variance(list, sample)` is short for `Variance(list, nil, sample)`.
+fn variance(vm: &mut VM, name: &str, args: &[Value]) -> Result<Option<f64>, Value> {
+    if args.is_empty() || args.len() > 3 {
+        return Err(Value::error(FsError::new(ErrorKind::ParameterCount, format!("{name}: 1 to 3 parameters expected"))));
+    }
+    let (input, flag) = match args {
+        [list, flag @ Value::Bool(_)] => (std::slice::from_ref(list), Some(flag)),
+        _ => (&args[..args.len().min(2)], args.get(2)),
+    };
+    let sample = match flag {
+        None | Some(Value::Nil) => false,
+        Some(Value::Bool(b)) => *b,
+        Some(Value::Error(e)) => return Err(Value::Error(e.clone())),
+        Some(_) => {
+            return Err(Value::error(FsError::new(ErrorKind::TypeMismatch, format!("{name}: sample flag should be Boolean"))))
+        }
+    };
+    let v = aggregate_input(vm, name, input, 0)?;
+    let min = if sample { 2 } else { 1 };
+    if let Some(r) = range_arg(&v) {
+        if r.count < min {
+            return Ok(None);
+        }
+        let n = r.count as f64;
+        return Ok(Some(if sample { n * (n + 1.0) / 12.0 } else { (n * n - 1.0) / 12.0 }));
+    }
+    let xs = match number_items(name, &v)? {
+        Some(items) => numbers_f64(name, &items)?,
+        None => return Ok(None),
+    };
+    if xs.len() < min {
+        return Ok(None);
+    }
+    let mean = xs.iter().sum::<f64>() / xs.len() as f64;
+    let squares: f64 = xs.iter().map(|x| (x - mean) * (x - mean)).sum();
+    Ok(Some(squares / (xs.len() - usize::from(sample)) as f64))
+}
+
+fn stat_variance(vm: &mut VM, args: &[Value]) -> Value {
+    variance(vm, "Variance", args).map_or_else(|e| e, |v| v.map_or(Value::Nil, Value::Number))
+}
+
+/// `StdDev(list, selector?, sample?)`: the square root of `Variance`.
+fn stat_std_dev(vm: &mut VM, args: &[Value]) -> Value {
+    variance(vm, "StdDev", args).map_or_else(|e| e, |v| v.map_or(Value::Nil, |v| Value::Number(v.sqrt())))
+}
+
+/// `Mode(list, selector?)`: the most frequent value; on a tie, the one that appears first.
+/// Works on anything `sortBy` can order, not just numbers.
+fn stat_mode(vm: &mut VM, args: &[Value]) -> Value {
+    let v = match aggregate_input(vm, "Mode", args, 0) {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+    if let Some(r) = range_arg(&v) {
+        return if r.count == 0 { Value::Nil } else { Value::Int(r.start) };
+    }
+    let items: Vec<Value> = match list_items("Mode", &v) {
+        Ok(Some(items)) => items.into_iter().filter(|v| !matches!(v, Value::Nil)).collect(),
+        Ok(None) => return Value::Nil,
+        Err(e) => return e,
+    };
+    if let Some(Value::Error(e)) = items.iter().find(|v| matches!(v, Value::Error(_))) {
+        return Value::Error(e.clone());
+    }
+    let mut order: Vec<usize> = (0..items.len()).collect();
+    let mut failure = None;
+    order.sort_by(|&a, &b| match key_order("Mode", &items[a], &items[b]) {
+        Ok(o) => o,
+        Err(e) => {
+            failure.get_or_insert(e);
+            std::cmp::Ordering::Equal
+        }
+    });
+    if let Some(e) = failure {
+        return e;
+    }
+    // Runs of equal values in sorted order; the stable sort puts each run's first occurrence
+    // at its head.
+    let mut best: Option<(usize, usize)> = None;
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start + 1;
+        while end < order.len() && VM::compare_values(&items[order[start]], &items[order[end]]) == Some(std::cmp::Ordering::Equal) {
+            end += 1;
+        }
+        let (count, first) = (end - start, order[start]);
+        if best.is_none_or(|(c, f)| count > c || (count == c && first < f)) {
+            best = Some((count, first));
+        }
+        start = end;
+    }
+    best.map_or(Value::Nil, |(_, first)| items[first].clone())
+}
+
+/// `Product(list, selector?)`: exact over integers like `Sum`; 1 for an empty list.
+fn stat_product(vm: &mut VM, args: &[Value]) -> Value {
+    let v = match aggregate_input(vm, "Product", args, 0) {
+        Ok(v) => v,
+        Err(e) => return e,
+    };
+    let items = match number_items("Product", &v) {
+        Ok(Some(items)) => items,
+        Ok(None) => return Value::Nil,
+        Err(e) => return e,
+    };
+    match Accumulator::fold("Product", &items, 1, true) {
+        Ok(acc) => acc.into_value(),
+        Err(e) => e,
     }
 }
@@ -1,13 +1,19 @@
 //! Heap-allocated object types used by the FuncScript VM.
+//!
+//! Objects are shared through `Arc` and KVC state sits behind a `Mutex`, so values and
+//! compiled functions can cross thread boundaries.
 
 use crate::value::Value;
 use std::collections::HashMap;
-use std::cell::RefCell;
 use std::collections::HashSet;
-use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::thread::ThreadId;
 use uuid::Uuid;
 
 use crate::chunk::Chunk;
+use crate::small_string::SmallString;
+use crate::host::HostContext;
+use crate::vm::VM;
 
 #[derive(Debug, Clone)]
 pub struct FsFunction {
@@ -27,19 +33,28 @@ impl PartialEq for FsFunction {
 
 #[derive(Debug, Clone)]
 pub enum Obj {
-    String(String),
+    String(SmallString),
     List(Vec<Value>),
     Range(RangeObject),
     Bytes(Vec<u8>),
     Guid(Uuid),
-    DateTimeTicks(i64),
+    /// Clock time in .NET ticks and the UTC offset in minutes it is local to; `None` is UTC,
+    /// written `Z` in RFC 3339 text.
+    DateTimeTicks(i64, Option<i16>),
+    /// Signed time span in .NET ticks (100 ns), like `System.TimeSpan`.
+    Duration(i64),
 
-    Kvc(Rc<RefCell<KvcObject>>),
+    Kvc(Arc<Mutex<KvcObject>>),
    
-    Provider(Rc<ProviderObject>),
-    Function(std::rc::Rc<FsFunction>),
+    Provider(Arc<ProviderObject>),
+    Function(Arc<FsFunction>),
 
     NativeFn(fn(&[Value]) -> Value),
+    /// Native that talks to the host (filesystem, logging, clock, random); called with the
+    /// running VM's `HostContext`.
+    HostFn(fn(&HostContext, &[Value]) -> Value),
+    /// Native that needs the running VM, to call script functions or use per-VM caches.
+    VmFn(fn(&mut VM, &[Value]) -> Value),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -56,14 +71,29 @@ pub struct ProviderObject {
 
 #[derive(Debug)]
 pub struct KvcObject {
-    pub entries: HashMap<String, Rc<FsFunction>>, 
+    pub entries: HashMap<String, Arc<FsFunction>>, 
     pub cache: HashMap<String, Value>,           
-    pub evaluating: HashSet<String>,           
+    // Keys being evaluated, per thread: a key only counts as a cycle on the thread that started it.
+    pub evaluating: HashSet<(String, ThreadId)>,
     pub parent: Option<Value>,                    
     pub order: Vec<String>,                       
     pub display_names: HashMap<String, String>,   
 }
 
+impl KvcObject {
+    /// Snapshot of `(display name, cached value)` pairs in insertion order.
+    /// Lets callers recurse into member values without holding the KVC lock.
+    pub fn cached_entries(&self) -> Vec<(String, Option<Value>)> {
+        self.order
+            .iter()
+            .map(|k| {
+                let display = self.display_names.get(k).cloned().unwrap_or_else(|| k.clone());
+                (display, self.cache.get(k).cloned())
+            })
+            .collect()
+    }
+}
+
 impl PartialEq for Obj {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
@@ -72,12 +102,21 @@ impl PartialEq for Obj {
             (Obj::Range(a), Obj::Range(b)) => a == b,
             (Obj::Bytes(a), Obj::Bytes(b)) => a == b,
             (Obj::Guid(a), Obj::Guid(b)) => a == b,
-            (Obj::DateTimeTicks(a), Obj::DateTimeTicks(b)) => a == b,
+            (Obj::DateTimeTicks(a, ao), Obj::DateTimeTicks(b, bo)) => {
+                crate::datetime::utc_ticks(*a, *ao) == crate::datetime::utc_ticks(*b, *bo)
+            }
+            (Obj::Duration(a), Obj::Duration(b)) => a == b,
             (Obj::Kvc(_), Obj::Kvc(_)) => false,
             (Obj::Provider(_), Obj::Provider(_)) => false,
             (Obj::NativeFn(a), Obj::NativeFn(b)) => {
                 std::ptr::eq(*a as *const (), *b as *const ())
             }
+            (Obj::HostFn(a), Obj::HostFn(b)) => {
+                std::ptr::eq(*a as *const (), *b as *const ())
+            }
+            (Obj::VmFn(a), Obj::VmFn(b)) => {
+                std::ptr::eq(*a as *const (), *b as *const ())
+            }
             _ => false,
         }
     }
@@ -94,16 +133,19 @@ impl std::fmt::Display for Obj {
             Obj::Range(r) => write!(f, "<range start={} count={}>", r.start, r.count),
             Obj::Bytes(b) => write!(f, "<bytes len={}>", b.len()),
             Obj::Guid(g) => write!(f, "{}", g),
-            Obj::DateTimeTicks(ticks) => write!(f, "<datetime ticks={}>", ticks),
+            Obj::DateTimeTicks(ticks, None) => write!(f, "<datetime ticks={}>", ticks),
+            Obj::DateTimeTicks(ticks, Some(offset)) => {
+                write!(f, "<datetime ticks={} offset={}>", ticks, crate::datetime::offset_text(*offset))
+            }
+            Obj::Duration(ticks) => write!(f, "{}", crate::datetime::duration_text(*ticks)),
             Obj::Kvc(kvc) => {
-                let kvc = kvc.borrow();
+                let entries = kvc.lock().unwrap().cached_entries();
                 write!(f, "{{ ")?;
                 let mut first = true;
-                for k in kvc.order.iter() {
+                for (display, v) in entries.iter() {
                     if !first { write!(f, ", ")?; }
                     first = false;
-                    let display = kvc.display_names.get(k).map(|s| s.as_str()).unwrap_or(k.as_str());
-                    if let Some(v) = kvc.cache.get(k) {
+                    if let Some(v) = v {
                         write!(f, "{}: {}", display, v)?;
                     } else {
                         write!(f, "{}: <lazy>", display)?;
@@ -113,7 +155,7 @@ impl std::fmt::Display for Obj {
             }
             Obj::Provider(_) => write!(f, "<provider>"),
             Obj::Function(func) => write!(f, "<fn {}>", func.name),
-            Obj::NativeFn(_) => write!(f, "<native fn>"),
+            Obj::NativeFn(_) | Obj::HostFn(_) | Obj::VmFn(_) => write!(f, "<native fn>"),
         }
     }
 }
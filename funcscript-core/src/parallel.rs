@@ -0,0 +1,104 @@
+//! Opt-in parallel evaluation for `map`/`filter`/`Sum` over large collections.
+//!
+//! The VM only fans work out when a `ParallelConfig` is set, the collection has at least
+//! `min_items` elements and the lambda is free of host side effects (see `VM::set_parallel`).
+//! Work is split into contiguous index ranges, one per worker, so results keep their order
+//! and the first error by index wins, exactly as in sequential evaluation.
+
+use crate::native;
+use crate::obj::Obj;
+use crate::value::Value;
+use crate::vm::VM;
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParallelConfig {
+    /// Number of worker threads used for a single `map`/`filter`/`Sum`.
+    pub workers: usize,
+    /// Collections shorter than this are always evaluated sequentially.
+    pub min_items: usize,
+}
+
+impl ParallelConfig {
+    pub fn new(workers: usize) -> Self {
+        ParallelConfig { workers: workers.max(1), min_items: 1024 }
+    }
+
+    pub(crate) fn applies_to(&self, len: usize) -> bool {
+        self.workers > 1 && len >= self.min_items.max(2)
+    }
+}
+
+impl Default for ParallelConfig {
+    fn default() -> Self {
+        let workers = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        ParallelConfig::new(workers)
+    }
+}
+
+/// Splits `0..len` into at most `workers` contiguous, in-order ranges.
+pub(crate) fn split(len: usize, workers: usize) -> Vec<Range<usize>> {
+    let workers = workers.clamp(1, len.max(1));
+    let base = len / workers;
+    let extra = len % workers;
+    let mut out = Vec::with_capacity(workers);
+    let mut start = 0;
+    for w in 0..workers {
+        let size = base + usize::from(w < extra);
+        out.push(start..start + size);
+        start += size;
+    }
+    out
+}
+
+/// Parallel fast path for `Sum(list)`.
+///
+/// Only integer lists are summed on workers: partial sums are exact, so the result matches
+/// the sequential fold bit for bit. Lists containing floats, errors or non-numbers return
+/// `None` and the caller runs the regular native, which keeps float rounding and error
+/// reporting identical.
+pub(crate) fn try_sum(f: fn(&mut VM, &[Value]) -> Value, args: &[Value], config: &ParallelConfig) -> Option<Value> {
+    if !native::is_sum(f) || args.len() != 1 {
+        return None;
+    }
+    let items = match &args[0] {
+        Value::Obj(o) => match &**o {
+            Obj::List(items) if config.applies_to(items.len()) => items,
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    let parts: Vec<Option<BigInt>> = std::thread::scope(|s| {
+        let handles: Vec<_> = split(items.len(), config.workers)
+            .into_iter()
+            .map(|r| {
+                let chunk = &items[r];
+                s.spawn(move || {
+                    let mut sum = BigInt::from(0);
+                    for v in chunk {
+                        match v {
+                            Value::Int(n) => sum += *n,
+                            Value::BigInt(n) => sum += &**n,
+                            Value::Nil => {}
+                            _ => return None,
+                        }
+                    }
+                    Some(sum)
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().ok().flatten()).collect()
+    });
+
+    let mut total = BigInt::from(0);
+    for part in parts {
+        total += part?;
+    }
+    Some(match total.to_i64() {
+        Some(v) => Value::Int(v),
+        None => Value::bigint(total),
+    })
+}
@@ -1,26 +1,185 @@
 //! Runtime value representation and structured error type.
+//!
+//! `Value` is kept at 16 bytes (tag + one word) so stack slots and list elements stay small:
+//! the rarely used `BigInt` and `Error` payloads are boxed, everything else is inline or
+//! behind the shared `Obj` pointer.
 
-use std::rc::Rc;
+use std::sync::Arc;
 use crate::obj::Obj;
 use num_bigint::BigInt;
 
+/// What went wrong, independent of the message text.
+///
+/// Each kind has a stable numeric code and a snake_case name; both are what hosts see in
+/// `FsErrorC.code`, the JSON error object and the `code`/`kind` accessors on error values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    /// The source could not be compiled.
+    Syntax,
+    /// Unclassified runtime failure.
+    Runtime,
+    /// A value of the wrong type was passed or operated on.
+    TypeMismatch,
+    /// A function was called with the wrong number of parameters.
+    ParameterCount,
+    /// A name or member that does not exist was used as a function.
+    Undefined,
+    /// Division or modulo by zero.
+    DivisionByZero,
+    /// A numeric result or index does not fit the target range.
+    OutOfRange,
+    /// A value has the right type but invalid content (unparsable text, bad pattern, ...).
+    InvalidValue,
+    /// An evaluation limit (call depth, code size, ...) was exceeded.
+    LimitExceeded,
+    /// The embedding API was misused (null pointers, stale handles, invalid UTF-8).
+    Api,
+    /// A host operation (file system, network, ...) failed.
+    HostIo,
+    /// The host did not provide the callback an operation needs.
+    HostUnavailable,
+    /// The host sandbox refused access to a path outside the allowed roots.
+    AccessDenied,
+    /// Raised by script code through `error(...)`.
+    User,
+}
+
+impl ErrorKind {
+    pub const ALL: [ErrorKind; 14] = [
+        ErrorKind::Syntax,
+        ErrorKind::Runtime,
+        ErrorKind::TypeMismatch,
+        ErrorKind::ParameterCount,
+        ErrorKind::Undefined,
+        ErrorKind::DivisionByZero,
+        ErrorKind::OutOfRange,
+        ErrorKind::InvalidValue,
+        ErrorKind::LimitExceeded,
+        ErrorKind::Api,
+        ErrorKind::HostIo,
+        ErrorKind::HostUnavailable,
+        ErrorKind::AccessDenied,
+        ErrorKind::User,
+    ];
+
+    pub fn code(self) -> u32 {
+        match self {
+            ErrorKind::Syntax => 1000,
+            ErrorKind::Runtime => 2000,
+            ErrorKind::TypeMismatch => 2001,
+            ErrorKind::ParameterCount => 2002,
+            ErrorKind::Undefined => 2003,
+            ErrorKind::DivisionByZero => 2004,
+            ErrorKind::OutOfRange => 2005,
+            ErrorKind::InvalidValue => 2006,
+            ErrorKind::LimitExceeded => 2007,
+            ErrorKind::Api => 2100,
+            ErrorKind::HostIo => 2600,
+            ErrorKind::HostUnavailable => 2601,
+            ErrorKind::AccessDenied => 2602,
+            ErrorKind::User => 3000,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        self.c_name().to_str().expect("error kind names are ASCII")
+    }
+
+    /// `name` with a trailing NUL, for the C ABI.
+    pub fn c_name(self) -> &'static std::ffi::CStr {
+        match self {
+            ErrorKind::Syntax => c"syntax",
+            ErrorKind::Runtime => c"runtime",
+            ErrorKind::TypeMismatch => c"type_mismatch",
+            ErrorKind::ParameterCount => c"parameter_count",
+            ErrorKind::Undefined => c"undefined",
+            ErrorKind::DivisionByZero => c"division_by_zero",
+            ErrorKind::OutOfRange => c"out_of_range",
+            ErrorKind::InvalidValue => c"invalid_value",
+            ErrorKind::LimitExceeded => c"limit_exceeded",
+            ErrorKind::Api => c"api",
+            ErrorKind::HostIo => c"host_io",
+            ErrorKind::HostUnavailable => c"host_unavailable",
+            ErrorKind::AccessDenied => c"access_denied",
+            ErrorKind::User => c"user",
+        }
+    }
+
+    pub fn from_code(code: u32) -> Option<ErrorKind> {
+        ErrorKind::ALL.into_iter().find(|k| k.code() == code)
+    }
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct FsError {
-    pub code: u32,
+    pub kind: ErrorKind,
     pub message: String,
+    /// Type name given to `error(message, type)`, if any.
+    pub user_type: Option<String>,
+    /// Structured payload attached by the raiser.
+    pub data: Option<Value>,
+    /// The error this one was raised in response to.
+    pub cause: Option<Box<FsError>>,
     pub line: i32,
     pub column: i32,
 }
 
+impl FsError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> FsError {
+        FsError { kind, message: message.into(), user_type: None, data: None, cause: None, line: -1, column: -1 }
+    }
+
+    pub fn code(&self) -> u32 {
+        self.kind.code()
+    }
+
+    pub fn at(mut self, line: i32, column: i32) -> FsError {
+        self.line = line;
+        self.column = column;
+        self
+    }
+
+    pub fn with_data(mut self, data: Value) -> FsError {
+        self.data = Some(data);
+        self
+    }
+
+    pub fn with_cause(mut self, cause: FsError) -> FsError {
+        self.cause = Some(Box::new(cause));
+        self
+    }
+
+    /// Iterates over this error and its causes, innermost last.
+    pub fn chain(&self) -> impl Iterator<Item = &FsError> {
+        std::iter::successors(Some(self), |e| e.cause.as_deref())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Bool(bool),
     Nil,
     Int(i64),
-    BigInt(BigInt),
+    BigInt(Box<BigInt>),
     Number(f64),
-    Obj(Rc<Obj>),
-    Error(FsError),
+    Obj(Arc<Obj>),
+    Error(Box<FsError>),
+}
+
+impl Value {
+    pub fn error(e: FsError) -> Value {
+        Value::Error(Box::new(e))
+    }
+
+    pub fn bigint(n: BigInt) -> Value {
+        Value::BigInt(Box::new(n))
+    }
 }
 
 impl std::fmt::Display for Value {
@@ -32,7 +191,7 @@ impl std::fmt::Display for Value {
             Value::BigInt(n) => write!(f, "{}", n),
             Value::Number(n) => write!(f, "{}", n),
             Value::Obj(o) => write!(f, "{}", o),
-            Value::Error(e) => write!(f, "Error({}): {}", e.code, e.message),
+            Value::Error(e) => write!(f, "Error({}): {}", e.code(), e.message),
         }
     }
 }
@@ -4,9 +4,10 @@
 //! - `providers` implements the FuncScript scoping/provider chain (for KVC + selectors).
 //! - `Obj::Range` is lazy to avoid allocating huge lists for `Range(start,count)`.
 //! - Many operations return `Value::Error` instead of panicking to keep scripts safe.
+//! - Values are `Arc`-based, so a `VM` is `Send` and compiled programs are `Send + Sync`.
 
-use crate::chunk::OpCode;
-use crate::value::{FsError, Value};
+use crate::chunk::{BinaryOp, OpCode};
+use crate::value::{ErrorKind, FsError, Value};
 use num_bigint::BigInt;
 use num_traits::ToPrimitive;
 use base64::{engine::general_purpose, Engine as _};
@@ -21,24 +22,39 @@ use std::collections::HashMap;
 
 use crate::obj::{Obj, FsFunction, KvcObject, ProviderObject};
 use crate::compiler::Compiler;
-use std::rc::Rc;
-use std::cell::RefCell;
+use crate::parallel::{self, ParallelConfig};
+use crate::gc::{KvcRegistry, MemoryStats};
+use crate::host::{HostCallbacks, HostContext};
+use std::sync::Arc;
+use std::sync::Mutex;
+use regex::Regex;
 
 const FRAMES_MAX: usize = 64;
 const STACK_MAX: usize = 256;
+/// How deep `force_members` descends into the arguments of natives that read KVCs.
+const KVC_FORCE_DEPTH: usize = 16;
+const REGEX_CACHE_MAX: usize = 256;
 
 struct CallFrame {
-    function: Rc<FsFunction>, 
+    function: Arc<FsFunction>, 
     ip: usize,
     slots: usize, 
 }
 
 impl CallFrame {
-    fn new(function: Rc<FsFunction>, slots: usize) -> Self {
+    fn new(function: Arc<FsFunction>, slots: usize) -> Self {
         Self { function, ip: 0, slots }
     }
 }
 
+/// An active `try`: the VM state to restore and where its catch block starts.
+struct TryHandler {
+    frames: usize,
+    stack: usize,
+    providers: usize,
+    catch_ip: usize,
+}
+
 pub struct VM {
     frames: Vec<CallFrame>,
     stack: Vec<Value>,
@@ -46,6 +62,22 @@ pub struct VM {
     providers: Vec<Value>,
     values: Vec<Option<Value>>,
     free_value_ids: Vec<u64>,
+    parallel: Option<ParallelConfig>,
+    kvcs: KvcRegistry,
+    handlers: Vec<TryHandler>,
+    host: HostContext,
+    regex_cache: HashMap<String, Regex>,
+}
+
+/// The code point at `index` as a one-character string, or nil when out of range.
+fn char_at(text: &str, index: i64) -> Value {
+    if index < 0 {
+        return Value::Nil;
+    }
+    match text.chars().nth(index as usize) {
+        Some(c) => Value::Obj(Arc::new(Obj::String(c.to_string().into()))),
+        None => Value::Nil,
+    }
 }
 
 impl VM {
@@ -60,6 +92,98 @@ impl VM {
             providers: Vec::new(),
             values: Vec::new(),
             free_value_ids: Vec::new(),
+            parallel: None,
+            kvcs: KvcRegistry::default(),
+            handlers: Vec::new(),
+            host: HostContext::default(),
+            regex_cache: HashMap::new(),
+        }
+    }
+
+    /// VM whose natives use `callbacks` for filesystem access, logging, clock and randomness.
+    pub fn with_host(callbacks: HostCallbacks) -> Self {
+        let mut vm = VM::new();
+        vm.set_host(callbacks);
+        vm
+    }
+
+    /// Replaces the host callbacks; a random seed set on this VM is kept.
+    pub fn set_host(&mut self, callbacks: HostCallbacks) {
+        self.host.callbacks = callbacks;
+    }
+
+    pub fn host(&self) -> &HostCallbacks {
+        &self.host.callbacks
+    }
+
+    /// Seeds `Random()` and `guid()` so that runs are reproducible, or with `None` goes back
+    /// to the host random callback (or ambient entropy). The sequence continues across
+    /// evaluations on this VM; set the seed again to restart it.
+    pub fn set_random_seed(&mut self, seed: Option<u64>) {
+        self.host.set_seed(seed);
+    }
+
+    /// Enables (or with `None`, disables) parallel `map`/`filter`/`Sum`.
+    ///
+    /// Lambdas are only run on workers when nothing they can reach calls a host native
+    /// (`log`, `file`, ...); otherwise evaluation stays sequential on the calling thread.
+    pub fn set_parallel(&mut self, config: Option<ParallelConfig>) {
+        self.parallel = config;
+    }
+
+    pub fn parallel(&self) -> Option<ParallelConfig> {
+        self.parallel
+    }
+
+    /// Allocates a KVC and registers it with the cycle collector.
+    pub(crate) fn alloc_kvc(&mut self, kvc: KvcObject) -> Arc<Mutex<KvcObject>> {
+        let kvc = Arc::new(Mutex::new(kvc));
+        self.kvcs.track(&kvc);
+        kvc
+    }
+
+    /// Frees KVC reference cycles that are no longer reachable from the host, value
+    /// handles or globals. Returns the number of KVCs freed.
+    ///
+    /// Long-lived VMs should call it between evaluations, or turn on automatic collection
+    /// with `set_auto_collect`. It must not run while another thread holds values from this
+    /// VM: references cloned or dropped there during the collection can get KVCs that are
+    /// still in use emptied.
+    pub fn collect_cycles(&mut self) -> usize {
+        self.kvcs.collect()
+    }
+
+    /// Lets `execute` collect cycles on its own once enough KVCs have been created. Off by
+    /// default, since values can be sent to other threads; only turn it on when no other
+    /// thread holds values from this VM while it evaluates.
+    pub fn set_auto_collect(&mut self, enabled: bool) {
+        self.kvcs.automatic = enabled;
+    }
+
+    pub fn memory_stats(&self) -> MemoryStats {
+        MemoryStats {
+            live_kvcs: self.kvcs.live(),
+            tracked_kvcs: self.kvcs.tracked(),
+            value_handles: self.values.iter().filter(|v| v.is_some()).count(),
+            collections: self.kvcs.collections,
+            collected_kvcs: self.kvcs.collected,
+        }
+    }
+
+    /// Fresh VM for a parallel worker: same globals and provider chain, no parallelism.
+    fn worker(&self) -> VM {
+        VM {
+            frames: Vec::with_capacity(FRAMES_MAX),
+            stack: Vec::with_capacity(STACK_MAX),
+            globals: self.globals.clone(),
+            providers: self.providers.clone(),
+            values: Vec::new(),
+            free_value_ids: Vec::new(),
+            parallel: None,
+            kvcs: KvcRegistry::default(),
+            handlers: Vec::new(),
+            host: self.host.clone(),
+            regex_cache: self.regex_cache.clone(),
         }
     }
 
@@ -106,6 +230,7 @@ impl VM {
         self.frames.clear();
         self.stack.clear();
         self.providers.clear();
+        self.handlers.clear();
 
         self.stack.push(callee);
         for a in args {
@@ -147,6 +272,7 @@ impl VM {
                         Value::Int(r.start + idx as i64)
                     }
                 }
+                Obj::String(text) => char_at(text, index),
                 Obj::Bytes(b) => {
                     if index < 0 {
                         return Value::Nil;
@@ -164,15 +290,15 @@ impl VM {
                     }
                     let idx = index as usize;
                     let key_l = {
-                        let b = k.borrow();
+                        let b = k.lock().unwrap();
                         b.order.get(idx).cloned()
                     };
                     if let Some(key_l) = key_l {
                         let display = {
-                            let b = k.borrow();
+                            let b = k.lock().unwrap();
                             b.display_names.get(&key_l).cloned().unwrap_or_else(|| key_l.clone())
                         };
-                        self.kvc_get(Rc::clone(k), &key_l, &display)
+                        self.kvc_get(Arc::clone(k), &key_l, &display)
                     } else {
                         Value::Nil
                     }
@@ -189,19 +315,19 @@ impl VM {
         }
         match v {
             Value::Obj(o) => match &**o {
-                Obj::String(s) => Value::Int(s.len() as i64),
+                Obj::String(s) => Value::Int(s.chars().count() as i64),
                 Obj::List(l) => Value::Int(l.len() as i64),
                 Obj::Range(r) => Value::Int(r.count as i64),
                 Obj::Bytes(b) => Value::Int(b.len() as i64),
-                Obj::Kvc(k) => Value::Int(k.borrow().order.len() as i64),
+                Obj::Kvc(k) => Value::Int(k.lock().unwrap().order.len() as i64),
                 _ => Value::Nil,
             },
             _ => Value::Nil,
         }
     }
 
-    pub fn kvc_keys(&self, k: Rc<RefCell<KvcObject>>) -> Vec<String> {
-        let b = k.borrow();
+    pub fn kvc_keys(&self, k: Arc<Mutex<KvcObject>>) -> Vec<String> {
+        let b = k.lock().unwrap();
         b.order
             .iter()
             .map(|key_l| b.display_names.get(key_l).cloned().unwrap_or_else(|| key_l.clone()))
@@ -214,8 +340,34 @@ impl VM {
 
     fn run(&mut self) -> Result<Value, InterpretResult> {
         loop {
-            if let Some(v) = self.step_current()? {
-                return Ok(v);
+            match self.step_current() {
+                Ok(Some(v)) => return Ok(v),
+                Ok(None) => {}
+                Err(e) => self.recover(e, 0)?,
+            }
+        }
+    }
+
+    /// Resumes at the catch block of the innermost `try` installed above `floor` frames.
+    /// Without one, handlers above `floor` are discarded and `err` is returned.
+    fn recover(&mut self, err: InterpretResult, floor: usize) -> Result<(), InterpretResult> {
+        let e = match err {
+            InterpretResult::RuntimeError(e) => e,
+            other => return Err(other),
+        };
+        match self.handlers.last() {
+            Some(h) if h.frames > floor && h.frames <= self.frames.len() => {
+                let h = self.handlers.pop().unwrap();
+                self.frames.truncate(h.frames);
+                self.stack.truncate(h.stack);
+                self.providers.truncate(h.providers);
+                self.frames[h.frames - 1].ip = h.catch_ip;
+                self.stack.push(Value::error(e));
+                Ok(())
+            }
+            _ => {
+                self.handlers.retain(|h| h.frames <= floor);
+                Err(InterpretResult::RuntimeError(e))
             }
         }
     }
@@ -239,8 +391,8 @@ impl VM {
             return Ok(None);
         }
 
-             let instruction = self.frames[frame_idx].function.chunk.code[self.frames[frame_idx].ip].clone();
-             self.frames[frame_idx].ip += 1;
+             let (instruction, next_ip) = self.frames[frame_idx].function.chunk.read(self.frames[frame_idx].ip);
+             self.frames[frame_idx].ip = next_ip;
 
             match instruction {
                 OpCode::OpConstant(idx) => {
@@ -260,72 +412,7 @@ impl VM {
                 }
                 self.stack.push(result);
             }
-                OpCode::OpAdd => {
-                    let b = self.pop();
-                    let a = self.pop();
-                    match (a, b) {
-                        (Value::Nil, Value::Nil) => self.stack.push(Value::Nil),
-                        (Value::Nil, other) => self.stack.push(other),
-                        (other, Value::Nil) => self.stack.push(other),
-                        (a, b) if VM::is_numeric(&a) && VM::is_numeric(&b) => {
-                            let v = self.numeric_add(a, b)?;
-                            self.stack.push(v);
-                        }
-                        (Value::Obj(a), b)
-                            if matches!(&*a, crate::obj::Obj::String(_)) && VM::is_numeric(&b) =>
-                        {
-                            let s1 = match &*a {
-                                crate::obj::Obj::String(s) => s,
-                                _ => unreachable!(),
-                            };
-                            let s = format!("{s1}{b}");
-                            self.stack.push(Value::Obj(Rc::new(Obj::String(s))));
-                        }
-                        (a, Value::Obj(b))
-                            if matches!(&*b, crate::obj::Obj::String(_)) && VM::is_numeric(&a) =>
-                        {
-                            let s2 = match &*b {
-                                crate::obj::Obj::String(s) => s,
-                                _ => unreachable!(),
-                            };
-                            let s = format!("{a}{s2}");
-                            self.stack.push(Value::Obj(Rc::new(Obj::String(s))));
-                        }
-                        (Value::Obj(a), Value::Obj(b)) => {
-                            match (&*a, &*b) {
-                                (crate::obj::Obj::String(s1), crate::obj::Obj::String(s2)) => {
-                                    let s = format!("{}{}", s1, s2);
-                                    let obj = crate::obj::Obj::String(s);
-                                    self.stack.push(Value::Obj(std::rc::Rc::new(obj)));
-                                },
-                                (crate::obj::Obj::List(l1), crate::obj::Obj::List(l2)) => {
-                                    let mut out = Vec::with_capacity(l1.len() + l2.len());
-                                    out.extend(l1.iter().cloned());
-                                    out.extend(l2.iter().cloned());
-                                    self.stack.push(Value::Obj(Rc::new(Obj::List(out))));
-                                }
-                                (crate::obj::Obj::List(l1), _) => {
-                                    let mut out = Vec::with_capacity(l1.len() + 1);
-                                    out.extend(l1.iter().cloned());
-                                    out.push(Value::Obj(Rc::clone(&b)));
-                                    self.stack.push(Value::Obj(Rc::new(Obj::List(out))));
-                                }
-                                (_, crate::obj::Obj::List(l2)) => {
-                                    let mut out = Vec::with_capacity(l2.len() + 1);
-                                    out.push(Value::Obj(Rc::clone(&a)));
-                                    out.extend(l2.iter().cloned());
-                                    self.stack.push(Value::Obj(Rc::new(Obj::List(out))));
-                                }
-                                (crate::obj::Obj::Kvc(k1), crate::obj::Obj::Kvc(k2)) => {
-                                    let merged = self.merge_kvc(Rc::clone(k1), Rc::clone(k2));
-                                    self.stack.push(merged);
-                                }
-                                _ => return Err(self.runtime_error()),
-                            }
-                        },
-                        _ => return Err(self.runtime_error()),
-                    }
-                }
+                OpCode::OpAdd => self.add()?,
                 OpCode::OpSubtract => self.numeric_subtract()?,
                 OpCode::OpMultiply => self.numeric_multiply()?,
                 OpCode::OpDivide => self.numeric_divide()?,
@@ -371,36 +458,21 @@ impl VM {
                     match value {
                         Value::Bool(b) => self.stack.push(Value::Bool(!b)),
                         Value::Error(e) => self.stack.push(Value::Error(e)),
-                        Value::Nil => return Err(self.runtime_error_with(2011, "not: bool expected (got nil)")),
-                        _ => return Err(self.runtime_error_with(2011, "not: bool expected")),
+                        Value::Nil => return Err(self.runtime_error_with(ErrorKind::TypeMismatch, "not: bool expected (got nil)")),
+                        _ => return Err(self.runtime_error_with(ErrorKind::TypeMismatch, "not: bool expected")),
                     }
                 }
-                OpCode::OpEqual => {
-                    let b = self.pop();
-                    let a = self.pop();
-                    let eq = self.values_equal(&a, &b);
-                    self.stack.push(Value::Bool(eq));
-                }
-                OpCode::OpGreater => {
-                    let b = self.pop();
-                    let a = self.pop();
-                    let gt = self.numeric_compare_gt(&a, &b)?;
-                    self.stack.push(Value::Bool(gt));
-                }
-                OpCode::OpLess => {
-                    let b = self.pop();
-                    let a = self.pop();
-                    let lt = self.numeric_compare_lt(&a, &b)?;
-                    self.stack.push(Value::Bool(lt));
-                }
+                OpCode::OpEqual => self.binary(BinaryOp::Equal)?,
+                OpCode::OpGreater => self.binary(BinaryOp::Greater)?,
+                OpCode::OpLess => self.binary(BinaryOp::Less)?,
                 OpCode::OpBuildList(count) => {
                     let start_idx = self.stack.len() - count;
                     let items: Vec<Value> = self.stack.drain(start_idx..).collect();
                     let obj = crate::obj::Obj::List(items);
-                    self.stack.push(Value::Obj(std::rc::Rc::new(obj)));
+                    self.stack.push(Value::Obj(std::sync::Arc::new(obj)));
                 }
                 OpCode::OpBuildKvc(count) => {
-                    let mut entries: HashMap<String, Rc<FsFunction>> = HashMap::new();
+                    let mut entries: HashMap<String, Arc<FsFunction>> = HashMap::new();
                     let mut order: Vec<String> = Vec::with_capacity(count);
                     let mut display_names: HashMap<String, String> = HashMap::new();
                      for _ in 0..count {
@@ -417,7 +489,7 @@ impl VM {
 
                         let thunk = match thunk {
                             Value::Obj(o) => match &*o {
-                                Obj::Function(f) => Rc::clone(f),
+                                Obj::Function(f) => Arc::clone(f),
                                 _ => return Err(self.runtime_error()),
                             },
                             _ => return Err(self.runtime_error()),
@@ -425,7 +497,7 @@ impl VM {
 
                         let k = key.to_lowercase();
                         entries.insert(k.clone(), thunk);
-                        display_names.insert(k.clone(), key);
+                        display_names.insert(k.clone(), key.to_string());
                         order.push(k);
                     }
 
@@ -458,7 +530,7 @@ impl VM {
                                 order: scope_order,
                                 display_names: scope_display_names,
                             };
-                            parent = Some(Value::Obj(Rc::new(Obj::Kvc(Rc::new(RefCell::new(scope_kvc))))));
+                            parent = Some(Value::Obj(Arc::new(Obj::Kvc(self.alloc_kvc(scope_kvc)))));
                         }
                     }
                     let kvc = KvcObject {
@@ -469,20 +541,12 @@ impl VM {
                         order: order.into_iter().rev().collect(),
                         display_names,
                     };
-                    self.stack.push(Value::Obj(Rc::new(Obj::Kvc(Rc::new(RefCell::new(kvc))))));
+                    let kvc = self.alloc_kvc(kvc);
+                    self.stack.push(Value::Obj(Arc::new(Obj::Kvc(kvc))));
                 }
                 OpCode::OpGetProp(idx) => {
-                     let name_val = {
-                         let f = &self.frames[frame_idx];
-                         f.function.chunk.constants[idx].clone()
-                     };
-                     let name = if let Value::Obj(o) = name_val {
-                         if let crate::obj::Obj::String(s) = &*o { s.clone() } else { return Err(self.runtime_error()); }
-                     } else { return Err(self.runtime_error()); };
-
-                     let receiver = self.peek(0);
-                     let val = self.provider_get(&receiver, &name);
-                     self.pop();
+                     let receiver = self.pop();
+                     let val = self.get_prop(frame_idx, receiver, idx)?;
                      self.stack.push(val);
                 }
                 OpCode::OpCall(arg_count) => self.call_value(arg_count)?,
@@ -498,13 +562,17 @@ impl VM {
                     let arity = match &fn_val {
                         Value::Obj(o) => match &**o {
                             Obj::Function(f) => f.arity,
-                            Obj::NativeFn(_) => 2,
+                            Obj::NativeFn(_) | Obj::HostFn(_) | Obj::VmFn(_) => 2,
                             _ => return Err(self.runtime_error()),
                         },
                         _ => return Err(self.runtime_error()),
                     };
                     if arity != 1 && arity != 2 {
-                        return Err(self.runtime_error_with(2015, "map: expected function of arity 1 or 2"));
+                        return Err(self.runtime_error_with(ErrorKind::TypeMismatch, "map: expected function of arity 1 or 2"));
+                    }
+                    if let Some(out) = self.parallel_apply(&fn_val, &list_val, arity, false)? {
+                        self.stack.push(Value::Obj(Arc::new(Obj::List(out))));
+                        return Ok(None);
                     }
 
                     let mut out: Vec<Value> = Vec::new();
@@ -551,7 +619,7 @@ impl VM {
                         },
                         _ => return Err(self.runtime_error()),
                     }
-                    self.stack.push(Value::Obj(Rc::new(Obj::List(out))));
+                    self.stack.push(Value::Obj(Arc::new(Obj::List(out))));
                 }
                 OpCode::OpFilter => {
                     let fn_val = self.pop();
@@ -565,13 +633,26 @@ impl VM {
                     let arity = match &fn_val {
                         Value::Obj(o) => match &**o {
                             Obj::Function(f) => f.arity,
-                            Obj::NativeFn(_) => 2,
+                            Obj::NativeFn(_) | Obj::HostFn(_) | Obj::VmFn(_) => 2,
                             _ => return Err(self.runtime_error()),
                         },
                         _ => return Err(self.runtime_error()),
                     };
                     if arity != 1 && arity != 2 {
-                        return Err(self.runtime_error_with(2016, "filter: expected function of arity 1 or 2"));
+                        return Err(self.runtime_error_with(ErrorKind::TypeMismatch, "filter: expected function of arity 1 or 2"));
+                    }
+                    if let Some(preds) = self.parallel_apply(&fn_val, &list_val, arity, true)? {
+                        let kept = preds.iter().enumerate().filter(|(_, p)| matches!(p, Value::Bool(true)));
+                        let out: Vec<Value> = match &list_val {
+                            Value::Obj(o) => match &**o {
+                                Obj::List(items) => kept.map(|(i, _)| items[i].clone()).collect(),
+                                Obj::Range(r) => kept.map(|(i, _)| Value::Int(r.start + i as i64)).collect(),
+                                _ => Vec::new(),
+                            },
+                            _ => Vec::new(),
+                        };
+                        self.stack.push(Value::Obj(Arc::new(Obj::List(out))));
+                        return Ok(None);
                     }
 
                     let mut out: Vec<Value> = Vec::new();
@@ -592,7 +673,7 @@ impl VM {
                                         self.pop()
                                     };
                                     if let Value::Error(e) = pred {
-                                        return Err(InterpretResult::RuntimeError(e));
+                                        return Err(InterpretResult::RuntimeError(*e));
                                     }
                                     if matches!(pred, Value::Bool(true)) {
                                         out.push(item);
@@ -615,7 +696,7 @@ impl VM {
                                         self.pop()
                                     };
                                     if let Value::Error(e) = pred {
-                                        return Err(InterpretResult::RuntimeError(e));
+                                        return Err(InterpretResult::RuntimeError(*e));
                                     }
                                     if matches!(pred, Value::Bool(true)) {
                                         out.push(item);
@@ -626,7 +707,7 @@ impl VM {
                         },
                         _ => return Err(self.runtime_error()),
                     }
-                    self.stack.push(Value::Obj(Rc::new(Obj::List(out))));
+                    self.stack.push(Value::Obj(Arc::new(Obj::List(out))));
                 }
                 OpCode::OpAny => {
                     let fn_val = self.pop();
@@ -640,13 +721,13 @@ impl VM {
                     let arity = match &fn_val {
                         Value::Obj(o) => match &**o {
                             Obj::Function(f) => f.arity,
-                            Obj::NativeFn(_) => 2,
+                            Obj::NativeFn(_) | Obj::HostFn(_) | Obj::VmFn(_) => 2,
                             _ => return Err(self.runtime_error()),
                         },
                         _ => return Err(self.runtime_error()),
                     };
                     if arity != 1 && arity != 2 {
-                        return Err(self.runtime_error_with(2017, "Any: expected function of arity 1 or 2"));
+                        return Err(self.runtime_error_with(ErrorKind::TypeMismatch, "Any: expected function of arity 1 or 2"));
                     }
 
                     let mut any = false;
@@ -667,7 +748,7 @@ impl VM {
                                         self.pop()
                                     };
                                     if let Value::Error(e) = pred {
-                                        return Err(InterpretResult::RuntimeError(e));
+                                        return Err(InterpretResult::RuntimeError(*e));
                                     }
                                     if matches!(pred, Value::Bool(true)) {
                                         any = true;
@@ -691,7 +772,7 @@ impl VM {
                                         self.pop()
                                     };
                                     if let Value::Error(e) = pred {
-                                        return Err(InterpretResult::RuntimeError(e));
+                                        return Err(InterpretResult::RuntimeError(*e));
                                     }
                                     if matches!(pred, Value::Bool(true)) {
                                         any = true;
@@ -717,13 +798,13 @@ impl VM {
                     let arity = match &fn_val {
                         Value::Obj(o) => match &**o {
                             Obj::Function(f) => f.arity,
-                            Obj::NativeFn(_) => 2,
+                            Obj::NativeFn(_) | Obj::HostFn(_) | Obj::VmFn(_) => 2,
                             _ => return Err(self.runtime_error()),
                         },
                         _ => return Err(self.runtime_error()),
                     };
                     if arity != 1 && arity != 2 {
-                        return Err(self.runtime_error_with(2018, "First: expected function of arity 1 or 2"));
+                        return Err(self.runtime_error_with(ErrorKind::TypeMismatch, "First: expected function of arity 1 or 2"));
                     }
 
                     let mut found: Option<Value> = None;
@@ -744,7 +825,7 @@ impl VM {
                                         self.pop()
                                     };
                                     if let Value::Error(e) = pred {
-                                        return Err(InterpretResult::RuntimeError(e));
+                                        return Err(InterpretResult::RuntimeError(*e));
                                     }
                                     if matches!(pred, Value::Bool(true)) {
                                         found = Some(item);
@@ -768,7 +849,7 @@ impl VM {
                                         self.pop()
                                     };
                                     if let Value::Error(e) = pred {
-                                        return Err(InterpretResult::RuntimeError(e));
+                                        return Err(InterpretResult::RuntimeError(*e));
                                     }
                                     if matches!(pred, Value::Bool(true)) {
                                         found = Some(item);
@@ -794,13 +875,13 @@ impl VM {
                     let arity = match &fn_val {
                         Value::Obj(o) => match &**o {
                             Obj::Function(f) => f.arity,
-                            Obj::NativeFn(_) => 2,
+                            Obj::NativeFn(_) | Obj::HostFn(_) | Obj::VmFn(_) => 2,
                             _ => return Err(self.runtime_error()),
                         },
                         _ => return Err(self.runtime_error()),
                     };
                     if arity != 2 {
-                        return Err(self.runtime_error_with(2019, "Sort: expected function of arity 2"));
+                        return Err(self.runtime_error_with(ErrorKind::TypeMismatch, "Sort: expected function of arity 2"));
                     }
 
                     let mut items: Vec<Value> = match &list_val {
@@ -824,9 +905,9 @@ impl VM {
                         };
                         let sign = match v {
                             Value::Int(i) => i,
-                            Value::BigInt(bi) => bi.to_i64().ok_or_else(|| self.runtime_error_with(2020, "Sort: comparator result out of range"))?,
-                            Value::Error(e) => return Err(InterpretResult::RuntimeError(e)),
-                            _ => return Err(self.runtime_error_with(2020, "Sort: comparator must return an integer")),
+                            Value::BigInt(bi) => bi.to_i64().ok_or_else(|| self.runtime_error_with(ErrorKind::OutOfRange, "Sort: comparator result out of range"))?,
+                            Value::Error(e) => return Err(InterpretResult::RuntimeError(*e)),
+                            _ => return Err(self.runtime_error_with(ErrorKind::TypeMismatch, "Sort: comparator must return an integer")),
                         };
                         Ok(if sign < 0 { std::cmp::Ordering::Less } else if sign > 0 { std::cmp::Ordering::Greater } else { std::cmp::Ordering::Equal })
                     };
@@ -843,7 +924,7 @@ impl VM {
                             }
                         }
                     }
-                    self.stack.push(Value::Obj(Rc::new(Obj::List(items))));
+                    self.stack.push(Value::Obj(Arc::new(Obj::List(items))));
                 }
                 OpCode::OpReduce(has_seed) => {
                     let seed = if has_seed { Some(self.pop()) } else { None };
@@ -858,7 +939,7 @@ impl VM {
                     let arity = match &fn_val {
                         Value::Obj(o) => match &**o {
                             Obj::Function(f) => f.arity,
-                            Obj::NativeFn(_) => 2,
+                            Obj::NativeFn(_) | Obj::HostFn(_) | Obj::VmFn(_) => 2,
                             _ => return Err(self.runtime_error()),
                         },
                         _ => return Err(self.runtime_error()),
@@ -926,31 +1007,75 @@ impl VM {
                      let val = self.stack[frame_slots + slot].clone();
                      self.stack.push(val);
                 }
+                OpCode::OpGetLocal2(a, b) => {
+                     let frame_slots = self.frames[frame_idx].slots;
+                     let va = self.stack[frame_slots + a].clone();
+                     let vb = self.stack[frame_slots + b].clone();
+                     self.stack.push(va);
+                     self.stack.push(vb);
+                }
+                OpCode::OpGetLocalConst(slot, idx) => {
+                     let f = &self.frames[frame_idx];
+                     let local = self.stack[f.slots + slot].clone();
+                     let constant = f.function.chunk.constants[idx].clone();
+                     self.stack.push(local);
+                     self.stack.push(constant);
+                }
+                OpCode::OpLocalConstBinary(slot, idx, op) => {
+                     let f = &self.frames[frame_idx];
+                     let local = self.stack[f.slots + slot].clone();
+                     let constant = f.function.chunk.constants[idx].clone();
+                     self.stack.push(local);
+                     self.stack.push(constant);
+                     self.binary(op)?;
+                }
+                OpCode::OpGetGlobalProp(name, prop) => {
+                     let receiver = self.get_global(frame_idx, name)?;
+                     let val = self.get_prop(frame_idx, receiver, prop)?;
+                     self.stack.push(val);
+                }
+                OpCode::OpTry(offset) => {
+                    let catch_ip = self.frames[frame_idx].ip + offset;
+                    self.handlers.push(TryHandler {
+                        frames: self.frames.len(),
+                        stack: self.stack.len(),
+                        providers: self.providers.len(),
+                        catch_ip,
+                    });
+                }
+                OpCode::OpEndTry(offset) => {
+                    self.handlers.pop();
+                    if !matches!(self.stack.last(), Some(Value::Error(_))) {
+                        self.frames[frame_idx].ip += offset;
+                    }
+                }
+                OpCode::OpCatch => {
+                    let handler = self.pop();
+                    let err = self.pop();
+                    let arity = match &handler {
+                        Value::Obj(o) => match &**o {
+                            Obj::Function(f) => Some(f.arity.min(1)),
+                            Obj::NativeFn(_) | Obj::HostFn(_) | Obj::VmFn(_) => Some(1),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    match arity {
+                        Some(arity) => {
+                            self.stack.push(handler);
+                            if arity == 1 {
+                                self.stack.push(err);
+                            }
+                            self.call_value(arity)?;
+                        }
+                        None => self.stack.push(handler),
+                    }
+                }
 
     
                 OpCode::OpGetGlobal(idx) => {
-                     let name_val = {
-                         let f = &self.frames[frame_idx];
-                         f.function.chunk.constants[idx].clone()
-                     };
-                     let name = if let Value::Obj(o) = name_val {
-                         if let crate::obj::Obj::String(s) = &*o { s.clone() } else { return Err(self.runtime_error()); }
-                     } else { return Err(self.runtime_error()); };
-                     let name_l = name.to_ascii_lowercase();
-
-                     if let Some(p) = self.current_provider() {
-                        if self.provider_is_defined(&p, &name) {
-                            let v = self.provider_get(&p, &name);
-                            self.stack.push(v);
-                            return Ok(None);
-                        }
-                     }
-                     
-                     if let Some(val) = self.globals.get(&name_l) {
-                         self.stack.push(val.clone());
-                     } else {
-                        self.stack.push(Value::Nil);
-                     }
+                     let val = self.get_global(frame_idx, idx)?;
+                     self.stack.push(val);
                 }
 
                 OpCode::OpGetParent(idx) => {
@@ -987,6 +1112,13 @@ impl VM {
                         return Ok(None);
                     }
                     match (receiver, index) {
+                        // `kvc['key']` reads a member like `kvc.key`, for keys that are not
+                        // identifiers (`headers['content-type']`).
+                        (Value::Obj(recv), Value::Obj(key)) if matches!((&*recv, &*key), (Obj::Kvc(_), Obj::String(_))) => {
+                            let Obj::String(key) = &*key else { unreachable!() };
+                            let v = self.provider_get(&Value::Obj(recv), key);
+                            self.stack.push(v);
+                        }
                         (Value::Obj(o), idx) => {
                             let i64_idx: Option<i64> = match idx {
                                 Value::Int(i) => Some(i),
@@ -1011,19 +1143,13 @@ impl VM {
                                             self.stack.push(Value::Int(r.start + i64_idx));
                                         }
                                     }
+                                    Obj::String(text) => self.stack.push(char_at(text, i64_idx)),
                                     _ => self.stack.push(Value::Nil),
                                 }
                             } else {
                                 self.stack.push(Value::Nil);
                             }
                         }
-                        (recv, Value::Obj(o)) => match &*o {
-                            Obj::String(s) => {
-                                let v = self.provider_get(&recv, s);
-                                self.stack.push(v);
-                            }
-                            _ => self.stack.push(Value::Nil),
-                        },
                         _ => self.stack.push(Value::Nil),
                     }
                 }
@@ -1032,7 +1158,7 @@ impl VM {
                     let current = self.pop();
                     let parent = self.current_provider();
                     let p = ProviderObject { current, parent };
-                    self.stack.push(Value::Obj(Rc::new(Obj::Provider(Rc::new(p)))));
+                    self.stack.push(Value::Obj(Arc::new(Obj::Provider(Arc::new(p)))));
                 }
 
                 OpCode::OpPushProvider => {
@@ -1053,7 +1179,7 @@ impl VM {
 
                     let selector_func = match selector_val {
                         Value::Obj(o) => match &*o {
-                            Obj::Function(f) => Rc::clone(f),
+                            Obj::Function(f) => Arc::clone(f),
                             _ => return Err(self.runtime_error()),
                         },
                         _ => return Err(self.runtime_error()),
@@ -1067,7 +1193,7 @@ impl VM {
                                 for item in items.iter().cloned() {
 
                                     let before = self.frames.len();
-                                    self.stack.push(Value::Obj(Rc::new(Obj::Function(Rc::clone(&selector_func)))));
+                                    self.stack.push(Value::Obj(Arc::new(Obj::Function(Arc::clone(&selector_func)))));
                                     self.stack.push(item);
                                     self.call_value(1)?;
                                     let v = self.run_nested(before)?;
@@ -1079,11 +1205,11 @@ impl VM {
                 }
                                     results.push(v);
                                 }
-                                self.stack.push(Value::Obj(Rc::new(Obj::List(results))));
+                                self.stack.push(Value::Obj(Arc::new(Obj::List(results))));
                             }
                             _ => {
                                 let before = self.frames.len();
-                                self.stack.push(Value::Obj(Rc::new(Obj::Function(Rc::clone(&selector_func)))));
+                                self.stack.push(Value::Obj(Arc::new(Obj::Function(Arc::clone(&selector_func)))));
                                 self.stack.push(Value::Obj(o));
                                 self.call_value(1)?;
                                 let v = self.run_nested(before)?;
@@ -1092,7 +1218,7 @@ impl VM {
                         },
                         other => {
                             let before = self.frames.len();
-                            self.stack.push(Value::Obj(Rc::new(Obj::Function(Rc::clone(&selector_func)))));
+                            self.stack.push(Value::Obj(Arc::new(Obj::Function(Arc::clone(&selector_func)))));
                             self.stack.push(other);
                             self.call_value(1)?;
                             let v = self.run_nested(before)?;
@@ -1106,17 +1232,279 @@ impl VM {
 
     fn run_nested(&mut self, target_frames_len: usize) -> Result<Value, InterpretResult> {
         while self.frames.len() > target_frames_len {
-            if let Some(v) = self.step_current()? {
-                return Ok(v);
+            match self.step_current() {
+                Ok(Some(v)) => return Ok(v),
+                Ok(None) => {}
+                Err(e) => self.recover(e, target_frames_len)?,
             }
         }
         Ok(self.pop())
     }
 
+    /// Calls a script or native function from inside a native and runs it to completion.
+    /// Runtime errors come back as error values.
+    pub(crate) fn call_function(&mut self, callee: &Value, args: &[Value]) -> Value {
+        let (before, stack_before, providers_before) = (self.frames.len(), self.stack.len(), self.providers.len());
+        self.stack.push(callee.clone());
+        self.stack.extend(args.iter().cloned());
+        let result = self.call_value(args.len()).and_then(|()| {
+            if self.frames.len() > before {
+                self.run_nested(before)
+            } else {
+                Ok(self.pop())
+            }
+        });
+        match result {
+            Ok(v) => v,
+            Err(InterpretResult::RuntimeError(e)) | Err(InterpretResult::CompileError(e)) => {
+                // Drop what is left of the failed call so it does not keep running once the
+                // native returns.
+                self.frames.truncate(before);
+                self.stack.truncate(stack_before);
+                self.providers.truncate(providers_before);
+                Value::error(e)
+            }
+        }
+    }
+
+    /// Compiled form of `pattern`, cached per VM since scripts tend to apply the same few
+    /// patterns to many strings.
+    pub(crate) fn cached_regex(&mut self, pattern: &str) -> Result<Regex, regex::Error> {
+        if let Some(re) = self.regex_cache.get(pattern) {
+            return Ok(re.clone());
+        }
+        let re = Regex::new(pattern)?;
+        if self.regex_cache.len() >= REGEX_CACHE_MAX {
+            self.regex_cache.clear();
+        }
+        self.regex_cache.insert(pattern.to_string(), re.clone());
+        Ok(re)
+    }
+
+    /// Calls `fn_val(item)` (or `fn_val(item, index)`) and runs it to completion.
+    fn call_for_item(&mut self, fn_val: &Value, item: Value, index: usize, arity: usize) -> Result<Value, InterpretResult> {
+        let before = self.frames.len();
+        self.stack.push(fn_val.clone());
+        self.stack.push(item);
+        if arity == 2 {
+            self.stack.push(Value::Int(index as i64));
+        }
+        self.call_value(arity)?;
+        if self.frames.len() > before {
+            self.run_nested(before)
+        } else {
+            Ok(self.pop())
+        }
+    }
+
+    /// Applies `fn_val` to every element of a list/range on worker threads, in order.
+    ///
+    /// Returns `Ok(None)` when parallel mode is off, the collection is small or the lambda
+    /// may reach a host native; the caller then evaluates sequentially. Each worker stops at
+    /// its first error and the error with the lowest index is returned. With `errors_abort`,
+    /// an error value returned by the lambda is treated like a runtime error (as `filter` does).
+    fn parallel_apply(
+        &mut self,
+        fn_val: &Value,
+        list_val: &Value,
+        arity: usize,
+        errors_abort: bool,
+    ) -> Result<Option<Vec<Value>>, InterpretResult> {
+        let config = match self.parallel {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+        let (items, start, len): (Option<&[Value]>, i64, usize) = match list_val {
+            Value::Obj(o) => match &**o {
+                Obj::List(items) => (Some(items.as_slice()), 0, items.len()),
+                Obj::Range(r) => (None, r.start, r.count),
+                _ => return Ok(None),
+            },
+            _ => return Ok(None),
+        };
+        if !config.applies_to(len) || !self.is_parallel_safe(fn_val, items.unwrap_or(&[])) {
+            return Ok(None);
+        }
+
+        let this = &*self;
+        let results: Vec<(Result<Vec<Value>, InterpretResult>, KvcRegistry)> = std::thread::scope(|s| {
+            let handles: Vec<_> = parallel::split(len, config.workers)
+                .into_iter()
+                .map(|r| {
+                    let mut vm = this.worker();
+                    s.spawn(move || {
+                        let run = || {
+                            let mut out = Vec::with_capacity(r.len());
+                            for i in r {
+                                let item = match items {
+                                    Some(items) => items[i].clone(),
+                                    None => Value::Int(start + i as i64),
+                                };
+                                match vm.call_for_item(fn_val, item, i, arity)? {
+                                    Value::Error(e) if errors_abort => return Err(InterpretResult::RuntimeError(*e)),
+                                    v => out.push(v),
+                                }
+                            }
+                            Ok(out)
+                        };
+                        let result = run();
+                        (result, std::mem::take(&mut vm.kvcs))
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| {
+                    h.join().unwrap_or_else(|_| {
+                        (Err(this.runtime_error_with(ErrorKind::Runtime, "parallel worker panicked")), KvcRegistry::default())
+                    })
+                })
+                .collect()
+        });
+
+        let mut out = Vec::with_capacity(len);
+        let mut first_err = None;
+        for (chunk, kvcs) in results {
+            self.kvcs.absorb(kvcs);
+            match chunk {
+                Ok(values) if first_err.is_none() => out.extend(values),
+                Ok(_) => {}
+                Err(e) => {
+                    first_err.get_or_insert(e);
+                }
+            }
+        }
+        if let Some(e) = first_err {
+            return Err(e);
+        }
+        Ok(Some(out))
+    }
+
+    /// Conservative check that nothing reachable from `fn_val` can call a host native: its
+    /// code and nested lambdas, KVC thunks and values its free names resolve to, and the
+    /// items it will be applied to. Lazy members are inspected, never evaluated.
+    fn is_parallel_safe(&self, fn_val: &Value, items: &[Value]) -> bool {
+        let scope = self.current_provider();
+        let mut pending: Vec<(Value, Option<Value>)> = vec![(fn_val.clone(), scope.clone())];
+        pending.extend(
+            items
+                .iter()
+                .filter(|v| matches!(v, Value::Obj(_)))
+                .map(|v| (v.clone(), scope.clone())),
+        );
+        // Thunks are re-wrapped on every visit, so functions are deduped on the function and
+        // the scope it resolves names in, and KVCs on the shared state. Visited values stay
+        // alive until the scan ends, so no address in `seen` is reused by a later wrapper.
+        let identity = |v: &Value| match v {
+            Value::Obj(o) => match &**o {
+                Obj::Kvc(k) => Arc::as_ptr(k) as *const () as usize,
+                Obj::Function(f) => Arc::as_ptr(f) as *const () as usize,
+                _ => Arc::as_ptr(o) as *const () as usize,
+            },
+            _ => 0,
+        };
+        let mut seen: std::collections::HashSet<(usize, usize)> = std::collections::HashSet::new();
+        let mut visited: Vec<(Value, Option<Value>)> = Vec::new();
+
+        while let Some((value, scope)) = pending.pop() {
+            let o = match &value {
+                Value::Obj(o) => o,
+                _ => continue,
+            };
+            let in_scope = match &**o {
+                Obj::Function(_) => scope.as_ref().map_or(0, identity),
+                _ => 0,
+            };
+            if !seen.insert((identity(&value), in_scope)) {
+                continue;
+            }
+            match &**o {
+                Obj::HostFn(_) => return false,
+                Obj::Function(func) => {
+                    for op in func.chunk.ops() {
+                        let idx = match op {
+                            OpCode::OpGetGlobal(idx) | OpCode::OpGetParent(idx) | OpCode::OpGetGlobalProp(idx, _) => idx,
+                            _ => continue,
+                        };
+                        let name = match func.chunk.constants.get(idx) {
+                            Some(Value::Obj(n)) => match &**n {
+                                Obj::String(n) => n.to_lowercase(),
+                                _ => continue,
+                            },
+                            _ => continue,
+                        };
+                        match scope.as_ref().and_then(|p| self.peek_binding(p, &name)) {
+                            Some(found) => pending.push(found),
+                            None => {
+                                if let Some(g) = self.globals.get(&name) {
+                                    pending.push((g.clone(), scope.clone()));
+                                }
+                            }
+                        }
+                    }
+                    pending.extend(
+                        func.chunk
+                            .constants
+                            .iter()
+                            .filter(|c| matches!(c, Value::Obj(_)))
+                            .map(|c| (c.clone(), scope.clone())),
+                    );
+                }
+                Obj::Kvc(k) => {
+                    let k = k.lock().unwrap();
+                    pending.extend(k.cache.values().map(|v| (v.clone(), Some(value.clone()))));
+                    pending.extend(
+                        k.entries
+                            .values()
+                            .map(|f| (Value::Obj(Arc::new(Obj::Function(Arc::clone(f)))), Some(value.clone()))),
+                    );
+                }
+                Obj::List(items) => {
+                    pending.extend(
+                        items
+                            .iter()
+                            .filter(|v| matches!(v, Value::Obj(_)))
+                            .map(|v| (v.clone(), scope.clone())),
+                    );
+                }
+                _ => {}
+            }
+            visited.push((value, scope));
+        }
+        true
+    }
+
+    /// Looks up `key_l` along a provider chain without forcing lazy members. Returns the
+    /// cached value or the unevaluated thunk, together with the KVC that defines it.
+    fn peek_binding(&self, provider: &Value, key_l: &str) -> Option<(Value, Option<Value>)> {
+        match provider {
+            Value::Obj(o) => match &**o {
+                Obj::Kvc(k) => {
+                    let parent = {
+                        let b = k.lock().unwrap();
+                        if let Some(v) = b.cache.get(key_l) {
+                            return Some((v.clone(), Some(provider.clone())));
+                        }
+                        if let Some(f) = b.entries.get(key_l) {
+                            return Some((Value::Obj(Arc::new(Obj::Function(Arc::clone(f)))), Some(provider.clone())));
+                        }
+                        b.parent.clone()
+                    };
+                    parent.and_then(|p| self.peek_binding(&p, key_l))
+                }
+                Obj::Provider(p) => self
+                    .peek_binding(&p.current, key_l)
+                    .or_else(|| p.parent.as_ref().and_then(|parent| self.peek_binding(parent, key_l))),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
     fn provider_parent(&self, provider: &Value) -> Option<Value> {
         match provider {
             Value::Obj(o) => match &**o {
-                Obj::Kvc(k) => k.borrow().parent.clone(),
+                Obj::Kvc(k) => k.lock().unwrap().parent.clone(),
                 Obj::Provider(p) => p.parent.clone(),
                 _ => None,
             },
@@ -1129,13 +1517,16 @@ impl VM {
         match provider {
             Value::Obj(o) => match &**o {
                 Obj::Kvc(k) => {
-                    let k = k.borrow();
-                    if k.entries.contains_key(&key_l) || k.cache.contains_key(&key_l) {
-                        true
-                    } else if let Some(parent) = &k.parent {
-                        self.provider_is_defined(parent, key)
-                    } else {
-                        false
+                    let parent = {
+                        let k = k.lock().unwrap();
+                        if k.entries.contains_key(&key_l) || k.cache.contains_key(&key_l) {
+                            return true;
+                        }
+                        k.parent.clone()
+                    };
+                    match parent {
+                        Some(parent) => self.provider_is_defined(&parent, key),
+                        None => false,
                     }
                 }
                 Obj::Provider(p) => {
@@ -1157,7 +1548,7 @@ impl VM {
         let key_l = key.to_lowercase();
         match provider {
             Value::Obj(o) => match &**o {
-                Obj::Kvc(k) => self.kvc_get(Rc::clone(k), &key_l, key),
+                Obj::Kvc(k) => self.kvc_get(Arc::clone(k), &key_l, key),
                 Obj::Provider(p) => {
                     if self.provider_is_defined(&p.current, key) {
                         self.provider_get(&p.current, key)
@@ -1167,19 +1558,43 @@ impl VM {
                         Value::Nil
                     }
                 }
+                Obj::VmFn(f) => crate::native::native_member(*f, &key_l),
+                Obj::DateTimeTicks(t, offset) => crate::datetime::member(*t, *offset, &key_l),
+                Obj::Duration(t) => crate::datetime::duration_member(*t, &key_l),
                 _ => Value::Nil,
             },
+            Value::Error(e) => VM::error_member(e, &key_l),
             _ => Value::Nil,
         }
     }
 
-    fn kvc_get(&mut self, kvc: Rc<RefCell<KvcObject>>, key_l: &str, key_orig: &str) -> Value {
-        if let Some(v) = kvc.borrow().cache.get(key_l) {
+    /// Members of an error value: `kind`, `code`, `message`, `type`, `data`, `cause`, `line`
+    /// and `column`. Any other member propagates the error itself.
+    fn error_member(e: &FsError, key_l: &str) -> Value {
+        let text = |s: &str| Value::Obj(Arc::new(Obj::String(s.into())));
+        match key_l {
+            "kind" => text(e.kind.name()),
+            "code" => Value::Int(e.code() as i64),
+            "message" => text(&e.message),
+            "type" => e.user_type.as_deref().map(text).unwrap_or(Value::Nil),
+            "data" => e.data.clone().unwrap_or(Value::Nil),
+            "cause" => e.cause.as_ref().map(|c| Value::Error(c.clone())).unwrap_or(Value::Nil),
+            "line" => Value::Int(e.line as i64),
+            "column" => Value::Int(e.column as i64),
+            _ => Value::Error(Box::new(e.clone())),
+        }
+    }
+
+    /// Member `key_l` of `kvc`, evaluating (and caching) its thunk on first access and falling
+    /// back to the parent scope.
+    pub(crate) fn kvc_get(&mut self, kvc: Arc<Mutex<KvcObject>>, key_l: &str, key_orig: &str) -> Value {
+        if let Some(v) = kvc.lock().unwrap().cache.get(key_l) {
             return v.clone();
         }
 
-        if kvc.borrow().evaluating.contains(key_l) {
-            let parent = kvc.borrow().parent.clone();
+        let marker = (key_l.to_string(), std::thread::current().id());
+        if kvc.lock().unwrap().evaluating.contains(&marker) {
+            let parent = kvc.lock().unwrap().parent.clone();
             return parent
                 .as_ref()
                 .map(|p| self.provider_get(p, key_orig))
@@ -1187,45 +1602,47 @@ impl VM {
         }
 
         let thunk = {
-            let k = kvc.borrow();
+            let k = kvc.lock().unwrap();
             k.entries.get(key_l).cloned()
         };
 
         if let Some(func) = thunk {
             {
-                kvc.borrow_mut().evaluating.insert(key_l.to_string());
-            }
-
-            let before = self.frames.len();
-            self.providers.push(Value::Obj(Rc::new(Obj::Kvc(Rc::clone(&kvc)))));
-            self.stack.push(Value::Obj(Rc::new(Obj::Function(Rc::clone(&func)))));
-            if self.call_value(0).is_err() {
-                self.providers.pop();
-                kvc.borrow_mut().evaluating.remove(key_l);
-                return Value::Nil;
+                kvc.lock().unwrap().evaluating.insert(marker.clone());
             }
 
-            let value = self.run_nested(before).unwrap_or(Value::Nil);
+            let (before, stack_before, providers_before) = (self.frames.len(), self.stack.len(), self.providers.len());
+            self.providers.push(Value::Obj(Arc::new(Obj::Kvc(Arc::clone(&kvc)))));
+            self.stack.push(Value::Obj(Arc::new(Obj::Function(Arc::clone(&func)))));
+            // A runtime error becomes the member's value, so `try` around the access sees it.
+            let value = match self.call_value(0).and_then(|()| self.run_nested(before)) {
+                Ok(v) => v,
+                Err(InterpretResult::RuntimeError(e)) | Err(InterpretResult::CompileError(e)) => {
+                    self.frames.truncate(before);
+                    self.stack.truncate(stack_before);
+                    Value::error(e)
+                }
+            };
 
-            self.providers.pop();
+            self.providers.truncate(providers_before);
 
             {
-                let mut k = kvc.borrow_mut();
+                let mut k = kvc.lock().unwrap();
                 k.cache.insert(key_l.to_string(), value.clone());
-                k.evaluating.remove(key_l);
+                k.evaluating.remove(&marker);
             }
 
             return value;
         }
 
-        let parent = kvc.borrow().parent.clone();
+        let parent = kvc.lock().unwrap().parent.clone();
         parent
             .as_ref()
             .map(|p| self.provider_get(p, key_orig))
             .unwrap_or(Value::Nil)
     }
 
-    fn merge_kvc(&mut self, left: Rc<RefCell<KvcObject>>, right: Rc<RefCell<KvcObject>>) -> Value {
+    fn merge_kvc(&mut self, left: Arc<Mutex<KvcObject>>, right: Arc<Mutex<KvcObject>>) -> Value {
     
         let left = self.normalize_for_merge(left);
         let right = self.normalize_for_merge(right);
@@ -1235,8 +1652,8 @@ impl VM {
         let mut display_names: std::collections::HashMap<String, String> = std::collections::HashMap::new();
         let mut cache: std::collections::HashMap<String, Value> = std::collections::HashMap::new();
 
-        let l_order = left.borrow().order.clone();
-        let r_order = right.borrow().order.clone();
+        let l_order = left.lock().unwrap().order.clone();
+        let r_order = right.lock().unwrap().order.clone();
 
         let mut seen: std::collections::HashSet<String> = std::collections::HashSet::new();
         for k in l_order.iter() {
@@ -1251,24 +1668,24 @@ impl VM {
         }
 
         {
-            let l = left.borrow();
+            let l = left.lock().unwrap();
             for (k, v) in l.display_names.iter() {
                 display_names.insert(k.clone(), v.clone());
             }
         }
         {
-            let r = right.borrow();
+            let r = right.lock().unwrap();
             for (k, v) in r.display_names.iter() {
                 display_names.insert(k.clone(), v.clone());
             }
         }
 
         let left_defines = |k: &String| {
-            let l = left.borrow();
+            let l = left.lock().unwrap();
             l.entries.contains_key(k) || l.cache.contains_key(k)
         };
         let right_defines = |k: &String| {
-            let r = right.borrow();
+            let r = right.lock().unwrap();
             r.entries.contains_key(k) || r.cache.contains_key(k)
         };
 
@@ -1277,12 +1694,12 @@ impl VM {
             let r_defined = right_defines(k);
 
             let merged_val = if r_defined {
-                let rv = self.kvc_get(Rc::clone(&right), k, k);
+                let rv = self.kvc_get(Arc::clone(&right), k, k);
                 if l_defined {
-                    let lv = self.kvc_get(Rc::clone(&left), k, k);
+                    let lv = self.kvc_get(Arc::clone(&left), k, k);
                     match (&lv, &rv) {
                         (Value::Obj(lo), Value::Obj(ro)) => match (&**lo, &**ro) {
-                            (Obj::Kvc(lk), Obj::Kvc(rk)) => self.merge_kvc(Rc::clone(lk), Rc::clone(rk)),
+                            (Obj::Kvc(lk), Obj::Kvc(rk)) => self.merge_kvc(Arc::clone(lk), Arc::clone(rk)),
                             _ => rv,
                         },
                         _ => rv,
@@ -1291,7 +1708,7 @@ impl VM {
                     rv
                 }
             } else if l_defined {
-                self.kvc_get(Rc::clone(&left), k, k)
+                self.kvc_get(Arc::clone(&left), k, k)
             } else {
                 Value::Nil
             };
@@ -1307,32 +1724,60 @@ impl VM {
             order,
             display_names,
         };
-        Value::Obj(Rc::new(Obj::Kvc(Rc::new(RefCell::new(kvc)))))
+        Value::Obj(Arc::new(Obj::Kvc(self.alloc_kvc(kvc))))
+    }
+
+    /// Evaluates the members of the KVCs in `v`, nested ones and list items included, so a
+    /// native that cannot run thunks finds them in the cache.
+    fn force_members(&mut self, v: &Value, depth: usize) {
+        let Value::Obj(o) = v else { return };
+        if depth == 0 {
+            return;
+        }
+        match &**o {
+            Obj::Kvc(k) => {
+                let (order, display_names) = {
+                    let k = k.lock().unwrap();
+                    (k.order.clone(), k.display_names.clone())
+                };
+                for key_l in order.iter() {
+                    let display = display_names.get(key_l).cloned().unwrap_or_else(|| key_l.clone());
+                    let member = self.kvc_get(Arc::clone(k), key_l, &display);
+                    self.force_members(&member, depth - 1);
+                }
+            }
+            Obj::List(items) => {
+                for item in items {
+                    self.force_members(item, depth - 1);
+                }
+            }
+            _ => {}
+        }
     }
 
-    fn normalize_for_merge(&mut self, k: Rc<RefCell<KvcObject>>) -> Rc<RefCell<KvcObject>> {
-        if k.borrow().parent.is_none() {
+    fn normalize_for_merge(&mut self, k: Arc<Mutex<KvcObject>>) -> Arc<Mutex<KvcObject>> {
+        if k.lock().unwrap().parent.is_none() {
             return k;
         }
-        let order = k.borrow().order.clone();
-        let display_names = k.borrow().display_names.clone();
+        let order = k.lock().unwrap().order.clone();
+        let display_names = k.lock().unwrap().display_names.clone();
         let mut cache = std::collections::HashMap::new();
         for key_l in order.iter() {
             let display = display_names
                 .get(key_l)
                 .cloned()
                 .unwrap_or_else(|| key_l.clone());
-            let v = self.kvc_get(Rc::clone(&k), key_l, &display);
+            let v = self.kvc_get(Arc::clone(&k), key_l, &display);
             cache.insert(key_l.clone(), v);
         }
-        Rc::new(RefCell::new(KvcObject {
+        self.alloc_kvc(KvcObject {
             entries: std::collections::HashMap::new(),
             cache,
             evaluating: std::collections::HashSet::new(),
             parent: None,
             order,
             display_names,
-        }))
+        })
     }
 
 
@@ -1340,17 +1785,17 @@ impl VM {
         matches!(v, Value::Int(_) | Value::BigInt(_) | Value::Number(_))
     }
 
-    fn bigint_to_value(n: BigInt) -> Value {
+    pub(crate) fn bigint_to_value(n: BigInt) -> Value {
         match n.to_i64() {
             Some(i) => Value::Int(i),
-            None => Value::BigInt(n),
+            None => Value::bigint(n),
         }
     }
 
     fn numeric_to_bigint(v: &Value) -> Option<BigInt> {
         match v {
             Value::Int(n) => Some(BigInt::from(*n)),
-            Value::BigInt(n) => Some(n.clone()),
+            Value::BigInt(n) => Some((**n).clone()),
             _ => None,
         }
     }
@@ -1364,15 +1809,151 @@ impl VM {
         }
     }
 
+    fn add(&mut self) -> Result<(), InterpretResult> {
+        let b = self.pop();
+        let a = self.pop();
+        match (a, b) {
+            (Value::Nil, Value::Nil) => self.stack.push(Value::Nil),
+            (Value::Nil, other) => self.stack.push(other),
+            (other, Value::Nil) => self.stack.push(other),
+            (a, b) if VM::is_numeric(&a) && VM::is_numeric(&b) => {
+                let v = self.numeric_add(a, b)?;
+                self.stack.push(v);
+            }
+            (Value::Obj(a), b)
+                if matches!(&*a, crate::obj::Obj::String(_)) && VM::is_numeric(&b) =>
+            {
+                let s1 = match &*a {
+                    crate::obj::Obj::String(s) => s,
+                    _ => unreachable!(),
+                };
+                let s = format!("{s1}{b}");
+                self.stack.push(Value::Obj(Arc::new(Obj::String(s.into()))));
+            }
+            (a, Value::Obj(b))
+                if matches!(&*b, crate::obj::Obj::String(_)) && VM::is_numeric(&a) =>
+            {
+                let s2 = match &*b {
+                    crate::obj::Obj::String(s) => s,
+                    _ => unreachable!(),
+                };
+                let s = format!("{a}{s2}");
+                self.stack.push(Value::Obj(Arc::new(Obj::String(s.into()))));
+            }
+            (Value::Obj(a), Value::Obj(b)) => {
+                if let Some(v) = VM::time_arithmetic(&a, &b, false) {
+                    self.stack.push(v);
+                    return Ok(());
+                }
+                match (&*a, &*b) {
+                    (crate::obj::Obj::String(s1), crate::obj::Obj::String(s2)) => {
+                        let s = format!("{}{}", s1, s2);
+                        let obj = crate::obj::Obj::String(s.into());
+                        self.stack.push(Value::Obj(std::sync::Arc::new(obj)));
+                    },
+                    (crate::obj::Obj::List(l1), crate::obj::Obj::List(l2)) => {
+                        let mut out = Vec::with_capacity(l1.len() + l2.len());
+                        out.extend(l1.iter().cloned());
+                        out.extend(l2.iter().cloned());
+                        self.stack.push(Value::Obj(Arc::new(Obj::List(out))));
+                    }
+                    (crate::obj::Obj::List(l1), _) => {
+                        let mut out = Vec::with_capacity(l1.len() + 1);
+                        out.extend(l1.iter().cloned());
+                        out.push(Value::Obj(Arc::clone(&b)));
+                        self.stack.push(Value::Obj(Arc::new(Obj::List(out))));
+                    }
+                    (_, crate::obj::Obj::List(l2)) => {
+                        let mut out = Vec::with_capacity(l2.len() + 1);
+                        out.push(Value::Obj(Arc::clone(&a)));
+                        out.extend(l2.iter().cloned());
+                        self.stack.push(Value::Obj(Arc::new(Obj::List(out))));
+                    }
+                    (crate::obj::Obj::Kvc(k1), crate::obj::Obj::Kvc(k2)) => {
+                        let merged = self.merge_kvc(Arc::clone(k1), Arc::clone(k2));
+                        self.stack.push(merged);
+                    }
+                    _ => return Err(self.runtime_error()),
+                }
+            },
+            _ => return Err(self.runtime_error()),
+        }
+        Ok(())
+    }
+
+    fn binary(&mut self, op: BinaryOp) -> Result<(), InterpretResult> {
+        match op {
+            BinaryOp::Add => self.add(),
+            BinaryOp::Subtract => self.numeric_subtract(),
+            BinaryOp::Multiply => self.numeric_multiply(),
+            BinaryOp::Divide => self.numeric_divide(),
+            BinaryOp::Modulo => self.numeric_modulo(),
+            BinaryOp::Equal => {
+                let b = self.pop();
+                let a = self.pop();
+                let eq = self.values_equal(&a, &b);
+                self.stack.push(Value::Bool(eq));
+                Ok(())
+            }
+            BinaryOp::Greater => {
+                let b = self.pop();
+                let a = self.pop();
+                let gt = self.numeric_compare_gt(&a, &b)?;
+                self.stack.push(Value::Bool(gt));
+                Ok(())
+            }
+            BinaryOp::Less => {
+                let b = self.pop();
+                let a = self.pop();
+                let lt = self.numeric_compare_lt(&a, &b)?;
+                self.stack.push(Value::Bool(lt));
+                Ok(())
+            }
+        }
+    }
+
+    /// Looks up the global named by constant `idx` of the frame's chunk, giving the current
+    /// provider precedence over built-ins.
+    fn get_global(&mut self, frame_idx: usize, idx: usize) -> Result<Value, InterpretResult> {
+        let name_val = self.frames[frame_idx].function.chunk.constants[idx].clone();
+        let name = match &name_val {
+            Value::Obj(o) => match &**o {
+                Obj::String(s) => s.as_str(),
+                _ => return Err(self.runtime_error()),
+            },
+            _ => return Err(self.runtime_error()),
+        };
+
+        if let Some(p) = self.current_provider() {
+            if self.provider_is_defined(&p, name) {
+                return Ok(self.provider_get(&p, name));
+            }
+        }
+        Ok(self.globals.get(&name.to_ascii_lowercase()).cloned().unwrap_or(Value::Nil))
+    }
+
+    /// Reads the property named by constant `idx` of the frame's chunk from `receiver`.
+    fn get_prop(&mut self, frame_idx: usize, receiver: Value, idx: usize) -> Result<Value, InterpretResult> {
+        let name_val = self.frames[frame_idx].function.chunk.constants[idx].clone();
+        let name = match &name_val {
+            Value::Obj(o) => match &**o {
+                Obj::String(s) => s.as_str(),
+                _ => return Err(self.runtime_error()),
+            },
+            _ => return Err(self.runtime_error()),
+        };
+        Ok(self.provider_get(&receiver, name))
+    }
+
     fn numeric_add(&self, a: Value, b: Value) -> Result<Value, InterpretResult> {
         match (a, b) {
             (Value::Int(a), Value::Int(b)) => match a.checked_add(b) {
                 Some(v) => Ok(Value::Int(v)),
                 None => Ok(VM::bigint_to_value(BigInt::from(a) + BigInt::from(b))),
             },
-            (Value::BigInt(a), Value::BigInt(b)) => Ok(VM::bigint_to_value(a + b)),
-            (Value::BigInt(a), Value::Int(b)) => Ok(VM::bigint_to_value(a + BigInt::from(b))),
-            (Value::Int(a), Value::BigInt(b)) => Ok(VM::bigint_to_value(BigInt::from(a) + b)),
+            (Value::BigInt(a), Value::BigInt(b)) => Ok(VM::bigint_to_value(*a + *b)),
+            (Value::BigInt(a), Value::Int(b)) => Ok(VM::bigint_to_value(*a + BigInt::from(b))),
+            (Value::Int(a), Value::BigInt(b)) => Ok(VM::bigint_to_value(BigInt::from(a) + *b)),
             (Value::Number(a), b) => {
                 let bf = VM::numeric_to_f64(&b).ok_or_else(|| self.runtime_error())?;
                 Ok(Value::Number(a + bf))
@@ -1385,6 +1966,29 @@ impl VM {
         }
     }
 
+    /// `date ± duration`, `duration + date`, `duration ± duration` and `date - date` (a
+    /// duration); `None` when the operands are not dates or durations.
+    fn time_arithmetic(a: &Obj, b: &Obj, subtract: bool) -> Option<Value> {
+        let sign = if subtract { -1 } else { 1 };
+        Some(match (a, b) {
+            (Obj::DateTimeTicks(d, offset), Obj::Duration(t)) | (Obj::Duration(t), Obj::DateTimeTicks(d, offset))
+                if !subtract || matches!(a, Obj::DateTimeTicks(..)) =>
+            {
+                let ticks = t.checked_mul(sign).and_then(|t| crate::datetime::add_ticks(*d, t));
+                crate::native::date_result("Date arithmetic", ticks, *offset)
+            }
+            (Obj::Duration(x), Obj::Duration(y)) => match y.checked_mul(sign).and_then(|y| x.checked_add(y)) {
+                Some(t) => Value::Obj(Arc::new(Obj::Duration(t))),
+                None => Value::error(FsError::new(ErrorKind::OutOfRange, "Duration arithmetic overflowed")),
+            },
+            (Obj::DateTimeTicks(x, xo), Obj::DateTimeTicks(y, yo)) if subtract => {
+                let span = crate::datetime::utc_ticks(*x, *xo) - crate::datetime::utc_ticks(*y, *yo);
+                Value::Obj(Arc::new(Obj::Duration(span)))
+            }
+            _ => return None,
+        })
+    }
+
     fn numeric_subtract(&mut self) -> Result<(), InterpretResult> {
         let b = self.pop();
         let a = self.pop();
@@ -1393,9 +1997,9 @@ impl VM {
                 Some(v) => Value::Int(v),
                 None => VM::bigint_to_value(BigInt::from(a) - BigInt::from(b)),
             },
-            (Value::BigInt(a), Value::BigInt(b)) => VM::bigint_to_value(a - b),
-            (Value::BigInt(a), Value::Int(b)) => VM::bigint_to_value(a - BigInt::from(b)),
-            (Value::Int(a), Value::BigInt(b)) => VM::bigint_to_value(BigInt::from(a) - b),
+            (Value::BigInt(a), Value::BigInt(b)) => VM::bigint_to_value(*a - *b),
+            (Value::BigInt(a), Value::Int(b)) => VM::bigint_to_value(*a - BigInt::from(b)),
+            (Value::Int(a), Value::BigInt(b)) => VM::bigint_to_value(BigInt::from(a) - *b),
             (Value::Number(a), b) => {
                 let bf = VM::numeric_to_f64(&b).ok_or_else(|| self.runtime_error())?;
                 Value::Number(a - bf)
@@ -1404,6 +2008,10 @@ impl VM {
                 let af = VM::numeric_to_f64(&a).ok_or_else(|| self.runtime_error())?;
                 Value::Number(af - b)
             }
+            (Value::Obj(a), Value::Obj(b)) => match VM::time_arithmetic(&a, &b, true) {
+                Some(v) => v,
+                None => return Err(self.runtime_error()),
+            },
             _ => return Err(self.runtime_error()),
         };
         self.stack.push(out);
@@ -1418,9 +2026,9 @@ impl VM {
                 Some(v) => Value::Int(v),
                 None => VM::bigint_to_value(BigInt::from(a) * BigInt::from(b)),
             },
-            (Value::BigInt(a), Value::BigInt(b)) => VM::bigint_to_value(a * b),
-            (Value::BigInt(a), Value::Int(b)) => VM::bigint_to_value(a * BigInt::from(b)),
-            (Value::Int(a), Value::BigInt(b)) => VM::bigint_to_value(BigInt::from(a) * b),
+            (Value::BigInt(a), Value::BigInt(b)) => VM::bigint_to_value(*a * *b),
+            (Value::BigInt(a), Value::Int(b)) => VM::bigint_to_value(*a * BigInt::from(b)),
+            (Value::Int(a), Value::BigInt(b)) => VM::bigint_to_value(BigInt::from(a) * *b),
             (Value::Number(a), b) => {
                 let bf = VM::numeric_to_f64(&b).ok_or_else(|| self.runtime_error())?;
                 Value::Number(a * bf)
@@ -1441,7 +2049,7 @@ impl VM {
         let out = match (a, b) {
             (Value::Int(a), Value::Int(b)) => {
                 if b == 0 {
-                    return Err(self.runtime_error_with(2009, "Division by zero"));
+                    return Err(self.runtime_error_with(ErrorKind::DivisionByZero, "Division by zero"));
                 }
                 if a % b == 0 {
                     Value::Int(a / b)
@@ -1450,41 +2058,44 @@ impl VM {
                 }
             }
             (Value::BigInt(a), Value::BigInt(b)) => {
+                let (a, b) = (*a, *b);
                 if b == BigInt::from(0) {
-                    return Err(self.runtime_error_with(2009, "Division by zero"));
+                    return Err(self.runtime_error_with(ErrorKind::DivisionByZero, "Division by zero"));
                 }
                 let r = &a % &b;
                 if r == BigInt::from(0) {
                     VM::bigint_to_value(a / b)
                 } else {
-                    let af = a.to_f64().ok_or_else(|| self.runtime_error_with(2010, "Division result not representable as float"))?;
-                    let bf = b.to_f64().ok_or_else(|| self.runtime_error_with(2010, "Division result not representable as float"))?;
+                    let af = a.to_f64().ok_or_else(|| self.runtime_error_with(ErrorKind::OutOfRange, "Division result not representable as float"))?;
+                    let bf = b.to_f64().ok_or_else(|| self.runtime_error_with(ErrorKind::OutOfRange, "Division result not representable as float"))?;
                     Value::Number(af / bf)
                 }
             }
             (Value::BigInt(a), Value::Int(b)) => {
+                let a = *a;
                 if b == 0 {
-                    return Err(self.runtime_error_with(2009, "Division by zero"));
+                    return Err(self.runtime_error_with(ErrorKind::DivisionByZero, "Division by zero"));
                 }
                 let bb = BigInt::from(b);
                 let r = &a % &bb;
                 if r == BigInt::from(0) {
                     VM::bigint_to_value(a / bb)
                 } else {
-                    let af = a.to_f64().ok_or_else(|| self.runtime_error_with(2010, "Division result not representable as float"))?;
+                    let af = a.to_f64().ok_or_else(|| self.runtime_error_with(ErrorKind::OutOfRange, "Division result not representable as float"))?;
                     Value::Number(af / (b as f64))
                 }
             }
             (Value::Int(a), Value::BigInt(b)) => {
+                let b = *b;
                 if b == BigInt::from(0) {
-                    return Err(self.runtime_error_with(2009, "Division by zero"));
+                    return Err(self.runtime_error_with(ErrorKind::DivisionByZero, "Division by zero"));
                 }
                 let aa = BigInt::from(a);
                 let r = &aa % &b;
                 if r == BigInt::from(0) {
                     VM::bigint_to_value(aa / b)
                 } else {
-                    let bf = b.to_f64().ok_or_else(|| self.runtime_error_with(2010, "Division result not representable as float"))?;
+                    let bf = b.to_f64().ok_or_else(|| self.runtime_error_with(ErrorKind::OutOfRange, "Division result not representable as float"))?;
                     Value::Number((a as f64) / bf)
                 }
             }
@@ -1508,32 +2119,35 @@ impl VM {
         let out = match (a, b) {
             (Value::Int(a), Value::Int(b)) => {
                 if b == 0 {
-                    return Err(self.runtime_error_with(2009, "Division by zero"));
+                    return Err(self.runtime_error_with(ErrorKind::DivisionByZero, "Division by zero"));
                 }
                 Value::Int(a / b)
             }
             (Value::BigInt(a), Value::BigInt(b)) => {
+                let (a, b) = (*a, *b);
                 if b == BigInt::from(0) {
-                    return Err(self.runtime_error_with(2009, "Division by zero"));
+                    return Err(self.runtime_error_with(ErrorKind::DivisionByZero, "Division by zero"));
                 }
                 VM::bigint_to_value(a / b)
             }
             (Value::BigInt(a), Value::Int(b)) => {
+                let a = *a;
                 if b == 0 {
-                    return Err(self.runtime_error_with(2009, "Division by zero"));
+                    return Err(self.runtime_error_with(ErrorKind::DivisionByZero, "Division by zero"));
                 }
                 VM::bigint_to_value(a / BigInt::from(b))
             }
             (Value::Int(a), Value::BigInt(b)) => {
+                let b = *b;
                 if b == BigInt::from(0) {
-                    return Err(self.runtime_error_with(2009, "Division by zero"));
+                    return Err(self.runtime_error_with(ErrorKind::DivisionByZero, "Division by zero"));
                 }
                 VM::bigint_to_value(BigInt::from(a) / b)
             }
             (Value::Number(_), _) | (_, Value::Number(_)) => {
-                return Err(self.runtime_error_with(2012, "div: integer parameters expected"));
+                return Err(self.runtime_error_with(ErrorKind::TypeMismatch, "div: integer parameters expected"));
             }
-            _ => return Err(self.runtime_error_with(2012, "div: integer parameters expected")),
+            _ => return Err(self.runtime_error_with(ErrorKind::TypeMismatch, "div: integer parameters expected")),
         };
         self.stack.push(out);
         Ok(())
@@ -1544,19 +2158,22 @@ impl VM {
         let a = self.pop();
         let out = match (a, b) {
             (Value::Int(a), Value::Int(b)) => {
-                if b == 0 { return Err(self.runtime_error_with(2013, "Modulo by zero")); }
+                if b == 0 { return Err(self.runtime_error_with(ErrorKind::DivisionByZero, "Modulo by zero")); }
                 Value::Int(a % b)
             }
             (Value::BigInt(a), Value::BigInt(b)) => {
-                if b == BigInt::from(0) { return Err(self.runtime_error_with(2013, "Modulo by zero")); }
+                let (a, b) = (*a, *b);
+                if b == BigInt::from(0) { return Err(self.runtime_error_with(ErrorKind::DivisionByZero, "Modulo by zero")); }
                 VM::bigint_to_value(a % b)
             }
             (Value::BigInt(a), Value::Int(b)) => {
-                if b == 0 { return Err(self.runtime_error_with(2013, "Modulo by zero")); }
+                let a = *a;
+                if b == 0 { return Err(self.runtime_error_with(ErrorKind::DivisionByZero, "Modulo by zero")); }
                 VM::bigint_to_value(a % BigInt::from(b))
             }
             (Value::Int(a), Value::BigInt(b)) => {
-                if b == BigInt::from(0) { return Err(self.runtime_error_with(2013, "Modulo by zero")); }
+                let b = *b;
+                if b == BigInt::from(0) { return Err(self.runtime_error_with(ErrorKind::DivisionByZero, "Modulo by zero")); }
                 VM::bigint_to_value(BigInt::from(a) % b)
             }
             (Value::Number(a), b) => {
@@ -1567,7 +2184,7 @@ impl VM {
                 let af = VM::numeric_to_f64(&a).ok_or_else(|| self.runtime_error())?;
                 Value::Number(af % b)
             }
-            _ => return Err(self.runtime_error_with(2014, "%: number expected")),
+            _ => return Err(self.runtime_error_with(ErrorKind::TypeMismatch, "%: number expected")),
         };
         self.stack.push(out);
         Ok(())
@@ -1576,8 +2193,8 @@ impl VM {
     fn numeric_pow(&mut self) -> Result<(), InterpretResult> {
         let b = self.pop();
         let a = self.pop();
-        let af = VM::numeric_to_f64(&a).ok_or_else(|| self.runtime_error_with(2021, "^: number expected"))?;
-        let bf = VM::numeric_to_f64(&b).ok_or_else(|| self.runtime_error_with(2021, "^: number expected"))?;
+        let af = VM::numeric_to_f64(&a).ok_or_else(|| self.runtime_error_with(ErrorKind::TypeMismatch, "^: number expected"))?;
+        let bf = VM::numeric_to_f64(&b).ok_or_else(|| self.runtime_error_with(ErrorKind::TypeMismatch, "^: number expected"))?;
         self.stack.push(Value::Number(af.powf(bf)));
         Ok(())
     }
@@ -1586,15 +2203,46 @@ impl VM {
         match v {
             Value::Int(n) => match n.checked_neg() {
                 Some(v) => Ok(Value::Int(v)),
-                None => Ok(Value::BigInt(-BigInt::from(n))),
+                None => Ok(Value::bigint(-BigInt::from(n))),
             },
-            Value::BigInt(n) => Ok(Value::BigInt(-n)),
+            Value::BigInt(n) => Ok(Value::bigint(-*n)),
             Value::Number(n) => Ok(Value::Number(-n)),
             _ => Err(self.runtime_error()),
         }
     }
 
+    /// Ordering of non-numeric values: strings by code point (ordinal), like the rest of the
+    /// text functions, dates by instant and durations by length.
+    fn compare_ordered(a: &Value, b: &Value) -> Option<std::cmp::Ordering> {
+        match (a, b) {
+            (Value::Obj(x), Value::Obj(y)) => match (&**x, &**y) {
+                (Obj::String(x), Obj::String(y)) => Some(x.as_str().cmp(y.as_str())),
+                (Obj::DateTimeTicks(x, xo), Obj::DateTimeTicks(y, yo)) => {
+                    Some(crate::datetime::utc_ticks(*x, *xo).cmp(&crate::datetime::utc_ticks(*y, *yo)))
+                }
+                (Obj::Duration(x), Obj::Duration(y)) => Some(x.cmp(y)),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Ordering of two sort keys: numbers by value, then the orderings of `compare_ordered`.
+    /// `None` when the values do not compare.
+    pub(crate) fn compare_values(a: &Value, b: &Value) -> Option<std::cmp::Ordering> {
+        if let Some(ord) = VM::compare_ordered(a, b) {
+            return Some(ord);
+        }
+        if let (Some(ai), Some(bi)) = (VM::numeric_to_bigint(a), VM::numeric_to_bigint(b)) {
+            return Some(ai.cmp(&bi));
+        }
+        VM::numeric_to_f64(a)?.partial_cmp(&VM::numeric_to_f64(b)?)
+    }
+
     fn numeric_compare_gt(&self, a: &Value, b: &Value) -> Result<bool, InterpretResult> {
+        if let Some(ord) = VM::compare_ordered(a, b) {
+            return Ok(ord.is_gt());
+        }
         if let (Some(ai), Some(bi)) = (VM::numeric_to_bigint(a), VM::numeric_to_bigint(b)) {
             return Ok(ai > bi);
         }
@@ -1604,6 +2252,9 @@ impl VM {
     }
 
     fn numeric_compare_lt(&self, a: &Value, b: &Value) -> Result<bool, InterpretResult> {
+        if let Some(ord) = VM::compare_ordered(a, b) {
+            return Ok(ord.is_lt());
+        }
         if let (Some(ai), Some(bi)) = (VM::numeric_to_bigint(a), VM::numeric_to_bigint(b)) {
             return Ok(ai < bi);
         }
@@ -1626,13 +2277,37 @@ impl VM {
                     self.stack.push(result);
                     Ok(())
                 },
+                crate::obj::Obj::HostFn(native) => {
+                    let start_idx = self.stack.len() - arg_count;
+                    let result = if crate::native::reads_kvc_args(*native) {
+                        let args = self.stack[start_idx..].to_vec();
+                        for arg in &args {
+                            self.force_members(arg, KVC_FORCE_DEPTH);
+                        }
+                        native(&self.host, &args)
+                    } else {
+                        native(&self.host, &self.stack[start_idx..])
+                    };
+                    self.stack.truncate(function_val_idx);
+                    self.stack.push(result);
+                    Ok(())
+                },
+                crate::obj::Obj::VmFn(native) => {
+                    let start_idx = self.stack.len() - arg_count;
+                    let args = self.stack[start_idx..].to_vec();
+                    let fast = self.parallel.as_ref().and_then(|config| parallel::try_sum(*native, &args, config));
+                    let result = fast.unwrap_or_else(|| native(self, &args));
+                    self.stack.truncate(function_val_idx);
+                    self.stack.push(result);
+                    Ok(())
+                },
                 crate::obj::Obj::Function(func) => {
                     if self.frames.len() == FRAMES_MAX {
-                        return Err(self.runtime_error()); 
+                        return Err(self.runtime_error_with(ErrorKind::LimitExceeded, "Stack overflow"));
                     }
                     if arg_count > func.arity {
                         return Err(self.runtime_error_with(
-                            2004,
+                            ErrorKind::ParameterCount,
                             format!(
                                 "Call arity mismatch for '{}': expected {} arguments but got {}",
                                 func.name, func.arity, arg_count
@@ -1649,15 +2324,17 @@ impl VM {
                     }
                     
                     let slots = function_val_idx;
-                    self.frames.push(CallFrame::new(Rc::clone(func), slots));
+                    self.frames.push(CallFrame::new(Arc::clone(func), slots));
                     Ok(())
                 },
                 _ => {
-                    Err(self.runtime_error_with(2005, "Can only call functions"))
+                    Err(self.runtime_error_with(ErrorKind::TypeMismatch, "Can only call functions"))
                 },
             }
+        } else if matches!(function_val, Value::Nil) {
+            Err(self.runtime_error_with(ErrorKind::Undefined, "Can only call functions (got nil)"))
         } else {
-            Err(self.runtime_error_with(2005, "Can only call functions"))
+            Err(self.runtime_error_with(ErrorKind::TypeMismatch, "Can only call functions"))
         }
     }
 
@@ -1665,8 +2342,8 @@ impl VM {
         match (a, b) {
              (Value::Int(a), Value::Int(b)) => a == b,
              (Value::BigInt(a), Value::BigInt(b)) => a == b,
-             (Value::Int(a), Value::BigInt(b)) => BigInt::from(*a) == *b,
-             (Value::BigInt(a), Value::Int(b)) => *a == BigInt::from(*b),
+             (Value::Int(a), Value::BigInt(b)) => BigInt::from(*a) == **b,
+             (Value::BigInt(a), Value::Int(b)) => **a == BigInt::from(*b),
              (Value::Number(n1), Value::Number(n2)) => (n1 - n2).abs() < f64::EPSILON,
              (Value::Int(i), Value::Number(n)) | (Value::Number(n), Value::Int(i)) => {
                  if !n.is_finite() || n.fract() != 0.0 { return false; }
@@ -1688,6 +2365,8 @@ impl VM {
              (Value::Error(e1), Value::Error(e2)) => e1 == e2,
              (Value::Obj(o1), Value::Obj(o2)) => match (&**o1, &**o2) {
                  (Obj::String(a), Obj::String(b)) => a == b,
+                 (Obj::DateTimeTicks(..), Obj::DateTimeTicks(..)) => o1 == o2,
+                 (Obj::Duration(a), Obj::Duration(b)) => a == b,
                  (Obj::List(a), Obj::List(b)) => {
                      if a.len() != b.len() { return false; }
                      for (x, y) in a.iter().zip(b.iter()) {
@@ -1695,37 +2374,37 @@ impl VM {
                      }
                      true
                  }
-                 (Obj::Kvc(k1), Obj::Kvc(k2)) => self.kvc_values_equal(Rc::clone(k1), Rc::clone(k2)),
+                 (Obj::Kvc(k1), Obj::Kvc(k2)) => self.kvc_values_equal(Arc::clone(k1), Arc::clone(k2)),
                  _ => o1 == o2,
              },
              _ => false,
         }
     }
 
-    fn kvc_values_equal(&mut self, k1: Rc<RefCell<KvcObject>>, k2: Rc<RefCell<KvcObject>>) -> bool {
+    fn kvc_values_equal(&mut self, k1: Arc<Mutex<KvcObject>>, k2: Arc<Mutex<KvcObject>>) -> bool {
         let mut keys: std::collections::HashSet<String> = std::collections::HashSet::new();
         {
-            let a = k1.borrow();
+            let a = k1.lock().unwrap();
             for k in a.order.iter() { keys.insert(k.clone()); }
             for k in a.entries.keys() { keys.insert(k.clone()); }
             for k in a.cache.keys() { keys.insert(k.clone()); }
         }
         {
-            let b = k2.borrow();
+            let b = k2.lock().unwrap();
             for k in b.order.iter() { keys.insert(k.clone()); }
             for k in b.entries.keys() { keys.insert(k.clone()); }
             for k in b.cache.keys() { keys.insert(k.clone()); }
         }
 
         let k1_keys: std::collections::HashSet<String> = {
-            let a = k1.borrow();
+            let a = k1.lock().unwrap();
             a.entries.keys()
                 .cloned()
                 .chain(a.cache.keys().cloned())
                 .collect()
         };
         let k2_keys: std::collections::HashSet<String> = {
-            let b = k2.borrow();
+            let b = k2.lock().unwrap();
             b.entries.keys()
                 .cloned()
                 .chain(b.cache.keys().cloned())
@@ -1736,8 +2415,8 @@ impl VM {
         }
 
         for k in keys.into_iter() {
-            let v1 = self.kvc_get(Rc::clone(&k1), &k, &k);
-            let v2 = self.kvc_get(Rc::clone(&k2), &k, &k);
+            let v1 = self.kvc_get(Arc::clone(&k1), &k, &k);
+            let v2 = self.kvc_get(Arc::clone(&k2), &k, &k);
             if !self.values_equal(&v1, &v2) {
                 return false;
             }
@@ -1746,18 +2425,28 @@ impl VM {
     }
 
     pub fn interpret(&mut self, source: &str) -> Result<Value, InterpretResult> {
+        let program = VM::compile(source).map_err(InterpretResult::CompileError)?;
+        self.execute(&program)
+    }
+
+    /// Compiles `source` without running it. The program is `Send + Sync`, so it can be
+    /// compiled once, shared via `Arc` and executed on per-thread VMs.
+    pub fn compile(source: &str) -> Result<Arc<FsFunction>, FsError> {
+        let mut compiler = Compiler::new(source);
+        compiler.compile().map(Arc::new)
+    }
+
+    /// Runs a program produced by `VM::compile` on this VM.
+    pub fn execute(&mut self, program: &Arc<FsFunction>) -> Result<Value, InterpretResult> {
         self.frames.clear();
         self.stack.clear();
         self.providers.clear();
-        let mut compiler = Compiler::new(source);
-        match compiler.compile() {
-            Ok(function) => {
-            let func = Rc::new(function);
-            self.frames.push(CallFrame::new(func, 0));
-            self.run()
-            }
-            Err(e) => Err(InterpretResult::CompileError(e)),
+        self.handlers.clear();
+        if self.kvcs.should_collect() {
+            self.kvcs.collect();
         }
+        self.frames.push(CallFrame::new(Arc::clone(program), 0));
+        self.run()
     }
 
     pub fn eval_result_json(&mut self, source: &str) -> String {
@@ -1818,31 +2507,38 @@ impl VM {
                     format!("{{\"type\":\"bytes\",\"base64\":\"{}\"}}", VM::json_escape(&s))
                 }
                 Obj::Guid(g) => format!("{{\"type\":\"guid\",\"value\":\"{}\"}}", VM::json_escape(&g.to_string())),
-                Obj::DateTimeTicks(ticks) => {
-                    // Provide ticks always; iso is best-effort conversion (UTC) for convenience.
+                Obj::DateTimeTicks(ticks, offset) => {
+                    // Provide clock ticks and offset always; iso is best-effort, written at the
+                    // date's own offset (UTC when it has none) so it round-trips through `Date`.
                     const UNIX_EPOCH_TICKS: i64 = 621_355_968_000_000_000;
                     const TICKS_PER_SEC: i64 = 10_000_000;
-                    let iso = if *ticks >= UNIX_EPOCH_TICKS {
-                        let dt_ticks = ticks - UNIX_EPOCH_TICKS;
+                    let utc = crate::datetime::utc_ticks(*ticks, *offset);
+                    let iso = if utc >= UNIX_EPOCH_TICKS {
+                        let dt_ticks = utc - UNIX_EPOCH_TICKS;
                         let secs = dt_ticks / TICKS_PER_SEC;
                         let rem = dt_ticks % TICKS_PER_SEC;
                         let nanos = (rem * 100) as u32;
-                        match time::OffsetDateTime::from_unix_timestamp(secs)
-                            .and_then(|d| d.replace_nanosecond(nanos))
-                        {
-                            Ok(d) => d.format(&time::format_description::well_known::Rfc3339).ok(),
-                            Err(_) => None,
+                        let zone = time::UtcOffset::from_whole_seconds(offset.unwrap_or(0) as i32 * 60);
+                        match (time::OffsetDateTime::from_unix_timestamp(secs).and_then(|d| d.replace_nanosecond(nanos)), zone) {
+                            (Ok(d), Ok(zone)) => d.to_offset(zone).format(&time::format_description::well_known::Rfc3339).ok(),
+                            _ => None,
                         }
                     } else {
                         None
                     };
+                    let offset = offset.map(|m| format!(",\"offset\":{}", m)).unwrap_or_default();
                     if let Some(iso) = iso {
-                        format!("{{\"type\":\"datetime\",\"ticks\":{},\"iso\":\"{}\"}}", ticks, VM::json_escape(&iso))
+                        format!("{{\"type\":\"datetime\",\"ticks\":{}{},\"iso\":\"{}\"}}", ticks, offset, VM::json_escape(&iso))
                     } else {
-                        format!("{{\"type\":\"datetime\",\"ticks\":{}}}", ticks)
+                        format!("{{\"type\":\"datetime\",\"ticks\":{}{}}}", ticks, offset)
                     }
                 }
-                Obj::Kvc(k) => self.kvc_to_json(Rc::clone(k)),
+                Obj::Duration(ticks) => format!(
+                    "{{\"type\":\"duration\",\"ticks\":{},\"iso\":\"{}\"}}",
+                    ticks,
+                    crate::datetime::duration_iso(*ticks)
+                ),
+                Obj::Kvc(k) => self.kvc_to_json(Arc::clone(k)),
                 Obj::Provider(p) => {
                     self.value_to_json(&p.current)
                 }
@@ -1851,7 +2547,7 @@ impl VM {
                         VM::json_escape(&f.name),
                         f.arity)
                 }
-                Obj::NativeFn(_) => "{\"type\":\"native\"}".to_string(),
+                Obj::NativeFn(_) | Obj::HostFn(_) | Obj::VmFn(_) => "{\"type\":\"native\"}".to_string(),
             }
         }
     }
@@ -1860,39 +2556,50 @@ impl VM {
         self.value_to_json(v)
     }
 
-    fn error_to_json(&self, e: &FsError, kind: &str) -> String {
+    fn error_to_json(&mut self, e: &FsError, kind: &str) -> String {
+        let user_type = match &e.user_type {
+            Some(t) => format!("\"{}\"", VM::json_escape(t)),
+            None => "null".to_string(),
+        };
+        let data = match &e.data {
+            Some(d) => self.value_to_json(d),
+            None => "null".to_string(),
+        };
+        let cause = match &e.cause {
+            Some(c) => self.error_to_json(c, kind),
+            None => "null".to_string(),
+        };
         format!(
-            "{{\"kind\":\"{}\",\"code\":{},\"message\":\"{}\",\"line\":{},\"column\":{}}}",
+            "{{\"kind\":\"{}\",\"code\":{},\"errorKind\":\"{}\",\"message\":\"{}\",\"type\":{},\"data\":{},\"cause\":{},\"line\":{},\"column\":{}}}",
             VM::json_escape(kind),
-            e.code,
+            e.code(),
+            e.kind.name(),
             VM::json_escape(&e.message),
+            user_type,
+            data,
+            cause,
             e.line,
             e.column
         )
     }
 
     fn runtime_error(&self) -> InterpretResult {
-        self.runtime_error_with(2000, "Runtime error")
+        self.runtime_error_with(ErrorKind::Runtime, "Runtime error")
     }
 
-    fn runtime_error_with(&self, code: u32, message: impl Into<String>) -> InterpretResult {
-        InterpretResult::RuntimeError(FsError {
-            code,
-            message: message.into(),
-            line: -1,
-            column: -1,
-        })
+    fn runtime_error_with(&self, kind: ErrorKind, message: impl Into<String>) -> InterpretResult {
+        InterpretResult::RuntimeError(FsError::new(kind, message))
     }
 
-    fn kvc_to_json(&mut self, k: Rc<RefCell<KvcObject>>) -> String {
-        let order = k.borrow().order.clone();
+    fn kvc_to_json(&mut self, k: Arc<Mutex<KvcObject>>) -> String {
+        let order = k.lock().unwrap().order.clone();
         let mut parts: Vec<String> = Vec::with_capacity(order.len());
         for key_l in order {
             let display = {
-                let b = k.borrow();
+                let b = k.lock().unwrap();
                 b.display_names.get(&key_l).cloned().unwrap_or_else(|| key_l.clone())
             };
-            let val = self.kvc_get(Rc::clone(&k), &key_l, &display);
+            let val = self.kvc_get(Arc::clone(&k), &key_l, &display);
             parts.push(format!("\"{}\":{}", VM::json_escape(&display), self.value_to_json(&val)));
         }
         format!("{{{}}}", parts.join(","))
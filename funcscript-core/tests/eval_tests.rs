@@ -2,7 +2,8 @@ use funcscript::vm::VM;
 use funcscript::value::Value;
 use funcscript::obj::Obj;
 use funcscript::host;
-use std::rc::Rc;
+use funcscript::parallel::ParallelConfig;
+use std::sync::Arc;
 use uuid::Uuid;
 use std::path::PathBuf;
 
@@ -12,7 +13,7 @@ fn eval(source: &str) -> Value {
 }
 
 fn s(text: &str) -> Value {
-    Value::Obj(Rc::new(Obj::String(text.to_string())))
+    Value::Obj(Arc::new(Obj::String(text.into())))
 }
 
 fn i(n: i64) -> Value {
@@ -88,12 +89,12 @@ fn null_coalesce_and_eval_if_not_null_work() {
 #[test]
 fn map_operator_works() {
     assert_eq!(eval("nil map (x)=>x"), Value::Nil);
-    assert_eq!(eval("[4,5] map (x)=>x+2"), Value::Obj(Rc::new(Obj::List(vec![i(6), i(7)]))));
+    assert_eq!(eval("[4,5] map (x)=>x+2"), Value::Obj(Arc::new(Obj::List(vec![i(6), i(7)]))));
     let exp = r#"{
         a:2;
         return [4,5] map (x)=>x+a;
     }"#;
-    assert_eq!(eval(exp), Value::Obj(Rc::new(Obj::List(vec![i(6), i(7)]))));
+    assert_eq!(eval(exp), Value::Obj(Arc::new(Obj::List(vec![i(6), i(7)]))));
 }
 
 #[test]
@@ -158,7 +159,7 @@ fn not_operator_is_boolean_only() {
 
 #[test]
 fn lists_work() {
-    assert_eq!(eval("[1, 2, 3]"), Value::Obj(Rc::new(Obj::List(vec![
+    assert_eq!(eval("[1, 2, 3]"), Value::Obj(Arc::new(Obj::List(vec![
         i(1),
         i(2),
         i(3),
@@ -212,6 +213,18 @@ fn kvc_addition_does_not_mutate_left_operand_semantically() {
     assert_eq!(eval(exp), Value::Bool(true));
 }
 
+#[test]
+fn kvc_members_can_be_indexed_by_key() {
+    assert_eq!(eval("{ 'content-type': 'text/plain' }['content-type']"), s("text/plain"));
+    assert_eq!(eval("{ Name: 1 }['name']"), i(1));
+    assert_eq!(eval("{ a: { b: 2 } }['a']['b']"), i(2));
+    assert_eq!(eval("{ k: 'a'; r: { a: 3 }[k] }.r"), i(3));
+    assert_eq!(eval("{ a: 1 }['missing']"), Value::Nil);
+    // Other values still give null for a text index.
+    assert_eq!(eval("[1, 2]['0']"), Value::Nil);
+    assert_eq!(eval("'abc'['length']"), Value::Nil);
+}
+
 #[test]
 fn native_functions_work() {
     assert_eq!(eval("Abs(-5)"), i(5));
@@ -230,14 +243,14 @@ fn native_functions_work() {
 #[test]
 fn change_type_supports_guid_datetime_and_bytearray() {
     let guid = eval("ChangeType('00000000-0000-0000-0000-000000000000','Guid')");
-    assert_eq!(guid, Value::Obj(Rc::new(Obj::Guid(Uuid::nil()))));
+    assert_eq!(guid, Value::Obj(Arc::new(Obj::Guid(Uuid::nil()))));
 
     let ticks = 637134336000000000i64;
     let dt = eval(&format!("ChangeType({ticks}l,'DateTime')"));
-    assert_eq!(dt, Value::Obj(Rc::new(Obj::DateTimeTicks(ticks))));
+    assert_eq!(dt, Value::Obj(Arc::new(Obj::DateTimeTicks(ticks, None))));
 
     let bytes = eval("ChangeType('AQID','ByteArray')");
-    assert_eq!(bytes, Value::Obj(Rc::new(Obj::Bytes(vec![1, 2, 3]))));
+    assert_eq!(bytes, Value::Obj(Arc::new(Obj::Bytes(vec![1, 2, 3]))));
 }
 
 #[test]
@@ -302,22 +315,394 @@ fn text_functions_work() {
     assert_eq!(eval(r#"Range(1,3) join ":""#), s("1:2:3"));
 }
 
+#[test]
+fn text_library_uses_char_indices() {
+    assert_eq!(eval(r#"split("a,b,,c", ",")"#).to_string(), "[a, b, , c]");
+    assert_eq!(eval(r#"split("  one two	three ")"#).to_string(), "[one, two, three]");
+    assert_eq!(eval(r#"replace("a-b-c", "-", "+")"#), s("a+b+c"));
+    assert_eq!(eval(r#"replace("abc", "", "x")"#), s("abc"));
+    assert_eq!(eval(r#"trim("  hi  ")"#), s("hi"));
+    assert_eq!(eval(r#"trimStart("xxhixx", "x")"#), s("hixx"));
+    assert_eq!(eval(r#"trimEnd("xxhixx", "x")"#), s("xxhi"));
+    assert_eq!(eval(r#"startswith("hello", "he")"#), Value::Bool(true));
+    assert_eq!(eval(r#"startswith(nil, "he")"#), Value::Bool(false));
+    assert_eq!(eval(r#"padLeft("7", 3, "0")"#), s("007"));
+    assert_eq!(eval(r#"padRight("héé", 5)"#), s("héé  "));
+    assert_eq!(eval(r#"padLeft("long", 2)"#), s("long"));
+    assert_eq!(eval(r#"repeat("ab", 3)"#), s("ababab"));
+    assert_eq!(eval(r#"indexOf("héllo wörld", "ö")"#), i(7));
+    assert_eq!(eval(r#"indexOf("abcabc", "b", 2)"#), i(4));
+    assert_eq!(eval(r#"lastIndexOf("abcabc", "b")"#), i(4));
+    assert_eq!(eval(r#"lastIndexOf("abcabc", "b", 3)"#), i(1));
+    assert_eq!(eval(r#"indexOf("abc", "z")"#), i(-1));
+    assert_eq!(eval(r#"chars("añb")"#).to_string(), "[a, ñ, b]");
+    assert_eq!(eval(r#"text.contains("Hello", "ell")"#), Value::Bool(true));
+    assert_eq!(eval(r#"text.split("a b")"#).to_string(), "[a, b]");
+    assert_eq!(eval(r#"text.padLeft("1", 2, "0")"#), s("01"));
+    assert_eq!(eval(r#"upper(nil)"#), Value::Nil);
+    assert_eq!(eval(r#"trim(nil)"#), Value::Nil);
+
+    assert_eq!(eval(r#"repeat("a", -1).kind"#), s("out_of_range"));
+    assert_eq!(eval(r#"indexOf("abc", "a", 9).kind"#), s("out_of_range"));
+    assert_eq!(eval(r#"padLeft("a", 3, "xy").kind"#), s("invalid_value"));
+    assert_eq!(eval(r#"split(1, ",").kind"#), s("type_mismatch"));
+    assert_eq!(eval(r#"replace("a", "b").kind"#), s("parameter_count"));
+}
+
+#[test]
+fn strings_use_code_point_semantics() {
+    assert_eq!(eval(r#"Len("héllo")"#), i(5));
+    assert_eq!(eval(r#""héllo"[1]"#), s("é"));
+    assert_eq!(eval(r#""héllo"[5]"#), Value::Nil);
+    assert_eq!(eval(r#"substring("héllo", 1, 2)"#), s("él"));
+    assert_eq!(eval(r#"find("héllo", "l")"#), i(2));
+    assert_eq!(eval(r#""apple" < "banana""#), Value::Bool(true));
+    assert_eq!(eval(r#""é" > "z""#), Value::Bool(true));
+    assert_eq!(eval(r#""b" >= "b""#), Value::Bool(true));
+    assert_eq!(eval("Len(\"e\u{301}\")"), i(2));
+}
+
+#[test]
+#[cfg(feature = "unicode")]
+fn graphemes_and_normalization() {
+    // "e" followed by a combining acute accent: two code points, one grapheme.
+    assert_eq!(eval("Len(graphemes(\"e\u{301}x\"))"), i(2));
+    assert_eq!(eval("normalize(\"e\u{301}\") = \"\u{e9}\""), Value::Bool(true));
+    assert_eq!(eval("Len(normalize(\"\u{e9}\", \"NFD\"))"), i(2));
+    assert_eq!(eval(r#"text.normalize("ﬁ", "nfkc")"#), s("fi"));
+    assert_eq!(eval(r#"normalize("a", "NFX").kind"#), s("invalid_value"));
+}
+
 #[test]
 fn list_functions_work() {
-    assert_eq!(eval("Take([1,2,3], 2)"), Value::Obj(Rc::new(Obj::List(vec![i(1), i(2)]))));
-    assert_eq!(eval("Skip([1,2,3], 2)"), Value::Obj(Rc::new(Obj::List(vec![i(3)]))));
-    assert_eq!(eval("Reverse([1,2,3])"), Value::Obj(Rc::new(Obj::List(vec![i(3), i(2), i(1)]))));
-    assert_eq!(eval("Distinct([1,1,2,nil,2,nil])"), Value::Obj(Rc::new(Obj::List(vec![i(1), i(2), Value::Nil]))));
+    assert_eq!(eval("Take([1,2,3], 2)"), Value::Obj(Arc::new(Obj::List(vec![i(1), i(2)]))));
+    assert_eq!(eval("Skip([1,2,3], 2)"), Value::Obj(Arc::new(Obj::List(vec![i(3)]))));
+    assert_eq!(eval("Reverse([1,2,3])"), Value::Obj(Arc::new(Obj::List(vec![i(3), i(2), i(1)]))));
+    assert_eq!(eval("Distinct([1,1,2,nil,2,nil])"), Value::Obj(Arc::new(Obj::List(vec![i(1), i(2), Value::Nil]))));
     assert_eq!(eval("Contains([1,2,3], 2)"), Value::Bool(true));
     assert_eq!(eval(r#"Contains("Hello","ell")"#), Value::Bool(true));
     assert_eq!(eval("Contains(Range(1,3), 2)"), Value::Bool(true));
 
-    assert_eq!(eval("Map([1,2,3], (x,i)=>x+i)"), Value::Obj(Rc::new(Obj::List(vec![i(1), i(3), i(5)]))));
-    assert_eq!(eval("[1,2,3] filter (x,i)=>x>1"), Value::Obj(Rc::new(Obj::List(vec![i(2), i(3)]))));
-    assert_eq!(eval("Filter([1,2,3], (x,i)=>x>1)"), Value::Obj(Rc::new(Obj::List(vec![i(2), i(3)]))));
+    assert_eq!(eval("Map([1,2,3], (x,i)=>x+i)"), Value::Obj(Arc::new(Obj::List(vec![i(1), i(3), i(5)]))));
+    assert_eq!(eval("[1,2,3] filter (x,i)=>x>1"), Value::Obj(Arc::new(Obj::List(vec![i(2), i(3)]))));
+    assert_eq!(eval("Filter([1,2,3], (x,i)=>x>1)"), Value::Obj(Arc::new(Obj::List(vec![i(2), i(3)]))));
     assert_eq!(eval("Any([1,2,3], (x,i)=>x=2)"), Value::Bool(true));
     assert_eq!(eval("First([1,2,3], (x,i)=>x>1)"), i(2));
-    assert_eq!(eval("Sort([3,1,2], (a,b)=>a-b)"), Value::Obj(Rc::new(Obj::List(vec![i(1), i(2), i(3)]))));
+    assert_eq!(eval("Sort([3,1,2], (a,b)=>a-b)"), Value::Obj(Arc::new(Obj::List(vec![i(1), i(2), i(3)]))));
+}
+
+/// `format` cases with the output of the C# runtime (`FsValueFormatter`, invariant culture).
+const FORMAT_PARITY: &[(&str, &str)] = &[
+    // From the C# test suite.
+    (r##"format(12.123, "#,0.00")"##, "12.12"),
+    (r##"format(null, "#,0.00")"##, "null"),
+    (r##"format(20, "#,#.0")"##, "20.0"),
+    (r##"format(12345678901234l)"##, "12345678901234"),
+    // Custom numeric patterns.
+    (r##"format(1234.5, "#,##0.00")"##, "1,234.50"),
+    (r##"format(1234567, "#,##0,K")"##, "1,235K"),
+    (r##"format(0.5, "0%")"##, "50%"),
+    (r##"format(123, "00000")"##, "00123"),
+    (r##"format(1.5, "#.##")"##, "1.5"),
+    (r##"format(2.5, "0")"##, "3"),
+    (r##"format(3.5, "0")"##, "4"),
+    (r##"format(0.5, "0")"##, "1"),
+    (r##"format(-2.5, "0")"##, "-3"),
+    (r##"format(0.125, "0.00")"##, "0.13"),
+    (r##"format(0.125, "F2")"##, "0.13"),
+    (r##"format(-5, "#,##0;(#,##0);zero")"##, "(5)"),
+    (r##"format(0, "#;(#);zero")"##, "zero"),
+    (r##"format(12345, "0.00E+00")"##, "1.23E+04"),
+    (r##"format(7, "'#'0")"##, "#7"),
+    (r##"format(1.5, ".")"##, "2"),
+    (r##"format(1.5, ",")"##, "2"),
+    (r##"format(-0.0, "0.00")"##, "-0.00"),
+    (r##"format(-0.001, "0.00")"##, "-0.00"),
+    (r##"format(-0.0, "0.00;(0.00);zero")"##, "zero"),
+    // Standard numeric formats.
+    (r##"format(0.25, "P1")"##, "25.0 %"),
+    (r##"format(1234567.891, "N")"##, "1,234,567.89"),
+    (r##"format(99.999, "F2")"##, "100.00"),
+    (r##"format(-1234.5, "C")"##, "(¤1,234.50)"),
+    (r##"format(42, "D6")"##, "000042"),
+    (r##"format(255, "X4")"##, "00FF"),
+    (r##"format(-1, "x")"##, "ffffffff"),
+    (r##"format(1234.5678, "E2")"##, "1.23E+003"),
+    (r##"format(123.456, "G4")"##, "123.5"),
+    (r##"format(1e20, "G")"##, "1E+20"),
+    (r##"format(0.0001234, "G")"##, "0.0001234"),
+    // Dates.
+    (r##"format(Date("2024-03-05T14:07:09Z"))"##, "2024-03-05 14:07:09"),
+    (r##"format(Date("2024-03-05T14:07:09Z"), "yyyy-MM-dd HH:mm")"##, "2024-03-05 14:07"),
+    (r##"format(Date("2024-03-05T14:07:09Z"), "dddd, MMMM d, yyyy h:mm tt")"##, "Tuesday, March 5, 2024 2:07 PM"),
+    (r##"format(Date("2024-03-05T14:07:09Z"), "ddd dd MMM yy")"##, "Tue 05 Mar 24"),
+    (r##"format(Date("2024-03-05T14:07:09Z"), "ss.FFF")"##, "09"),
+    (r##"format(Date("2024-03-05T14:07:09Z"), "o")"##, "2024-03-05T14:07:09.0000000Z"),
+    (r##"format(Date("2024-03-05T14:07:09Z"), "yyyy K")"##, "2024 Z"),
+    (r##"format(Date("2024-03-05T14:07:09Z"), "r")"##, "Tue, 05 Mar 2024 14:07:09 GMT"),
+    (r##"format(Date("2024-03-05T14:07:09Z"), "d")"##, "03/05/2024"),
+    // GUIDs.
+    (r##"format(guid("6F9619FF-8B86-D011-B42D-00CF4FC964FF"), "N")"##, "6f9619ff8b86d011b42d00cf4fc964ff"),
+    (r##"format(guid("6F9619FF-8B86-D011-B42D-00CF4FC964FF"), "B")"##, "{6f9619ff-8b86-d011-b42d-00cf4fc964ff}"),
+    (r##"format(guid("6F9619FF-8B86-D011-B42D-00CF4FC964FF"), "X")"##, "{0x6f9619ff,0x8b86,0xd011,{0xb4,0x2d,0x00,0xcf,0x4f,0xc9,0x64,0xff}}"),
+];
+
+#[test]
+fn format_patterns_match_the_csharp_runtime() {
+    for (src, expected) in FORMAT_PARITY {
+        assert_eq!(eval(src), s(expected), "{src}");
+    }
+    assert_eq!(eval(r#"format(1, "Q").kind"#), s("invalid_value"));
+    assert_eq!(eval(r#"format(1.5, "D").kind"#), s("invalid_value"));
+    assert_eq!(eval(r#"format(Date("2024-03-05T14:07:09Z"), "q").kind"#), s("invalid_value"));
+    assert_eq!(eval(r#"format(guid("6F9619FF-8B86-D011-B42D-00CF4FC964FF"), "Z").kind"#), s("invalid_value"));
+}
+
+#[test]
+fn dates_parse_with_patterns_and_support_calendar_math() {
+    assert_eq!(eval(r#"Date("05/03/2024 14:07", "dd/MM/yyyy HH:mm") = Date("2024-03-05T14:07:00Z")"#), Value::Bool(true));
+    assert_eq!(eval(r#"format(Date("March 5, 2024 2:07 PM", "MMMM d, yyyy h:mm tt"), "s")"#), s("2024-03-05T14:07:00"));
+    assert_eq!(eval(r#"format(Date("2024-03-05 14:07:09+03:00", "yyyy-MM-dd HH:mm:sszzz"), "s")"#), s("2024-03-05T14:07:09"));
+    assert_eq!(eval(r#"format(Date("2024-03-05 14:07:09+03:00", "yyyy-MM-dd HH:mm:sszzz"), "u")"#), s("2024-03-05 11:07:09Z"));
+    assert_eq!(eval(r#"format(Date("Tue, 05 Mar 2024 14:07:09 GMT", "r"), "s")"#), s("2024-03-05T14:07:09"));
+    assert_eq!(eval(r#"Date("2024-02-30", "yyyy-MM-dd").kind"#), s("invalid_value"));
+
+    assert_eq!(
+        eval(r#"{d: Date("2024-03-05T14:07:09Z"); return [d.year, d.month, d.day, d.hour, d.weekday, d.dayOfYear]}"#).to_string(),
+        "[2024, 3, 5, 14, 2, 65]"
+    );
+
+    let day = |src: &str| eval(&format!(r#"format({src}, "yyyy-MM-dd")"#));
+    assert_eq!(day(r#"addMonths(Date("2024-01-31", "yyyy-MM-dd"), 1)"#), s("2024-02-29"));
+    assert_eq!(day(r#"addYears(Date("2024-02-29", "yyyy-MM-dd"), 1)"#), s("2025-02-28"));
+    assert_eq!(day(r#"addMonths(Date("2024-03-31", "yyyy-MM-dd"), -13)"#), s("2023-02-28"));
+    assert_eq!(day(r#"addDays(Date("2024-03-01", "yyyy-MM-dd"), -1)"#), s("2024-02-29"));
+    assert_eq!(day(r#"startOf(Date("2024-03-07T10:00:00Z"), "week")"#), s("2024-03-04"));
+    assert_eq!(day(r#"startOf(Date("2024-03-07T10:00:00Z"), "month")"#), s("2024-03-01"));
+    assert_eq!(eval(r#"format(endOf(Date("2024-02-07T10:00:00Z"), "month"), "o")"#), s("2024-02-29T23:59:59.9999999Z"));
+    assert_eq!(eval(r#"format(endOf(Date("2024-02-07T10:00:00Z"), "day"), "HH:mm:ss")"#), s("23:59:59"));
+
+    assert_eq!(eval(r#"Date("2024-03-05T00:00:00Z") < Date("2024-03-06T00:00:00Z")"#), Value::Bool(true));
+    assert_eq!(eval(r#"Date("2024-03-05T00:00:00Z") >= Date("2024-03-06T00:00:00Z")"#), Value::Bool(false));
+    assert_eq!(eval(r#"Date("2024-03-05T00:00:00Z") = Date("2024-03-05", "yyyy-MM-dd")"#), Value::Bool(true));
+
+    assert_eq!(eval(r#"addYears(Date("9999-12-07", "yyyy-MM-dd"), 1).kind"#), s("out_of_range"));
+    assert_eq!(eval(r#"addMonths(Date("2024-01-31T00:00:00Z"), -9223372036854775807 - 1).kind"#), s("out_of_range"));
+    assert_eq!(eval(r#"addYears(Date("2024-01-31T00:00:00Z"), -9223372036854775807 - 1).kind"#), s("out_of_range"));
+    assert_eq!(eval(r#"startOf(Date("2024-03-07", "yyyy-MM-dd"), "fortnight").kind"#), s("invalid_value"));
+    assert_eq!(eval(r#"addDays("2024-03-07", 1).kind"#), s("type_mismatch"));
+    assert_eq!(eval("addDays(nil, 1)"), Value::Nil);
+}
+
+#[test]
+fn durations_construct_and_combine_with_dates() {
+    assert_eq!(eval("duration(1, 30, 15)").to_string(), "01:30:15");
+    assert_eq!(eval("duration(2, 3, 0, 0.5)").to_string(), "2.03:00:00.5000000");
+    assert_eq!(eval(r#"duration("P1DT2H")"#).to_string(), "1.02:00:00");
+    assert_eq!(eval(r#"duration("-PT1.5S")"#).to_string(), "-00:00:01.5000000");
+    assert_eq!(eval(r#"duration("P1Y").kind"#), s("invalid_value"));
+    assert_eq!(eval(r#"duration("PT").kind"#), s("invalid_value"));
+
+    assert_eq!(
+        eval(r#"{d: duration("P1DT2H30M"); return [d.days, d.hours, d.minutes, d.totalHours]}"#).to_string(),
+        "[1, 2, 30, 26.5]"
+    );
+    assert_eq!(eval("duration(0, 90, 0) = duration(1, 30, 0)"), Value::Bool(true));
+    assert_eq!(eval("duration(0, 90, 0) > duration(1, 0, 0)"), Value::Bool(true));
+    assert_eq!(eval("duration(1, 0, 0) - duration(0, 0, 1)").to_string(), "00:59:59");
+
+    assert_eq!(
+        eval(r#"format(Date("2024-03-05T22:00:00Z") + duration(3, 0, 0), "s")"#),
+        s("2024-03-06T01:00:00")
+    );
+    assert_eq!(
+        eval(r#"format(duration("P1D") + Date("2024-02-28T00:00:00Z"), "s")"#),
+        s("2024-02-29T00:00:00")
+    );
+    assert_eq!(
+        eval(r#"format(Date("2024-03-01T00:00:00Z") - duration("PT1S"), "s")"#),
+        s("2024-02-29T23:59:59")
+    );
+    assert_eq!(
+        eval(r#"(Date("2024-03-06T01:00:00Z") - Date("2024-03-05T22:30:00Z")).totalMinutes"#),
+        Value::Number(150.0)
+    );
+    assert_eq!(eval(r#"(Date("9999-12-31T00:00:00Z") + duration("P1D")).kind"#), s("out_of_range"));
+
+    let mut vm = VM::new();
+    assert_eq!(
+        vm.eval_result_json(r#"duration("P1DT2H30M")"#),
+        r#"{"ok":true,"value":{"type":"duration","ticks":954000000000,"iso":"P1DT2H30M"},"error":null}"#
+    );
+}
+
+#[test]
+fn dates_keep_their_utc_offset() {
+    assert_eq!(eval(r#"format(Date("2024-03-05T14:07:09+03:00"), "o")"#), s("2024-03-05T14:07:09.0000000+03:00"));
+    assert_eq!(eval(r#"format(Date("2024-03-05T14:07:09-05:30"), "yyyy-MM-dd HH:mm zz")"#), s("2024-03-05 14:07 -05"));
+    assert_eq!(eval(r#"{d: Date("2024-03-05T14:07:09+03:00"); return [d.hour, d.offset.totalHours]}"#).to_string(), "[14, 3]");
+    assert_eq!(eval(r#"Date("2024-03-05T14:07:09Z").offset"#), Value::Nil);
+
+    // Equality and ordering compare instants; subtraction spans them.
+    assert_eq!(eval(r#"Date("2024-03-05T14:00:00+03:00") = Date("2024-03-05T11:00:00Z")"#), Value::Bool(true));
+    assert_eq!(eval(r#"Date("2024-03-05T14:00:00+03:00") < Date("2024-03-05T12:30:00+01:00")"#), Value::Bool(true));
+    assert_eq!(eval(r#"Date("2024-03-05T14:00:00+03:00") - Date("2024-03-05T10:00:00Z")"#).to_string(), "01:00:00");
+
+    assert_eq!(eval(r#"format(toOffset(Date("2024-03-05T22:30:00Z"), "+03:00"), "o")"#), s("2024-03-06T01:30:00.0000000+03:00"));
+    assert_eq!(eval(r#"format(toOffset(Date("2024-03-05T22:30:00Z"), duration(-4, 0, 0)), "o")"#), s("2024-03-05T18:30:00.0000000-04:00"));
+    assert_eq!(eval(r#"format(utc(Date("2024-03-06T01:30:00+03:00")), "o")"#), s("2024-03-05T22:30:00.0000000Z"));
+    assert_eq!(eval(r#"format(addDays(Date("2024-03-05T23:00:00+03:00"), 1), "o")"#), s("2024-03-06T23:00:00.0000000+03:00"));
+    assert_eq!(eval(r#"format(Date("2024-03-05T23:00:00+03:00") + duration(2, 0, 0), "o")"#), s("2024-03-06T01:00:00.0000000+03:00"));
+    assert_eq!(eval(r#"toOffset(Date("2024-03-05T22:30:00Z"), "+15:00").kind"#), s("invalid_value"));
+    assert_eq!(eval(r#"toOffset(Date("2024-03-05T22:30:00Z"), "Europe/Berlin").kind"#), s("invalid_value"));
+
+    let mut vm = VM::new();
+    assert_eq!(
+        vm.eval_result_json(r#"Date("2024-03-05T14:07:09+03:00")"#),
+        r#"{"ok":true,"value":{"type":"datetime","ticks":638452444290000000,"offset":180,"iso":"2024-03-05T14:07:09+03:00"},"error":null}"#
+    );
+    assert_eq!(
+        eval(r#"Date("2024-03-05T14:07:09+03:00") = Date(format(Date("2024-03-05T14:07:09+03:00"), "o"))"#),
+        Value::Bool(true)
+    );
+    assert_eq!(eval(r#"Date(format(Date("2024-03-05T14:07:09Z"), "o")).offset"#), Value::Nil);
+}
+
+#[test]
+fn list_library_works_on_lists_and_ranges() {
+    let show = |src: &str| eval(src).to_string();
+    assert_eq!(show(r#"zip([1, 2, 3], ["a", "b"])"#), "[[1, a], [2, b]]");
+    assert_eq!(show("groupBy(Range(1, 7), (x) => x % 3) map (g) => [g.key, Len(g.items)]"), "[[1, 3], [2, 2], [0, 2]]");
+    assert_eq!(show("flatten([[1, 2], 3, Range(5, 2)])"), "[1, 2, 3, 5, 6]");
+    assert_eq!(show("flatMap(Range(1, 2), (x) => [x, x * 10])"), "[1, 10, 2, 20]");
+    assert_eq!(show("chunk(Range(1, 5), 2)"), "[[1, 2], [3, 4], [5]]");
+    assert_eq!(show("window([1, 2, 3, 4], 3)"), "[[1, 2, 3], [2, 3, 4]]");
+    assert_eq!(eval("indexOf(Range(10, 5), 12)"), i(2));
+    assert_eq!(eval(r#"indexOf([1, "a", 3, "a"], "a", 2)"#), i(3));
+    assert_eq!(eval(r#"indexOf("hello", "l")"#), i(2));
+    assert_eq!(eval("last(Range(1, 5))"), i(5));
+    assert_eq!(eval("count(Range(1, 10), (x) => x % 2 = 0)"), i(5));
+    assert_eq!(eval("count(Range(1, 10))"), i(10));
+
+    // sortBy is stable in both directions.
+    assert_eq!(
+        show(r#"sortBy([{n: "b", a: 2}, {n: "a", a: 1}, {n: "c", a: 2}], (r) => r.a, true) map (r) => r.n"#),
+        "[b, c, a]"
+    );
+    assert_eq!(show("sortBy([{a: 2}, {b: 1}, {a: 1}], (r) => r.a) map (r) => r.a"), "[1, 2, nil]");
+    assert_eq!(eval(r#"minBy(["ccc", "a", "bb"], (s) => Len(s))"#), s("a"));
+    assert_eq!(eval("maxBy(Range(1, 5), (x) => -x * x + 4 * x)"), i(2));
+    assert_eq!(eval("minBy([], (x) => x)"), Value::Nil);
+
+    assert_eq!(eval(r#"sortBy([3, "x"], (x) => x).kind"#), s("type_mismatch"));
+    assert_eq!(eval("chunk([1], 0).kind"), s("invalid_value"));
+    assert_eq!(eval("groupBy([1], 5).kind"), s("type_mismatch"));
+    assert_eq!(eval("count([1, 2], (x) => error('no')).message"), s("no"));
+    // A key or predicate that fails partway through is abandoned, not resumed after the native.
+    let failed = "Error(2001): not: bool expected";
+    assert_eq!(show("[1, 2, sortBy([3, 1, 2], (x) => [not x, 99, 98])]"), format!("[1, 2, {}]", failed));
+    assert_eq!(show("[1, count([1, 2, 3], (x) => [not x, 99])]"), format!("[1, {}]", failed));
+    assert_eq!(show("[1, groupBy([1, 2], (x) => [not x, 99])]"), format!("[1, {}]", failed));
+    assert_eq!(show("[1, minBy([1, 2], (x) => [not x, 99])]"), format!("[1, {}]", failed));
+    assert_eq!(show("[1, flatMap(Range(1, 2), (x) => [not x, 99])]"), format!("[1, {}]", failed));
+}
+
+#[test]
+fn kvc_library_preserves_order_and_laziness() {
+    let show = |src: &str| eval(src).to_string();
+    assert_eq!(show("keys({ Zeta: 1, alpha: 2, Mid: 3 })"), "[Zeta, alpha, Mid]");
+    assert_eq!(show("values({ b: 2, a: 1 })"), "[2, 1]");
+    assert_eq!(show("entries({ B: 2, a: 1 }) map (e) => [e.key, e.value]"), "[[B, 2], [a, 1]]");
+    assert_eq!(show("keys(fromEntries([['x', 1], { key: 'Y', value: 2 }, ['X', 3]]))"), "[x, Y]");
+    assert_eq!(eval("fromEntries([['x', 1], ['X', 3]]).x"), i(3));
+    assert_eq!(eval("hasKey({ Name: 1 }, 'name')"), Value::Bool(true));
+    assert_eq!(eval("hasKey({ a: 1 }, 'b')"), Value::Bool(false));
+
+    // Members that are dropped or only listed are never evaluated.
+    assert_eq!(eval("hasKey({ bad: error('no') }, 'bad')"), Value::Bool(true));
+    assert_eq!(show("keys({ a: 1, bad: error('no') })"), "[a, bad]");
+    assert_eq!(show("keys(omit({ a: 1, bad: error('no'), C: a + 1 }, ['BAD']))"), "[a, C]");
+    assert_eq!(eval("omit({ a: 1, bad: error('no'), c: a + 1 }, ['bad']).c"), i(2));
+    assert_eq!(show("keys(pick({ a: 1, bad: error('no'), c: a + 1 }, ['c', 'a']))"), "[a, c]");
+
+    assert_eq!(eval("getPath({ a: { b: [10, { c: 'x' }] } }, ['a', 'b', 1, 'c'])"), s("x"));
+    assert_eq!(eval("getPath({ a: 1 }, ['a', 'b'])"), Value::Nil);
+    assert_eq!(eval("getPath({ a: [1] }, ['a', 5])"), Value::Nil);
+
+    assert_eq!(eval("setPath({ a: { b: 1, c: 2 }, d: 3 }, ['a', 'b'], 9).a.b"), i(9));
+    assert_eq!(show("keys(setPath({ a: { b: 1, c: 2 }, d: 3 }, ['A', 'B'], 9).a)"), "[b, c]");
+    assert_eq!(eval("{ r: { a: { b: 1 } }; s: setPath(r, ['a', 'b'], 2); return r.a.b }"), i(1));
+    assert_eq!(eval("setPath({}, ['x', 'y'], 5).x.y"), i(5));
+    assert_eq!(eval("setPath({ a: 1 }, [], 2).kind"), s("invalid_value"));
+    assert_eq!(eval("keys(5).kind"), s("type_mismatch"));
+}
+
+#[test]
+fn kvc_library_copies_do_not_evaluate_unread_members() {
+    let lines: Arc<std::sync::Mutex<Vec<String>>> = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let sink = Arc::clone(&lines);
+    let mut vm = VM::with_host(host::HostCallbacks {
+        log_line: Some(Arc::new(move |l| sink.lock().unwrap().push(l.to_string()))),
+        ..Default::default()
+    });
+    let mut run = |src: &str| vm.interpret(src).expect("eval");
+
+    assert_eq!(run("setPath({ a: 1, b: log('evaluated-b') }, ['a'], 5).a"), i(5));
+    assert_eq!(run("setPath({ a: { x: 1 }, b: log('evaluated-b') }, ['a', 'x'], 5).a.x"), i(5));
+    assert_eq!(run("pick({ a: 1, b: log('evaluated-b') }, ['a']).a"), i(1));
+    assert_eq!(run("omit({ a: 1, b: log('evaluated-b') }, ['c']).a"), i(1));
+    assert!(lines.lock().unwrap().is_empty());
+
+    assert_eq!(run("setPath({ a: 1, b: log('evaluated-b') }, ['a'], 5).b"), s("evaluated-b"));
+    assert_eq!(run("pick({ a: 1, b: 2, c: a + b }, ['c']).c"), i(3));
+    assert_eq!(*lines.lock().unwrap(), vec![r#""evaluated-b""#.to_string()]);
+}
+
+#[test]
+fn aggregates_take_selectors_and_stay_lazy_on_ranges() {
+    let orders = "[{ total: 10 }, { total: 25 }, { total: null }, { total: 40 }]";
+    assert_eq!(eval(&format!("Sum({orders}, (o) => o.total)")), i(75));
+    assert_eq!(eval(&format!("Avg({orders}, (o) => o.total)")), Value::Number(25.0));
+    assert_eq!(eval("Sum(Range(1, 3), (x) => x * x)"), i(14));
+    assert_eq!(eval("Avg([1, 2, 3, 4])"), Value::Number(2.5));
+    assert_eq!(eval("Avg([])"), Value::Nil);
+
+    assert_eq!(eval("Median([5, 1, 3])"), i(3));
+    assert_eq!(eval("Median([4, 1, 3, 2])"), Value::Number(2.5));
+    assert_eq!(eval("Percentile([1, 2, 3, 4, 5], 25)"), Value::Number(2.0));
+    assert_eq!(eval("Percentile([10, 20], 50)"), Value::Number(15.0));
+    assert_eq!(eval("Percentile([1], 101).kind"), s("invalid_value"));
+
+    assert_eq!(eval("Variance([2, 4, 4, 4, 5, 5, 7, 9])"), Value::Number(4.0));
+    assert_eq!(eval("StdDev([2, 4, 4, 4, 5, 5, 7, 9])"), Value::Number(2.0));
+    assert_eq!(eval("Variance([1, 2, 3, 4], nil, true)"), eval("Variance([1, 2, 3, 4]) * 4 / 3"));
+    assert_eq!(eval("StdDev([1], nil, true)"), Value::Nil);
+    assert_eq!(eval("Variance([1, 2, 3, 4], true)"), eval("Variance([1, 2, 3, 4], nil, true)"));
+    assert_eq!(eval("StdDev([1, 2, 3, 4], false)"), eval("StdDev([1, 2, 3, 4])"));
+    assert_eq!(eval("StdDev([1], true)"), Value::Nil);
+
+    assert_eq!(eval("Mode([3, 1, 3, 1, 2])"), i(3));
+    assert_eq!(eval(r#"Mode(["b", "a", "a"])"#), s("a"));
+    assert_eq!(eval(r#"Mode([1, "a"]).kind"#), s("type_mismatch"));
+    assert_eq!(eval("Product(Range(1, 25))").to_string(), "15511210043330985984000000");
+    assert_eq!(eval("Product([2, 0.5])"), Value::Number(1.0));
+    assert_eq!(eval("Product([])"), i(1));
+
+    // Ranges are never expanded, so huge ones are exact and cheap.
+    let huge = "Range(1, 4000000000000)";
+    assert_eq!(eval(&format!("Sum({huge})")).to_string(), "8000000000002000000000000");
+    assert_eq!(eval(&format!("Avg({huge})")), Value::Number(2000000000000.5));
+    assert_eq!(eval("Median(Range(1, 4000000000001))"), i(2000000000001));
+    assert_eq!(eval(&format!("Percentile({huge}, 100)")), Value::Number(4000000000000.0));
+    assert_eq!(eval("Variance(Range(1, 8))"), eval("Variance([1, 2, 3, 4, 5, 6, 7, 8])"));
+    assert_eq!(eval("Variance(Range(1, 8), nil, true)"), eval("Variance([1, 2, 3, 4, 5, 6, 7, 8], nil, true)"));
+
+    assert_eq!(eval("Avg(['x']).kind"), s("type_mismatch"));
+    assert_eq!(eval("Avg([1], 5).kind"), s("type_mismatch"));
+    assert_eq!(eval("Median([1], (x) => error('no')).message"), s("no"));
+    assert_eq!(eval("Variance([1, 2], nil, 1).kind"), s("type_mismatch"));
+    // A selector that fails partway through is abandoned, not resumed after the native.
+    assert_eq!(eval("[5, Avg([1, 2], (x) => [not x, 42])]").to_string(), "[5, Error(2001): not: bool expected]");
 }
 
 #[test]
@@ -345,9 +730,38 @@ fn regex_parse_format_and_htmlencode_work() {
     assert_eq!(eval(r#"HEncode("'")"#), s("&#39;"));
 }
 
+#[test]
+fn regex_toolkit_matches_replaces_and_splits() {
+    assert_eq!(
+        eval(r#"regex.match("price: 42 EUR", "(?P<amount>\d+) ([a-z]+)", "i")"#).to_string(),
+        "{ value: 42 EUR, index: 7, groups: [42, EUR], named: { amount: 42 } }"
+    );
+    assert_eq!(eval(r#"regex.match("héllo 42", "\d+").index"#), i(6));
+    assert_eq!(eval(r#"regex.match("abc", "\d")"#), Value::Nil);
+    assert_eq!(eval(r#"regex.match("ab", "a(x)?b").groups"#).to_string(), "[nil]");
+    assert_eq!(eval(r#"regex.matches("a1b22c333", "\d+") map (m) => m.value"#).to_string(), "[1, 22, 333]");
+    assert_eq!(eval(r#"regex.replace("2024-01-05", "(\d+)-(\d+)-(\d+)", "${3}/$2/$1")"#), s("05/01/2024"));
+    assert_eq!(eval(r#"regex.replace("John Smith", "(?P<first>\w+) (?P<last>\w+)", "${last}, ${first}")"#), s("Smith, John"));
+    assert_eq!(eval(r#"regex.replace("a1b2", "\d", (m) => parse(m.value, "l") * 2)"#), s("a2b4"));
+    assert_eq!(eval(r#"regex.replace("AbA", "a", (m) => m.index, "i")"#), s("0b2"));
+    assert_eq!(eval(r#"regex.replace("ab", "b", (m) => error("boom")).message"#), s("boom"));
+    // A replacement that fails partway through is abandoned, not resumed after the native.
+    assert_eq!(
+        eval(r#"[1, regex.replace("aaa", "a", (m) => [not m, 7])]"#).to_string(),
+        "[1, Error(2001): not: bool expected]"
+    );
+    assert_eq!(eval(r#"regex.split("a, b,c", ",\s*")"#).to_string(), "[a, b, c]");
+    assert_eq!(eval(r#"text.regex.split("a1b", "\d")"#).to_string(), "[a, b]");
+    assert_eq!(eval(r#"regex.split("a", "[").kind"#), s("invalid_value"));
+    assert_eq!(eval(r#"regex.match("a", "a", "q").kind"#), s("invalid_value"));
+    assert_eq!(eval(r#"regex.replace("a", "a", 1).kind"#), s("type_mismatch"));
+    assert_eq!(eval(r#"regex.match("a").kind"#), s("parameter_count"));
+}
+
 #[test]
 fn os_functions_work() {
-    let _host = host::push(host::std_fs_callbacks());
+    let mut vm = VM::with_host(host::std_fs_callbacks());
+    let mut eval = |src: String| vm.interpret(&src).expect("eval");
     let base: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
         .join("target")
         .join("fs_test_tmp");
@@ -364,16 +778,663 @@ fn os_functions_work() {
     let file_s = file_path.to_string_lossy().to_string();
     let dir_s = dir_path.to_string_lossy().to_string();
 
-    assert_eq!(eval(&format!("fileexists('{file_s}')")), Value::Bool(true));
-    assert_eq!(eval(&format!("isfile('{file_s}')")), Value::Bool(true));
-    assert_eq!(eval(&format!("file('{file_s}')")), s("hello"));
+    assert_eq!(eval(format!("fileexists('{file_s}')")), Value::Bool(true));
+    assert_eq!(eval(format!("isfile('{file_s}')")), Value::Bool(true));
+    assert_eq!(eval(format!("file('{file_s}')")), s("hello"));
 
-    assert_eq!(eval(&format!("fileexists('{dir_s}')")), Value::Bool(true));
-    assert_eq!(eval(&format!("isfile('{dir_s}')")), Value::Bool(false));
+    assert_eq!(eval(format!("fileexists('{dir_s}')")), Value::Bool(true));
+    assert_eq!(eval(format!("isfile('{dir_s}')")), Value::Bool(false));
 
-    assert_eq!(eval(&format!("Len(dirlist('{base_s}'))")), i(2));
-    assert_eq!(eval(&format!("Contains(dirlist('{base_s}'), '{file_s}')")), Value::Bool(true));
-    assert_eq!(eval(&format!("Contains(dirlist('{base_s}'), '{dir_s}')")), Value::Bool(true));
+    assert_eq!(eval(format!("Len(dirlist('{base_s}'))")), i(2));
+    assert_eq!(eval(format!("Contains(dirlist('{base_s}'), '{file_s}')")), Value::Bool(true));
+    assert_eq!(eval(format!("Contains(dirlist('{base_s}'), '{dir_s}')")), Value::Bool(true));
+
+    // Callbacks belong to the VM: another VM on the same thread has no filesystem.
+    assert_eq!(VM::new().interpret(&format!("file('{file_s}').kind")).unwrap(), s("host_unavailable"));
 }
 
 
+
+#[test]
+fn compiled_programs_are_shared_across_threads() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Value>();
+    assert_send_sync::<funcscript::obj::FsFunction>();
+    fn assert_send<T: Send>() {}
+    assert_send::<VM>();
+
+    let program = VM::compile("{ xs: Range(1, 10) map (x)=>x*x; eval Sum(xs) }").expect("compile");
+    let results: Vec<Value> = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let program = Arc::clone(&program);
+                scope.spawn(move || VM::new().execute(&program).expect("execute"))
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+    assert_eq!(results, vec![i(385); 4]);
+}
+
+#[test]
+fn vm_and_result_values_can_move_between_threads() {
+    let mut vm = VM::new();
+    assert_eq!(vm.interpret("1+1").unwrap(), i(2));
+    let (vm, value) = std::thread::spawn(move || {
+        let v = vm.interpret("{a:{b:[1,2]}}").unwrap();
+        (vm, v)
+    })
+    .join()
+    .unwrap();
+    let mut vm = vm;
+    let a = vm.value_get_prop(&value, "a");
+    assert_eq!(vm.value_to_json_string(&a), r#"{"b":[1,2]}"#);
+}
+
+fn eval_parallel(source: &str) -> Result<Value, funcscript::vm::InterpretResult> {
+    let mut vm = VM::new();
+    vm.set_parallel(Some(ParallelConfig { workers: 4, min_items: 16 }));
+    vm.interpret(source)
+}
+
+#[test]
+fn parallel_map_filter_and_sum_match_sequential() {
+    let cases = [
+        "Range(1, 1000) map (x, i) => x * i",
+        "Range(1, 1000) filter (x) => x % 3 = 0",
+        "{ k: 7; xs: Range(0, 500) map (x) => x + k; eval xs filter (x) => x % 2 = 0 }",
+        "{ rows: Range(0, 200) map (x) => { id: x; score: x * 2 }; eval rows map (r) => r.score + r.id }",
+        "Sum(Range(1, 5000) map (x) => x * x)",
+        "Sum(Range(1, 100) map (x) => x / 3)",
+        "Sum([1, 2, 'x'] + (Range(1, 100) map (x) => x))",
+    ];
+    for src in cases {
+        let seq = VM::new().interpret(src).expect("sequential");
+        let par = eval_parallel(src).expect("parallel");
+        let mut vm = VM::new();
+        assert_eq!(vm.value_to_json_string(&par), vm.value_to_json_string(&seq), "{src}");
+    }
+}
+
+#[test]
+fn parallel_filter_reports_first_error_by_index() {
+    let src = "Range(0, 1000) filter (x) => If(x = 700, error('late'), If(x = 300, error('early'), true))";
+    match eval_parallel(src) {
+        Err(funcscript::vm::InterpretResult::RuntimeError(e)) => assert_eq!(e.message, "early"),
+        other => panic!("expected runtime error, got {other:?}"),
+    }
+}
+
+#[test]
+fn parallel_mode_keeps_host_side_effects_sequential() {
+    let lines: Arc<std::sync::Mutex<Vec<String>>> = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let sink = Arc::clone(&lines);
+    let mut vm = VM::with_host(host::HostCallbacks {
+        log_line: Some(Arc::new(move |l| sink.lock().unwrap().push(l.to_string()))),
+        ..Default::default()
+    });
+    vm.set_parallel(Some(ParallelConfig { workers: 4, min_items: 16 }));
+
+    let v = vm.interpret("{ f: (x) => log(x); eval Range(0, 100) map (x) => f(x) * 2 }").expect("eval");
+    assert_eq!(VM::new().value_len(&v), i(100));
+    let expected: Vec<String> = (0..100).map(|n| n.to_string()).collect();
+    assert_eq!(*lines.lock().unwrap(), expected);
+}
+
+#[test]
+fn parallel_mode_finds_host_natives_behind_nested_bindings() {
+    let threads: Arc<std::sync::Mutex<Vec<std::thread::ThreadId>>> = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let sink = Arc::clone(&threads);
+    let mut vm = VM::with_host(host::HostCallbacks {
+        log_line: Some(Arc::new(move |_| sink.lock().unwrap().push(std::thread::current().id()))),
+        ..Default::default()
+    });
+    vm.set_parallel(Some(ParallelConfig { workers: 4, min_items: 16 }));
+
+    // `log` is two KVC bindings away from the lambda handed to `map`.
+    let v = vm.interpret("{ b: (x) => log(x); a: (x) => b(x); eval Len(Range(1, 3000) map (x) => a(x)) }").expect("eval");
+    assert_eq!(v, i(3000));
+    let threads = threads.lock().unwrap();
+    assert_eq!(threads.len(), 3000);
+    assert!(threads.iter().all(|t| *t == std::thread::current().id()));
+}
+
+#[test]
+fn cycle_collection_frees_nested_kvcs_between_evaluations() {
+    let mut vm = VM::new();
+    for n in 0..50 {
+        let v = vm.interpret(&format!("{{ a: {{ b: {n}; c: b + 1 }}; d: a.c }}")).unwrap();
+        assert_eq!(vm.value_to_json_string(&v), format!(r#"{{"a":{{"b":{n},"c":{m}}},"d":{m}}}"#, m = n + 1));
+    }
+    assert!(vm.memory_stats().live_kvcs >= 50);
+
+    let freed = vm.collect_cycles();
+    let stats = vm.memory_stats();
+    assert!(freed >= 100);
+    assert_eq!(stats.live_kvcs, 0);
+    assert_eq!(stats.collections, 1);
+    assert_eq!(stats.collected_kvcs, freed as u64);
+
+    // Nothing is collected on its own unless the host opts in.
+    for _ in 0..3000 {
+        vm.interpret("{ a: { b: 1 }; c: a.b }").unwrap();
+    }
+    assert_eq!(vm.memory_stats().collections, 1);
+    vm.collect_cycles();
+
+    // Long-lived VMs that opt in collect on their own once enough KVCs pile up.
+    vm.set_auto_collect(true);
+    for _ in 0..3000 {
+        vm.interpret("{ a: { b: 1 }; c: a.b }").unwrap();
+    }
+    let stats = vm.memory_stats();
+    assert!(stats.collections > 2);
+    assert!(stats.live_kvcs < 3000);
+}
+
+#[test]
+fn cycle_collection_keeps_values_held_by_the_host() {
+    let mut vm = VM::new();
+    let kept = vm.interpret("{ a: { b: 2; c: b * 10 }; d: a.b }").unwrap();
+    let stored = vm.interpret("{ x: { y: 3 }; z: x.y }").unwrap();
+    let handle = vm.store_value(stored);
+    let _ = vm.interpret("{ p: { q: 1 }; r: p.q }").unwrap();
+
+    vm.collect_cycles();
+    assert!(vm.memory_stats().live_kvcs > 0);
+    assert_eq!(vm.memory_stats().value_handles, 1);
+
+    let a = vm.value_get_prop(&kept, "a");
+    assert_eq!(vm.value_get_prop(&a, "c"), i(20));
+    let held = vm.clone_value(handle).unwrap();
+    assert_eq!(vm.value_to_json_string(&held), r#"{"x":{"y":3},"z":3}"#);
+
+    drop((kept, a, held));
+    vm.free_value(handle);
+    vm.collect_cycles();
+    assert_eq!(vm.memory_stats().live_kvcs, 0);
+}
+
+#[test]
+fn cycle_collection_can_wait_for_values_used_on_other_threads() {
+    let mut vm = VM::new();
+    let root = vm.interpret("{ a: { b: { c: 7 }; d: b.c }; e: a.d }").unwrap();
+    let a = vm.value_get_prop(&root, "a");
+    drop(root);
+
+    // The worker moves its reference between `a` and `b` (found from `b` through its
+    // parent scope) while the VM keeps evaluating without collecting.
+    std::thread::scope(|scope| {
+        let worker = scope.spawn(|| {
+            let mut reader = VM::new();
+            let mut held = a;
+            for _ in 0..2_000 {
+                let b = reader.value_get_prop(&held, "b");
+                drop(held);
+                assert_eq!(reader.value_get_prop(&b, "c"), i(7));
+                held = reader.value_get_prop(&b, "a");
+            }
+            held
+        });
+        for _ in 0..3000 {
+            vm.interpret("{ a: { b: 1 }; c: a.b }").unwrap();
+        }
+        assert_eq!(vm.memory_stats().collections, 0);
+
+        let held = worker.join().unwrap();
+        vm.collect_cycles();
+        assert_eq!(vm.value_to_json_string(&held), r#"{"b":{"c":7},"d":7}"#);
+    });
+    vm.collect_cycles();
+    assert_eq!(vm.memory_stats().live_kvcs, 0);
+}
+
+#[test]
+fn values_are_compact_and_strings_keep_semantics() {
+    assert_eq!(std::mem::size_of::<Value>(), 16);
+
+    let short = funcscript::small_string::SmallString::from("key");
+    let long = funcscript::small_string::SmallString::from("a string that is too long to inline");
+    assert!(short.is_inline());
+    assert!(!long.is_inline());
+    assert_eq!(short, "key");
+    assert_eq!(long.as_str(), "a string that is too long to inline");
+
+    assert_eq!(eval("'ab' + 'cd'"), s("abcd"));
+    assert_eq!(eval("Len('a string that is too long to inline' + '!')"), i(36));
+    assert_eq!(eval("'short' = 'sh' + 'ort'"), Value::Bool(true));
+    assert_eq!(eval("{ key: 1 }.KEY"), i(1));
+    assert_eq!(eval("9223372036854775807 + 1").to_string(), "9223372036854775808");
+    assert_eq!(eval("error('boom')").to_string(), "Error(3000): boom");
+}
+
+#[test]
+fn bytecode_fuses_hot_sequences_without_changing_results() {
+    use funcscript::chunk::{BinaryOp, OpCode};
+
+    let program = VM::compile("(x, y) => x * y + x - 1").expect("compile");
+    let lambda = program
+        .chunk
+        .constants
+        .iter()
+        .find_map(|c| match c {
+            Value::Obj(o) => match &**o {
+                Obj::Function(f) => Some(Arc::clone(f)),
+                _ => None,
+            },
+            _ => None,
+        })
+        .expect("lambda constant");
+    let ops: Vec<OpCode> = lambda.chunk.ops().collect();
+    assert!(ops.contains(&OpCode::OpGetLocal2(1, 2)), "{ops:?}");
+
+    let add_one = VM::compile("(x) => x + 1").expect("compile");
+    let ops: Vec<OpCode> = add_one
+        .chunk
+        .constants
+        .iter()
+        .find_map(|c| match c {
+            Value::Obj(o) => match &**o {
+                Obj::Function(f) => Some(f.chunk.ops().collect()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .expect("lambda constant");
+    assert!(matches!(ops[0], OpCode::OpLocalConstBinary(1, _, BinaryOp::Add)), "{ops:?}");
+
+    assert_eq!(eval("((x, y) => x * y + x - 1)(3, 4)"), i(14));
+    assert_eq!(eval("((x) => x + 1)(41)"), i(42));
+    assert_eq!(eval("((x) => x = 2)(2)"), Value::Bool(true));
+    assert_eq!(eval("((x) => x < 2)(5)"), Value::Bool(false));
+    assert_eq!(eval("((x) => x % 4)(10)"), i(2));
+    assert_eq!(eval("((x) => x / 2)(9)"), Value::Number(4.5));
+    assert_eq!(eval("p: { q: 7 }; eval p.q"), i(7));
+    // Jump targets must not be fused into the instruction before them.
+    assert_eq!(eval("((x, y) => If(x > y, x, y) + 1)(3, 8)"), i(9));
+    assert_eq!(eval("((x, y) => (x ?? y) - 1)(nil, 8)"), i(7));
+    assert_eq!(eval("((x, y) => if x then y else 0)(false, 5)"), i(0));
+}
+
+#[test]
+fn errors_have_kinds_payloads_and_causes() {
+    use funcscript::value::ErrorKind;
+
+    let e = match eval("error('outer', 'Validation', { field: 'x' }, error('inner'))") {
+        Value::Error(e) => e,
+        other => panic!("expected error, got {other}"),
+    };
+    assert_eq!(e.kind, ErrorKind::User);
+    assert_eq!(e.message, "outer");
+    assert_eq!(e.user_type.as_deref(), Some("Validation"));
+    assert_eq!(e.chain().map(|c| c.message.as_str()).collect::<Vec<_>>(), vec!["outer", "inner"]);
+
+    assert_eq!(eval("x: error('boom', 'Validation', 42); eval x.kind"), s("user"));
+    assert_eq!(eval("x: error('boom', 'Validation', 42); eval x.type"), s("Validation"));
+    assert_eq!(eval("x: error('boom', 'Validation', 42); eval x.data"), i(42));
+    assert_eq!(eval("x: error('boom'); eval x.code"), i(3000));
+    assert_eq!(eval("x: error('a', nil, nil, error('b')); eval x.cause.message"), s("b"));
+    assert!(matches!(eval("x: error('boom'); eval x.missing"), Value::Error(_)));
+
+    let mut vm = VM::new();
+    let json = vm.eval_result_json("error('boom', 'T', [1])");
+    assert!(json.contains("\"code\":3000,\"errorKind\":\"user\",\"message\":\"boom\",\"type\":\"T\",\"data\":[1],\"cause\":null"), "{json}");
+
+    match VM::new().interpret("1 / 0") {
+        Err(funcscript::vm::InterpretResult::RuntimeError(e)) => assert_eq!(e.kind, ErrorKind::DivisionByZero),
+        other => panic!("expected division error, got {other:?}"),
+    }
+    match VM::new().interpret("Take(1)") {
+        Ok(Value::Error(e)) => assert_eq!((e.kind, e.code()), (ErrorKind::ParameterCount, 2002)),
+        other => panic!("expected parameter count error, got {other:?}"),
+    }
+    assert_eq!(ErrorKind::from_code(2001), Some(ErrorKind::TypeMismatch));
+}
+
+#[test]
+fn try_recovers_from_error_values_and_runtime_errors() {
+    assert_eq!(eval("try(1 / 0, (e) => e.kind)"), s("division_by_zero"));
+    assert_eq!(eval("try(Date('not a date'), (e) => 'fallback')"), s("fallback"));
+    assert_eq!(eval("try(error('boom', 'Parse'), (e) => e.type + ':' + e.message)"), s("Parse:boom"));
+    assert_eq!(eval("try(error('boom').code, (e) => 'unreachable')"), i(3000));
+    assert_eq!(eval("try(1 + 2, (e) => 0)"), i(3));
+    assert_eq!(eval("try(1 / 0, -1)"), i(-1));
+    assert_eq!(eval("try(1 / 0)"), Value::Nil);
+    assert_eq!(eval("try(1 / 0, () => 7)"), i(7));
+    // Runtime errors raised while a member is evaluated are caught too.
+    assert_eq!(eval("try({ a: not 5 }.a, -1)"), i(-1));
+    assert_eq!(eval("x: not 5; eval try(x, -1)"), i(-1));
+
+    // Errors raised deep inside lambdas and list operations unwind to the guard.
+    assert_eq!(eval("f: (x) => 10 / x; eval try([1, 2, 0] map (x) => f(x), (e) => e.message)"), s("Division by zero"));
+    assert_eq!(eval("[2, 0, 5] map (x) => try(10 / x, 0)"), Value::Obj(Arc::new(Obj::List(vec![i(5), i(0), i(2)]))));
+    assert_eq!(eval("try(try(1 / 0, (e) => error('wrapped', nil, nil, e)), (e) => e.cause.kind)"), s("division_by_zero"));
+    // The handler itself is not guarded by its own try.
+    assert!(matches!(VM::new().interpret("try(1 / 0, (e) => 1 / 0)"), Err(funcscript::vm::InterpretResult::RuntimeError(_))));
+
+    assert_eq!(eval("iserror(error('x'))"), Value::Bool(true));
+    assert_eq!(eval("iserror(5)"), Value::Bool(false));
+    assert_eq!(eval("x: 5; eval iserror(try(x / 0, (e) => e))"), Value::Bool(true));
+
+    // A binding named `try` is called like any other function.
+    assert_eq!(eval("{ try: (x) => 3; r: try(1) }.r"), i(3));
+    assert_eq!(eval("{ r: try(1, 2); try: (x, y) => x + y }.r"), i(3));
+    assert_eq!(eval("{ f: (try) => try(2); r: f((x) => x * 10) }.r"), i(20));
+    assert_eq!(eval("{ try: (x) => x; r: (() => try(4))() }.r"), i(4));
+}
+
+#[test]
+fn seeded_vm_and_host_clock_make_runs_reproducible() {
+    let run = |seed: u64| {
+        let mut vm = VM::new();
+        vm.set_random_seed(Some(seed));
+        let v = vm.interpret("[Random(), Random(), guid()]").unwrap();
+        vm.value_to_json_string(&v)
+    };
+    assert_eq!(run(7), run(7));
+    assert_ne!(run(7), run(8));
+    // Seeded values stay in range and produce v4 GUIDs; Random(seed) is unaffected.
+    let v = eval("x: Random(); eval x >= 0 and x < 1");
+    assert_eq!(v, Value::Bool(true));
+    assert_eq!(eval("Random(3) = Random(3)"), Value::Bool(true));
+    let mut vm = VM::new();
+    vm.set_random_seed(Some(1));
+    match vm.interpret("guid()").unwrap() {
+        Value::Obj(o) => match &*o {
+            Obj::Guid(g) => assert_eq!(g.get_version_num(), 4),
+            other => panic!("expected guid, got {other:?}"),
+        },
+        other => panic!("expected guid, got {other:?}"),
+    }
+
+    let mut vm = VM::with_host(host::HostCallbacks {
+        now: Some(Arc::new(|| host::HostTime { utc_ticks: 638_000_000_000_000_000 + 3_600 * 10_000_000, offset_minutes: -120 })),
+        ..Default::default()
+    });
+    let mut ticks = |src: &str| match vm.interpret(src).expect("eval") {
+        Value::Obj(o) => match &*o {
+            Obj::DateTimeTicks(t, _) => *t,
+            other => panic!("expected datetime, got {other:?}"),
+        },
+        other => panic!("expected datetime, got {other:?}"),
+    };
+    let utc = 638_000_000_000_000_000 + 3_600 * 10_000_000;
+    let local = utc - 120 * 600_000_000;
+    assert_eq!(ticks("utcnow()"), utc);
+    assert_eq!(ticks("now()"), local);
+    assert_eq!(ticks("today()"), local - local.rem_euclid(864_000_000_000));
+    assert_eq!(vm.interpret("now().offset.totalMinutes").unwrap(), Value::Number(-120.0));
+    assert_eq!(vm.interpret("now() = utcnow()").unwrap(), Value::Bool(true));
+
+    let mut vm = VM::with_host(host::HostCallbacks {
+        now: Some(Arc::new(|| host::HostTime { utc_ticks: 638_000_000_000_000_000, offset_minutes: i16::MIN as i32 })),
+        ..Default::default()
+    });
+    assert_eq!(vm.interpret("now().offset").unwrap(), Value::Nil);
+}
+
+#[test]
+fn host_callbacks_can_reenter_another_vm() {
+    let inner = Arc::new(std::sync::Mutex::new(VM::with_host(host::HostCallbacks {
+        file_read_text: Some(Arc::new(|path| Ok(format!("inner:{path}")))),
+        ..Default::default()
+    })));
+    let from_callback = Arc::clone(&inner);
+    let mut outer = VM::with_host(host::HostCallbacks {
+        file_read_text: Some(Arc::new(move |path| {
+            let v = from_callback.lock().unwrap().interpret(&format!("'outer:' + file('{path}')")).expect("inner eval");
+            Ok(v.to_string())
+        })),
+        ..Default::default()
+    });
+    assert_eq!(outer.interpret("file('a') + '|' + file('b')").unwrap(), s("outer:inner:a|outer:inner:b"));
+    assert_eq!(inner.lock().unwrap().interpret("file('c')").unwrap(), s("inner:c"));
+}
+
+#[test]
+fn fs_sandbox_confines_reads_to_allowed_roots() {
+    let base: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target").join("fs_sandbox_tmp");
+    let _ = std::fs::remove_dir_all(&base);
+    let inside = base.join("inside");
+    std::fs::create_dir_all(&inside).unwrap();
+    std::fs::write(inside.join("a.txt"), "hello").unwrap();
+    std::fs::write(inside.join("big.txt"), "x".repeat(64)).unwrap();
+    std::fs::write(base.join("secret.txt"), "secret").unwrap();
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(base.join("secret.txt"), inside.join("link.txt")).unwrap();
+
+    let sandbox = host::FsSandbox { max_file_bytes: 32, ..host::FsSandbox::new().allow_read(&inside) };
+    let mut vm = VM::with_host(host::sandboxed_fs_callbacks(&sandbox));
+    let mut eval = |src: String| vm.interpret(&src).expect("eval");
+    let (inside_s, base_s) = (inside.to_string_lossy().to_string(), base.to_string_lossy().to_string());
+
+    assert_eq!(eval(format!("file('{inside_s}/a.txt')")), s("hello"));
+    assert_eq!(eval(format!("fileexists('{inside_s}/missing.txt')")), Value::Bool(false));
+    assert_eq!(eval(format!("file('{inside_s}/big.txt').kind")), s("out_of_range"));
+    assert_eq!(eval(format!("file('{base_s}/secret.txt').code")), i(2602));
+    assert_eq!(eval(format!("file('{inside_s}/../secret.txt').kind")), s("access_denied"));
+    assert_eq!(eval(format!("fileexists('{base_s}/missing.txt').kind")), s("access_denied"));
+    assert_eq!(eval(format!("dirlist('{base_s}').kind")), s("access_denied"));
+    #[cfg(unix)]
+    assert_eq!(eval(format!("file('{inside_s}/link.txt').kind")), s("access_denied"));
+
+    let mut locked = VM::with_host(host::sandboxed_fs_callbacks(&host::FsSandbox::new()));
+    assert_eq!(locked.interpret(&format!("isfile('{inside_s}/a.txt').kind")).unwrap(), s("access_denied"));
+}
+
+#[test]
+fn file_bytes_walk_and_metadata() {
+    let base: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target").join("fs_walk_tmp");
+    let _ = std::fs::remove_dir_all(&base);
+    std::fs::create_dir_all(base.join("src/nested")).unwrap();
+    std::fs::write(base.join("main.fs"), [0u8, 159, 146, 150]).unwrap();
+    std::fs::write(base.join("src/a.fs"), "a").unwrap();
+    std::fs::write(base.join("src/nested/b.fs"), "b").unwrap();
+    std::fs::write(base.join("src/notes.txt"), "n").unwrap();
+
+    let mut vm = VM::with_host(host::sandboxed_fs_callbacks(&host::FsSandbox::new().allow_read(&base)));
+    let base_s = base.to_string_lossy().to_string();
+    let mut json = |src: String| {
+        let v = vm.interpret(&src).expect("eval");
+        vm.value_to_json_string(&v)
+    };
+
+    assert_eq!(json(format!("Len(fileBytes('{base_s}/main.fs'))")), "4");
+    let rel = |paths: &[&str]| {
+        let full: Vec<String> = paths.iter().map(|p| format!("\"{}\"", base.join(p).to_string_lossy())).collect();
+        format!("[{}]", full.join(","))
+    };
+    assert_eq!(json(format!("walk('{base_s}', '**/*.fs')")), rel(&["main.fs", "src/a.fs", "src/nested/b.fs"]));
+    assert_eq!(json(format!("walk('{base_s}', 'src/*.fs')")), rel(&["src/a.fs"]));
+    assert_eq!(json(format!("Len(walk('{base_s}'))")), "6");
+    assert_eq!(json(format!("{{ m: metadata('{base_s}/src/a.fs'); eval [m.size, m.isDirectory] }}")), "[1,false]");
+    assert_eq!(json(format!("metadata('{base_s}/src').isDirectory")), "true");
+    assert_eq!(json(format!("metadata('{base_s}/missing')")), "null");
+
+    // Hosts can serve the same built-ins from a virtual file system.
+    let mut virt = VM::with_host(host::HostCallbacks {
+        file_read_bytes: Some(Arc::new(|path| Ok(path.as_bytes().to_vec()))),
+        dir_walk: Some(Arc::new(|_| Ok(vec!["a.fs".into(), "lib".into(), "lib/b.fs".into(), "lib/c.txt".into()]))),
+        file_metadata: Some(Arc::new(|path| {
+            Ok((path == "/v/a.fs").then_some(host::FileMetadata { size: 3, modified_ticks: 638_000_000_000_000_000, is_dir: false }))
+        })),
+        ..Default::default()
+    });
+    assert_eq!(virt.interpret("Len(fileBytes('/v/a.fs'))").unwrap(), i(7));
+    assert_eq!(virt.interpret("Len(walk('/v', '**/*.fs'))").unwrap(), i(2));
+    assert_eq!(
+        virt.interpret("metadata('/v/a.fs').modified").unwrap(),
+        Value::Obj(Arc::new(Obj::DateTimeTicks(638_000_000_000_000_000, None)))
+    );
+    assert_eq!(virt.interpret("metadata('/v/b.fs')").unwrap(), Value::Nil);
+
+    let long_name = format!("{}c", "a".repeat(60));
+    let names = vec![long_name.clone(), "lib/x/y.fs".into(), "lib/b.fs".into(), "b.fs".into(), "lib/c.txt".into()];
+    let mut globs = VM::with_host(host::HostCallbacks { dir_walk: Some(Arc::new(move |_| Ok(names.clone()))), ..Default::default() });
+    let mut matched = |pattern: &str| {
+        let v = globs.interpret(&format!("walk('', '{pattern}') map (p) => p")).unwrap();
+        globs.value_to_json_string(&v)
+    };
+    assert_eq!(matched("**/b.fs"), r#"["lib/b.fs","b.fs"]"#);
+    assert_eq!(matched("lib/**"), r#"["lib/x/y.fs","lib/b.fs","lib/c.txt"]"#);
+    assert_eq!(matched("lib/**/*.fs"), r#"["lib/x/y.fs","lib/b.fs"]"#);
+    assert_eq!(matched("l*/?.*"), r#"["lib/b.fs","lib/c.txt"]"#);
+    assert_eq!(matched("*.fs"), r#"["b.fs"]"#);
+    assert_eq!(matched("**"), matched("**/*"));
+    // Patterns that made the recursive matcher backtrack exponentially.
+    assert_eq!(matched("*a*a*a*a*a*a*a*a*a*a*b"), "[]");
+    assert_eq!(matched("**a**a**a**a**a**a**a**a**b"), "[]");
+    assert_eq!(matched("*a*a*a*a*a*a*a*a*a*a*c"), format!(r#"["{long_name}"]"#));
+
+    // A trailing `**/` also matches no directories at all.
+    let mut edges = VM::with_host(host::HostCallbacks { dir_walk: Some(Arc::new(|_| Ok(vec!["".into(), "a".into(), "b/c".into()]))), ..Default::default() });
+    let mut matched = |pattern: &str| {
+        let v = edges.interpret(&format!("walk('', '{pattern}') map (p) => p")).unwrap();
+        edges.value_to_json_string(&v)
+    };
+    assert_eq!(matched("**/"), r#"[""]"#);
+    assert_eq!(matched("a**/*"), r#"["a"]"#);
+    assert_eq!(matched("b**/*"), r#"["b/c"]"#);
+    assert_eq!(matched("*/"), "[]");
+}
+
+#[test]
+fn writefile_requires_an_allowed_write_root() {
+    let base: PathBuf = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target").join("fs_write_tmp");
+    let _ = std::fs::remove_dir_all(&base);
+    let out = base.join("out");
+    std::fs::create_dir_all(&out).unwrap();
+    let (base_s, out_s) = (base.to_string_lossy().to_string(), out.to_string_lossy().to_string());
+
+    assert_eq!(VM::new().interpret(&format!("writefile('{out_s}/a.txt', 'x').kind")).unwrap(), s("host_unavailable"));
+    let mut read_only = VM::with_host(host::std_fs_callbacks());
+    assert_eq!(read_only.interpret(&format!("writefile('{out_s}/a.txt', 'x').kind")).unwrap(), s("host_unavailable"));
+
+    let sandbox = host::FsSandbox::new().allow_read(&base).allow_write(&out);
+    let mut vm = VM::with_host(host::sandboxed_fs_callbacks(&sandbox));
+    let written = vm.interpret(&format!("['a', 'b'] map (n) => writefile('{out_s}/gen/' + n + '.txt', 'file ' + n)")).unwrap();
+    assert_eq!(vm.value_len(&written), i(2));
+    assert_eq!(std::fs::read_to_string(out.join("gen/b.txt")).unwrap(), "file b");
+    vm.interpret(&format!("writefile('{out_s}/raw.bin', fileBytes('{out_s}/gen/a.txt'))")).unwrap();
+    assert_eq!(std::fs::read(out.join("raw.bin")).unwrap(), b"file a");
+
+    assert_eq!(vm.interpret(&format!("writefile('{base_s}/escape.txt', 'x').kind")).unwrap(), s("access_denied"));
+    assert_eq!(vm.interpret(&format!("writefile('{out_s}/../escape.txt', 'x').code")).unwrap(), i(2602));
+    assert!(!base.join("escape.txt").exists());
+}
+
+#[test]
+fn env_reads_host_variables_with_defaults() {
+    assert_eq!(eval("env('REGION')"), Value::Nil);
+    assert_eq!(eval("env('REGION', 'us-east')"), s("us-east"));
+
+    let vars = std::collections::HashMap::from([("REGION".to_string(), "eu-west".to_string())]);
+    let mut vm = VM::with_host(host::HostCallbacks { env_var: Some(host::env_from_map(vars)), ..Default::default() });
+    assert_eq!(vm.interpret("env('REGION', 'us-east')").unwrap(), s("eu-west"));
+    assert_eq!(vm.interpret("env('STAGE', 3)").unwrap(), i(3));
+    assert_eq!(vm.interpret("env(1).kind").unwrap(), s("type_mismatch"));
+
+    let mut vm = VM::with_host(host::HostCallbacks { env_var: Some(host::process_env(vec!["CARGO_PKG_*".into()])), ..Default::default() });
+    assert_eq!(vm.interpret("env('CARGO_PKG_NAME')").unwrap(), s(env!("CARGO_PKG_NAME")));
+    assert_eq!(vm.interpret("env('PATH')").unwrap(), Value::Nil);
+}
+
+#[test]
+fn recorded_host_calls_replay_without_the_host() {
+    use funcscript::value::{ErrorKind, FsError};
+    use std::sync::Mutex;
+    let src = "[file('a.txt'), fileexists('b.txt'), env('REGION'), guid(), now(), metadata('a.txt').size, file('secret').kind]";
+    let host_cb = host::HostCallbacks {
+        file_read_text: Some(Arc::new(|path: &str| match path {
+            "a.txt" => Ok("hello".to_string()),
+            _ => Err(FsError::new(ErrorKind::AccessDenied, "file: denied")),
+        })),
+        file_exists: Some(Arc::new(|_: &str| Ok(true))),
+        file_metadata: Some(Arc::new(|_: &str| Ok(Some(host::FileMetadata { size: 5, modified_ticks: 0, is_dir: false })))),
+        env_var: Some(host::env_from_map(std::collections::HashMap::from([("REGION".to_string(), "eu".to_string())]))),
+        ..Default::default()
+    };
+    let recording = Arc::new(Mutex::new(host::HostRecording::default()));
+    let mut vm = VM::with_host(host::recording_callbacks(host_cb, Arc::clone(&recording)));
+    let recorded = vm.interpret(src).unwrap().to_string();
+    assert!(recorded.starts_with("[hello, true, eu, "), "{recorded}");
+    assert!(recorded.ends_with(", 5, access_denied]"), "{recorded}");
+
+    let json = recording.lock().unwrap().to_json();
+    let loaded = host::HostRecording::from_json(&json).unwrap();
+    assert_eq!(loaded, *recording.lock().unwrap());
+    assert_eq!(
+        loaded.callbacks().collect::<Vec<_>>(),
+        ["file_read_text", "file_exists", "env_var", "random", "random", "now", "file_metadata", "file_read_text"]
+    );
+
+    let mut vm = VM::with_host(host::replay_callbacks(&loaded));
+    assert_eq!(vm.interpret(src).unwrap().to_string(), recorded);
+    let mut vm = VM::with_host(host::replay_callbacks(&loaded));
+    assert_eq!(vm.interpret("file('c.txt').kind").unwrap(), s("host_unavailable"));
+    assert!(host::HostRecording::from_json("{\"version\":2,\"calls\":[]}").is_err());
+}
+
+#[test]
+#[should_panic(expected = "replay exhausted: replay: no recorded call now()")]
+fn replayed_clock_reads_need_a_recorded_value() {
+    let mut vm = VM::with_host(host::replay_callbacks(&host::HostRecording::default()));
+    let _ = vm.interpret("now()");
+}
+
+#[test]
+#[should_panic(expected = "replay exhausted: replay: malformed result for random")]
+fn replayed_random_reads_need_a_well_formed_value() {
+    let recording = host::HostRecording::from_json(r#"{"version":1,"calls":[{"callback":"random","args":[],"result":true}]}"#).unwrap();
+    let mut vm = VM::with_host(host::replay_callbacks(&recording));
+    let _ = vm.interpret("guid()");
+}
+
+#[test]
+fn fetch_goes_through_the_host_callback() {
+    use funcscript::value::{ErrorKind, FsError};
+    assert_eq!(eval("fetch('http://example.test/').kind"), s("host_unavailable"));
+
+    let stub: Arc<host::FetchFn> = Arc::new(|req: &host::HttpRequest| {
+        if req.url.ends_with("/down") {
+            return Err(FsError::new(ErrorKind::HostIo, "fetch: connection refused"));
+        }
+        let headers: Vec<String> = req.headers.iter().map(|(n, v)| format!("{n}={v}")).collect();
+        let body = format!(
+            r#"{{"method":"{}","headers":"{}","body":"{}","n":[1,2.5,-3e2],"ok":true,"none":null,"esc":"a\"é"}}"#,
+            req.method,
+            headers.join(";"),
+            String::from_utf8_lossy(&req.body).replace('"', "'"),
+        );
+        Ok(host::HttpResponse { status: 201, headers: vec![("Content-Type".into(), "application/json".into())], body: body.into_bytes() })
+    });
+    let mut vm = VM::with_host(host::HostCallbacks { fetch: Some(stub), ..Default::default() });
+
+    assert_eq!(vm.interpret("fetch('http://example.test/').status").unwrap(), i(201));
+    assert_eq!(vm.interpret("fetch('http://example.test/').ok").unwrap(), Value::Bool(true));
+    assert_eq!(vm.interpret("fetch('http://example.test/').headers['content-type']").unwrap(), s("application/json"));
+    assert_eq!(vm.interpret("fetch('http://example.test/', {json:true}).json.method").unwrap(), s("GET"));
+    assert_eq!(
+        vm.interpret("r: fetch('http://example.test/', {method:'post', headers:{Accept:'text/plain'}, body:{q:1+1}, json:true}).json; eval [r.method, r.headers, r.body]").unwrap().to_string(),
+        "[POST, Accept=text/plain;Content-Type=application/json, {'q':2}]"
+    );
+    assert_eq!(vm.interpret("fetch('http://example.test/', {json:true}).json.n").unwrap().to_string(), "[1, 2.5, -300]");
+    assert_eq!(vm.interpret("fetch('http://example.test/', {json:true}).json.esc").unwrap(), s("a\"é"));
+    assert_eq!(vm.interpret("fetch('http://example.test/', {json:true}).json.none").unwrap(), Value::Nil);
+    assert!(vm.interpret("fetch('http://example.test/', {body:'raw'}).body").unwrap().to_string().contains(r#""body":"raw""#));
+    assert_eq!(vm.interpret("fetch('http://example.test/down').kind").unwrap(), s("host_io"));
+    assert_eq!(vm.interpret("fetch('http://example.test/', {headers:1}).kind").unwrap(), s("type_mismatch"));
+
+    // Nothing a script passes may add lines to the request head.
+    for options in [
+        "{method:'GET / HTTP/1.1\r\nX-Evil: 1\r\n\r\nGET'}",
+        "{method:''}",
+        "{headers:{Accept:'text/plain\r\nX-Evil: 1'}}",
+        "{headers:{'X-Evil: 1\r\nAccept':'text/plain'}}",
+        "{headers:{'Bad Name':'x'}}",
+    ] {
+        assert_eq!(vm.interpret(&format!("fetch('http://example.test/', {options}).kind")).unwrap(), s("invalid_value"), "{options}");
+    }
+    assert_eq!(vm.interpret("fetch('http://example.test/ HTTP/1.1\r\nX-Evil: 1').kind").unwrap(), s("invalid_value"));
+    assert_eq!(vm.interpret("fetch('http://example.test/', {method:'patch', json:true}).json.method").unwrap(), s("PATCH"));
+}
@@ -3,8 +3,12 @@ use std::ptr;
 
 use funcscript::ffi::{
     fs_error_free, fs_free_string, fs_vm_eval, fs_vm_eval_value, fs_vm_free, fs_vm_new, fs_vm_value_call,
-    fs_vm_value_free, fs_vm_value_to_json, FsErrorC, FsValue,
+    fs_vm_collect_cycles, fs_vm_memory_stats, fs_vm_set_auto_collect, fs_vm_set_parallelism, fs_vm_value_free, fs_vm_value_len, fs_vm_value_to_json,
+    fs_vm_value_type,
+    fs_vm_clear_random_seed, fs_vm_set_host_callbacks, fs_vm_set_random_seed, FsErrorC, FsFileMetadataC,
+    FsHostCallbacksC, FsHostWriteFn, FsHttpRequestC, FsMemoryStatsC, FsValue, FS_VALUE_DURATION,
 };
+use std::ffi::c_void;
 
 #[test]
 fn c_abi_vm_reuse_and_ok_json() {
@@ -13,7 +17,7 @@ fn c_abi_vm_reuse_and_ok_json() {
 
     let src = CString::new("1+2").unwrap();
     let mut out_json: *mut i8 = ptr::null_mut();
-    let mut out_err = FsErrorC { code: 0, line: 0, column: 0, message: ptr::null_mut() };
+    let mut out_err = FsErrorC::default();
 
     let rc = fs_vm_eval(vm, src.as_ptr(), &mut out_json, &mut out_err);
     assert_eq!(rc, 0);
@@ -33,7 +37,7 @@ fn c_abi_compile_error_has_location() {
     let vm = fs_vm_new();
     let src = CString::new("If(true, 1, )").unwrap();
     let mut out_json: *mut i8 = ptr::null_mut();
-    let mut out_err = FsErrorC { code: 0, line: 0, column: 0, message: ptr::null_mut() };
+    let mut out_err = FsErrorC::default();
 
     let rc = fs_vm_eval(vm, src.as_ptr(), &mut out_json, &mut out_err);
     assert_eq!(rc, 1);
@@ -52,12 +56,13 @@ fn c_abi_value_error_returns_error() {
     let vm = fs_vm_new();
     let src = CString::new("Range(1, -1)").unwrap();
     let mut out_json: *mut i8 = ptr::null_mut();
-    let mut out_err = FsErrorC { code: 0, line: 0, column: 0, message: ptr::null_mut() };
+    let mut out_err = FsErrorC::default();
 
     let rc = fs_vm_eval(vm, src.as_ptr(), &mut out_json, &mut out_err);
     assert_eq!(rc, 1);
     assert!(out_json.is_null());
-    assert_eq!(out_err.code, 1);
+    assert_eq!(out_err.code, 2006);
+    assert_eq!(unsafe { CStr::from_ptr(out_err.kind) }.to_str().unwrap(), "invalid_value");
     assert!(!out_err.message.is_null());
 
     fs_error_free(&mut out_err);
@@ -68,12 +73,12 @@ fn c_abi_value_error_returns_error() {
 fn c_abi_null_vm_returns_error() {
     let src = CString::new("1+2").unwrap();
     let mut out_json: *mut i8 = ptr::null_mut();
-    let mut out_err = FsErrorC { code: 0, line: 0, column: 0, message: ptr::null_mut() };
+    let mut out_err = FsErrorC::default();
 
     let rc = fs_vm_eval(ptr::null_mut(), src.as_ptr(), &mut out_json, &mut out_err);
     assert_eq!(rc, 1);
     assert!(out_json.is_null());
-    assert_eq!(out_err.code, 2001);
+    assert_eq!(out_err.code, 2100);
     assert!(!out_err.message.is_null());
 
     fs_error_free(&mut out_err);
@@ -84,12 +89,13 @@ fn c_abi_arity_mismatch_returns_runtime_error_with_message() {
     let vm = fs_vm_new();
     let src = CString::new("((x)=>x)(1,2)").unwrap();
     let mut out_json: *mut i8 = ptr::null_mut();
-    let mut out_err = FsErrorC { code: 0, line: 0, column: 0, message: ptr::null_mut() };
+    let mut out_err = FsErrorC::default();
 
     let rc = fs_vm_eval(vm, src.as_ptr(), &mut out_json, &mut out_err);
     assert_eq!(rc, 1);
     assert!(out_json.is_null());
-    assert_eq!(out_err.code, 2004);
+    assert_eq!(out_err.code, 2002);
+    assert_eq!(unsafe { CStr::from_ptr(out_err.kind) }.to_str().unwrap(), "parameter_count");
     assert!(!out_err.message.is_null());
 
     fs_error_free(&mut out_err);
@@ -103,7 +109,7 @@ fn c_abi_value_handles_can_call_returned_function() {
 
     let src_fn = CString::new("(x)=>x+1").unwrap();
     let mut out_fn = FsValue { id: 0 };
-    let mut out_err = FsErrorC { code: 0, line: 0, column: 0, message: ptr::null_mut() };
+    let mut out_err = FsErrorC::default();
     let rc = fs_vm_eval_value(vm, src_fn.as_ptr(), &mut out_fn, &mut out_err);
     assert_eq!(rc, 0);
     assert!(out_fn.id != 0);
@@ -135,3 +141,240 @@ fn c_abi_value_handles_can_call_returned_function() {
     fs_error_free(&mut out_err);
     fs_vm_free(vm);
 }
+
+#[test]
+fn c_abi_value_len_counts_code_points() {
+    let vm = fs_vm_new();
+    let src = CString::new(r#""héllo wörld""#).unwrap();
+    let mut out_val = FsValue { id: 0 };
+    let mut out_err = FsErrorC::default();
+    assert_eq!(fs_vm_eval_value(vm, src.as_ptr(), &mut out_val, &mut out_err), 0);
+
+    let mut len = 0u64;
+    assert_eq!(fs_vm_value_len(vm, out_val, &mut len, &mut out_err), 0);
+    assert_eq!(len, 11);
+
+    assert_eq!(fs_vm_value_free(vm, out_val), 0);
+    fs_error_free(&mut out_err);
+    fs_vm_free(vm);
+}
+
+#[test]
+fn c_abi_duration_has_its_own_type_tag() {
+    let vm = fs_vm_new();
+    let src = CString::new(r#"Date("2024-03-06T01:00:00Z") - Date("2024-03-05T22:30:00Z")"#).unwrap();
+    let mut out_val = FsValue { id: 0 };
+    let mut out_err = FsErrorC::default();
+    assert_eq!(fs_vm_eval_value(vm, src.as_ptr(), &mut out_val, &mut out_err), 0);
+    assert_eq!(fs_vm_value_type(vm, out_val), FS_VALUE_DURATION);
+
+    let mut out_json: *mut std::os::raw::c_char = ptr::null_mut();
+    assert_eq!(fs_vm_value_to_json(vm, out_val, &mut out_json, &mut out_err), 0);
+    let json = unsafe { CStr::from_ptr(out_json) }.to_str().unwrap().to_string();
+    assert_eq!(json, r#"{"type":"duration","ticks":90000000000,"iso":"PT2H30M"}"#);
+
+    fs_free_string(out_json);
+    assert_eq!(fs_vm_value_free(vm, out_val), 0);
+    fs_error_free(&mut out_err);
+    fs_vm_free(vm);
+}
+
+#[test]
+fn c_abi_parallelism_toggle() {
+    let vm = fs_vm_new();
+    assert_eq!(fs_vm_set_parallelism(vm, 4, 8), 0);
+    assert_eq!(fs_vm_set_parallelism(ptr::null_mut(), 4, 8), 1);
+
+    let src = CString::new("Sum(Range(1, 100) map (x) => x * 2)").unwrap();
+    let mut out_json: *mut i8 = ptr::null_mut();
+    let mut out_err = FsErrorC::default();
+    let rc = fs_vm_eval(vm, src.as_ptr(), &mut out_json, &mut out_err);
+    assert_eq!(rc, 0);
+    let got = unsafe { CStr::from_ptr(out_json) }.to_str().unwrap().to_string();
+    fs_free_string(out_json);
+    assert_eq!(got, "10100");
+
+    assert_eq!(fs_vm_set_parallelism(vm, 0, 0), 0);
+    fs_error_free(&mut out_err);
+    fs_vm_free(vm);
+}
+
+#[test]
+fn c_abi_memory_stats_and_cycle_collection() {
+    let vm = fs_vm_new();
+    let src = CString::new("{ a: { b: 1 }; c: a.b }").unwrap();
+    for _ in 0..10 {
+        let mut out_json: *mut i8 = ptr::null_mut();
+        let mut out_err = FsErrorC::default();
+        assert_eq!(fs_vm_eval(vm, src.as_ptr(), &mut out_json, &mut out_err), 0);
+        fs_free_string(out_json);
+    }
+
+    let mut stats = FsMemoryStatsC::default();
+    assert_eq!(fs_vm_memory_stats(vm, &mut stats), 0);
+    assert!(stats.live_kvcs >= 10);
+
+    assert!(fs_vm_collect_cycles(vm) >= 20);
+    assert_eq!(fs_vm_memory_stats(vm, &mut stats), 0);
+    assert_eq!(stats.live_kvcs, 0);
+    assert_eq!(stats.collections, 1);
+    assert_eq!(fs_vm_memory_stats(ptr::null_mut(), &mut stats), 1);
+
+    // C VMs collect during evaluation unless the host turns it off.
+    let eval_many = |count: usize| {
+        for _ in 0..count {
+            let mut out_json: *mut i8 = ptr::null_mut();
+            let mut out_err = FsErrorC::default();
+            assert_eq!(fs_vm_eval(vm, src.as_ptr(), &mut out_json, &mut out_err), 0);
+            fs_free_string(out_json);
+        }
+    };
+    eval_many(3000);
+    assert_eq!(fs_vm_memory_stats(vm, &mut stats), 0);
+    assert!(stats.collections > 1);
+    let before = stats.collections;
+    assert_eq!(fs_vm_set_auto_collect(vm, 0), 0);
+    eval_many(3000);
+    assert_eq!(fs_vm_memory_stats(vm, &mut stats), 0);
+    assert_eq!(stats.collections, before);
+    assert_eq!(fs_vm_set_auto_collect(ptr::null_mut(), 1), 1);
+    fs_vm_free(vm);
+}
+
+#[test]
+fn c_abi_error_carries_kind_data_and_cause() {
+    let vm = fs_vm_new();
+    let src = CString::new("error('outer', 'Wrap', { id: 7 }, error('inner'))").unwrap();
+    let mut out_json: *mut i8 = ptr::null_mut();
+    let mut out_err = FsErrorC::default();
+
+    let rc = fs_vm_eval(vm, src.as_ptr(), &mut out_json, &mut out_err);
+    assert_eq!(rc, 1);
+    assert_eq!(out_err.code, 3000);
+    assert_eq!(unsafe { CStr::from_ptr(out_err.kind) }.to_str().unwrap(), "user");
+    assert_eq!(unsafe { CStr::from_ptr(out_err.data_json) }.to_str().unwrap(), "{\"id\":7}");
+    assert!(!out_err.cause.is_null());
+    let cause = unsafe { &*out_err.cause };
+    assert_eq!(unsafe { CStr::from_ptr(cause.message) }.to_str().unwrap(), "inner");
+    assert!(cause.cause.is_null());
+
+    fs_error_free(&mut out_err);
+    assert!(out_err.cause.is_null() && out_err.data_json.is_null());
+    fs_vm_free(vm);
+}
+
+fn eval_json(vm: *mut funcscript::ffi::FsVm, source: &str) -> String {
+    let src = CString::new(source).unwrap();
+    let mut out_json: *mut i8 = ptr::null_mut();
+    let mut out_err = FsErrorC::default();
+    assert_eq!(fs_vm_eval(vm, src.as_ptr(), &mut out_json, &mut out_err), 0);
+    let got = unsafe { CStr::from_ptr(out_json) }.to_str().unwrap().to_string();
+    fs_free_string(out_json);
+    got
+}
+
+extern "C" fn fixed_now(_user_data: *mut c_void, out_utc_ticks: *mut i64, out_offset_minutes: *mut i32) {
+    unsafe {
+        *out_utc_ticks = 638_000_000_000_000_000;
+        *out_offset_minutes = 180;
+    }
+}
+
+extern "C" fn constant_random(_user_data: *mut c_void) -> u64 {
+    0
+}
+
+#[test]
+fn c_abi_host_clock_random_and_seed() {
+    let vm = fs_vm_new();
+    let callbacks = FsHostCallbacksC { now: Some(fixed_now), random: Some(constant_random), ..Default::default() };
+    assert_eq!(fs_vm_set_host_callbacks(vm, &callbacks), 0);
+
+    assert!(eval_json(vm, "utcnow()").contains(r#""iso":"2022-09-28T22:13:20Z""#));
+    assert!(eval_json(vm, "now()").contains(r#""iso":"2022-09-29T01:13:20+03:00""#));
+    assert_eq!(eval_json(vm, "Random()"), "0");
+
+    assert_eq!(fs_vm_set_random_seed(vm, 42), 0);
+    let first = eval_json(vm, "[Random(), guid()]");
+    assert_ne!(first, r#"[0,"00000000-0000-4000-8000-000000000000"]"#);
+    assert_eq!(fs_vm_set_random_seed(vm, 42), 0);
+    assert_eq!(eval_json(vm, "[Random(), guid()]"), first);
+
+    assert_eq!(fs_vm_clear_random_seed(vm), 0);
+    assert_eq!(eval_json(vm, "Random()"), "0");
+    fs_vm_free(vm);
+}
+
+extern "C" fn region_env(_user_data: *mut c_void, name: *const i8, out_found: *mut i32, out_ctx: *mut c_void, out_write: FsHostWriteFn) {
+    if unsafe { CStr::from_ptr(name) }.to_bytes() == b"REGION" {
+        unsafe { *out_found = 1 };
+        out_write.unwrap()(out_ctx, b"eu".as_ptr(), 2);
+    }
+}
+
+extern "C" fn virtual_walk(_user_data: *mut c_void, _path: *const i8, out_ctx: *mut c_void, out_write: FsHostWriteFn, _out_error: *mut FsErrorC) -> i32 {
+    let listing = b"a.fs\nlib\nlib/b.fs\nlib/c.txt";
+    out_write.unwrap()(out_ctx, listing.as_ptr(), listing.len() as u64);
+    0
+}
+
+extern "C" fn virtual_metadata(_user_data: *mut c_void, path: *const i8, out_metadata: *mut FsFileMetadataC, _out_error: *mut FsErrorC) -> i32 {
+    if unsafe { CStr::from_ptr(path) }.to_bytes() == b"/v/lib" {
+        unsafe { *out_metadata = FsFileMetadataC { exists: 1, is_dir: 1, size: 0, modified_ticks: 638_000_000_000_000_000 } };
+    }
+    0
+}
+
+#[test]
+fn c_abi_host_can_provide_a_virtual_file_system_and_environment() {
+    let vm = fs_vm_new();
+    let callbacks = FsHostCallbacksC {
+        dir_walk: Some(virtual_walk),
+        file_metadata: Some(virtual_metadata),
+        env_var: Some(region_env),
+        ..Default::default()
+    };
+    assert_eq!(fs_vm_set_host_callbacks(vm, &callbacks), 0);
+
+    assert_eq!(eval_json(vm, "walk('/v', '**/*.fs')"), r#"["/v/a.fs","/v/lib/b.fs"]"#);
+    assert_eq!(eval_json(vm, "[metadata('/v/lib').isDirectory, metadata('/v/none')]"), "[true,null]");
+    assert_eq!(eval_json(vm, "fileBytes('/v/a.fs').kind"), r#""host_unavailable""#);
+    assert_eq!(eval_json(vm, "writefile('/v/out.txt', 'x').kind"), r#""host_unavailable""#);
+    assert_eq!(eval_json(vm, "[env('REGION'), env('STAGE', 'dev')]"), r#"["eu","dev"]"#);
+    fs_vm_free(vm);
+}
+
+extern "C" fn echo_fetch(
+    _user_data: *mut c_void,
+    request: *const FsHttpRequestC,
+    out_status: *mut i32,
+    out_headers_ctx: *mut c_void,
+    out_body_ctx: *mut c_void,
+    out_write: FsHostWriteFn,
+    _out_error: *mut FsErrorC,
+) -> i32 {
+    let request = unsafe { &*request };
+    let method = unsafe { CStr::from_ptr(request.method) }.to_str().unwrap();
+    let headers = unsafe { CStr::from_ptr(request.headers) }.to_str().unwrap();
+    let body = unsafe { std::slice::from_raw_parts(request.body, request.body_len as usize) };
+    let json = format!(r#"{{"method":"{method}","headers":{},"body":{}}}"#, headers.lines().count(), String::from_utf8_lossy(body));
+    let response_headers = b"Content-Type: application/json\nX-Echo: 1\n";
+    unsafe { *out_status = 200 };
+    out_write.unwrap()(out_headers_ctx, response_headers.as_ptr(), response_headers.len() as u64);
+    out_write.unwrap()(out_body_ctx, json.as_ptr(), json.len() as u64);
+    0
+}
+
+#[test]
+fn c_abi_host_fetch_callback() {
+    let vm = fs_vm_new();
+    assert_eq!(eval_json(vm, "fetch('http://example.test/').kind"), r#""host_unavailable""#);
+
+    let callbacks = FsHostCallbacksC { fetch: Some(echo_fetch), ..Default::default() };
+    assert_eq!(fs_vm_set_host_callbacks(vm, &callbacks), 0);
+    assert_eq!(
+        eval_json(vm, "r: fetch('http://example.test/', {method:'put', body:[1,2], json:true}); eval [r.status, r.headers['x-echo'], r.json]"),
+        r#"[200,"1",{"method":"PUT","headers":1,"body":[1,2]}]"#
+    );
+    fs_vm_free(vm);
+}